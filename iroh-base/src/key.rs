@@ -19,8 +19,8 @@ use serde::{Deserialize, Serialize};
 use ssh_key::LineEnding;
 use ttl_cache::TtlCache;
 
-pub use self::encryption::SharedSecret;
 use self::encryption::{public_ed_box, secret_ed_box};
+pub use self::encryption::{SharedSecret, NONCE_LEN, TAG_LEN};
 
 #[derive(Debug)]
 struct CryptoKeys {
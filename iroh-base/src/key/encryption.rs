@@ -5,7 +5,11 @@ use std::fmt::Debug;
 use aead::Buffer;
 use anyhow::{anyhow, ensure, Context, Result};
 
-pub(crate) const NONCE_LEN: usize = 24;
+/// Length of the nonce used by [`SharedSecret`].
+pub const NONCE_LEN: usize = 24;
+
+/// Length of the authentication tag that [`SharedSecret`] adds to a sealed message.
+pub const TAG_LEN: usize = 16;
 
 pub(super) fn public_ed_box(key: &ed25519_dalek::VerifyingKey) -> crypto_box::PublicKey {
     crypto_box::PublicKey::from(key.to_montgomery())
@@ -41,6 +45,38 @@ impl SharedSecret {
         buffer.extend_from_slice(&nonce).expect("buffer too small");
     }
 
+    /// Seals the provided cleartext with the given nonce.
+    ///
+    /// Unlike [`Self::seal`], this is deterministic. A nonce must never be used for two
+    /// different cleartexts, so it should be derived from the cleartext with a keyed hash.
+    /// The result can be opened with [`Self::open`].
+    pub fn seal_with_nonce(&self, nonce: &[u8; NONCE_LEN], buffer: &mut dyn Buffer) {
+        self.seal_detached(nonce, buffer);
+        buffer.extend_from_slice(nonce).expect("buffer too small");
+    }
+
+    /// Seals the provided cleartext with the given nonce, without appending the nonce.
+    ///
+    /// The same nonce must be passed to [`Self::open_detached`] to open the result. A nonce
+    /// must never be used for two different cleartexts.
+    pub fn seal_detached(&self, nonce: &[u8; NONCE_LEN], buffer: &mut dyn Buffer) {
+        use aead::AeadInPlace;
+
+        self.0
+            .encrypt_in_place(nonce.into(), &[], buffer)
+            .expect("encryption failed");
+    }
+
+    /// Opens the ciphertext, which must have been created using [`Self::seal_detached`] with
+    /// the same nonce, and places the clear text into the provided buffer.
+    pub fn open_detached(&self, nonce: &[u8; NONCE_LEN], buffer: &mut dyn Buffer) -> Result<()> {
+        use aead::AeadInPlace;
+
+        self.0
+            .decrypt_in_place(nonce.into(), &[], buffer)
+            .map_err(|e| anyhow!("decryption failed: {:?}", e))
+    }
+
     /// Opens the ciphertext, which must have been created using `Self::seal`, and places the clear text into the provided buffer.
     pub fn open(&self, buffer: &mut dyn Buffer) -> Result<()> {
         use aead::AeadInPlace;
@@ -95,6 +131,39 @@ mod tests {
         assert_eq!(&msg[..], &decrypted_message);
     }
 
+    #[test]
+    fn test_seal_open_detached() {
+        let key = crate::key::SecretKey::generate();
+        let shared = key.shared(&key.public());
+        let msg = b"super secret message!!!!".to_vec();
+        let mut sealed = msg.clone();
+        shared.seal_detached(&[1u8; NONCE_LEN], &mut sealed);
+        assert_eq!(sealed.len(), msg.len() + TAG_LEN);
+        let mut wrong_nonce = sealed.clone();
+        assert!(shared
+            .open_detached(&[2u8; NONCE_LEN], &mut wrong_nonce)
+            .is_err());
+        shared
+            .open_detached(&[1u8; NONCE_LEN], &mut sealed)
+            .unwrap();
+        assert_eq!(&msg[..], &sealed);
+    }
+
+    #[test]
+    fn test_seal_with_nonce_deterministic() {
+        let key = crate::key::SecretKey::generate();
+        let shared = key.shared(&key.public());
+        let msg = b"super secret message!!!!".to_vec();
+        let nonce = [7u8; NONCE_LEN];
+        let mut sealed_a = msg.clone();
+        shared.seal_with_nonce(&nonce, &mut sealed_a);
+        let mut sealed_b = msg.clone();
+        shared.seal_with_nonce(&nonce, &mut sealed_b);
+        assert_eq!(sealed_a, sealed_b);
+        shared.open(&mut sealed_a).unwrap();
+        assert_eq!(&msg[..], &sealed_a);
+    }
+
     #[test]
     fn test_roundtrip_public_key() {
         let key = crypto_box::SecretKey::generate(&mut rand::thread_rng());
@@ -92,7 +92,16 @@ async fn main() -> Result<()> {
 
             // spawn a task to handle the connection
             tokio::spawn(async move {
-                iroh_bytes::provider::handle_connection(conn, db, MockEventSender, lp).await
+                iroh_bytes::provider::handle_connection(
+                    conn,
+                    db,
+                    MockEventSender,
+                    None,
+                    None,
+                    Default::default(),
+                    lp,
+                )
+                .await
             });
         }
     });
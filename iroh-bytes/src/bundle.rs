@@ -0,0 +1,511 @@
+//! A portable archive format to move blobs between stores without a network connection.
+//!
+//! A bundle contains the data of a set of blobs, encoded the same way as a
+//! response to a get request, and the tags that point to them. Since the data
+//! is bao encoded, it is verified against the blob hashes on import, so a
+//! bundle can be carried over untrusted media.
+//!
+//! Each root of a bundle is given as a [`HashAndFormat`] and a [`RangeSpecSeq`]
+//! with the same meaning as in a [`GetRequest`](crate::protocol::GetRequest),
+//! so bundles can contain just a subset of a blob or hash sequence.
+//!
+//! # Format
+//!
+//! - the magic bytes `irohbndl`, followed by a version byte
+//! - a frame containing the [`BundleHeader`]
+//! - for each blob, a frame containing its hash and ranges, followed by its
+//!   size as a little endian u64 and the bao encoding of the ranges
+//! - a frame marking the end of the bundle
+//!
+//! A frame is a little endian u32 length, followed by a postcard encoded value.
+use std::io;
+
+use anyhow::{ensure, Context};
+use bao_tree::{
+    io::fsm::{
+        encode_ranges_validated, BaoContentItem, Outboard, ResponseDecoder, ResponseDecoderNext,
+    },
+    BaoTree,
+};
+use iroh_base::hash::{Hash, HashAndFormat};
+use iroh_io::{
+    AsyncSliceReaderExt, AsyncStreamReader, AsyncStreamWriter, TokioStreamReader, TokioStreamWriter,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    get::db::has_all_chunks,
+    hashseq::HashSeq,
+    protocol::{RangeSpec, RangeSpecSeq},
+    store::{BaoBatchWriter, EntryStatus, Map, MapEntry, MapEntryMut, ReadableStore, Store},
+    Tag, TempTag, IROH_BLOCK_SIZE,
+};
+
+const MAGIC: &[u8; 8] = b"irohbndl";
+
+const VERSION: u8 = 1;
+
+/// Maximum size of a frame, to avoid allocating huge buffers for corrupt bundles.
+const MAX_FRAME_SIZE: usize = 1024 * 1024 * 16;
+
+/// The header of a bundle, describing its content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleHeader {
+    /// The roots of the bundle, and the ranges of them that are included.
+    pub roots: Vec<(HashAndFormat, RangeSpecSeq)>,
+    /// Tags pointing to the roots.
+    pub tags: Vec<(Tag, HashAndFormat)>,
+}
+
+/// A frame following the header.
+#[derive(Debug, Serialize, Deserialize)]
+enum Item {
+    /// A blob, followed by its size and the bao encoding of the ranges.
+    Blob { hash: Hash, ranges: RangeSpec },
+    /// The end of the bundle.
+    End,
+}
+
+/// Write a bundle of `roots` from `db` to `writer`.
+///
+/// All tags in `db` that point to one of the roots are included in the bundle.
+/// Fails if a blob is missing, or if it does not contain the requested ranges.
+pub async fn write_bundle<D: ReadableStore>(
+    db: &D,
+    roots: Vec<(HashAndFormat, RangeSpecSeq)>,
+    writer: impl AsyncWrite + Unpin,
+) -> anyhow::Result<BundleHeader> {
+    let mut tags = Vec::new();
+    for item in db.tags().await? {
+        let (tag, value) = item?;
+        if roots.iter().any(|(root, _)| *root == value) {
+            tags.push((tag, value));
+        }
+    }
+    let header = BundleHeader { roots, tags };
+    let mut writer = TokioStreamWriter(writer);
+    writer.write(MAGIC).await?;
+    writer.write(&[VERSION]).await?;
+    write_frame(&mut writer, &header).await?;
+    for (root, ranges) in &header.roots {
+        write_content(db, *root, ranges, &mut writer).await?;
+    }
+    write_frame(&mut writer, &Item::End).await?;
+    writer.sync().await?;
+    Ok(header)
+}
+
+/// The outcome of [`read_bundle`].
+#[derive(Debug)]
+pub struct ReadBundleOutcome {
+    /// The header of the bundle.
+    pub header: BundleHeader,
+    /// Temp tags for the roots of the bundle.
+    pub temp_tags: Vec<TempTag>,
+    /// Tags of the bundle that were not set, since a tag with the same name
+    /// already points to other content.
+    pub conflicting_tags: Vec<Tag>,
+}
+
+/// Read a bundle from `reader` and import it into `db`.
+///
+/// All blobs are verified while they are imported. Blobs that are complete
+/// after the import are marked as complete, and the tags of the bundle are set
+/// once all blobs are imported. Existing tags are never overwritten, tags of
+/// the bundle that conflict with them are skipped and reported.
+///
+/// Bundles with tags that do not point to one of their roots, or with blobs
+/// that are not part of the declared ranges of their roots, are rejected.
+pub async fn read_bundle<D: Store>(
+    db: &D,
+    reader: impl AsyncRead + Unpin,
+) -> anyhow::Result<ReadBundleOutcome> {
+    let mut reader = TokioStreamReader::new(reader);
+    let magic = reader.read::<8>().await?;
+    ensure!(&magic == MAGIC, "not an iroh bundle");
+    let [version] = reader.read::<1>().await?;
+    ensure!(version == VERSION, "unsupported bundle version {version}");
+    let header: BundleHeader = read_frame(&mut reader).await?;
+    for (tag, value) in &header.tags {
+        ensure!(
+            header.roots.iter().any(|(root, _)| root == value),
+            "tag {tag} does not point to a root of the bundle"
+        );
+    }
+    // protect the roots, and through them their children, from gc until the
+    // caller has dropped the temp tags, which is after the tags are set
+    let temp_tags = header
+        .roots
+        .iter()
+        .map(|(root, _)| db.temp_tag(*root))
+        .collect::<Vec<_>>();
+    for (root, ranges) in &header.roots {
+        reader = read_content(db, *root, ranges, reader).await?;
+    }
+    let item: Item = read_frame(&mut reader).await?;
+    ensure!(
+        matches!(item, Item::End),
+        "bundle contains blobs that are not part of its roots"
+    );
+    let mut conflicting_tags = Vec::new();
+    for (tag, value) in &header.tags {
+        match db
+            .compare_and_swap_tag(tag.clone(), None, Some(*value))
+            .await?
+        {
+            Ok(()) => {}
+            Err(Some(current)) if current == *value => {}
+            Err(_) => conflicting_tags.push(tag.clone()),
+        }
+    }
+    Ok(ReadBundleOutcome {
+        header,
+        temp_tags,
+        conflicting_tags,
+    })
+}
+
+/// Write the ranges of a root and its children, if it is a hash sequence.
+async fn write_content<D: Map>(
+    db: &D,
+    root: HashAndFormat,
+    ranges: &RangeSpecSeq,
+    writer: &mut impl AsyncStreamWriter,
+) -> anyhow::Result<()> {
+    // if just the root is requested, we don't need to parse the hash seq
+    let just_root = matches!(ranges.as_single(), Some((0, _)));
+    let children = if root.format.is_hash_seq() && !just_root {
+        let entry = db
+            .get(&root.hash)
+            .await?
+            .with_context(|| format!("blob {} not found", root.hash))?;
+        let mut reader = entry.data_reader().await?;
+        let data = reader.read_to_end().await?;
+        Some(HashSeq::try_from(data)?)
+    } else {
+        None
+    };
+    for (offset, ranges) in ranges.iter_non_empty() {
+        let hash = if offset == 0 {
+            root.hash
+        } else {
+            let Some(hash) = children.as_ref().and_then(|c| c.get(offset as usize - 1)) else {
+                break;
+            };
+            hash
+        };
+        write_blob(db, hash, ranges, writer).await?;
+    }
+    Ok(())
+}
+
+async fn write_blob<D: Map>(
+    db: &D,
+    hash: Hash,
+    ranges: &RangeSpec,
+    writer: &mut impl AsyncStreamWriter,
+) -> anyhow::Result<()> {
+    let entry = db
+        .get(&hash)
+        .await?
+        .with_context(|| format!("blob {hash} not found"))?;
+    let outboard = entry.outboard().await?;
+    let size = outboard.tree().size();
+    let item = Item::Blob {
+        hash,
+        ranges: ranges.clone(),
+    };
+    write_frame(writer, &item).await?;
+    writer.write(&size.to_le_bytes()).await?;
+    encode_ranges_validated(
+        entry.data_reader().await?,
+        outboard,
+        &ranges.to_chunk_ranges(),
+        &mut *writer,
+    )
+    .await
+    .with_context(|| format!("failed to encode blob {hash}"))?;
+    Ok(())
+}
+
+/// Read the ranges of a root and its children, if it is a hash sequence.
+///
+/// The blobs must appear in the same order as they are written by
+/// [`write_content`], so that no data outside of the declared ranges is imported.
+async fn read_content<D: Store, R: AsyncStreamReader>(
+    db: &D,
+    root: HashAndFormat,
+    ranges: &RangeSpecSeq,
+    mut reader: R,
+) -> anyhow::Result<R> {
+    let mut children = None;
+    for (offset, ranges) in ranges.iter_non_empty() {
+        let hash = if offset == 0 {
+            root.hash
+        } else if !root.format.is_hash_seq() {
+            break;
+        } else {
+            if children.is_none() {
+                // the hash seq is either part of the bundle or already in the store
+                ensure!(
+                    db.entry_status(&root.hash).await? == EntryStatus::Complete,
+                    "hash seq {} is incomplete",
+                    root.hash
+                );
+                let entry = db
+                    .get(&root.hash)
+                    .await?
+                    .with_context(|| format!("blob {} not found", root.hash))?;
+                let data = entry.data_reader().await?.read_to_end().await?;
+                children = Some(HashSeq::try_from(data)?);
+            }
+            let Some(hash) = children.as_ref().and_then(|c| c.get(offset as usize - 1)) else {
+                break;
+            };
+            hash
+        };
+        match read_frame(&mut reader).await? {
+            Item::Blob {
+                hash: actual,
+                ranges: actual_ranges,
+            } => ensure!(
+                actual == hash && actual_ranges == *ranges,
+                "unexpected blob {actual} in bundle, expected {hash}"
+            ),
+            Item::End => anyhow::bail!("unexpected end of bundle, expected blob {hash}"),
+        }
+        reader = import_blob(db, hash, ranges, reader)
+            .await
+            .with_context(|| format!("failed to import blob {hash}"))?;
+    }
+    Ok(reader)
+}
+
+async fn import_blob<D: Store, R: AsyncStreamReader>(
+    db: &D,
+    hash: Hash,
+    ranges: &RangeSpec,
+    mut reader: R,
+) -> anyhow::Result<R> {
+    let size = u64::from_le_bytes(reader.read::<8>().await?);
+    let tree = BaoTree::new(size, IROH_BLOCK_SIZE);
+    let decoder = ResponseDecoder::new(hash.into(), ranges.to_chunk_ranges(), tree, reader);
+    if db.entry_status(&hash).await? == EntryStatus::Complete {
+        // still decode the data, to verify it and to get to the next item
+        return decode_blob(decoder, size, Discard).await;
+    }
+    let entry = db.get_or_create(hash, size).await?;
+    let reader = decode_blob(decoder, size, entry.batch_writer().await?).await?;
+    if has_all_chunks::<D>(&entry, size).await? {
+        db.insert_complete(entry).await?;
+    }
+    Ok(reader)
+}
+
+/// Decode the bao encoded ranges of a blob into `writer`, and return the reader.
+async fn decode_blob<R: AsyncStreamReader>(
+    mut decoder: ResponseDecoder<R>,
+    size: u64,
+    mut writer: impl BaoBatchWriter,
+) -> anyhow::Result<R> {
+    let mut batch = Vec::new();
+    let reader = loop {
+        match decoder.next().await {
+            ResponseDecoderNext::Done(reader) => break reader,
+            ResponseDecoderNext::More((next, item)) => {
+                decoder = next;
+                let item = item?;
+                let leaf = matches!(item, BaoContentItem::Leaf(_));
+                batch.push(item);
+                // write a batch every time we see a leaf
+                if leaf {
+                    writer.write_batch(size, std::mem::take(&mut batch)).await?;
+                }
+            }
+        }
+    };
+    writer.sync().await?;
+    Ok(reader)
+}
+
+/// A batch writer that ignores all writes, for blobs that are already complete.
+struct Discard;
+
+impl BaoBatchWriter for Discard {
+    async fn write_batch(&mut self, _size: u64, _batch: Vec<BaoContentItem>) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+async fn write_frame(
+    writer: &mut impl AsyncStreamWriter,
+    value: &impl Serialize,
+) -> anyhow::Result<()> {
+    let data = postcard::to_stdvec(value)?;
+    ensure!(data.len() <= MAX_FRAME_SIZE, "bundle frame too large");
+    writer.write(&(data.len() as u32).to_le_bytes()).await?;
+    writer.write(&data).await?;
+    Ok(())
+}
+
+async fn read_frame<T: DeserializeOwned>(reader: &mut impl AsyncStreamReader) -> anyhow::Result<T> {
+    let len = u32::from_le_bytes(reader.read::<4>().await?) as usize;
+    ensure!(len <= MAX_FRAME_SIZE, "bundle frame too large");
+    let data = reader.read_bytes(len).await?;
+    ensure!(data.len() == len, "unexpected end of bundle");
+    Ok(postcard::from_bytes(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bao_tree::ChunkRanges;
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        store::{mem, MapMut},
+        BlobFormat,
+    };
+
+    async fn read_all(db: &mem::Store, hash: Hash) -> anyhow::Result<Bytes> {
+        let entry = db.get(&hash).await?.context("not found")?;
+        let mut reader = entry.data_reader().await?;
+        Ok(reader.read_to_end().await?)
+    }
+
+    #[tokio::test]
+    async fn roundtrip() -> anyhow::Result<()> {
+        let source = mem::Store::new();
+        let a = source
+            .import_bytes(vec![1u8; 100_000].into(), BlobFormat::Raw)
+            .await?;
+        let b = source
+            .import_bytes(vec![2u8; 10].into(), BlobFormat::Raw)
+            .await?;
+        let seq = [*a.hash(), *b.hash()].into_iter().collect::<HashSeq>();
+        let seq = source
+            .import_bytes(seq.into_inner(), BlobFormat::HashSeq)
+            .await?;
+        source
+            .set_tag(Tag::from("collection"), Some(*seq.inner()))
+            .await?;
+
+        let mut bundle = Vec::new();
+        let header = write_bundle(
+            &source,
+            vec![(*seq.inner(), RangeSpecSeq::all())],
+            &mut bundle,
+        )
+        .await?;
+        assert_eq!(header.tags, vec![(Tag::from("collection"), *seq.inner())]);
+
+        let target = mem::Store::new();
+        let outcome = read_bundle(&target, bundle.as_slice()).await?;
+        assert_eq!(outcome.header, header);
+        assert_eq!(outcome.temp_tags.len(), 1);
+        assert!(outcome.conflicting_tags.is_empty());
+        for hash in [*a.hash(), *b.hash(), *seq.hash()] {
+            assert_eq!(target.entry_status(&hash).await?, EntryStatus::Complete);
+            assert_eq!(
+                read_all(&target, hash).await?,
+                read_all(&source, hash).await?
+            );
+        }
+        let tags = target.tags().await?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(tags, header.tags);
+
+        // importing again is a no-op
+        let outcome = read_bundle(&target, bundle.as_slice()).await?;
+        assert!(outcome.conflicting_tags.is_empty());
+
+        // existing tags are not overwritten
+        let other = mem::Store::new();
+        other
+            .set_tag(Tag::from("collection"), Some(*b.inner()))
+            .await?;
+        let outcome = read_bundle(&other, bundle.as_slice()).await?;
+        assert_eq!(outcome.conflicting_tags, vec![Tag::from("collection")]);
+        let tags = other.tags().await?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(tags, vec![(Tag::from("collection"), *b.inner())]);
+
+        // tags that point outside of the bundle are rejected
+        let mut outside = header.clone();
+        outside.tags = vec![(Tag::from("outside"), *a.inner())];
+        let mut forged = Vec::new();
+        forged.extend_from_slice(MAGIC);
+        forged.push(VERSION);
+        let mut writer = TokioStreamWriter(&mut forged);
+        write_frame(&mut writer, &outside).await?;
+        write_frame(&mut writer, &Item::End).await?;
+        assert!(read_bundle(&mem::Store::new(), forged.as_slice())
+            .await
+            .is_err());
+
+        // blobs that are not part of the roots are rejected
+        let mut injected = Vec::new();
+        injected.extend_from_slice(MAGIC);
+        injected.push(VERSION);
+        let mut writer = TokioStreamWriter(&mut injected);
+        let header = BundleHeader {
+            roots: vec![(*b.inner(), RangeSpecSeq::all())],
+            tags: vec![],
+        };
+        write_frame(&mut writer, &header).await?;
+        write_blob(&source, *b.hash(), &RangeSpec::all(), &mut writer).await?;
+        write_blob(&source, *a.hash(), &RangeSpec::all(), &mut writer).await?;
+        write_frame(&mut writer, &Item::End).await?;
+        let other = mem::Store::new();
+        assert!(read_bundle(&other, injected.as_slice()).await.is_err());
+        assert_eq!(other.entry_status(a.hash()).await?, EntryStatus::NotFound);
+
+        // corrupt data is rejected
+        let mut corrupt = bundle.clone();
+        let len = corrupt.len();
+        corrupt[len - 100] ^= 1;
+        assert!(read_bundle(&mem::Store::new(), corrupt.as_slice())
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subset() -> anyhow::Result<()> {
+        let source = mem::Store::new();
+        let a = source
+            .import_bytes(vec![1u8; 100_000].into(), BlobFormat::Raw)
+            .await?;
+        let b = source
+            .import_bytes(vec![2u8; 10].into(), BlobFormat::Raw)
+            .await?;
+        let seq = [*a.hash(), *b.hash()].into_iter().collect::<HashSeq>();
+        let seq = source
+            .import_bytes(seq.into_inner(), BlobFormat::HashSeq)
+            .await?;
+
+        // the root, the first 16 KiB of the first child and nothing of the second one
+        let ranges = RangeSpecSeq::from_ranges([
+            ChunkRanges::all(),
+            ChunkRanges::from(..bao_tree::ChunkNum(16)),
+        ]);
+        let mut bundle = Vec::new();
+        write_bundle(&source, vec![(*seq.inner(), ranges)], &mut bundle).await?;
+
+        let target = mem::Store::new();
+        read_bundle(&target, bundle.as_slice()).await?;
+        assert_eq!(
+            target.entry_status(seq.hash()).await?,
+            EntryStatus::Complete
+        );
+        assert_eq!(target.entry_status(a.hash()).await?, EntryStatus::Partial);
+        assert_eq!(target.entry_status(b.hash()).await?, EntryStatus::NotFound);
+        let entry = target.get_mut(a.hash()).await?.context("not found")?;
+        let valid = crate::get::db::valid_ranges::<mem::Store>(&entry).await?;
+        assert_eq!(valid, ChunkRanges::from(..bao_tree::ChunkNum(16)));
+        Ok(())
+    }
+}
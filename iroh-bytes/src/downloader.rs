@@ -16,6 +16,10 @@
 //!    to wait for the new connection to be established if necessary.
 //! 3. Once a request is ready to be sent after a delay (initial or for a retry), the preferred
 //!    node is used if available. The request is now considered active.
+//! 4. If several nodes can provide a raw blob that is larger than a single segment, the first
+//!    segment is requested on its own to learn the size of the blob. The remaining segments are
+//!    then handed out to the providers one at a time, so that slow nodes end up with less work
+//!    than fast ones. Segments that a node is already downloading are not taken away from it.
 //!
 //! Concurrency is limited in different ways:
 //! - *Total number of active request:* This is a way to prevent a self DoS by overwhelming our own
@@ -50,6 +54,7 @@ use tracing::{debug, error_span, trace, warn, Instrument};
 
 use crate::{
     get::{db::DownloadProgress, Stats},
+    protocol::RangeSpecSeq,
     store::Store,
     util::{progress::ProgressSender, SetTagOption, TagSet},
     TempTag,
@@ -58,9 +63,13 @@ use crate::{
 mod get;
 mod invariants;
 mod progress;
+mod swarm;
 mod test;
 
-use self::progress::{BroadcastProgressSender, ProgressSubscriber, ProgressTracker};
+use self::{
+    progress::{BroadcastProgressSender, ProgressSubscriber, ProgressTracker},
+    swarm::Swarm,
+};
 
 /// Duration for which we keep nodes connected after they were last useful to us.
 const IDLE_PEER_TIMEOUT: Duration = Duration::from_secs(10);
@@ -103,10 +112,11 @@ type GetFut = BoxedLocal<InternalDownloadResult>;
 pub trait Getter {
     /// Type of connections the Getter requires to perform a download.
     type Connection;
-    /// Return a future that performs the download using the given connection.
+    /// Return a future that performs the download of `ranges` using the given connection.
     fn get(
         &mut self,
         kind: DownloadKind,
+        ranges: RangeSpecSeq,
         conn: Self::Connection,
         progress_sender: BroadcastProgressSender,
     ) -> GetFut;
@@ -187,6 +197,7 @@ impl Default for RetryConfig {
 #[derive(Debug, Clone)]
 pub struct DownloadRequest {
     kind: DownloadKind,
+    ranges: RangeSpecSeq,
     nodes: Vec<NodeAddr>,
     tag: Option<SetTagOption>,
     progress: Option<ProgressSubscriber>,
@@ -202,6 +213,7 @@ impl DownloadRequest {
     ) -> Self {
         Self {
             kind: resource.into(),
+            ranges: RangeSpecSeq::all(),
             nodes: nodes.into_iter().map(|n| n.into()).collect(),
             tag: Some(SetTagOption::Auto),
             progress: None,
@@ -232,6 +244,19 @@ impl DownloadRequest {
         self.progress = Some(sender);
         self
     }
+
+    /// Only download the given chunk ranges.
+    ///
+    /// For raw blobs, only the first [`crate::protocol::RangeSpec`] of `ranges` is used. For
+    /// hash sequences, the first one applies to the hash sequence itself, and the following
+    /// ones to its children. The default is to download everything.
+    ///
+    /// Intents for the same [`DownloadKind`] are coalesced, so a download will fetch the union
+    /// of the ranges of all its intents, and only complete once all of them are present.
+    pub fn ranges(mut self, ranges: RangeSpecSeq) -> Self {
+        self.ranges = ranges;
+        self
+    }
 }
 
 /// The kind of resource to download.
@@ -452,16 +477,29 @@ struct RequestInfo {
     intents: HashMap<IntentId, IntentHandlers>,
     /// Tags requested for the blob to be created once the download finishes.
     tags: TagSet,
+    /// Union of the ranges requested by all intents.
+    ranges: RangeSpecSeq,
+    /// Whether the first segment of the blob was requested on its own to learn its size.
+    probed: bool,
+    /// Segments of the blob, if the download is split among several nodes.
+    swarm: Option<Swarm>,
 }
 
 /// Information about a request in progress.
 #[derive(derive_more::Debug)]
 struct ActiveRequestInfo {
-    /// Token used to cancel the future doing the request.
+    /// Token used to cancel the futures doing the request.
     #[debug(skip)]
     cancellation: CancellationToken,
-    /// Peer doing this request attempt.
-    node: NodeId,
+    /// Nodes doing this request attempt, with the ranges requested from each of them.
+    transfers: HashMap<NodeId, RangeSpecSeq>,
+    /// Ranges of the transfers that completed successfully in this attempt.
+    ranges: RangeSpecSeq,
+    /// Accumulated stats of the transfers that completed successfully in this attempt.
+    stats: Stats,
+    /// Progress sender shared by all transfers of this attempt.
+    #[debug(skip)]
+    progress_sender: BroadcastProgressSender,
     /// Temporary tag to protect the partial blob from being garbage collected.
     temp_tag: TempTag,
 }
@@ -559,7 +597,7 @@ struct Service<G: Getter, D: Dialer, DB: Store> {
     /// State of running downloads.
     active_requests: HashMap<DownloadKind, ActiveRequestInfo>,
     /// Tasks for currently running downloads.
-    in_progress_downloads: JoinSet<(DownloadKind, InternalDownloadResult)>,
+    in_progress_downloads: JoinSet<(DownloadKind, NodeId, InternalDownloadResult)>,
     /// Progress tracker
     progress_tracker: ProgressTracker,
     /// The [`Store`] where tags are saved after a download completes.
@@ -612,9 +650,9 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
                 }
                 Some(res) = self.in_progress_downloads.join_next(), if !self.in_progress_downloads.is_empty() => {
                     match res {
-                        Ok((kind, result)) => {
-                            trace!(%kind, "tick: transfer completed");
-                            self.on_download_completed(kind, result).await;
+                        Ok((kind, node, result)) => {
+                            trace!(%kind, node=%node.fmt_short(), "tick: transfer completed");
+                            self.on_download_completed(kind, node, result).await;
                         }
                         Err(err) => {
                             warn!(?err, "transfer task panicked");
@@ -661,6 +699,10 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
                     .add_nodes_if_hash_exists(hash, nodes.iter().cloned());
                 if updated {
                     self.queue.unpark_hash(hash);
+                    let kind = HashAndFormat::raw(hash).into();
+                    if self.has_pending_segments(&kind) {
+                        self.queue.insert(kind);
+                    }
                 }
             }
         }
@@ -678,6 +720,7 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
     ) {
         let DownloadRequest {
             kind,
+            ranges,
             nodes,
             tag,
             progress,
@@ -718,6 +761,10 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
                     debug!(?err, %kind, "failed to subscribe progress sender to transfer");
                 }
             }
+            // new nodes can take over segments that are not assigned yet.
+            if updated && self.has_pending_segments(&kind) {
+                self.queue.insert(kind);
+            }
         } else {
             // the transfer is not running.
             if updated && self.queue.is_parked(&kind) {
@@ -735,6 +782,9 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
         if let Some(tag) = &tag {
             request_info.tags.insert(tag.clone());
         }
+        // coalesce the ranges. If the transfer is already running and does not cover these
+        // ranges, the remainder will be requested once it completes.
+        request_info.ranges = request_info.ranges.union(&ranges);
     }
 
     /// Cancels a download intent.
@@ -768,12 +818,14 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
 
         if request_info.intents.is_empty() {
             occupied_entry.remove();
-            if let Entry::Occupied(occupied_entry) = self.active_requests.entry(kind) {
-                occupied_entry.remove().cancellation.cancel();
+            self.queue.remove(&kind);
+            if let Some(active_request_info) = self.active_requests.get(&kind) {
+                // the running transfers will complete with `FailureAction::AllIntentsDropped`,
+                // and the request is cleaned up once the last of them is done.
+                active_request_info.cancellation.cancel();
             } else {
-                self.queue.remove(&kind);
+                self.remove_hash_if_not_queued(&kind.hash());
             }
-            self.remove_hash_if_not_queued(&kind.hash());
         }
     }
 
@@ -789,6 +841,15 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
                 let drop_key = self.goodbye_nodes_queue.insert(node, IDLE_PEER_TIMEOUT);
                 self.connected_nodes
                     .insert(node, ConnectionInfo::new_idle(connection, drop_key));
+                // the node can take over segments of running downloads that are not assigned yet.
+                let hashes = self.providers.node_hash.get(&node).into_iter().flatten();
+                let pending = hashes
+                    .map(|hash| DownloadKind::from(HashAndFormat::raw(*hash)))
+                    .filter(|kind| self.has_pending_segments(kind))
+                    .collect::<Vec<_>>();
+                for kind in pending {
+                    self.queue.insert(kind);
+                }
             }
             Err(err) => {
                 debug!(%node, %err, "connection to node failed");
@@ -797,17 +858,20 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
         }
     }
 
-    async fn on_download_completed(&mut self, kind: DownloadKind, result: InternalDownloadResult) {
-        // first remove the request
-        let active_request_info = self
+    async fn on_download_completed(
+        &mut self,
+        kind: DownloadKind,
+        node: NodeId,
+        result: InternalDownloadResult,
+    ) {
+        // first remove the transfer
+        let ranges = self
             .active_requests
-            .remove(&kind)
-            .expect("request was active");
-
-        // get general request info
-        let request_info = self.requests.remove(&kind).expect("request was active");
-
-        let ActiveRequestInfo { node, temp_tag, .. } = active_request_info;
+            .get_mut(&kind)
+            .expect("request was active")
+            .transfers
+            .remove(&node)
+            .expect("transfer was active");
 
         // get node info
         let node_info = self
@@ -857,28 +921,109 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
             }
         };
 
-        // we finalize the download if either the download was successful,
-        // or if it should never proceed because all intents were dropped,
-        // or if we don't have any candidates to proceed with anymore.
-        let finalize = match &result {
-            Ok(_) | Err(FailureAction::AllIntentsDropped) => true,
-            _ => !self.providers.has_candidates(&kind.hash()),
+        let active_request_info = self
+            .active_requests
+            .get_mut(&kind)
+            .expect("request was active");
+        // the request info is gone if all intents were dropped
+        let mut request_info = self.requests.get_mut(&kind);
+        let swarm = request_info.as_mut().and_then(|info| info.swarm.as_mut());
+        match result {
+            Ok(stats) => {
+                active_request_info.ranges = active_request_info.ranges.union(&ranges);
+                let acc = &mut active_request_info.stats;
+                acc.bytes_written += stats.bytes_written;
+                acc.bytes_read += stats.bytes_read;
+                // transfers run concurrently, so the longest one is the best estimate
+                acc.elapsed = acc.elapsed.max(stats.elapsed);
+                if let Some(swarm) = swarm {
+                    swarm.complete(&node);
+                } else if let Some(info) = request_info.filter(|info| info.probed) {
+                    // the first segment is done, so we now know the size of the blob and can
+                    // split the remaining ranges among the providers.
+                    if let Some(size) = self.progress_tracker.root_size(&kind) {
+                        let swarm =
+                            Swarm::new(info.ranges.clone(), &active_request_info.ranges, size);
+                        debug!(%kind, ?swarm, "split download into segments");
+                        info.swarm = Some(swarm);
+                    }
+                }
+            }
+            Err(_) => {
+                // hand the segment of the node to another node
+                if let Some(swarm) = swarm {
+                    swarm.release(&node);
+                }
+            }
+        }
+
+        if !active_request_info.transfers.is_empty() {
+            // other nodes are still working on this request.
+            if !self.providers.has_candidates(&kind.hash()) {
+                self.queue.remove(&kind);
+            } else if self.has_pending_segments(&kind) {
+                self.queue.insert_front(kind);
+            }
+            return;
+        }
+
+        // this was the last transfer of this attempt
+        let active_request_info = self
+            .active_requests
+            .remove(&kind)
+            .expect("request was active");
+        let ActiveRequestInfo {
+            ranges,
+            stats,
+            temp_tag,
+            ..
+        } = active_request_info;
+
+        let Some(request_info) = self.requests.remove(&kind) else {
+            // all intents were dropped
+            drop(temp_tag);
+            self.progress_tracker.remove(&kind);
+            self.remove_hash_if_not_queued(&kind.hash());
+            return;
+        };
+
+        // we finalize the download if either all requested ranges were downloaded, or if we
+        // don't have any candidates to proceed with anymore.
+        let complete = match &request_info.swarm {
+            Some(swarm) => swarm.is_done() && swarm.covers(&request_info.ranges),
+            None => request_info.ranges.is_subset(&ranges),
         };
+        let finalize = complete || !self.providers.has_candidates(&kind.hash());
 
         if finalize {
-            let result = result.map_err(|_| DownloadError::DownloadFailed);
+            let result = match complete {
+                true => Ok(stats),
+                false => Err(DownloadError::DownloadFailed),
+            };
             if result.is_ok() {
                 request_info.tags.apply(&self.db, kind.0).await.ok();
             }
             drop(temp_tag);
             self.finalize_download(kind, request_info.intents, result);
         } else {
+            debug!(%kind, "requested ranges not complete yet, requeue download");
             // reinsert the download at the front of the queue to try from the next node
             self.requests.insert(kind, request_info);
             self.queue.insert_front(kind);
         }
     }
 
+    /// Whether `kind` is being downloaded from several nodes, and has segments that are not
+    /// assigned to any of them yet.
+    fn has_pending_segments(&self, kind: &DownloadKind) -> bool {
+        self.active_requests.contains_key(kind)
+            && self
+                .requests
+                .get(kind)
+                .and_then(|info| info.swarm.as_ref())
+                .is_some_and(|swarm| swarm.has_pending())
+    }
+
     /// Finalize a download.
     ///
     /// This triggers the intent return channels, and removes the download from the progress tracker
@@ -940,11 +1085,22 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
             trace!(%kind, ?next_step, "process_head");
 
             match next_step {
+                NextStep::Wait | NextStep::Park | NextStep::OutOfProviders
+                    if self.active_requests.contains_key(&kind) =>
+                {
+                    // the download is split among several nodes and no further node can take
+                    // over a segment right now. It is queued again once one becomes available.
+                    trace!(%kind, "no free node for pending segments");
+                    let _ = self.queue.pop_front();
+                }
                 NextStep::Wait => break,
                 NextStep::StartTransfer(node) => {
                     let _ = self.queue.pop_front();
                     debug!(%kind, node=%node.fmt_short(), "start transfer");
                     self.start_download(kind, node);
+                    if self.has_pending_segments(&kind) {
+                        self.queue.insert(kind);
+                    }
                 }
                 NextStep::Dial(node) => {
                     debug!(%kind, node=%node.fmt_short(), "dial node");
@@ -1005,6 +1161,8 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
         };
 
         let mut candidates = self.providers.get_candidates(&kind.hash()).peekable();
+        // Nodes already transferring a segment of this download.
+        let transfers = self.active_requests.get(kind).map(|info| &info.transfers);
         // If we have no provider candidates for this download, there's nothing else we can do.
         if candidates.peek().is_none() {
             return NextStep::OutOfProviders;
@@ -1025,6 +1183,10 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
         let mut has_retrying_provider = false;
 
         for node in candidates {
+            if transfers.is_some_and(|transfers| transfers.contains_key(node)) {
+                has_exhausted_provider = true;
+                continue;
+            }
             match self.node_state(node) {
                 NodeState::Connected(info) => {
                     let active_requests = info.active_requests();
@@ -1110,28 +1272,72 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
 
     /// Start downloading from the given node.
     ///
+    /// If the download is already running on other nodes, the node is assigned the next segment
+    /// which is not yet assigned to any node.
+    ///
     /// Panics if hash is not in self.requests or node is not in self.nodes.
     fn start_download(&mut self, kind: DownloadKind, node: NodeId) {
         let node_info = self.connected_nodes.get_mut(&node).expect("node exists");
-        let request_info = self.requests.get(&kind).expect("hash exists");
-
-        // create a progress sender and subscribe all intents to the progress sender
-        let subscribers = request_info
-            .intents
-            .values()
-            .flat_map(|state| state.on_progress.clone());
-        let progress_sender = self.progress_tracker.track(kind, subscribers);
-
-        // create the active request state
-        let cancellation = CancellationToken::new();
-        let temp_tag = self.db.temp_tag(kind.0);
-        let state = ActiveRequestInfo {
-            cancellation: cancellation.clone(),
-            node,
-            temp_tag,
+        let request_info = self.requests.get_mut(&kind).expect("hash exists");
+
+        let ranges = if self.active_requests.contains_key(&kind) {
+            request_info
+                .swarm
+                .as_mut()
+                .and_then(|swarm| swarm.assign(node))
+                .expect("only downloads with pending segments are started again")
+        } else {
+            match request_info.swarm.as_mut() {
+                Some(swarm) if swarm.covers(&request_info.ranges) && swarm.has_pending() => {
+                    swarm.assign(node).expect("just checked")
+                }
+                _ => {
+                    // more ranges were requested since the swarm was planned: start over.
+                    request_info.swarm = None;
+                    // if there are several candidates for a raw blob, only request the first
+                    // segment, to learn the size and split the rest among the candidates.
+                    let first_segment = (!request_info.probed
+                        && kind.format() == BlobFormat::Raw
+                        && self.providers.get_candidates(&kind.hash()).nth(1).is_some())
+                    .then(|| Swarm::first_segment(&request_info.ranges))
+                    .flatten();
+                    match first_segment {
+                        Some(ranges) => {
+                            request_info.probed = true;
+                            ranges
+                        }
+                        None => request_info.ranges.clone(),
+                    }
+                }
+            }
+        };
+
+        let state = match self.active_requests.entry(kind) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // create a progress sender and subscribe all intents to the progress sender
+                let subscribers = request_info
+                    .intents
+                    .values()
+                    .flat_map(|state| state.on_progress.clone());
+                let progress_sender = self.progress_tracker.track(kind, subscribers);
+
+                // create the active request state
+                entry.insert(ActiveRequestInfo {
+                    cancellation: CancellationToken::new(),
+                    transfers: Default::default(),
+                    ranges: RangeSpecSeq::empty(),
+                    stats: Default::default(),
+                    progress_sender,
+                    temp_tag: self.db.temp_tag(kind.0),
+                })
+            }
         };
+        state.transfers.insert(node, ranges.clone());
+        let cancellation = state.cancellation.clone();
+        let progress_sender = state.progress_sender.clone();
         let conn = node_info.conn.clone();
-        let get_fut = self.getter.get(kind, conn, progress_sender);
+        let get_fut = self.getter.get(kind, ranges, conn, progress_sender);
         let fut = async move {
             // NOTE: it's an open question if we should do timeouts at this point. Considerations from @Frando:
             // > at this stage we do not know the size of the download, so the timeout would have
@@ -1145,7 +1351,7 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
             };
             trace!("transfer finished");
 
-            (kind, res)
+            (kind, node, res)
         }
         .instrument(error_span!("transfer", %kind, node=%node.fmt_short()));
         node_info.state = match &node_info.state {
@@ -1159,7 +1365,6 @@ impl<DB: Store, G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D,
                 }
             }
         };
-        self.active_requests.insert(kind, state);
         self.in_progress_downloads.spawn_local(fut);
     }
 
@@ -1,7 +1,8 @@
 //! [`Getter`] implementation that performs requests over [`quinn::Connection`]s.
 
 use crate::{
-    get::{db::get_to_db, error::GetError},
+    get::{db::get_ranges_to_db, error::GetError},
+    protocol::RangeSpecSeq,
     store::Store,
 };
 use futures_lite::FutureExt;
@@ -17,6 +18,7 @@ impl From<GetError> for FailureAction {
     fn from(e: GetError) -> Self {
         match e {
             e @ GetError::NotFound(_) => FailureAction::AbortRequest(e.into()),
+            e @ GetError::Unauthorized(_) => FailureAction::AbortRequest(e.into()),
             e @ GetError::RemoteReset(_) => FailureAction::RetryLater(e.into()),
             e @ GetError::NoncompliantNode(_) => FailureAction::DropPeer(e.into()),
             e @ GetError::Io(_) => FailureAction::RetryLater(e.into()),
@@ -38,13 +40,21 @@ impl<S: Store> Getter for IoGetter<S> {
     fn get(
         &mut self,
         kind: DownloadKind,
+        ranges: RangeSpecSeq,
         conn: Self::Connection,
         progress_sender: BroadcastProgressSender,
     ) -> GetFut {
         let store = self.store.clone();
         let fut = async move {
             let get_conn = || async move { Ok(conn) };
-            let res = get_to_db(&store, get_conn, &kind.hash_and_format(), progress_sender).await;
+            let res = get_ranges_to_db(
+                &store,
+                get_conn,
+                &kind.hash_and_format(),
+                &ranges,
+                progress_sender,
+            )
+            .await;
             match res {
                 Ok(stats) => {
                     #[cfg(feature = "metrics")]
@@ -75,19 +75,35 @@ impl<G: Getter<Connection = D::Connection>, D: Dialer, S: Store> Service<G, D, S
     #[track_caller]
     fn check_active_request_count(&self) {
         // check that the count of futures we are polling for downloads is consistent with the
-        // number of requests
+        // number of transfers of the requests
+        let transfers = self
+            .active_requests
+            .values()
+            .map(|req_info| req_info.transfers.len())
+            .sum::<usize>();
         assert_eq!(
             self.in_progress_downloads.len(),
-            self.active_requests.len(),
+            transfers,
             "active_requests and in_progress_downloads are out of sync"
         );
+        // check that every active request has at least one running transfer
+        assert!(
+            self.active_requests
+                .values()
+                .all(|req_info| !req_info.transfers.is_empty()),
+            "active requests without transfers"
+        );
         // check that the count of requests per peer matches the number of requests that have that
         // peer as active
         let mut real_count: HashMap<NodeId, usize> =
             HashMap::with_capacity(self.connected_nodes.len());
-        for req_info in self.active_requests.values() {
+        for node in self
+            .active_requests
+            .values()
+            .flat_map(|req_info| req_info.transfers.keys())
+        {
             // nothing like some classic word count
-            *real_count.entry(req_info.node).or_default() += 1;
+            *real_count.entry(*node).or_default() += 1;
         }
         for (peer, info) in self.connected_nodes.iter() {
             assert_eq!(
@@ -91,6 +91,12 @@ impl ProgressTracker {
     pub fn remove(&mut self, kind: &DownloadKind) {
         self.running.remove(kind);
     }
+
+    /// Get the size of the root blob of a tracked download, if it is known already.
+    pub fn root_size(&self, kind: &DownloadKind) -> Option<u64> {
+        let size = self.running.get(kind)?.lock().state.root.size?;
+        Some(size.value())
+    }
 }
 
 type Shared = Arc<Mutex<Inner>>;
@@ -0,0 +1,126 @@
+//! Splitting the download of a single raw blob among several nodes.
+//!
+//! Once the size of a blob is known, the chunk ranges that are still needed are cut into
+//! segments of [`SEGMENT_CHUNKS`] chunks. Nodes pull the next unassigned segment whenever they
+//! have finished their previous one, so faster nodes end up downloading more segments than slow
+//! ones. A segment stays with its node until that node finishes or fails, so a slow node can
+//! still delay the end of the download by one segment. Since every chunk is verified against
+//! the blob's hash, mixing data from different nodes is safe.
+
+use std::collections::{HashMap, VecDeque};
+
+use bao_tree::{ChunkNum, ChunkRanges};
+use iroh_net::NodeId;
+
+use crate::protocol::RangeSpecSeq;
+
+/// Number of chunks in a segment (4 MiB).
+///
+/// This is a multiple of the chunk group size, so segments never split a chunk group.
+pub(super) const SEGMENT_CHUNKS: u64 = 4096;
+
+/// Segments of a blob download split among several nodes.
+#[derive(Debug)]
+pub(super) struct Swarm {
+    /// The ranges of the request this swarm was planned for.
+    ranges: RangeSpecSeq,
+    /// Segments that are not assigned to any node yet, in order.
+    pending: VecDeque<ChunkRanges>,
+    /// Segments currently being downloaded, by node.
+    assigned: HashMap<NodeId, ChunkRanges>,
+}
+
+impl Swarm {
+    /// Get the part of the requested ranges that is downloaded before the size is known.
+    ///
+    /// This is at most the first segment of the requested ranges, plus the last chunk of the
+    /// blob. The size reported by the node is only verified by the last chunk, and the remaining
+    /// segments are planned with it. Returns `None` if all of the requested ranges fit into a
+    /// single segment, in which case splitting is pointless.
+    pub fn first_segment(ranges: &RangeSpecSeq) -> Option<RangeSpecSeq> {
+        let requested = raw_ranges(ranges);
+        let start = *requested.boundaries().first()?;
+        let segment = ChunkRanges::from(start..start + SEGMENT_CHUNKS);
+        let first: ChunkRanges = requested.intersection(&segment);
+        if first == requested {
+            None
+        } else {
+            let last_chunk = ChunkRanges::from(ChunkNum(u64::MAX)..);
+            let first: ChunkRanges = first.union(&last_chunk);
+            Some(RangeSpecSeq::from_ranges([first]))
+        }
+    }
+
+    /// Plan the download of `ranges` for a blob of `size` bytes, of which `done` was already
+    /// downloaded.
+    pub fn new(ranges: RangeSpecSeq, done: &RangeSpecSeq, size: u64) -> Self {
+        let end = ChunkNum::chunks(size);
+        let remaining: ChunkRanges = raw_ranges(&ranges).difference(&raw_ranges(done));
+        let remaining: ChunkRanges = remaining.intersection(&ChunkRanges::from(..end));
+        let mut pending = VecDeque::new();
+        if let Some(start) = remaining.boundaries().first() {
+            let mut offset = ChunkNum(start.0 - start.0 % SEGMENT_CHUNKS);
+            while offset < end {
+                let next = ChunkNum(offset.0 + SEGMENT_CHUNKS);
+                let segment: ChunkRanges = remaining.intersection(&ChunkRanges::from(offset..next));
+                if !segment.is_empty() {
+                    pending.push_back(segment);
+                }
+                offset = next;
+            }
+        }
+        Self {
+            ranges,
+            pending,
+            assigned: Default::default(),
+        }
+    }
+
+    /// Whether the ranges this swarm was planned for include all of `ranges`.
+    pub fn covers(&self, ranges: &RangeSpecSeq) -> bool {
+        raw_ranges(ranges).is_subset(&raw_ranges(&self.ranges))
+    }
+
+    /// Whether there are segments which are not assigned to any node.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Whether all segments were downloaded.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty() && self.assigned.is_empty()
+    }
+
+    /// Assign the next pending segment to `node`.
+    pub fn assign(&mut self, node: NodeId) -> Option<RangeSpecSeq> {
+        debug_assert!(
+            !self.assigned.contains_key(&node),
+            "node already has a segment"
+        );
+        let segment = self.pending.pop_front()?;
+        let ranges = RangeSpecSeq::from_ranges([&segment]);
+        self.assigned.insert(node, segment);
+        Some(ranges)
+    }
+
+    /// Mark the segment assigned to `node` as downloaded.
+    pub fn complete(&mut self, node: &NodeId) {
+        self.assigned.remove(node);
+    }
+
+    /// Return the segment assigned to `node` so that it can be assigned to another node.
+    pub fn release(&mut self, node: &NodeId) {
+        if let Some(segment) = self.assigned.remove(node) {
+            self.pending.push_front(segment);
+        }
+    }
+}
+
+/// Get the ranges of the root blob from a [`RangeSpecSeq`].
+fn raw_ranges(ranges: &RangeSpecSeq) -> ChunkRanges {
+    ranges
+        .iter()
+        .next()
+        .map(|spec| spec.to_chunk_ranges())
+        .unwrap_or_else(ChunkRanges::empty)
+}
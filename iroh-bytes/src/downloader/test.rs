@@ -10,6 +10,7 @@ use iroh_net::key::SecretKey;
 
 use crate::{
     get::{db::BlobId, progress::TransferState},
+    protocol::RangeSpec,
     util::progress::{FlumeProgressSender, IdGenerator, ProgressSender},
 };
 
@@ -250,6 +251,7 @@ async fn concurrent_progress() {
                     child: BlobId::Root,
                     hash,
                     size: 100,
+                    ranges: RangeSpec::all(),
                 })
                 .await
                 .unwrap();
@@ -502,3 +504,105 @@ async fn retry_nodes_jump_queue() {
     // assert history
     dialer.assert_history(&[bad_node, good_node, bad_node]);
 }
+
+/// Tests that intents for different ranges of the same blob are coalesced.
+#[tokio::test]
+async fn range_coalescing() {
+    let _guard = iroh_test::logging::setup();
+    let dialer = dialer::TestingDialer::default();
+    let getter = getter::TestingGetter::default();
+    // make request take some time to ensure the later intent arrives while it is running
+    getter.set_request_duration(Duration::from_millis(300));
+    let downloader = Downloader::spawn_for_test(dialer.clone(), getter.clone(), Default::default());
+
+    let peer = SecretKey::generate().public();
+    let kind: DownloadKind = HashAndFormat::raw(Hash::new([0u8; 32])).into();
+    let ranges = |r: std::ops::Range<u64>| {
+        RangeSpecSeq::from_ranges([bao_tree::ChunkRanges::from(
+            bao_tree::ChunkNum(r.start)..bao_tree::ChunkNum(r.end),
+        )])
+    };
+
+    // two intents queued at the same time are served by a single request
+    let req_a = DownloadRequest::new(kind, vec![peer]).ranges(ranges(0..2));
+    let req_b = DownloadRequest::new(kind, vec![peer]).ranges(ranges(1..4));
+    let handle_a = downloader.queue(req_a).await;
+    let handle_b = downloader.queue(req_b).await;
+    // an intent arriving while the request runs is served by a follow-up request
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let req_c = DownloadRequest::new(kind, vec![peer]).ranges(ranges(8..10));
+    let handle_c = downloader.queue(req_c).await;
+    let (res_a, res_b, res_c) = tokio::join!(handle_a, handle_b, handle_c);
+    res_a.expect("should report success");
+    res_b.expect("should report success");
+    res_c.expect("should report success");
+
+    let first = ranges(0..4);
+    let second = first.union(&ranges(8..10));
+    getter.assert_history(&[(kind, peer), (kind, peer)]);
+    getter.assert_ranges_history(&[first, second]);
+}
+
+/// Tests that a large raw blob is split among several nodes, and that slow nodes get less work.
+#[tokio::test]
+async fn swarm_download() {
+    let _guard = iroh_test::logging::setup();
+    let dialer = dialer::TestingDialer::default();
+    let getter = getter::TestingGetter::default();
+    let downloader = Downloader::spawn_for_test(dialer.clone(), getter.clone(), Default::default());
+
+    let fast = SecretKey::generate().public();
+    let slow = SecretKey::generate().public();
+    let segments = 6;
+    let size = segments * swarm::SEGMENT_CHUNKS * 1024;
+    getter.set_handler(Arc::new(move |kind, node, progress, _duration| {
+        async move {
+            let id = progress.new_id();
+            progress
+                .send(DownloadProgress::Found {
+                    id,
+                    child: BlobId::Root,
+                    hash: kind.hash(),
+                    size,
+                    ranges: RangeSpec::all(),
+                })
+                .await
+                .unwrap();
+            let duration = if node == slow { 500 } else { 10 };
+            tokio::time::sleep(Duration::from_millis(duration)).await;
+            progress.send(DownloadProgress::Done { id }).await.unwrap();
+            Ok(Stats::default())
+        }
+        .boxed()
+    }));
+
+    let kind = HashAndFormat::raw(Hash::new([0u8; 32]));
+    let req = DownloadRequest::new(kind, vec![fast, slow]);
+    downloader
+        .queue(req)
+        .await
+        .await
+        .expect("should report success");
+
+    let requests = getter.requests();
+    // the first segment is requested on its own, then each segment is requested exactly once
+    assert_eq!(requests.len() as u64, segments);
+    // the first request includes the last chunk, to verify the size before splitting
+    let last_chunk = bao_tree::ChunkRanges::from(bao_tree::ChunkNum(u64::MAX)..);
+    let first = bao_tree::ChunkRanges::from(..bao_tree::ChunkNum(swarm::SEGMENT_CHUNKS));
+    let first: bao_tree::ChunkRanges = first.union(&last_chunk);
+    assert_eq!(requests[0].1, RangeSpecSeq::from_ranges([first]));
+    let mut all = RangeSpecSeq::empty();
+    for (_node, ranges) in &requests {
+        assert!(!ranges.is_subset(&all), "each request adds new ranges");
+        all = all.union(ranges);
+    }
+    let expected: bao_tree::ChunkRanges =
+        bao_tree::ChunkRanges::from(..bao_tree::ChunkNum(segments * swarm::SEGMENT_CHUNKS))
+            .union(&last_chunk);
+    let expected = RangeSpecSeq::from_ranges([expected]);
+    assert_eq!(all, expected);
+    // the slow node only had time for a single segment, besides possibly the first one
+    let slow_requests = requests.iter().filter(|(node, _)| *node == slow).count();
+    assert!(slow_requests <= 2, "slow node got {slow_requests} requests");
+}
@@ -27,6 +27,8 @@ struct TestingGetterInner {
     request_duration: Duration,
     /// History of requests performed by the [`Getter`] and if they were successful.
     request_history: Vec<(DownloadKind, NodeId)>,
+    /// History of the ranges requested by the [`Getter`].
+    ranges_history: Vec<RangeSpecSeq>,
     /// Set a handler function which actually handles the requests.
     request_handler: Option<RequestHandlerFn>,
 }
@@ -39,11 +41,13 @@ impl Getter for TestingGetter {
     fn get(
         &mut self,
         kind: DownloadKind,
+        ranges: RangeSpecSeq,
         peer: NodeId,
         progress_sender: BroadcastProgressSender,
     ) -> GetFut {
         let mut inner = self.0.write();
         inner.request_history.push((kind, peer));
+        inner.ranges_history.push(ranges);
         let request_duration = inner.request_duration;
         let handler = inner.request_handler.clone();
         async move {
@@ -70,4 +74,15 @@ impl TestingGetter {
     pub(super) fn assert_history(&self, history: &[(DownloadKind, NodeId)]) {
         assert_eq!(self.0.read().request_history, history);
     }
+    /// Get the nodes and ranges of all requests performed so far.
+    pub(super) fn requests(&self) -> Vec<(NodeId, RangeSpecSeq)> {
+        let inner = self.0.read();
+        let nodes = inner.request_history.iter().map(|(_kind, node)| *node);
+        nodes.zip(inner.ranges_history.iter().cloned()).collect()
+    }
+    /// Verify that the ranges history is as expected
+    #[track_caller]
+    pub(super) fn assert_ranges_history(&self, history: &[RangeSpecSeq]) {
+        assert_eq!(self.0.read().ranges_history, history);
+    }
 }
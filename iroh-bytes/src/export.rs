@@ -1,15 +1,21 @@
 //! Functions to export data from a store
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use bytes::Bytes;
 use iroh_base::rpc::RpcError;
+use iroh_io::AsyncSliceReaderExt;
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
 use tracing::trace;
 
 use crate::{
-    format::collection::Collection,
+    format::{
+        collection::Collection,
+        tree::{EntryKind, Tree},
+    },
+    hashseq::HashSeq,
     store::{BaoBlobSize, ExportFormat, ExportMode, MapEntry, Store as BaoStore},
     util::progress::{IdGenerator, ProgressSender},
     Hash,
@@ -34,6 +40,8 @@ pub async fn export<D: BaoStore>(
     match format {
         ExportFormat::Blob => export_blob(db, hash, outpath, mode, progress).await,
         ExportFormat::Collection => export_collection(db, hash, outpath, mode, progress).await,
+        ExportFormat::Chunked => export_chunked(db, hash, outpath, progress).await,
+        ExportFormat::Tree => export_tree(db, hash, outpath, mode, progress).await,
     }
 }
 
@@ -55,6 +63,134 @@ pub async fn export_collection<D: BaoStore>(
     Ok(())
 }
 
+/// Export a tree, recursively, to a directory on the local filesystem.
+///
+/// The metadata of the entries is restored where the platform supports it.
+pub async fn export_tree<D: BaoStore>(
+    db: &D,
+    hash: Hash,
+    outpath: PathBuf,
+    mode: ExportMode,
+    progress: impl ProgressSender<Msg = ExportProgress> + IdGenerator,
+) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(&outpath).await?;
+    // names are validated on load, so every entry is a direct child of outpath
+    let tree = Tree::load(db, &hash).await?;
+    for entry in tree {
+        let path = outpath.join(&entry.name);
+        ensure_not_symlink(&path).await?;
+        match entry.kind {
+            EntryKind::File(hash) => {
+                export_blob(db, hash, path.clone(), mode, progress.clone()).await?;
+                if mode == ExportMode::Copy {
+                    unshare_file(&path)?;
+                }
+            }
+            EntryKind::Dir(hash) => {
+                Box::pin(export_tree(db, hash, path.clone(), mode, progress.clone())).await?;
+            }
+            EntryKind::Symlink(target) => {
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(target, &path)?;
+                #[cfg(not(unix))]
+                {
+                    tracing::warn!("skipping symlink {} -> {target}", path.display());
+                    continue;
+                }
+            }
+        }
+        entry
+            .meta
+            .restore(&path)
+            .with_context(|| format!("failed to restore metadata of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Fail if `path` is an existing symlink, so an export never writes through one.
+async fn ensure_not_symlink(path: &Path) -> anyhow::Result<()> {
+    match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_symlink() => {
+            anyhow::bail!("refusing to write through symlink {}", path.display())
+        }
+        Ok(_) => Ok(()),
+        Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(cause) => Err(cause.into()),
+    }
+}
+
+/// Make sure that a copied file does not share its inode with the store.
+///
+/// Stores may export read-only files as hard links, and restoring the metadata must not
+/// change the file in the store. Only the fs store creates hard links.
+fn unshare_file(path: &Path) -> std::io::Result<()> {
+    #[cfg(all(unix, feature = "fs-store"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if std::fs::metadata(path)?.nlink() > 1 {
+            let parent = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let temp = tempfile::NamedTempFile::new_in(parent)?;
+            std::fs::copy(path, temp.path())?;
+            temp.persist(path).map_err(|cause| cause.error)?;
+        }
+    }
+    #[cfg(not(all(unix, feature = "fs-store")))]
+    let _ = path;
+    Ok(())
+}
+
+/// Export a file that was imported in chunks to a single file on the local filesystem.
+///
+/// `hash` is the hash seq of the chunks, as created by [`crate::store::ImportMode::Chunked`].
+pub async fn export_chunked<D: BaoStore>(
+    db: &D,
+    hash: Hash,
+    outpath: PathBuf,
+    progress: impl ProgressSender<Msg = ExportProgress> + IdGenerator,
+) -> anyhow::Result<()> {
+    if let Some(parent) = outpath.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    trace!("exporting chunked blob {} to {}", hash, outpath.display());
+    let id = progress.new_id();
+    let entry = db.get(&hash).await?.context("entry not there")?;
+    let hash_seq = HashSeq::try_from(entry.data_reader().await?.read_to_end().await?)?;
+    let mut chunks = Vec::with_capacity(hash_seq.len());
+    let mut size = 0;
+    for chunk in hash_seq.iter() {
+        let entry = db
+            .get(&chunk)
+            .await?
+            .filter(|entry| entry.is_complete())
+            .with_context(|| format!("chunk {chunk} not there"))?;
+        size += entry.size().value();
+        chunks.push(entry);
+    }
+    progress
+        .send(ExportProgress::Found {
+            id,
+            hash,
+            outpath: outpath.clone(),
+            size: BaoBlobSize::Verified(size),
+            meta: None,
+        })
+        .await?;
+    let mut file = tokio::fs::File::create(&outpath).await?;
+    let mut offset = 0;
+    for entry in chunks {
+        let data = entry.data_reader().await?.read_to_end().await?;
+        file.write_all(&data).await?;
+        offset += data.len() as u64;
+        progress.try_send(ExportProgress::Progress { id, offset })?;
+    }
+    file.flush().await?;
+    progress.send(ExportProgress::Done { id }).await?;
+    Ok(())
+}
+
 /// Export a single blob to a file on the local fileystem.
 pub async fn export_blob<D: BaoStore>(
     db: &D,
@@ -14,3 +14,5 @@
 //!
 //! [postcard]: https://docs.rs/postcard/latest/postcard/
 pub mod collection;
+pub mod diff;
+pub mod tree;
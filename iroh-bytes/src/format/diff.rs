@@ -0,0 +1,188 @@
+//! Structured diffs between two collections or two trees.
+//!
+//! Collections are compared by name. Trees are compared recursively, with the paths of nested
+//! entries joined by `/`. Subtrees with the same hash are identical and are not descended
+//! into, so comparing two snapshots of a large directory only loads the parts that differ.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    pin::Pin,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    collection::Collection,
+    tree::{EntryKind, Tree, TreeEntry},
+};
+use crate::Hash;
+
+/// A single difference between two collections or trees.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// The path of the entry that changed.
+    pub path: String,
+    /// How the entry changed.
+    pub kind: ChangeKind,
+}
+
+/// The kind of a [`Change`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    /// The entry exists only in the new collection or tree.
+    Added,
+    /// The entry exists only in the old collection or tree.
+    Removed,
+    /// The entry exists in both, but its content or metadata differs.
+    Modified,
+}
+
+/// Compute the changes from the collection or tree `from` to the collection or tree `to`.
+///
+/// Both hashes must refer to the same format. For trees, this requires the links and
+/// metadata of all subtrees that differ to be stored in `db`, but none of the files.
+///
+/// The changes are ordered by name, with the contents of a directory following the directory.
+pub async fn diff<D>(db: &D, from: &Hash, to: &Hash) -> anyhow::Result<Vec<Change>>
+where
+    D: crate::store::Map,
+{
+    let mut changes = Vec::new();
+    if let Ok(from) = Tree::load(db, from).await {
+        let to = Tree::load(db, to)
+            .await
+            .context("can only compare a tree with another tree")?;
+        diff_trees(db, String::new(), from, to, &mut changes).await?;
+    } else {
+        let from = Collection::load(db, from).await?;
+        let to = Collection::load(db, to).await?;
+        diff_collections(from, to, &mut changes);
+    }
+    Ok(changes)
+}
+
+fn diff_collections(from: Collection, to: Collection, changes: &mut Vec<Change>) {
+    let from = from.into_iter().collect::<BTreeMap<_, _>>();
+    let to = to.into_iter().collect::<BTreeMap<_, _>>();
+    let names = from.keys().chain(to.keys()).collect::<BTreeSet<_>>();
+    for name in names {
+        let kind = match (from.get(name), to.get(name)) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(_), Some(_)) => ChangeKind::Modified,
+            (Some(_), None) => ChangeKind::Removed,
+            (None, _) => ChangeKind::Added,
+        };
+        changes.push(Change {
+            path: name.clone(),
+            kind,
+        });
+    }
+}
+
+type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+fn diff_trees<'a, D>(
+    db: &'a D,
+    prefix: String,
+    from: Tree,
+    to: Tree,
+    changes: &'a mut Vec<Change>,
+) -> LocalBoxFuture<'a, anyhow::Result<()>>
+where
+    D: crate::store::Map,
+{
+    Box::pin(async move {
+        let names = from
+            .iter()
+            .chain(to.iter())
+            .map(|entry| entry.name.as_str())
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            let path = format!("{prefix}{name}");
+            match (from.get(name), to.get(name)) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some(a), Some(b)) => match (&a.kind, &b.kind) {
+                    (EntryKind::Dir(x), EntryKind::Dir(y)) => {
+                        if a.meta != b.meta {
+                            changes.push(Change {
+                                path: path.clone(),
+                                kind: ChangeKind::Modified,
+                            });
+                        }
+                        if x != y {
+                            let x = Tree::load(db, x).await?;
+                            let y = Tree::load(db, y).await?;
+                            diff_trees(db, format!("{path}/"), x, y, changes).await?;
+                        }
+                    }
+                    (EntryKind::Dir(_), _) | (_, EntryKind::Dir(_)) => {
+                        walk(db, path.clone(), a, ChangeKind::Removed, changes).await?;
+                        walk(db, path, b, ChangeKind::Added, changes).await?;
+                    }
+                    _ => changes.push(Change {
+                        path,
+                        kind: ChangeKind::Modified,
+                    }),
+                },
+                (Some(a), None) => walk(db, path, a, ChangeKind::Removed, changes).await?,
+                (None, Some(b)) => walk(db, path, b, ChangeKind::Added, changes).await?,
+                (None, None) => unreachable!("name is from one of the trees"),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Record `entry` and, if it is a directory, everything below it as `kind`.
+fn walk<'a, D>(
+    db: &'a D,
+    path: String,
+    entry: &'a TreeEntry,
+    kind: ChangeKind,
+    changes: &'a mut Vec<Change>,
+) -> LocalBoxFuture<'a, anyhow::Result<()>>
+where
+    D: crate::store::Map,
+{
+    Box::pin(async move {
+        changes.push(Change {
+            path: path.clone(),
+            kind,
+        });
+        if let EntryKind::Dir(hash) = &entry.kind {
+            let tree = Tree::load(db, hash).await?;
+            for child in tree.iter() {
+                walk(db, format!("{path}/{}", child.name), child, kind, changes).await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collections() {
+        let a = Hash::new(b"a");
+        let b = Hash::new(b"b");
+        let from = Collection::from_iter([("same", a), ("changed", a), ("removed", a)]);
+        let to = Collection::from_iter([("same", a), ("changed", b), ("added", b)]);
+        let mut changes = Vec::new();
+        diff_collections(from, to, &mut changes);
+        let change = |path: &str, kind| Change {
+            path: path.to_string(),
+            kind,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                change("added", ChangeKind::Added),
+                change("changed", ChangeKind::Modified),
+                change("removed", ChangeKind::Removed),
+            ]
+        );
+    }
+}
@@ -0,0 +1,590 @@
+//! A directory tree format with per-entry metadata.
+//!
+//! Unlike a [`Collection`](super::collection::Collection), which is a flat list of names and
+//! blobs, a [`Tree`] describes a single directory. Subdirectories are trees of their own and
+//! are referenced by hash, so every subtree is individually addressable and identical
+//! subtrees are stored only once.
+//!
+//! Each entry carries the metadata needed to restore it on export: the file mode, the
+//! modification time and the extended attributes. Symlinks and empty directories are
+//! preserved as well.
+//!
+//! The root of a tree is a [`BlobFormat::HashSeq`]. Its first child is the metadata blob, which
+//! starts with [`Tree::HEADER`]. The remaining children are all blobs reachable from the tree,
+//! including the blobs of nested subtrees. A tree can therefore be transferred and protected
+//! from garbage collection like any other hash seq.
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use iroh_io::AsyncSliceReaderExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hashseq::HashSeq,
+    store::{ImportMode, ImportProgress, MapEntry},
+    util::{
+        progress::{IdGenerator, ProgressSender},
+        TempTag,
+    },
+    BlobFormat, Hash, HashAndFormat,
+};
+
+/// A directory, as a list of named entries with metadata.
+///
+/// Note that the format is subject to change.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct Tree {
+    entries: Vec<TreeEntry>,
+}
+
+/// An entry in a [`Tree`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TreeEntry {
+    /// The name of the entry, a single path component.
+    pub name: String,
+    /// What the entry refers to.
+    pub kind: EntryKind,
+    /// The metadata of the entry.
+    pub meta: EntryMeta,
+}
+
+/// The content of a [`TreeEntry`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EntryKind {
+    /// A regular file, with the hash of its content.
+    File(Hash),
+    /// A directory, with the root hash of its [`Tree`].
+    Dir(Hash),
+    /// A symbolic link, with its target.
+    Symlink(String),
+}
+
+/// Metadata of a [`TreeEntry`].
+///
+/// Fields that are not available on the platform the entry was created on are empty, and are
+/// not restored on export.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct EntryMeta {
+    /// The unix permission bits, without setuid, setgid and sticky bits.
+    pub mode: Option<u32>,
+    /// The modification time.
+    pub mtime: Option<SystemTime>,
+    /// Extended attributes, as pairs of name and value.
+    pub xattrs: Vec<(String, Bytes)>,
+}
+
+/// Metadata for a tree
+///
+/// This is the wire format for the metadata blob.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct TreeMeta {
+    header: [u8; 7], // Must contain "TreeV0."
+    entries: Vec<TreeEntry>,
+}
+
+impl FromIterator<TreeEntry> for Tree {
+    fn from_iter<T: IntoIterator<Item = TreeEntry>>(iter: T) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Tree {
+    type Item = TreeEntry;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl Tree {
+    /// The header for the tree format.
+    ///
+    /// This is the start of the metadata blob.
+    pub const HEADER: &'static [u8; 7] = b"TreeV0.";
+
+    /// Load a tree from a store given a root hash
+    ///
+    /// This requires the links and the metadata of the tree to be stored in the store, but
+    /// neither the files nor the subtrees.
+    pub async fn load<D>(db: &D, root: &Hash) -> anyhow::Result<Self>
+    where
+        D: crate::store::Map,
+    {
+        let mut links = load_hash_seq(db, root).await?;
+        let meta_hash = links.pop_front().context("meta hash not found")?;
+        let meta_entry = db.get(&meta_hash).await?.context("meta not found")?;
+        anyhow::ensure!(meta_entry.is_complete(), "meta not complete");
+        let meta_bytes = meta_entry.data_reader().await?.read_to_end().await?;
+        let meta: TreeMeta = postcard::from_bytes(&meta_bytes)?;
+        anyhow::ensure!(
+            meta.header == *Self::HEADER,
+            "expected header {:?}, got {:?}",
+            Self::HEADER,
+            meta.header
+        );
+        validate_entries(&meta.entries)?;
+        Ok(Self {
+            entries: meta.entries,
+        })
+    }
+
+    /// Store a tree in a store. returns the root hash of the tree as a TempTag.
+    ///
+    /// The subtrees of this tree must already be stored in the store, since their links are
+    /// included in the links of this tree.
+    pub async fn store<D>(self, db: &D) -> anyhow::Result<TempTag>
+    where
+        D: crate::store::Store,
+    {
+        validate_entries(&self.entries)?;
+        let mut seen = HashSet::new();
+        let mut links = Vec::new();
+        for entry in &self.entries {
+            match &entry.kind {
+                EntryKind::File(hash) => links.push(*hash),
+                EntryKind::Dir(hash) => {
+                    links.push(*hash);
+                    links.extend(load_hash_seq(db, hash).await?.iter());
+                }
+                EntryKind::Symlink(_) => {}
+            }
+        }
+        links.retain(|hash| seen.insert(*hash));
+        let meta = TreeMeta {
+            header: *Self::HEADER,
+            entries: self.entries,
+        };
+        let meta_bytes = postcard::to_stdvec(&meta)?;
+        let meta_tag = db.import_bytes(meta_bytes.into(), BlobFormat::Raw).await?;
+        let links_bytes = std::iter::once(*meta_tag.hash())
+            .chain(links)
+            .collect::<HashSeq>();
+        let links_tag = db
+            .import_bytes(links_bytes.into(), BlobFormat::HashSeq)
+            .await?;
+        Ok(links_tag)
+    }
+
+    /// Import a path from the local file system as a tree.
+    ///
+    /// If `path` is a directory, the returned tree contains its entries. Otherwise the tree
+    /// contains a single entry for `path`. Files are imported with `mode`, and import
+    /// progress is reported through `progress`.
+    ///
+    /// If `previous` is the root hash of an earlier import of the same path, files whose size
+    /// and modification time did not change since then are not read again. Their hash is
+    /// taken from the previous tree instead. The previous tree must be stored in `db`.
+    pub async fn import<D>(
+        db: &D,
+        path: PathBuf,
+        mode: ImportMode,
+        previous: Option<Hash>,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> anyhow::Result<TempTag>
+    where
+        D: crate::store::Store,
+    {
+        let previous = match previous {
+            Some(hash) => Some(Tree::load(db, &hash).await?),
+            None => None,
+        };
+        // keep the imported blobs alive until the root tree protects them
+        let mut tags = Vec::new();
+        let metadata = tokio::fs::symlink_metadata(&path).await?;
+        let mut ctx = ImportContext {
+            db,
+            mode,
+            progress: &progress,
+            tags: &mut tags,
+        };
+        if metadata.is_dir() {
+            import_dir(&mut ctx, path, previous).await
+        } else {
+            let name = path
+                .file_name()
+                .context("path is invalid")?
+                .to_str()
+                .context("file name is not valid unicode")?
+                .to_string();
+            let previous = previous.as_ref().and_then(|tree| tree.get(&name));
+            let entry = import_entry(&mut ctx, name, path, metadata, previous).await?;
+            Tree::from_iter(entry).store(db).await
+        }
+    }
+
+    /// Iterate over the entries in this tree
+    pub fn iter(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries.iter()
+    }
+
+    /// Get the entry with the given name
+    pub fn get(&self, name: &str) -> Option<&TreeEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Get the number of entries in this tree
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if this tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add the given entry to the tree.
+    pub fn push(&mut self, entry: TreeEntry) {
+        self.entries.push(entry);
+    }
+}
+
+impl EntryMeta {
+    /// Read the metadata of the file system entry at `path`, without following symlinks.
+    pub fn read(path: &Path, metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let (mode, xattrs) = {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = (!metadata.is_symlink()).then(|| metadata.permissions().mode() & 0o777);
+            (mode, read_xattrs(path))
+        };
+        #[cfg(not(unix))]
+        let (mode, xattrs) = {
+            let _ = path;
+            (None, Vec::new())
+        };
+        // times before the epoch can not be serialized
+        let mtime = metadata
+            .modified()
+            .ok()
+            .filter(|mtime| mtime.duration_since(UNIX_EPOCH).is_ok());
+        Self {
+            mode,
+            mtime,
+            xattrs,
+        }
+    }
+
+    /// Restore this metadata on the file system entry at `path`, without following symlinks.
+    ///
+    /// Directories must be restored after their contents, since creating the contents
+    /// changes the modification time, and the mode might not allow it.
+    pub fn restore(&self, path: &Path) -> std::io::Result<()> {
+        let is_symlink = std::fs::symlink_metadata(path)?.is_symlink();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for (name, value) in &self.xattrs {
+                // some namespaces require privileges, so this is best effort
+                if let Err(cause) = xattr::set(path, name, value) {
+                    tracing::warn!("failed to set xattr {name} on {}: {cause}", path.display());
+                }
+            }
+            if let (Some(mode), false) = (self.mode, is_symlink) {
+                // never restore setuid, setgid or sticky bits from a tree
+                let mode = mode & 0o777;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        if let Some(mtime) = self.mtime {
+            let mtime = filetime::FileTime::from_system_time(mtime);
+            if is_symlink {
+                filetime::set_symlink_file_times(path, mtime, mtime)?;
+            } else {
+                filetime::set_file_mtime(path, mtime)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Vec<(String, Bytes)> {
+    let Ok(names) = xattr::list(path) else {
+        // the file system does not support xattrs
+        return Vec::new();
+    };
+    let mut xattrs = names
+        .filter_map(|name| {
+            let value = xattr::get(path, &name).ok()??;
+            Some((name.into_string().ok()?, Bytes::from(value)))
+        })
+        .collect::<Vec<_>>();
+    xattrs.sort();
+    xattrs
+}
+
+/// Check that `name` is a single normal path component, so it can be joined to a path.
+pub(crate) fn validate_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']),
+        "invalid tree entry name {name:?}"
+    );
+    Ok(())
+}
+
+/// Check that all entry names are valid and unique, so no entry can overwrite another.
+fn validate_entries(entries: &[TreeEntry]) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    for entry in entries {
+        validate_name(&entry.name)?;
+        anyhow::ensure!(
+            names.insert(entry.name.as_str()),
+            "duplicate tree entry name {:?}",
+            entry.name
+        );
+    }
+    Ok(())
+}
+
+async fn load_hash_seq<D>(db: &D, root: &Hash) -> anyhow::Result<HashSeq>
+where
+    D: crate::store::Map,
+{
+    let links_entry = db.get(root).await?.context("links not found")?;
+    anyhow::ensure!(links_entry.is_complete(), "links not complete");
+    let links_bytes = links_entry.data_reader().await?.read_to_end().await?;
+    HashSeq::try_from(links_bytes)
+}
+
+type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// The state shared by all entries of a single [`Tree::import`].
+struct ImportContext<'a, D, P> {
+    db: &'a D,
+    mode: ImportMode,
+    progress: &'a P,
+    tags: &'a mut Vec<TempTag>,
+}
+
+/// Import the entries of the directory at `path` as a tree.
+///
+/// `previous` is the tree of the same directory in an earlier import, if any.
+fn import_dir<'a, 'b, D, P>(
+    ctx: &'a mut ImportContext<'b, D, P>,
+    path: PathBuf,
+    previous: Option<Tree>,
+) -> LocalBoxFuture<'a, anyhow::Result<TempTag>>
+where
+    D: crate::store::Store,
+    P: ProgressSender<Msg = ImportProgress> + IdGenerator,
+{
+    Box::pin(async move {
+        let mut children = Vec::new();
+        let mut dir = tokio::fs::read_dir(&path).await?;
+        while let Some(child) = dir.next_entry().await? {
+            let name = child.file_name().into_string().map_err(|name| {
+                anyhow::anyhow!(
+                    "file name {name:?} in {} is not valid unicode",
+                    path.display()
+                )
+            })?;
+            children.push((name, child.path()));
+        }
+        // sort, so that the tree does not depend on the directory order
+        children.sort();
+        let mut tree = Tree::default();
+        for (name, path) in children {
+            let metadata = tokio::fs::symlink_metadata(&path).await?;
+            let previous = previous.as_ref().and_then(|tree| tree.get(&name));
+            if let Some(entry) = import_entry(ctx, name, path, metadata, previous).await? {
+                tree.push(entry);
+            }
+        }
+        tree.store(ctx.db).await
+    })
+}
+
+/// Import a single directory entry. Returns `None` for unsupported file types.
+async fn import_entry<D, P>(
+    ctx: &mut ImportContext<'_, D, P>,
+    name: String,
+    path: PathBuf,
+    metadata: std::fs::Metadata,
+    previous: Option<&TreeEntry>,
+) -> anyhow::Result<Option<TreeEntry>>
+where
+    D: crate::store::Store,
+    P: ProgressSender<Msg = ImportProgress> + IdGenerator,
+{
+    let meta = EntryMeta::read(&path, &metadata);
+    let kind = if metadata.is_dir() {
+        let previous = match previous.map(|entry| &entry.kind) {
+            Some(EntryKind::Dir(hash)) => Some(Tree::load(ctx.db, hash).await?),
+            _ => None,
+        };
+        let tag = import_dir(ctx, path, previous).await?;
+        let hash = *tag.hash();
+        ctx.tags.push(tag);
+        EntryKind::Dir(hash)
+    } else if metadata.is_symlink() {
+        let target = tokio::fs::read_link(&path).await?;
+        let target = target
+            .into_os_string()
+            .into_string()
+            .map_err(|target| anyhow::anyhow!("symlink target {target:?} is not valid unicode"))?;
+        EntryKind::Symlink(target)
+    } else if metadata.is_file() {
+        let tag = match unchanged_file(ctx.db, previous, &meta, metadata.len()).await? {
+            Some(hash) => {
+                tracing::trace!("reusing {hash} for unchanged file {}", path.display());
+                ctx.db.temp_tag(HashAndFormat::raw(hash))
+            }
+            None => {
+                let (tag, _size) = ctx
+                    .db
+                    .import_file(path, ctx.mode, BlobFormat::Raw, ctx.progress.clone())
+                    .await?;
+                tag
+            }
+        };
+        let hash = *tag.hash();
+        ctx.tags.push(tag);
+        EntryKind::File(hash)
+    } else {
+        tracing::debug!("skipping special file {}", path.display());
+        return Ok(None);
+    };
+    Ok(Some(TreeEntry { name, kind, meta }))
+}
+
+/// Get the hash of a file from a previous import, if the file did not change since.
+///
+/// A file is considered unchanged if it had a modification time, the modification time is
+/// the same, and the blob in the store is complete and has the same size as the file.
+async fn unchanged_file<D>(
+    db: &D,
+    previous: Option<&TreeEntry>,
+    meta: &EntryMeta,
+    size: u64,
+) -> anyhow::Result<Option<Hash>>
+where
+    D: crate::store::Map,
+{
+    let Some(TreeEntry {
+        kind: EntryKind::File(hash),
+        meta: previous_meta,
+        ..
+    }) = previous
+    else {
+        return Ok(None);
+    };
+    if previous_meta.mtime.is_none() || previous_meta.mtime != meta.mtime {
+        return Ok(None);
+    }
+    let Some(entry) = db.get(hash).await? else {
+        return Ok(None);
+    };
+    Ok((entry.is_complete() && entry.size().value() == size).then_some(*hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_tree_meta() {
+        let hash = Hash::new(b"test");
+        let expected = TreeMeta {
+            header: *Tree::HEADER,
+            entries: vec![
+                TreeEntry {
+                    name: "file".to_string(),
+                    kind: EntryKind::File(hash),
+                    meta: EntryMeta {
+                        mode: Some(0o644),
+                        mtime: Some(UNIX_EPOCH + std::time::Duration::from_millis(1234)),
+                        xattrs: vec![("user.test".to_string(), Bytes::from_static(b"value"))],
+                    },
+                },
+                TreeEntry {
+                    name: "dir".to_string(),
+                    kind: EntryKind::Dir(hash),
+                    meta: EntryMeta::default(),
+                },
+                TreeEntry {
+                    name: "link".to_string(),
+                    kind: EntryKind::Symlink("file".to_string()),
+                    meta: EntryMeta::default(),
+                },
+            ],
+        };
+        let buf = postcard::to_stdvec(&expected).unwrap();
+        let actual: TreeMeta = postcard::from_bytes(&buf).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn names() {
+        for name in ["a", "a.txt", ".hidden", "..."] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".", "..", "a/b", "a\\b"] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn load_rejects_invalid_entries() -> anyhow::Result<()> {
+        use crate::store::Store;
+        let db = crate::store::mem::Store::new();
+        let file = |name: &str| TreeEntry {
+            name: name.to_string(),
+            kind: EntryKind::File(Hash::new(b"test")),
+            meta: EntryMeta::default(),
+        };
+        // store validates as well, so write the metadata blob by hand
+        let store_raw = |entries: Vec<TreeEntry>| {
+            let db = db.clone();
+            async move {
+                let meta = TreeMeta {
+                    header: *Tree::HEADER,
+                    entries,
+                };
+                let meta_bytes = postcard::to_stdvec(&meta)?;
+                let meta_tag = db.import_bytes(meta_bytes.into(), BlobFormat::Raw).await?;
+                let links = std::iter::once(*meta_tag.hash()).collect::<HashSeq>();
+                let tag = db.import_bytes(links.into(), BlobFormat::HashSeq).await?;
+                anyhow::Ok((meta_tag, tag))
+            }
+        };
+        let (_meta, valid) = store_raw(vec![file("a"), file("b")]).await?;
+        assert_eq!(Tree::load(&db, valid.hash()).await?.len(), 2);
+        for entries in [
+            vec![file("a"), file("a")],
+            vec![file("..")],
+            vec![file("a/b")],
+        ] {
+            let (_meta, invalid) = store_raw(entries.clone()).await?;
+            assert!(Tree::load(&db, invalid.hash()).await.is_err());
+            assert!(Tree::from_iter(entries).store(&db).await.is_err());
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restore_masks_mode() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        std::fs::write(&path, b"data")?;
+        let meta = EntryMeta {
+            mode: Some(0o4755),
+            ..Default::default()
+        };
+        meta.restore(&path)?;
+        let mode = std::fs::metadata(&path)?.permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o755);
+        Ok(())
+    }
+}
@@ -24,12 +24,14 @@ use quinn::RecvStream;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error};
 
-use crate::protocol::RangeSpecSeq;
+use crate::protocol::{Closed, RangeSpecSeq};
 use crate::util::io::{TrackingReader, TrackingWriter};
 use crate::IROH_BLOCK_SIZE;
 
 pub mod db;
 pub mod error;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod progress;
 pub mod request;
 
@@ -89,6 +91,15 @@ pub mod fsm {
         AtInitial::new(connection, request)
     }
 
+    /// The entry point of the get response machine for data pushed to us
+    ///
+    /// The receiver of a push request sends `request` for the data it is missing itself,
+    /// and then reads the pushed data from `reader` like the response to a get request.
+    pub fn start_pushed(reader: quinn::RecvStream, request: GetRequest) -> ConnectedNext {
+        let reader = TrackingReader::new(TokioStreamReader::new(reader));
+        ConnectedNext::new(Instant::now(), reader, request, 0)
+    }
+
     /// Owned iterator for the ranges in a request
     ///
     /// We need an owned iterator for a fsm style API, otherwise we would have
@@ -106,8 +117,12 @@ pub mod fsm {
             Self(RangesIterInner::new(owner, |owner| owner.iter_non_empty()))
         }
 
+        /// The offset of the blob last yielded by this iterator in the request.
         pub fn offset(&self) -> u64 {
-            self.0.with_dependent(|_owner, iter| iter.offset())
+            // the inner iterator counts the blobs it has yielded
+            self.0
+                .with_dependent(|_owner, iter| iter.offset())
+                .saturating_sub(1)
         }
     }
 
@@ -240,7 +255,9 @@ pub mod fsm {
                 let wrapped = Request::Get(request);
                 let request_bytes =
                     postcard::to_stdvec(&wrapped).map_err(ConnectedNextError::PostcardSer)?;
-                let Request::Get(x) = wrapped;
+                let Request::Get(x) = wrapped else {
+                    unreachable!("just created a get request");
+                };
                 request = x;
 
                 if request_bytes.len() > MAX_MESSAGE_SIZE {
@@ -258,6 +275,17 @@ pub mod fsm {
             let (mut writer, bytes_written) = writer.into_parts();
             writer.finish().await?;
 
+            Ok(ConnectedNext::new(start, reader, request, bytes_written))
+        }
+    }
+
+    impl ConnectedNext {
+        fn new(
+            start: Instant,
+            reader: WrappedRecvStream,
+            request: GetRequest,
+            bytes_written: u64,
+        ) -> Self {
             let hash = request.hash;
             let ranges_iter = RangesIter::new(request.ranges);
             // this is in a box so we don't have to memcpy it on every state transition
@@ -266,7 +294,7 @@ pub mod fsm {
                 bytes_written,
                 ranges_iter,
             });
-            Ok(match misc.ranges_iter.next() {
+            match misc.ranges_iter.next() {
                 Some((offset, ranges)) => {
                     if offset == 0 {
                         AtStartRoot {
@@ -287,7 +315,7 @@ pub mod fsm {
                     }
                 }
                 None => AtClosing::new(misc, reader, true).into(),
-            })
+            }
         }
     }
 
@@ -887,7 +915,10 @@ pub enum GetResponseError {
     Write(#[from] quinn::WriteError),
     /// Error when reading from the stream
     #[error("read: {0}")]
-    Read(#[from] quinn::ReadError),
+    Read(quinn::ReadError),
+    /// The provider refused to serve the request
+    #[error("unauthorized")]
+    Unauthorized,
     /// Error when decoding, e.g. hash mismatch
     #[error("decode: {0}")]
     Decode(bao_tree::io::DecodeError),
@@ -896,6 +927,17 @@ pub enum GetResponseError {
     Generic(anyhow::Error),
 }
 
+impl From<quinn::ReadError> for GetResponseError {
+    fn from(cause: quinn::ReadError) -> Self {
+        match cause {
+            quinn::ReadError::Reset(code) if code == Closed::Unauthorized.into() => {
+                Self::Unauthorized
+            }
+            cause => Self::Read(cause),
+        }
+    }
+}
+
 impl From<postcard::Error> for GetResponseError {
     fn from(cause: postcard::Error) -> Self {
         Self::Generic(cause.into())
@@ -912,7 +954,7 @@ impl From<bao_tree::io::DecodeError> for GetResponseError {
                         return Self::Connection(error.clone());
                     }
                     if let Some(error) = source.downcast_ref::<quinn::ReadError>() {
-                        return Self::Read(error.clone());
+                        return error.clone().into();
                     }
                     if let Some(error) = source.downcast_ref::<quinn::WriteError>() {
                         return Self::Write(error.clone());
@@ -26,7 +26,7 @@ use crate::{
     protocol::{GetRequest, RangeSpecSeq},
     store::{MapEntry, MapEntryMut, MapMut, Store as BaoStore},
     util::progress::{IdGenerator, ProgressSender},
-    BlobFormat, HashAndFormat,
+    BlobFormat, HashAndFormat, IROH_BLOCK_SIZE,
 };
 use anyhow::anyhow;
 use bao_tree::{ChunkNum, ChunkRanges};
@@ -52,23 +52,110 @@ pub async fn get_to_db<
     sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
 ) -> Result<Stats, GetError> {
     let HashAndFormat { hash, format } = hash_and_format;
+    let start_response = |request| start_get(get_conn, request);
     match format {
-        BlobFormat::Raw => get_blob(db, get_conn, hash, sender).await,
-        BlobFormat::HashSeq => get_hash_seq(db, get_conn, hash, sender).await,
+        BlobFormat::Raw => get_blob(db, start_response, hash, sender).await,
+        BlobFormat::HashSeq => get_hash_seq(db, start_response, hash, sender).await,
     }
 }
 
-/// Get a blob that was requested completely.
+/// Get a blob or collection pushed by a remote node into a store.
 ///
-/// We need to create our own files and handle the case where an outboard
-/// is not needed.
-async fn get_blob<
+/// Like [`get_to_db`], this considers data that is already in the store. The request for
+/// the missing data is passed to `send_request`, which sends it to the remote. The pushed
+/// data is then read from `reader` and verified like the response to a get request. If
+/// nothing is missing, `send_request` is not called.
+pub async fn get_pushed_to_db<
+    D: BaoStore,
+    S: FnOnce(GetRequest) -> F,
+    F: Future<Output = io::Result<()>>,
+>(
+    db: &D,
+    send_request: S,
+    reader: quinn::RecvStream,
+    hash_and_format: &HashAndFormat,
+    sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
+) -> Result<Stats, GetError> {
+    let HashAndFormat { hash, format } = hash_and_format;
+    let start_response = |request: GetRequest| async move {
+        send_request(request.clone()).await?;
+        Ok(get::fsm::start_pushed(reader, request))
+    };
+    match format {
+        BlobFormat::Raw => get_blob(db, start_response, hash, sender).await,
+        BlobFormat::HashSeq => get_hash_seq(db, start_response, hash, sender).await,
+    }
+}
+
+/// Send `request` on a new stream of the connection returned by `get_conn`.
+async fn start_get<C: FnOnce() -> F, F: Future<Output = anyhow::Result<quinn::Connection>>>(
+    get_conn: C,
+    request: GetRequest,
+) -> Result<ConnectedNext, GetError> {
+    let conn = get_conn().await.map_err(GetError::Io)?;
+    let request = get::fsm::start(conn, request);
+    // create a new bidi stream
+    let connected = request.next().await?;
+    Ok(connected.next().await?)
+}
+
+/// Get the given ranges of a blob or collection into a store.
+///
+/// Like [`get_to_db`], this considers data that is already in the store, and will only
+/// request the remaining data within `ranges`.
+///
+/// For [`BlobFormat::Raw`], only the first [`RangeSpec`] of `ranges` is used. For
+/// [`BlobFormat::HashSeq`], the first [`RangeSpec`] applies to the hash seq itself and the
+/// following ones apply to its children. Since the hash seq is needed to verify the children,
+/// it is always downloaded completely if any child ranges are requested.
+///
+/// Blobs are only marked as complete once all their chunks are present in the store.
+pub async fn get_ranges_to_db<
     D: BaoStore,
     C: FnOnce() -> F,
     F: Future<Output = anyhow::Result<quinn::Connection>>,
 >(
     db: &D,
     get_conn: C,
+    hash_and_format: &HashAndFormat,
+    ranges: &RangeSpecSeq,
+    sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
+) -> Result<Stats, GetError> {
+    let HashAndFormat { hash, format } = hash_and_format;
+    match format {
+        BlobFormat::Raw => {
+            // unwrapping is safe because the iterator never terminates
+            let ranges = ranges.iter().next().unwrap().to_chunk_ranges();
+            if ranges.is_all() {
+                let start_response = |request| start_get(get_conn, request);
+                get_blob(db, start_response, hash, sender).await
+            } else {
+                get_blob_ranges(db, get_conn, hash, ranges, sender).await
+            }
+        }
+        BlobFormat::HashSeq => {
+            if ranges == &RangeSpecSeq::all() {
+                let start_response = |request| start_get(get_conn, request);
+                get_hash_seq(db, start_response, hash, sender).await
+            } else {
+                get_hash_seq_ranges(db, get_conn, hash, ranges, sender).await
+            }
+        }
+    }
+}
+
+/// Get a blob that was requested completely.
+///
+/// We need to create our own files and handle the case where an outboard
+/// is not needed. `start_response` sends the request for the missing data and returns
+/// the start of the response.
+async fn get_blob<
+    D: BaoStore,
+    S: FnOnce(GetRequest) -> F,
+    F: Future<Output = Result<ConnectedNext, GetError>>,
+>(
+    db: &D,
+    start_response: S,
     hash: &Hash,
     progress: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
 ) -> Result<Stats, GetError> {
@@ -102,13 +189,8 @@ async fn get_blob<
             let required_ranges: ChunkRanges = ChunkRanges::all().difference(&valid_ranges);
 
             let request = GetRequest::new(*hash, RangeSpecSeq::from_ranges([required_ranges]));
-            // full request
-            let conn = get_conn().await.map_err(GetError::Io)?;
-            let request = get::fsm::start(conn, request);
-            // create a new bidi stream
-            let connected = request.next().await?;
-            // next step. we have requested a single hash, so this must be StartRoot
-            let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            // we have requested a single hash, so this must be StartRoot
+            let ConnectedNext::StartRoot(start) = start_response(request).await? else {
                 return Err(GetError::NoncompliantNode(anyhow!("expected StartRoot")));
             };
             // move to the header
@@ -118,13 +200,9 @@ async fn get_blob<
             get_blob_inner_partial(db, header, entry, progress).await?
         }
         None => {
-            // full request
-            let conn = get_conn().await.map_err(GetError::Io)?;
-            let request = get::fsm::start(conn, GetRequest::single(*hash));
-            // create a new bidi stream
-            let connected = request.next().await?;
-            // next step. we have requested a single hash, so this must be StartRoot
-            let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            // full request, we have requested a single hash, so this must be StartRoot
+            let ConnectedNext::StartRoot(start) = start_response(GetRequest::single(*hash)).await?
+            else {
                 return Err(GetError::NoncompliantNode(anyhow!("expected StartRoot")));
             };
             // move to the header
@@ -145,6 +223,11 @@ async fn get_blob<
 
 /// Given a partial entry, get the valid ranges.
 pub async fn valid_ranges<D: MapMut>(entry: &D::EntryMut) -> anyhow::Result<ChunkRanges> {
+    entry_valid_ranges(entry).await
+}
+
+/// Given an entry of any map, get the valid ranges from its data and outboard.
+pub(crate) async fn entry_valid_ranges(entry: &impl MapEntry) -> anyhow::Result<ChunkRanges> {
     use tracing::trace as log;
     // compute the valid range from just looking at the data file
     let mut data_reader = entry.data_reader().await?;
@@ -158,12 +241,46 @@ pub async fn valid_ranges<D: MapMut>(entry: &D::EntryMut) -> anyhow::Result<Chun
     while let Some(range) = stream.next().await {
         valid_from_outboard |= ChunkRanges::from(range?);
     }
-    let valid: ChunkRanges = valid_from_data.intersection(&valid_from_outboard);
+    let mut valid: ChunkRanges = valid_from_data.intersection(&valid_from_outboard);
+    // the outboard only has hashes for whole chunk groups, but the last chunk group can be
+    // written partially, e.g. when just the last chunk was requested to verify the size.
+    // So the data of the last chunk group has to be checked against the outboard.
+    let group_chunks = 1u64 << IROH_BLOCK_SIZE.chunk_log();
+    let last = ChunkNum::chunks(data_size).0.saturating_sub(1);
+    let last_group = ChunkRanges::from(ChunkNum(last - last % group_chunks)..);
+    if !valid.is_disjoint(&last_group) {
+        let outboard = entry.outboard().await?;
+        let mut stream = bao_tree::io::fsm::valid_ranges(outboard, data_reader, &last_group);
+        let mut valid_from_data = ChunkRanges::empty();
+        while let Some(range) = stream.next().await {
+            valid_from_data |= ChunkRanges::from(range?);
+        }
+        valid = valid.difference(&last_group);
+        valid |= valid_from_data.intersection(&valid_from_outboard);
+    }
     log!("valid_from_data: {:?}", valid_from_data);
     log!("valid_from_outboard: {:?}", valid_from_data);
     Ok(valid)
 }
 
+/// Check if a partial entry contains all chunks of a blob of the given size.
+///
+/// Unlike [`valid_ranges`], this also takes the last chunk into account if it is incomplete.
+pub(crate) async fn has_all_chunks<D: MapMut>(entry: &D::EntryMut, size: u64) -> io::Result<bool> {
+    let mut data_reader = entry.data_reader().await?;
+    if data_reader.size().await? < size {
+        return Ok(false);
+    }
+    let mut outboard = entry.outboard().await?;
+    let all = ChunkRanges::from(..ChunkNum::chunks(size));
+    let mut stream = bao_tree::io::fsm::valid_outboard_ranges(&mut outboard, &all);
+    let mut valid = ChunkRanges::empty();
+    while let Some(range) = stream.next().await {
+        valid |= ChunkRanges::from(range?);
+    }
+    Ok(all.is_subset(&valid))
+}
+
 /// Get a blob that was requested completely.
 ///
 /// We need to create our own files and handle the case where an outboard
@@ -173,6 +290,8 @@ async fn get_blob_inner<D: BaoStore>(
     at_header: AtBlobHeader,
     sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
 ) -> Result<AtEndBlob, GetError> {
+    // remember the requested ranges for progress reporting
+    let ranges = RangeSpec::new(at_header.ranges());
     // read the size. The size we get here is not verified, but since we use
     // it for the tree traversal we are guaranteed not to get more than size.
     let (at_content, size) = at_header.next().await?;
@@ -190,6 +309,7 @@ async fn get_blob_inner<D: BaoStore>(
             hash,
             size,
             child: BlobId::from_offset(child_offset),
+            ranges,
         })
         .await?;
     let sender2 = sender.clone();
@@ -226,6 +346,8 @@ async fn get_blob_inner_partial<D: BaoStore>(
     entry: D::EntryMut,
     sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
 ) -> Result<AtEndBlob, GetError> {
+    // remember the requested ranges for progress reporting
+    let ranges = RangeSpec::new(at_header.ranges());
     // read the size. The size we get here is not verified, but since we use
     // it for the tree traversal we are guaranteed not to get more than size.
     let (at_content, size) = at_header.next().await?;
@@ -241,6 +363,7 @@ async fn get_blob_inner_partial<D: BaoStore>(
             hash,
             size,
             child: BlobId::from_offset(child_offset),
+            ranges,
         })
         .await?;
     let sender2 = sender.clone();
@@ -271,6 +394,118 @@ async fn get_blob_inner_partial<D: BaoStore>(
     Ok(at_end)
 }
 
+/// Get some ranges of a single blob.
+async fn get_blob_ranges<
+    D: BaoStore,
+    C: FnOnce() -> F,
+    F: Future<Output = anyhow::Result<quinn::Connection>>,
+>(
+    db: &D,
+    get_conn: C,
+    hash: &Hash,
+    ranges: ChunkRanges,
+    progress: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
+) -> Result<Stats, GetError> {
+    let info = blob_info(db, hash).await?;
+    if let Some(size) = info.size() {
+        progress
+            .send(DownloadProgress::FoundLocal {
+                child: BlobId::Root,
+                hash: *hash,
+                size,
+                valid_ranges: RangeSpec::new(info.valid_ranges()),
+            })
+            .await?;
+    }
+    let required_ranges = ranges.intersection(&info.missing_ranges());
+    if required_ranges.is_empty() {
+        trace!("already got requested ranges of {}", hash);
+        return Ok(Stats::default());
+    }
+    let request = GetRequest::new(*hash, RangeSpecSeq::from_ranges([required_ranges.clone()]));
+    let conn = get_conn().await.map_err(GetError::Io)?;
+    let request = get::fsm::start(conn, request);
+    // create a new bidi stream
+    let connected = request.next().await?;
+    // next step. we have requested a single hash, so this must be StartRoot
+    let ConnectedNext::StartRoot(start) = connected.next().await? else {
+        return Err(GetError::NoncompliantNode(anyhow!("expected StartRoot")));
+    };
+    // move to the header
+    let header = start.next();
+    let end = get_blob_inner_ranges(db, header, &info, &required_ranges, progress).await?;
+    // we have requested a single hash, so we must be at closing
+    let EndBlobNext::Closing(end) = end.next() else {
+        return Err(GetError::NoncompliantNode(anyhow!("expected Closing")));
+    };
+    let stats = end.next().await?;
+    Ok(stats)
+}
+
+/// Get some ranges of a blob, which might already be partially present.
+///
+/// The entry is only marked as complete if the requested ranges fill all the gaps
+/// in the local data.
+async fn get_blob_inner_ranges<D: BaoStore>(
+    db: &D,
+    at_header: AtBlobHeader,
+    info: &BlobInfo<D>,
+    required_ranges: &ChunkRanges,
+    sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
+) -> Result<AtEndBlob, GetError> {
+    // remember the requested ranges for progress reporting
+    let ranges = RangeSpec::new(at_header.ranges());
+    // read the size. The size we get here is not verified, but since we use
+    // it for the tree traversal we are guaranteed not to get more than size.
+    let (at_content, size) = at_header.next().await?;
+    let hash = at_content.hash();
+    let child_offset = at_content.offset();
+    let entry = match info {
+        BlobInfo::Partial { entry, .. } => entry.clone(),
+        _ => db.get_or_create(hash, size).await?,
+    };
+    let bw = entry.batch_writer().await?;
+    // allocate a new id for progress reports for this transfer
+    let id = sender.new_id();
+    sender
+        .send(DownloadProgress::Found {
+            id,
+            hash,
+            size,
+            child: BlobId::from_offset(child_offset),
+            ranges,
+        })
+        .await?;
+    let sender2 = sender.clone();
+    let on_write = move |offset: u64, _length: usize| {
+        // if try send fails it means that the receiver has been dropped.
+        // in that case we want to abort the write_all_with_outboard.
+        sender2
+            .try_send(DownloadProgress::Progress { id, offset })
+            .map_err(|e| {
+                tracing::info!("aborting download of {}", hash);
+                e
+            })?;
+        Ok(())
+    };
+    let mut bw = FallibleProgressBatchWriter::new(bw, on_write);
+    // use the convenience method to write all to the batch writer
+    let at_end = at_content.write_all_batch(&mut bw).await?;
+    // sync the underlying storage, if needed
+    bw.sync().await?;
+    drop(bw);
+    // only mark the entry as complete if the request covered all chunks we did not have, or
+    // if concurrent downloads of other ranges of the blob filled the remaining gaps.
+    let all_chunks = ChunkRanges::from(..ChunkNum::chunks(size));
+    let missing: ChunkRanges = all_chunks.difference(&info.valid_ranges());
+    if missing.is_subset(required_ranges) || has_all_chunks::<D>(&entry, size).await? {
+        db.insert_complete(entry).await?;
+    }
+    // notify that we are done
+    sender.send(DownloadProgress::Done { id }).await?;
+    Ok(at_end)
+}
+
 /// Get information about a blob in a store.
 ///
 /// This will compute the valid ranges for partial blobs, so it is somewhat expensive for those.
@@ -304,11 +539,11 @@ async fn blob_infos<D: BaoStore>(db: &D, hash_seq: &[Hash]) -> io::Result<Vec<Bl
 /// Get a sequence of hashes
 async fn get_hash_seq<
     D: BaoStore,
-    C: FnOnce() -> F,
-    F: Future<Output = anyhow::Result<quinn::Connection>>,
+    S: FnOnce(GetRequest) -> F,
+    F: Future<Output = Result<ConnectedNext, GetError>>,
 >(
     db: &D,
-    get_conn: C,
+    start_response: S,
     root_hash: &Hash,
     sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
 ) -> Result<Stats, GetError> {
@@ -367,13 +602,8 @@ async fn get_hash_seq<
                 .collect::<Vec<_>>();
             log!("requesting chunks {:?}", missing_iter);
             let request = GetRequest::new(*root_hash, RangeSpecSeq::from_ranges(missing_iter));
-            let conn = get_conn().await.map_err(GetError::Io)?;
-            let request = get::fsm::start(conn, request);
-            // create a new bidi stream
-            let connected = request.next().await?;
-            log!("connected");
             // we have not requested the root, so this must be StartChild
-            let ConnectedNext::StartChild(start) = connected.next().await? else {
+            let ConnectedNext::StartChild(start) = start_response(request).await? else {
                 return Err(GetError::NoncompliantNode(anyhow!("expected StartChild")));
             };
             let mut next = EndBlobNext::MoreChildren(start);
@@ -413,12 +643,10 @@ async fn get_hash_seq<
         _ => {
             tracing::debug!("don't have collection - doing full download");
             // don't have the collection, so probably got nothing
-            let conn = get_conn().await.map_err(GetError::Io)?;
-            let request = get::fsm::start(conn, GetRequest::all(*root_hash));
-            // create a new bidi stream
-            let connected = request.next().await?;
-            // next step. we have requested a single hash, so this must be StartRoot
-            let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            // we have requested the root, so this must be StartRoot
+            let ConnectedNext::StartRoot(start) =
+                start_response(GetRequest::all(*root_hash)).await?
+            else {
                 return Err(GetError::NoncompliantNode(anyhow!("expected StartRoot")));
             };
             // move to the header
@@ -469,6 +697,170 @@ async fn get_hash_seq<
     Ok(stats)
 }
 
+/// Get some ranges of a sequence of hashes.
+async fn get_hash_seq_ranges<
+    D: BaoStore,
+    C: FnOnce() -> F,
+    F: Future<Output = anyhow::Result<quinn::Connection>>,
+>(
+    db: &D,
+    get_conn: C,
+    root_hash: &Hash,
+    ranges: &RangeSpecSeq,
+    sender: impl ProgressSender<Msg = DownloadProgress> + IdGenerator,
+) -> Result<Stats, GetError> {
+    let needs_children = ranges.iter_non_empty().any(|(offset, _)| offset > 0);
+    if !needs_children {
+        // unwrapping is safe because the iterator never terminates
+        let root_ranges = ranges.iter().next().unwrap().to_chunk_ranges();
+        return get_blob_ranges(db, get_conn, root_hash, root_ranges, sender).await;
+    }
+    let root_info = blob_info(db, root_hash).await?;
+    if let Some(size) = root_info.size() {
+        sender
+            .send(DownloadProgress::FoundLocal {
+                child: BlobId::Root,
+                hash: *root_hash,
+                size,
+                valid_ranges: RangeSpec::new(root_info.valid_ranges()),
+            })
+            .await?;
+    }
+    let mut children = None;
+    if let BlobInfo::Complete { .. } = root_info {
+        let (hashes, infos) = hash_seq_children_info(db, root_hash, &sender).await?;
+        let required = required_child_ranges(ranges, &infos);
+        if required.iter().all(|ranges| ranges.is_empty()) {
+            tracing::info!("already got requested ranges of {}", root_hash);
+            return Ok(Stats::default());
+        }
+        children = Some((hashes, infos, required));
+    }
+    let conn = get_conn().await.map_err(GetError::Io)?;
+    let mut stats = Stats::default();
+    let (hashes, infos, required) = match children {
+        Some(children) => children,
+        None => {
+            // we need the entire hash seq to know the children
+            let required_ranges = root_info.missing_ranges();
+            let request = GetRequest::new(
+                *root_hash,
+                RangeSpecSeq::from_ranges([required_ranges.clone()]),
+            );
+            let request = get::fsm::start(conn.clone(), request);
+            let connected = request.next().await?;
+            let ConnectedNext::StartRoot(start) = connected.next().await? else {
+                return Err(GetError::NoncompliantNode(anyhow!("expected StartRoot")));
+            };
+            let header = start.next();
+            let end =
+                get_blob_inner_ranges(db, header, &root_info, &required_ranges, sender.clone())
+                    .await?;
+            let EndBlobNext::Closing(end) = end.next() else {
+                return Err(GetError::NoncompliantNode(anyhow!("expected Closing")));
+            };
+            stats = end.next().await?;
+            let (hashes, infos) = hash_seq_children_info(db, root_hash, &sender).await?;
+            let required = required_child_ranges(ranges, &infos);
+            if required.iter().all(|ranges| ranges.is_empty()) {
+                return Ok(stats);
+            }
+            (hashes, infos, required)
+        }
+    };
+    let request = GetRequest::new(
+        *root_hash,
+        RangeSpecSeq::from_ranges(std::iter::once(ChunkRanges::empty()).chain(required.clone())),
+    );
+    let request = get::fsm::start(conn, request);
+    let connected = request.next().await?;
+    // we have not requested the root, so this must be StartChild
+    let ConnectedNext::StartChild(start) = connected.next().await? else {
+        return Err(GetError::NoncompliantNode(anyhow!("expected StartChild")));
+    };
+    let mut next = EndBlobNext::MoreChildren(start);
+    let finishing = loop {
+        let start = match next {
+            EndBlobNext::MoreChildren(start) => start,
+            EndBlobNext::Closing(finish) => break finish,
+        };
+        let child_offset = usize::try_from(start.child_offset())
+            .map_err(|_| GetError::NoncompliantNode(anyhow!("child offset too large")))?;
+        let (child_hash, info, required_ranges) = match (
+            hashes.get(child_offset),
+            infos.get(child_offset),
+            required.get(child_offset),
+        ) {
+            (Some(hash), Some(info), Some(required_ranges)) => (*hash, info, required_ranges),
+            _ => break start.finish(),
+        };
+        let header = start.next(child_hash);
+        let end_blob =
+            get_blob_inner_ranges(db, header, info, required_ranges, sender.clone()).await?;
+        next = end_blob.next();
+    };
+    let child_stats = finishing.next().await?;
+    stats.bytes_written += child_stats.bytes_written;
+    stats.bytes_read += child_stats.bytes_read;
+    stats.elapsed += child_stats.elapsed;
+    Ok(stats)
+}
+
+/// Read the children of a locally complete hash seq and get information about them.
+///
+/// This also reports the hash seq and the locally available children as progress.
+async fn hash_seq_children_info<D: BaoStore>(
+    db: &D,
+    root_hash: &Hash,
+    sender: &impl ProgressSender<Msg = DownloadProgress>,
+) -> Result<(Vec<Hash>, Vec<BlobInfo<D>>), GetError> {
+    let entry = db
+        .get(root_hash)
+        .await?
+        .ok_or_else(|| GetError::LocalFailure(anyhow!("hash seq not in db")))?;
+    let reader = entry.data_reader().await?;
+    let (mut hash_seq, count) = parse_hash_seq(reader).await.map_err(|err| {
+        GetError::NoncompliantNode(anyhow!("Failed to parse downloaded HashSeq: {err}"))
+    })?;
+    sender
+        .send(DownloadProgress::FoundHashSeq {
+            hash: *root_hash,
+            children: count,
+        })
+        .await?;
+    let mut children = vec![];
+    while let Some(hash) = hash_seq.next().await? {
+        children.push(hash);
+    }
+    let infos = blob_infos(db, &children).await?;
+    for (i, info) in infos.iter().enumerate() {
+        if let Some(size) = info.size() {
+            sender
+                .send(DownloadProgress::FoundLocal {
+                    child: BlobId::from_offset((i as u64) + 1),
+                    hash: children[i],
+                    size,
+                    valid_ranges: RangeSpec::new(info.valid_ranges()),
+                })
+                .await?;
+        }
+    }
+    Ok((children, infos))
+}
+
+/// Compute the ranges that still need to be requested for each child of a hash seq.
+fn required_child_ranges<D: BaoStore>(
+    ranges: &RangeSpecSeq,
+    infos: &[BlobInfo<D>],
+) -> Vec<ChunkRanges> {
+    ranges
+        .iter()
+        .skip(1)
+        .zip(infos)
+        .map(|(spec, info)| spec.to_chunk_ranges().intersection(&info.missing_ranges()))
+        .collect()
+}
+
 /// Information about a the status of a blob in a store.
 #[derive(Debug, Clone)]
 pub enum BlobInfo<D: BaoStore> {
@@ -555,6 +947,10 @@ pub enum DownloadProgress {
         hash: Hash,
         /// The size of the entry in bytes.
         size: u64,
+        /// The ranges that are requested from the remote for this entry.
+        ///
+        /// [`DownloadProgress::Progress`] events for `id` report progress within these ranges.
+        ranges: RangeSpec,
     },
     /// An item was found with hash `hash`, from now on referred to via `id`.
     FoundHashSeq {
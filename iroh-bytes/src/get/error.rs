@@ -1,6 +1,6 @@
 //! Error returned from get operations
 
-use crate::util::progress::ProgressSendError;
+use crate::{protocol::Closed, util::progress::ProgressSendError};
 
 /// Failures for a get operation
 #[derive(Debug, thiserror::Error)]
@@ -11,6 +11,9 @@ pub enum GetError {
     /// Remote has reset the connection.
     #[error("Remote has reset the connection")]
     RemoteReset(#[source] anyhow::Error),
+    /// Remote refused to serve the request.
+    #[error("Remote refused to serve the request")]
+    Unauthorized(#[source] anyhow::Error),
     /// Remote behaved in a non-compliant way.
     #[error("Remote behaved in a non-compliant way")]
     NoncompliantNode(#[source] anyhow::Error),
@@ -77,6 +80,10 @@ impl From<quinn::ConnectionError> for GetError {
 impl From<quinn::ReadError> for GetError {
     fn from(value: quinn::ReadError) -> Self {
         match value {
+            e @ quinn::ReadError::Reset(code) if code == Closed::Unauthorized.into() => {
+                // the remote denied the request, it will not serve it if we retry
+                GetError::Unauthorized(e.into())
+            }
             e @ quinn::ReadError::Reset(_) => GetError::RemoteReset(e.into()),
             quinn::ReadError::ConnectionLost(conn_error) => conn_error.into(),
             quinn::ReadError::UnknownStream
@@ -0,0 +1,166 @@
+//! Get data from untrusted HTTP servers.
+//!
+//! A server, such as the gateway of an iroh node, serves the bao encoding of a blob at
+//! `<base>/bao/<hash>?ranges=<ranges>`, where `<ranges>` is the text form of a [`RangeSpec`].
+//! If the query is omitted, the entire blob is served. The response body is the same as the
+//! response of a provider for a single blob: the size of the blob as a little endian u64,
+//! followed by the encoded ranges.
+//!
+//! The response is verified against the hash while it is decoded, just like a response from a
+//! peer, so the server does not have to be trusted. This allows mirrors and CDNs to serve
+//! iroh content.
+use std::{io, time::Instant};
+
+use anyhow::anyhow;
+use bao_tree::{
+    io::fsm::{BaoContentItem, ResponseDecoder, ResponseDecoderNext},
+    BaoTree, ChunkNum, ChunkRanges,
+};
+use futures_lite::StreamExt;
+use iroh_io::{AsyncStreamReader, TokioStreamReader};
+use reqwest::{StatusCode, Url};
+use tokio_util::io::StreamReader;
+use tracing::trace;
+
+use crate::{
+    get::{
+        db::{blob_info, has_all_chunks, BlobInfo},
+        error::GetError,
+        fsm::DecodeError,
+        Stats,
+    },
+    protocol::RangeSpec,
+    store::{BaoBatchWriter, MapEntryMut, Store as BaoStore},
+    util::io::TrackingReader,
+    Hash, IROH_BLOCK_SIZE,
+};
+
+/// The URL of the bao encoding of `ranges` of the blob `hash` on the server at `base`.
+pub fn bao_url(base: &Url, hash: &Hash, ranges: &RangeSpec) -> anyhow::Result<Url> {
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    let mut url = base.join(&format!("bao/{hash}"))?;
+    if !ranges.is_all() {
+        url.query_pairs_mut()
+            .append_pair("ranges", &ranges.to_string());
+    }
+    Ok(url)
+}
+
+/// Get `ranges` of the blob `hash` from the HTTP server at `base` into a store.
+///
+/// Like [`get_ranges_to_db`](super::db::get_ranges_to_db), this does not send a request if
+/// all of `ranges` are present locally. Otherwise all of `ranges` are requested, not just the
+/// missing ones, so that all clients use the same URL and the response can be cached.
+///
+/// The blob is only marked as complete once all its chunks are present in the store.
+pub async fn get_to_db<D: BaoStore>(
+    db: &D,
+    client: &reqwest::Client,
+    base: &Url,
+    hash: &Hash,
+    ranges: &RangeSpec,
+) -> Result<Stats, GetError> {
+    let start = Instant::now();
+    let info = blob_info(db, hash).await?;
+    let chunk_ranges = ranges.to_chunk_ranges();
+    let missing = info.missing_ranges();
+    let wanted: ChunkRanges = chunk_ranges.intersection(&missing);
+    if wanted.is_empty() {
+        trace!("already got requested ranges of {}", hash);
+        return Ok(Stats::default());
+    }
+    let url = bao_url(base, hash, ranges).map_err(GetError::BadRequest)?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| GetError::Io(e.into()))?;
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => return Err(GetError::NotFound(anyhow!("blob {hash} not found"))),
+        status => {
+            return Err(GetError::NoncompliantNode(anyhow!(
+                "unexpected status {status}"
+            )))
+        }
+    }
+    let body = response
+        .bytes_stream()
+        .map(|item| item.map_err(io::Error::other));
+    let mut reader = TrackingReader::new(TokioStreamReader::new(StreamReader::new(Box::pin(body))));
+    // read the size. The size we get here is not verified, but since we use
+    // it for the tree traversal we are guaranteed not to get more than size.
+    let size = reader.read::<8>().await.map_err(|cause| {
+        if cause.kind() == io::ErrorKind::UnexpectedEof {
+            GetError::NotFound(cause.into())
+        } else {
+            cause.into()
+        }
+    })?;
+    let size = u64::from_le_bytes(size);
+    let entry = match &info {
+        BlobInfo::Partial { entry, .. } => entry.clone(),
+        _ => db.get_or_create(*hash, size).await?,
+    };
+    let mut bw = entry.batch_writer().await?;
+    let mut decoder = ResponseDecoder::new(
+        (*hash).into(),
+        chunk_ranges.clone(),
+        BaoTree::new(size, IROH_BLOCK_SIZE),
+        reader,
+    );
+    // write parents together with the leaf that follows them, like the get state machine
+    let mut batch = Vec::new();
+    let reader = loop {
+        match decoder.next().await {
+            ResponseDecoderNext::More((next, item)) => {
+                let item = item.map_err(DecodeError::from)?;
+                let is_leaf = matches!(item, BaoContentItem::Leaf(_));
+                batch.push(item);
+                if is_leaf {
+                    bw.write_batch(size, std::mem::take(&mut batch)).await?;
+                }
+                decoder = next;
+            }
+            ResponseDecoderNext::Done(reader) => break reader,
+        }
+    };
+    bw.sync().await?;
+    drop(bw);
+    // only mark the entry as complete if the request covered all chunks we did not have, or
+    // if concurrent downloads of other ranges of the blob filled the remaining gaps.
+    let all_chunks = ChunkRanges::from(..ChunkNum::chunks(size));
+    let missing: ChunkRanges = all_chunks.difference(&info.valid_ranges());
+    if missing.is_subset(&chunk_ranges) || has_all_chunks::<D>(&entry, size).await? {
+        db.insert_complete(entry).await?;
+    }
+    let (_, bytes_read) = reader.into_parts();
+    Ok(Stats {
+        bytes_written: 0,
+        bytes_read,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let hash = Hash::new(b"hello");
+        let base: Url = "https://cdn.example.com/iroh".parse().unwrap();
+        let url = bao_url(&base, &hash, &RangeSpec::all()).unwrap();
+        assert_eq!(
+            url.as_str(),
+            format!("https://cdn.example.com/iroh/bao/{hash}")
+        );
+        let ranges = RangeSpec::new(ChunkRanges::from(ChunkNum(2)..ChunkNum(7)));
+        let url = bao_url(&base, &hash, &ranges).unwrap();
+        assert_eq!(url.query(), Some("ranges=2%2C5"));
+    }
+}
@@ -52,6 +52,8 @@ pub struct BlobState {
     pub progress: BlobProgress,
     /// Ranges already available locally at the time of starting the transfer.
     pub local_ranges: Option<RangeSpec>,
+    /// Ranges requested from the remote. Only known once the transfer of this blob started.
+    pub requested_ranges: Option<RangeSpec>,
     /// Number of children (only applies to hashseqs, None for raw blobs).
     pub child_count: Option<u64>,
 }
@@ -75,6 +77,7 @@ impl BlobState {
             hash,
             size: None,
             local_ranges: None,
+            requested_ranges: None,
             child_count: None,
             progress: BlobProgress::default(),
         }
@@ -143,6 +146,7 @@ impl TransferState {
                 child: blob_id,
                 hash,
                 size,
+                ranges,
             } => {
                 let blob = self.get_or_insert_blob(blob_id, hash);
                 blob.size = match blob.size {
@@ -152,6 +156,7 @@ impl TransferState {
                     // Otherwise, keep the existing verified size.
                     value @ Some(BaoBlobSize::Verified(_)) => value,
                 };
+                blob.requested_ranges = Some(ranges);
                 blob.progress = BlobProgress::Progressing(0);
                 self.progress_id_to_blob.insert(progress_id, blob_id);
                 self.current = Some(blob_id);
@@ -3,14 +3,16 @@ use std::sync::Arc;
 
 use crate::{
     hashseq::HashSeq,
-    protocol::{GetRequest, RangeSpecSeq},
-    Hash, HashAndFormat,
+    protocol::{
+        Closed, GetRequest, HaveRequest, HaveResponse, RangeSpecSeq, Request, MAX_MESSAGE_SIZE,
+    },
+    BlobFormat, Hash, HashAndFormat,
 };
 use bao_tree::{ChunkNum, ChunkRanges};
 use bytes::Bytes;
 use rand::Rng;
 
-use super::{fsm, Stats};
+use super::{fsm, GetResponseError, Stats};
 
 /// Get the claimed size of a blob from a peer.
 ///
@@ -171,6 +173,35 @@ pub async fn get_chunk_probe(
     Ok(stats)
 }
 
+/// Ask a peer which ranges of a blob or collection it has.
+///
+/// For [`BlobFormat::HashSeq`], the result also contains the ranges of the children,
+/// if the peer has the complete hash seq. The ranges are not validated, so getting
+/// them can still fail.
+pub async fn get_available_ranges(
+    connection: &quinn::Connection,
+    hash: &Hash,
+    format: BlobFormat,
+) -> anyhow::Result<RangeSpecSeq> {
+    let request = Request::Have(HaveRequest::new(*hash, format));
+    let request_bytes = postcard::to_stdvec(&request)?;
+    let (mut writer, mut reader) = connection.open_bi().await?;
+    writer.write_all(&request_bytes).await?;
+    writer.finish().await?;
+    let response = match reader.read_to_end(MAX_MESSAGE_SIZE).await {
+        Ok(response) => response,
+        Err(quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)))
+            if code == Closed::TooLarge.into() =>
+        {
+            anyhow::bail!("the ranges of {hash} are too large to be listed")
+        }
+        Err(quinn::ReadToEndError::Read(cause)) => return Err(GetResponseError::from(cause).into()),
+        Err(cause) => return Err(cause.into()),
+    };
+    let HaveResponse { ranges } = postcard::from_bytes(&response)?;
+    Ok(ranges)
+}
+
 /// Given a sequence of sizes of children, generate a range spec that selects a
 /// random chunk of a random child.
 ///
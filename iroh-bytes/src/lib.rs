@@ -3,6 +3,7 @@
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 #![recursion_limit = "256"]
 
+pub mod bundle;
 #[cfg(feature = "downloader")]
 pub mod downloader;
 pub mod export;
@@ -14,6 +14,8 @@ pub struct Metrics {
     pub downloads_success: Counter,
     pub downloads_error: Counter,
     pub downloads_notfound: Counter,
+    pub upload_bytes_total: Counter,
+    pub upload_throttle_time_total: Counter,
 }
 
 impl Default for Metrics {
@@ -24,6 +26,10 @@ impl Default for Metrics {
             downloads_success: Counter::new("Total number of successful downloads"),
             downloads_error: Counter::new("Total number of downloads failed with error"),
             downloads_notfound: Counter::new("Total number of downloads failed with not found"),
+            upload_bytes_total: Counter::new("Total number of content bytes uploaded"),
+            upload_throttle_time_total: Counter::new(
+                "Total time in ms uploads waited for the upload rate limits",
+            ),
         }
     }
 }
@@ -57,12 +57,10 @@
 //!
 //! - Do not support discovery.
 //!
-//!   The protocol does not yet have a discovery mechanism for asking the provider
-//! what ranges are available for a given blob. Currently you have to have some
-//! out-of-band knowledge about what node has data for a given hash, or you can
-//! just try to retrieve the data and see if it is available.
-//!
-//! A discovery protocol is planned in the future though.
+//!   The protocol has no mechanism for finding out which node has data for a given
+//! hash. You have to have some out-of-band knowledge about that. Once you know a
+//! node that might have the data, you can ask it which ranges it has using a
+//! [`HaveRequest`].
 //!
 //! # Requests
 //!
@@ -87,6 +85,46 @@
 //! the same format as the getter defined requests, followed by the bao encoded
 //! data. From then on the protocol is the same as for getter defined requests.
 //!
+//! ## Push requests
+//!
+//! In this case the requester has data it wants to store on the other side,
+//! e.g. because the other side can not dial it. The requester opens a stream
+//! and sends a [`PushRequest`] with the hash and format of the data. Unlike the
+//! other requests, the stream is not finished after the request, so the push
+//! request has a fixed size of [`PushRequest::REQUEST_LEN`] bytes.
+//!
+//! The receiver decides whether to accept the push. If it refuses, it resets the
+//! stream with [`Closed::Unauthorized`]. If it accepts, it responds with a
+//! [`GetRequest`] for the data it is missing, postcard encoded and prefixed with
+//! its length as a little endian `u32`, or finishes the stream right away if it
+//! has all of the data.
+//!
+//! The requester then sends the bao encoded data for the [`GetRequest`] on the
+//! push stream, exactly like the response to a get request, and finishes it.
+//! The receiver verifies the data incrementally while storing it. Once all of it
+//! is stored, the receiver finishes its side of the stream. If it fails to store
+//! the data, it resets the stream with [`Closed::PushFailed`].
+//!
+//! ## Have requests
+//!
+//! In this case the requester wants to know which parts of a blob or collection
+//! the provider has, e.g. to pick a provider that has the parts it is missing.
+//! The requester sends a [`HaveRequest`] with the hash and format of the data.
+//!
+//! The provider responds with a postcard encoded [`HaveResponse`] and then
+//! finishes the stream. The response contains a [`RangeSpecSeq`] in the same
+//! shape as the one of a [`GetRequest`]: the first element is the set of chunks
+//! of the root blob the provider has, the subsequent elements are the sets of
+//! chunks of the children. Children can only be listed if the provider has the
+//! complete root blob. Complete blobs are given as [`RangeSpec::all`].
+//!
+//! The provider does not validate the data when answering a have request, so a
+//! get request for the returned ranges can still fail.
+//!
+//! A provider does not list the children of a hash seq with more than
+//! [`MAX_HAVE_CHILDREN`] children, or send a response larger than
+//! [`MAX_MESSAGE_SIZE`]. It resets the stream with [`Closed::TooLarge`] instead.
+//!
 //! ## Specifying the required data
 //!
 //! A [`GetRequest`] contains a hash and a specification of what data related to
@@ -343,13 +381,16 @@ use derive_more::From;
 use quinn::VarInt;
 use serde::{Deserialize, Serialize};
 mod range_spec;
-pub use range_spec::{NonEmptyRequestRangeSpecIter, RangeSpec, RangeSpecSeq};
+pub use range_spec::{NonEmptyRequestRangeSpecIter, RangeSpec, RangeSpecParseError, RangeSpecSeq};
 
-use crate::Hash;
+use crate::{BlobFormat, Hash};
 
 /// Maximum message size is limited to 100MiB for now.
 pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 100;
 
+/// Maximum number of children of a hash seq for which a have request is answered.
+pub const MAX_HAVE_CHILDREN: u64 = 64 * 1024;
+
 /// The ALPN used with quic for the iroh bytes protocol.
 pub const ALPN: &[u8] = b"/iroh-bytes/4";
 
@@ -358,6 +399,74 @@ pub const ALPN: &[u8] = b"/iroh-bytes/4";
 pub enum Request {
     /// A get request for a blob or collection
     Get(GetRequest),
+    /// A request to store a blob or collection served by the requester
+    Push(PushRequest),
+    /// A request for the ranges of a blob or collection the provider has
+    Have(HaveRequest),
+}
+
+/// A request for the ranges of a blob or collection the provider has
+///
+/// See the [module documentation](self) for the response.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct HaveRequest {
+    /// blake3 hash
+    pub hash: Hash,
+    /// The format of the data, if this is [`BlobFormat::HashSeq`] the ranges of all
+    /// children are returned as well.
+    pub format: BlobFormat,
+}
+
+impl HaveRequest {
+    /// Request the ranges of a blob or collection
+    pub fn new(hash: Hash, format: BlobFormat) -> Self {
+        Self { hash, format }
+    }
+
+    /// The get request for all of the data this request asks about
+    ///
+    /// This is what a have request is authorized as, so that a node that may not get
+    /// some data also can not find out whether it is available.
+    pub fn to_get_request(&self) -> GetRequest {
+        match self.format {
+            BlobFormat::Raw => GetRequest::single(self.hash),
+            BlobFormat::HashSeq => GetRequest::all(self.hash),
+        }
+    }
+}
+
+/// The response to a [`HaveRequest`]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct HaveResponse {
+    /// The ranges the provider has
+    ///
+    /// The first element is the root blob, all subsequent elements are children.
+    pub ranges: RangeSpecSeq,
+}
+
+/// A request to store a blob or collection
+///
+/// See the [module documentation](self) for how the data is transferred.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct PushRequest {
+    /// blake3 hash
+    pub hash: Hash,
+    /// The format of the data, if this is [`BlobFormat::HashSeq`] all children are
+    /// pushed as well.
+    pub format: BlobFormat,
+}
+
+impl PushRequest {
+    /// The size of a [`Request::Push`] on the wire: the variant, the hash and the format.
+    pub const REQUEST_LEN: usize = 1 + 32 + 1;
+
+    /// The postcard variant of [`Request::Push`].
+    pub(crate) const VARIANT: u8 = 1;
+
+    /// Request to store a blob or collection
+    pub fn new(hash: Hash, format: BlobFormat) -> Self {
+        Self { hash, format }
+    }
 }
 
 /// A request
@@ -442,6 +551,16 @@ pub enum Closed {
     /// Only a single request is allowed on a stream, if more data is received after this a
     /// provider may send this error code in a STOP_STREAM frame.
     RequestReceived = 2,
+    /// The provider refused to serve the request.
+    ///
+    /// Sent by a provider when resetting the stream of a request that was denied by its
+    /// [`RequestAuthorizationHandler`](crate::provider::RequestAuthorizationHandler), or
+    /// of a push request that was refused by its [`PushPolicy`](crate::provider::PushPolicy).
+    Unauthorized = 3,
+    /// The receiver of a push request failed to store the data.
+    PushFailed = 4,
+    /// The response to a have request would be too large.
+    TooLarge = 5,
 }
 
 impl Closed {
@@ -451,6 +570,9 @@ impl Closed {
             Closed::StreamDropped => b"stream dropped",
             Closed::ProviderTerminating => b"provider terminating",
             Closed::RequestReceived => b"request received",
+            Closed::Unauthorized => b"unauthorized",
+            Closed::PushFailed => b"push failed",
+            Closed::TooLarge => b"too large",
         }
     }
 }
@@ -474,6 +596,9 @@ impl TryFrom<VarInt> for Closed {
             0 => Ok(Self::StreamDropped),
             1 => Ok(Self::ProviderTerminating),
             2 => Ok(Self::RequestReceived),
+            3 => Ok(Self::Unauthorized),
+            4 => Ok(Self::PushFailed),
+            5 => Ok(Self::TooLarge),
             val => Err(UnknownErrorCode(val)),
         }
     }
@@ -483,7 +608,8 @@ impl TryFrom<VarInt> for Closed {
 mod tests {
     use iroh_test::{assert_eq_hex, hexdump::parse_hexdump};
 
-    use super::{GetRequest, Request};
+    use super::{GetRequest, HaveRequest, PushRequest, Request};
+    use crate::BlobFormat;
 
     #[test]
     fn request_wire_format() {
@@ -505,10 +631,31 @@ mod tests {
                     01000100 # the RangeSpecSeq
             ",
             ),
+            (
+                Request::from(PushRequest::new(hash, BlobFormat::HashSeq)),
+                r"
+                    01 # enum variant for PushRequest
+                    dadadadadadadadadadadadadadadadadadadadadadadadadadadadadadadada # the hash
+                    01 # the format
+            ",
+            ),
+            (
+                Request::from(HaveRequest::new(hash, BlobFormat::Raw)),
+                r"
+                    02 # enum variant for HaveRequest
+                    dadadadadadadadadadadadadadadadadadadadadadadadadadadadadadadada # the hash
+                    00 # the format
+            ",
+            ),
         ];
         for (case, expected_hex) in cases {
             let expected = parse_hexdump(expected_hex).unwrap();
             let bytes = postcard::to_stdvec(&case).unwrap();
+            // the data of a push follows the request, so the request has a fixed size
+            if let Request::Push(_) = case {
+                assert_eq!(bytes.len(), PushRequest::REQUEST_LEN);
+                assert_eq!(bytes[0], PushRequest::VARIANT);
+            }
             assert_eq_hex!(bytes, expected);
         }
     }
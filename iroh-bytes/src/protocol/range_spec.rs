@@ -5,7 +5,7 @@
 //!
 //! The [`RangeSpecSeq`] builds on top of this to select blob chunks in an entire
 //! collection.
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 use bao_tree::{ChunkNum, ChunkRanges, ChunkRangesRef};
 use serde::{Deserialize, Serialize};
@@ -116,6 +116,53 @@ impl fmt::Debug for RangeSpec {
     }
 }
 
+/// The text form of a [`RangeSpec`] is its offsets separated by commas, e.g. `2,5,3,1`.
+///
+/// The entire blob is `0`, and the empty selection is the empty string. The text form is
+/// canonical, so it can be used in URLs that are cached by their text.
+impl fmt::Display for RangeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, offset) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{offset}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error when parsing the text form of a [`RangeSpec`].
+#[derive(Debug, thiserror::Error)]
+pub enum RangeSpecParseError {
+    /// An offset is not a number.
+    #[error("invalid offset: {0}")]
+    Offset(#[from] std::num::ParseIntError),
+    /// The offsets contain empty spans, so they are not in canonical form.
+    #[error("range spec is not canonical")]
+    NotCanonical,
+}
+
+impl FromStr for RangeSpec {
+    type Err = RangeSpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self::EMPTY);
+        }
+        let offsets = s
+            .split(',')
+            .map(|offset| offset.parse())
+            .collect::<Result<SmallVec<_>, _>>()?;
+        let spec = Self(offsets);
+        // only the first span may be empty
+        if spec.0.iter().skip(1).any(|&width| width == 0) {
+            return Err(RangeSpecParseError::NotCanonical);
+        }
+        Ok(spec)
+    }
+}
+
 /// A chunk range specification for a sequence of blobs.
 ///
 /// To select chunks in a sequence of blobs this is encoded as a sequence of `(blob_offset,
@@ -146,7 +193,7 @@ impl fmt::Debug for RangeSpec {
 ///
 /// This is a smallvec so that we can avoid allocations in the common case of a single child
 /// range.
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Hash, Default)]
 #[repr(transparent)]
 pub struct RangeSpecSeq(SmallVec<[(u64, RangeSpec); 2]>);
 
@@ -248,6 +295,32 @@ impl RangeSpecSeq {
     pub fn iter_non_empty(&self) -> NonEmptyRequestRangeSpecIter<'_> {
         NonEmptyRequestRangeSpecIter::new(self.iter())
     }
+
+    /// Creates a [`RangeSpecSeq`] selecting all chunks that are selected by either `self` or
+    /// `other`.
+    pub fn union(&self, other: &RangeSpecSeq) -> RangeSpecSeq {
+        let mut a = self.iter();
+        let mut b = other.iter();
+        let mut res = Vec::new();
+        loop {
+            // once both iterators are at the end, they repeat their last value forever,
+            // so one more element is enough to describe the infinite tail.
+            let done = a.is_at_end() && b.is_at_end();
+            // unwrapping is safe because the iterators never terminate
+            let x = a.next().unwrap().to_chunk_ranges();
+            let y = b.next().unwrap().to_chunk_ranges();
+            res.push(RangeSpec::new(&x | &y));
+            if done {
+                break;
+            }
+        }
+        Self::new(res)
+    }
+
+    /// Checks if all chunks selected by `self` are also selected by `other`.
+    pub fn is_subset(&self, other: &RangeSpecSeq) -> bool {
+        &self.union(other) == other
+    }
 }
 
 static EMPTY_RANGE_SPEC: RangeSpec = RangeSpec::EMPTY;
@@ -534,6 +607,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn range_spec_seq_union() {
+        let a = RangeSpecSeq::from_ranges(mk_case(vec![0..2, 0..0, 4..8]));
+        let b = RangeSpecSeq::from_ranges(mk_case(vec![1..4, 3..5]));
+        let expected = RangeSpecSeq::from_ranges(mk_case(vec![0..4, 3..5, 4..8]));
+        assert_eq!(a.union(&b), expected);
+        assert!(a.is_subset(&expected));
+        assert!(b.is_subset(&expected));
+        assert!(!expected.is_subset(&a));
+        assert_eq!(a.union(&RangeSpecSeq::all()), RangeSpecSeq::all());
+        assert_eq!(a.union(&RangeSpecSeq::empty()), a);
+    }
+
+    #[test]
+    fn range_spec_text() {
+        assert_eq!(RangeSpec::all().to_string(), "0");
+        assert_eq!(RangeSpec::EMPTY.to_string(), "");
+        let spec = RangeSpec::new(ChunkRanges::from(ChunkNum(2)..ChunkNum(7)));
+        assert_eq!(spec.to_string(), "2,5");
+        assert_eq!("2,5".parse::<RangeSpec>().unwrap(), spec);
+        assert_eq!("".parse::<RangeSpec>().unwrap(), RangeSpec::EMPTY);
+        assert!("2,0,3".parse::<RangeSpec>().is_err());
+        assert!("2,x".parse::<RangeSpec>().is_err());
+    }
+
     proptest! {
         #[test]
         fn range_spec_roundtrip(ranges in ranges(0..1000)) {
@@ -542,6 +640,13 @@ mod tests {
             prop_assert_eq!(ranges, ranges2);
         }
 
+        #[test]
+        fn range_spec_text_roundtrip(ranges in ranges(0..1000)) {
+            let spec = RangeSpec::new(&ranges);
+            let spec2 = spec.to_string().parse::<RangeSpec>().unwrap();
+            prop_assert_eq!(spec, spec2);
+        }
+
         #[test]
         fn range_spec_seq_roundtrip(ranges in proptest::collection::vec(ranges(0..100), 0..10)) {
             let expected = ranges.clone();
@@ -1,26 +1,43 @@
 //! The server side API
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use bao_tree::io::fsm::{encode_ranges_validated, Outboard};
 use bao_tree::io::EncodeError;
 use futures_lite::future::Boxed as BoxFuture;
+use iroh_base::key::NodeId;
 use iroh_base::rpc::RpcError;
 use iroh_io::stats::{
     SliceReaderStats, StreamWriterStats, TrackingSliceReader, TrackingStreamWriter,
 };
 use iroh_io::{AsyncSliceReader, AsyncStreamWriter, TokioStreamWriter};
+#[cfg(feature = "metrics")]
+use iroh_metrics::inc_by;
+#[cfg(feature = "net")]
+use iroh_net::magic_endpoint::get_remote_node_id;
 use serde::{Deserialize, Serialize};
 use tokio_util::task::LocalPoolHandle;
 use tracing::{debug, debug_span, info, trace, warn};
 use tracing_futures::Instrument;
 
+use crate::get::db::{entry_valid_ranges, get_pushed_to_db};
 use crate::hashseq::parse_hash_seq;
-use crate::protocol::{GetRequest, RangeSpec, Request};
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::protocol::{
+    Closed, GetRequest, HaveRequest, HaveResponse, PushRequest, RangeSpec, RangeSpecSeq, Request,
+    MAX_HAVE_CHILDREN, MAX_MESSAGE_SIZE,
+};
 use crate::store::*;
+use crate::util::progress::IgnoreProgressSender;
 use crate::util::Tag;
-use crate::{BlobFormat, Hash};
+use crate::{BlobFormat, Hash, HashAndFormat};
+
+mod rate_limit;
+pub use rate_limit::{RateLimiter, RateLimits};
+use rate_limit::{Throttle, ThrottledWriter};
 
 /// Events emitted by the provider informing about the current status.
 #[derive(Debug, Clone)]
@@ -57,6 +74,28 @@ pub enum Event {
         /// The size of the custom get request.
         len: usize,
     },
+    /// A push request was received from a client.
+    PushRequestReceived {
+        /// An unique connection id.
+        connection_id: u64,
+        /// An identifier uniquely identifying this push request.
+        request_id: u64,
+        /// The hash of the data the client wants to push.
+        hash: Hash,
+        /// The format of the data the client wants to push.
+        format: BlobFormat,
+    },
+    /// A have request was received from a client.
+    HaveRequestReceived {
+        /// An unique connection id.
+        connection_id: u64,
+        /// An identifier uniquely identifying this have request.
+        request_id: u64,
+        /// The hash the client wants to know the available ranges of.
+        hash: Hash,
+        /// The format the client is asking about.
+        format: BlobFormat,
+    },
     /// A sequence of hashes has been found and is being transferred.
     TransferHashSeqStarted {
         /// An unique connection id.
@@ -109,6 +148,10 @@ pub struct TransferStats {
     pub read: SliceReaderStats,
     /// The total duration of the transfer.
     pub duration: Duration,
+    /// The time spent waiting for the upload rate limits.
+    ///
+    /// This is included in the duration of the transfer and of sending to the client.
+    pub throttled: Duration,
 }
 
 /// Progress updates for the add operation.
@@ -137,6 +180,21 @@ pub enum AddProgress {
         /// The hash of the entry.
         hash: Hash,
     },
+    /// We are done splitting item `id` into chunks.
+    ///
+    /// This is only sent for chunked imports, before `Done`.
+    ChunkingDone {
+        /// The unique id of the entry.
+        id: u64,
+        /// The number of chunks.
+        chunks: u64,
+        /// The size of the entry in bytes.
+        size: u64,
+        /// The size of the chunks that were not in the store before, in bytes.
+        ///
+        /// The dedup ratio is `size / stored`.
+        stored: u64,
+    },
     /// We are done with the whole operation.
     AllDone {
         /// The hash of the created data.
@@ -157,11 +215,19 @@ pub enum AddProgress {
 /// Will fail if there is an error while reading, if the reader
 /// contains more data than the Request, or if no valid request is sent.
 ///
-/// When successful, the buffer is empty after this function call.
-pub async fn read_request(mut reader: quinn::RecvStream) -> Result<Request> {
-    let payload = reader
-        .read_to_end(crate::protocol::MAX_MESSAGE_SIZE)
-        .await?;
+/// When successful, the buffer is empty after this function call, except for a push request,
+/// which is followed by the pushed data.
+pub async fn read_request(reader: &mut quinn::RecvStream) -> Result<Request> {
+    let mut variant = [0u8];
+    reader.read_exact(&mut variant).await?;
+    let mut payload = variant.to_vec();
+    if variant[0] == PushRequest::VARIANT {
+        payload.resize(PushRequest::REQUEST_LEN, 0);
+        reader.read_exact(&mut payload[1..]).await?;
+    } else {
+        let rest = reader.read_to_end(MAX_MESSAGE_SIZE - 1).await?;
+        payload.extend_from_slice(&rest);
+    }
     let request: Request = postcard::from_bytes(&payload)?;
     Ok(request)
 }
@@ -227,6 +293,7 @@ pub async fn transfer_collection<D: Map, E: EventSender>(
                 &mut tw,
             )
             .await?;
+            db.record_served(&hash).await?;
             stats.read += tracking_reader.stats();
             stats.send += tw.stats();
             debug!(
@@ -278,11 +345,39 @@ pub trait EventSender: Clone + Sync + Send + 'static {
     fn send(&self, event: Event) -> BoxFuture<()>;
 }
 
+/// Trait for deciding whether a get request is served.
+pub trait RequestAuthorizationHandler: Send + Sync + Debug + 'static {
+    /// Decide whether the node `node_id` may perform `request`.
+    ///
+    /// Returning an error denies the request: the provider resets the response stream with
+    /// [`Closed::Unauthorized`] and does not send any data.
+    fn authorize(&self, node_id: NodeId, request: &GetRequest) -> BoxFuture<Result<()>>;
+}
+
+/// Trait for deciding whether a push request is accepted.
+pub trait PushPolicy: Send + Sync + Debug + 'static {
+    /// Decide whether the data of `request` from the node `node_id` is stored.
+    ///
+    /// Returning an error refuses the push: the receiver resets the stream of the push request
+    /// with [`Closed::Unauthorized`] and does not ask for any data.
+    fn accept(&self, node_id: NodeId, request: &PushRequest) -> BoxFuture<Result<()>>;
+}
+
 /// Handle a single connection.
-pub async fn handle_connection<D: Map, E: EventSender>(
+///
+/// If an `authorization_handler` is given, every get request is checked with it before it
+/// is served. Otherwise all requests are served.
+///
+/// Push requests are only accepted if a `push_policy` is given and it accepts them.
+///
+/// All data sent on the connection is subject to the limits of `rate_limiter`.
+pub async fn handle_connection<D: Store, E: EventSender>(
     connecting: quinn::Connecting,
     db: D,
     events: E,
+    authorization_handler: Option<Arc<dyn RequestAuthorizationHandler>>,
+    push_policy: Option<Arc<dyn PushPolicy>>,
+    rate_limiter: RateLimiter,
     rt: LocalPoolHandle,
 ) {
     let remote_addr = connecting.remote_address();
@@ -293,43 +388,89 @@ pub async fn handle_connection<D: Map, E: EventSender>(
             return;
         }
     };
+    let node_id = match get_remote_node_id(&connection) {
+        Ok(node_id) => Some(node_id),
+        // requests can only be authorized if we know who is asking
+        Err(err) if authorization_handler.is_some() => {
+            warn!(%remote_addr, "Failed to get remote node id: {err:#}");
+            return;
+        }
+        Err(_) => None,
+    };
     let connection_id = connection.stable_id() as u64;
     let span = debug_span!("connection", connection_id, %remote_addr);
-    async move {
-        while let Ok((writer, reader)) = connection.accept_bi().await {
-            // The stream ID index is used to identify this request.  Requests only arrive in
-            // bi-directional RecvStreams initiated by the client, so this uniquely identifies them.
-            let request_id = reader.id().index();
-            let span = debug_span!("stream", stream_id = %request_id);
-            let writer = ResponseWriter {
-                connection_id,
-                events: events.clone(),
-                inner: writer,
-            };
-            events.send(Event::ClientConnected { connection_id }).await;
-            let db = db.clone();
-            rt.spawn_pinned(|| {
-                async move {
-                    if let Err(err) = handle_stream(db, reader, writer).await {
-                        warn!("error: {err:#?}",);
-                    }
+    let context = ConnectionContext {
+        connection,
+        node_id,
+        authorization_handler,
+        push_policy,
+    };
+    serve_requests(context, db, events, rate_limiter, rt)
+        .instrument(span)
+        .await
+}
+
+/// Get the node id of the remote side of a connection.
+///
+/// Without the `net` feature the node id is unknown, so requests can not be authorized.
+#[cfg(not(feature = "net"))]
+fn get_remote_node_id(_connection: &quinn::Connection) -> Result<NodeId> {
+    anyhow::bail!("node ids are only known with the `net` feature")
+}
+
+/// The connection requests arrive on, and the handlers deciding which of them are served.
+#[derive(Debug, Clone)]
+struct ConnectionContext {
+    connection: quinn::Connection,
+    node_id: Option<NodeId>,
+    authorization_handler: Option<Arc<dyn RequestAuthorizationHandler>>,
+    push_policy: Option<Arc<dyn PushPolicy>>,
+}
+
+/// Serve the requests arriving on a connection until it is closed.
+async fn serve_requests<D: Store, E: EventSender>(
+    context: ConnectionContext,
+    db: D,
+    events: E,
+    rate_limiter: RateLimiter,
+    rt: LocalPoolHandle,
+) {
+    let connection_id = context.connection.stable_id() as u64;
+    let node_id = context.node_id;
+    while let Ok((writer, reader)) = context.connection.accept_bi().await {
+        // The stream ID index is used to identify this request.  Requests only arrive in
+        // bi-directional RecvStreams initiated by the client, so this uniquely identifies them.
+        let request_id = reader.id().index();
+        let span = debug_span!("stream", stream_id = %request_id);
+        let writer = ResponseWriter {
+            connection_id,
+            events: events.clone(),
+            inner: writer,
+            throttle: Throttle::new(rate_limiter.clone(), node_id),
+        };
+        events.send(Event::ClientConnected { connection_id }).await;
+        let db = db.clone();
+        let context = context.clone();
+        rt.spawn_pinned(|| {
+            async move {
+                if let Err(err) = handle_stream(db, context, reader, writer).await {
+                    warn!("error: {err:#?}",);
                 }
-                .instrument(span)
-            });
-        }
+            }
+            .instrument(span)
+        });
     }
-    .instrument(span)
-    .await
 }
 
-async fn handle_stream<D: Map, E: EventSender>(
+async fn handle_stream<D: Store, E: EventSender>(
     db: D,
-    reader: quinn::RecvStream,
-    writer: ResponseWriter<E>,
+    context: ConnectionContext,
+    mut reader: quinn::RecvStream,
+    mut writer: ResponseWriter<E>,
 ) -> Result<()> {
     // 1. Decode the request.
     debug!("reading request");
-    let request = match read_request(reader).await {
+    let request = match read_request(&mut reader).await {
         Ok(r) => r,
         Err(e) => {
             writer.notify_transfer_aborted(None).await;
@@ -337,8 +478,238 @@ async fn handle_stream<D: Map, E: EventSender>(
         }
     };
 
+    // 2. Authorize the request.
+    let authorized = match (&request, context.node_id) {
+        (Request::Get(request), Some(node_id)) => match &context.authorization_handler {
+            Some(handler) => handler.authorize(node_id, request).await,
+            None => Ok(()),
+        },
+        (Request::Get(_), None) => Ok(()),
+        (Request::Have(request), Some(node_id)) => match &context.authorization_handler {
+            Some(handler) => handler.authorize(node_id, &request.to_get_request()).await,
+            None => Ok(()),
+        },
+        (Request::Have(_), None) => Ok(()),
+        (Request::Push(request), Some(node_id)) => match &context.push_policy {
+            Some(policy) => policy.accept(node_id, request).await,
+            None => Err(anyhow::anyhow!("pushes are not accepted")),
+        },
+        (Request::Push(_), None) => Err(anyhow::anyhow!("pushes need a known node id")),
+    };
+    if let Err(err) = authorized {
+        debug!(node = ?context.node_id, "request denied: {err:#}");
+        writer.notify_transfer_aborted(None).await;
+        writer.inner.reset(Closed::Unauthorized.into()).ok();
+        return Ok(());
+    }
+
     match request {
         Request::Get(request) => handle_get(db, request, writer).await,
+        Request::Push(request) => handle_push(db, reader, request, writer).await,
+        Request::Have(request) => handle_have(db, request, writer).await,
+    }
+}
+
+/// Handle a single have request.
+///
+/// Responds with the ranges of the requested data that are in the store.
+pub async fn handle_have<D: Map, E: EventSender>(
+    db: D,
+    request: HaveRequest,
+    mut writer: ResponseWriter<E>,
+) -> Result<()> {
+    let HaveRequest { hash, format } = request;
+    debug!(%hash, ?format, "received have request");
+    writer
+        .events
+        .send(Event::HaveRequestReceived {
+            hash,
+            format,
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+        })
+        .await;
+    let response = available_ranges(&db, &hash, format)
+        .await?
+        .map(|ranges| postcard::to_stdvec(&HaveResponse { ranges }))
+        .transpose()?
+        .filter(|response| response.len() <= MAX_MESSAGE_SIZE);
+    let Some(response) = response else {
+        debug!(%hash, "have response too large");
+        writer.inner.reset(Closed::TooLarge.into()).ok();
+        return Ok(());
+    };
+    writer.inner.write_all(&response).await?;
+    writer.inner.finish().await?;
+    Ok(())
+}
+
+/// Get the ranges of a blob or collection that are in the store.
+///
+/// Returns `None` if the hash seq has more than [`MAX_HAVE_CHILDREN`] children.
+async fn available_ranges<D: Map>(
+    db: &D,
+    hash: &Hash,
+    format: BlobFormat,
+) -> Result<Option<RangeSpecSeq>> {
+    let Some(entry) = db.get(hash).await? else {
+        return Ok(Some(RangeSpecSeq::empty()));
+    };
+    let root = entry_ranges(&entry).await?;
+    let mut specs = vec![root];
+    // the children can only be listed if the hash seq is complete
+    if format.is_hash_seq() && entry.is_complete() {
+        if entry.size().value() / 32 > MAX_HAVE_CHILDREN {
+            return Ok(None);
+        }
+        let (mut children, _) = parse_hash_seq(entry.data_reader().await?).await?;
+        while let Some(child) = children.next().await? {
+            let spec = match db.get(&child).await? {
+                Some(entry) => entry_ranges(&entry).await?,
+                None => RangeSpec::EMPTY,
+            };
+            specs.push(spec);
+        }
+    }
+    specs.push(RangeSpec::EMPTY);
+    Ok(Some(RangeSpecSeq::new(specs)))
+}
+
+/// Get the ranges of a single entry, all ranges if it is complete.
+async fn entry_ranges(entry: &impl MapEntry) -> Result<RangeSpec> {
+    if entry.is_complete() {
+        return Ok(RangeSpec::all());
+    }
+    Ok(RangeSpec::new(entry_valid_ranges(entry).await?))
+}
+
+/// Handle a single push request.
+///
+/// Asks the requester for the data that is missing, verifies the pushed data while storing it
+/// and tags it once it is complete. The tag is `pushed-` followed by the [`HashAndFormat`] of
+/// the data, so each content is tagged once, no matter how often it is pushed.
+pub async fn handle_push<D: Store, E: EventSender>(
+    db: D,
+    reader: quinn::RecvStream,
+    request: PushRequest,
+    mut writer: ResponseWriter<E>,
+) -> Result<()> {
+    let PushRequest { hash, format } = request;
+    debug!(%hash, ?format, "received push request");
+    writer
+        .events
+        .send(Event::PushRequestReceived {
+            hash,
+            format,
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+        })
+        .await;
+
+    let content = HashAndFormat { hash, format };
+    // protect the data from gc until it is tagged
+    let _temp_tag = db.temp_tag(content);
+    let stream = &mut writer.inner;
+    let send_request = |request: GetRequest| async move {
+        let request = postcard::to_stdvec(&request).map_err(std::io::Error::other)?;
+        // the stream stays open after the request, so it needs a length prefix
+        let len = u32::try_from(request.len()).map_err(std::io::Error::other)?;
+        stream.write_all(&len.to_le_bytes()).await?;
+        stream.write_all(&request).await?;
+        Ok(())
+    };
+    let res = get_pushed_to_db(
+        &db,
+        send_request,
+        reader,
+        &content,
+        IgnoreProgressSender::default(),
+    )
+    .await;
+    if let Err(err) = res {
+        writer.inner.reset(Closed::PushFailed.into()).ok();
+        return Err(err.into());
+    }
+    // the tag is named after the content, so pushing the same data again reuses it
+    let tag = Tag::from(format!("pushed-{content}"));
+    db.set_tag(tag.clone(), Some(content)).await?;
+    debug!(%hash, %tag, "push completed");
+    writer
+        .events
+        .send(Event::TaggedBlobAdded { hash, format, tag })
+        .await;
+    writer.inner.finish().await?;
+    Ok(())
+}
+
+/// Push a blob or collection to the node at the other end of `connection`.
+///
+/// This sends a [`PushRequest`] and then sends the bao encoded data the receiver asks for on
+/// the same stream, like the response to a get request. It returns once the receiver has
+/// verified and stored all of the data.
+pub async fn push<D: Map, E: EventSender>(
+    connection: quinn::Connection,
+    db: D,
+    request: PushRequest,
+    events: E,
+    rate_limiter: RateLimiter,
+) -> Result<()> {
+    let node_id = get_remote_node_id(&connection)?;
+    let hash = request.hash;
+    db.get(&hash)
+        .await?
+        .filter(|entry| entry.is_complete())
+        .with_context(|| format!("{hash} is not complete in the store"))?;
+    let (writer, mut reader) = connection.open_bi().await?;
+    let mut writer = ResponseWriter {
+        connection_id: connection.stable_id() as u64,
+        events,
+        inner: writer,
+        throttle: Throttle::new(rate_limiter, Some(node_id)),
+    };
+    let request_bytes = postcard::to_stdvec(&Request::Push(request))?;
+    writer.inner.write_all(&request_bytes).await?;
+
+    // the receiver asks for the data it is missing, or finishes the stream if it has all of it
+    let mut len = [0u8; 4];
+    let sent = match reader.read_exact(&mut len).await {
+        Ok(()) => {
+            let len = u32::from_le_bytes(len) as usize;
+            anyhow::ensure!(len <= MAX_MESSAGE_SIZE, "request of the receiver too big");
+            let mut request = vec![0u8; len];
+            reader
+                .read_exact(&mut request)
+                .await
+                .map_err(|err| match err {
+                    quinn::ReadExactError::ReadError(err) => push_error(node_id, err),
+                    err => err.into(),
+                })?;
+            let request: GetRequest = postcard::from_bytes(&request)?;
+            anyhow::ensure!(request.hash == hash, "receiver asked for other data");
+            handle_get(db, request, writer).await
+        }
+        Err(quinn::ReadExactError::FinishedEarly) => Ok(()),
+        Err(quinn::ReadExactError::ReadError(err)) => return Err(push_error(node_id, err)),
+    };
+    // the receiver finishes the stream once it has stored the data, this also tells why
+    // sending failed if the receiver gave up
+    match reader.read_to_end(0).await {
+        Ok(_) => sent,
+        Err(quinn::ReadToEndError::Read(err)) => Err(push_error(node_id, err)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Describe why the receiver of a push reset the stream.
+fn push_error(node_id: NodeId, err: quinn::ReadError) -> anyhow::Error {
+    match err {
+        quinn::ReadError::Reset(code) if code == Closed::Unauthorized.into() => {
+            anyhow::anyhow!("push refused by {}", node_id.fmt_short())
+        }
+        quinn::ReadError::Reset(code) if code == Closed::PushFailed.into() => {
+            anyhow::anyhow!("{} failed to store the pushed data", node_id.fmt_short())
+        }
+        err => err.into(),
     }
 }
 
@@ -376,6 +747,7 @@ pub async fn handle_get<D: Map, E: EventSender>(
             )
             .await;
             stats.duration = t0.elapsed();
+            stats.throttled = writer.throttle.waited();
             match res {
                 Ok(SentStatus::Sent) => {
                     writer.notify_transfer_completed(&hash, stats).await;
@@ -407,13 +779,17 @@ pub struct ResponseWriter<E> {
     inner: quinn::SendStream,
     events: E,
     connection_id: u64,
+    throttle: Throttle,
 }
 
 impl<E: EventSender> ResponseWriter<E> {
     fn tracking_writer(
         &mut self,
-    ) -> TrackingStreamWriter<TokioStreamWriter<&mut quinn::SendStream>> {
-        TrackingStreamWriter::new(TokioStreamWriter(&mut self.inner))
+    ) -> TrackingStreamWriter<ThrottledWriter<TokioStreamWriter<&mut quinn::SendStream>>> {
+        TrackingStreamWriter::new(ThrottledWriter::new(
+            TokioStreamWriter(&mut self.inner),
+            self.throttle.clone(),
+        ))
     }
 
     fn connection_id(&self) -> u64 {
@@ -441,8 +817,9 @@ impl<E: EventSender> ResponseWriter<E> {
             total_duration.as_secs_f64()
         );
         debug!(
-            "{}s sending, {}s reading, {}s other",
+            "{}s sending ({}s throttled), {}s reading, {}s other",
             send_duration.as_secs_f64(),
+            stats.throttled.as_secs_f64(),
             read_duration.as_secs_f64(),
             other_duration.as_secs_f64()
         );
@@ -453,9 +830,21 @@ impl<E: EventSender> ResponseWriter<E> {
         )
     }
 
+    #[cfg(feature = "metrics")]
+    fn record_metrics(stats: &TransferStats) {
+        inc_by!(Metrics, upload_bytes_total, stats.send.total().size);
+        inc_by!(
+            Metrics,
+            upload_throttle_time_total,
+            stats.throttled.as_millis() as u64
+        );
+    }
+
     async fn notify_transfer_completed(&self, hash: &Hash, stats: Box<TransferStats>) {
         info!("transfer completed for {}", hash);
         Self::print_stats(&stats);
+        #[cfg(feature = "metrics")]
+        Self::record_metrics(&stats);
         self.events
             .send(Event::TransferCompleted {
                 connection_id: self.connection_id(),
@@ -468,6 +857,8 @@ impl<E: EventSender> ResponseWriter<E> {
     async fn notify_transfer_aborted(&self, stats: Option<Box<TransferStats>>) {
         if let Some(stats) = &stats {
             Self::print_stats(stats);
+            #[cfg(feature = "metrics")]
+            Self::record_metrics(stats);
         };
         self.events
             .send(Event::TransferAborted {
@@ -509,6 +900,7 @@ pub async fn send_blob<D: Map, W: AsyncStreamWriter>(
             )
             .await
             .map_err(|e| encode_error_to_anyhow(e, &hash))?;
+            db.record_served(&hash).await?;
 
             Ok((SentStatus::Sent, size, file_reader.stats()))
         }
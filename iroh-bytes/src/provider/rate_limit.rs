@@ -0,0 +1,282 @@
+//! Limiting the rate at which the provider sends data.
+//!
+//! Every write of a transfer first reserves its size from the budget of the peer it is sent to and
+//! waits until that budget allows it. Only then it reserves its size from the global budget and
+//! waits again, so a peer that is held back by its own limit does not use up the global budget of
+//! the other peers. Reservations are handed out in the order in which they are made. Since
+//! transfers write in small pieces, concurrent transfers take turns instead of the first one using
+//! up all of the bandwidth.
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use iroh_base::key::NodeId;
+use iroh_io::AsyncStreamWriter;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// How far ahead of the limited rate a sender may get after being idle.
+const BURST: Duration = Duration::from_millis(100);
+
+/// Limits for the rate at which the provider sends data, in bytes per second.
+///
+/// The default is to not limit anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Limit for all data sent by the provider.
+    pub global: Option<u64>,
+    /// Limit for the data sent to a single peer.
+    pub per_peer: Option<u64>,
+}
+
+/// Rate limiter shared by all transfers of a provider.
+///
+/// This is cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    global: Bucket,
+    peers: HashMap<NodeId, Bucket>,
+}
+
+/// Schedule of a single budget.
+#[derive(Debug, Default)]
+struct Bucket {
+    /// The time at which all data reserved so far has been sent at the limited rate.
+    ///
+    /// A bucket for which this is in the past behaves like a new bucket.
+    ready_at: Option<Instant>,
+}
+
+impl Bucket {
+    /// Reserve `len` bytes at `rate` bytes per second, returning when they may be sent.
+    fn reserve(&mut self, now: Instant, rate: u64, len: usize) -> Instant {
+        let start = self.ready_at.map_or(now, |t| t.max(now));
+        let ready_at = start + Duration::from_secs_f64(len as f64 / rate.max(1) as f64);
+        self.ready_at = Some(ready_at);
+        ready_at.checked_sub(BURST).unwrap_or(now).max(now)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.ready_at.map_or(true, |t| t <= now)
+    }
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter with the given limits.
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            state: Default::default(),
+        }
+    }
+
+    /// The limits enforced by this rate limiter.
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    /// Reserve `len` bytes from the budget of `peer`, returning when they may be sent.
+    ///
+    /// Data sent to connections without a known peer is only subject to the global limit.
+    fn reserve_peer(&self, peer: Option<NodeId>, len: usize) -> Option<Instant> {
+        let (rate, peer) = self.limits.per_peer.zip(peer)?;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if !state.peers.contains_key(&peer) {
+            // forget about peers that are not sending, they start out fresh anyway
+            state.peers.retain(|_, bucket| !bucket.is_idle(now));
+        }
+        let bucket = state.peers.entry(peer).or_default();
+        Some(bucket.reserve(now, rate, len))
+    }
+
+    /// Reserve `len` bytes from the global budget, returning when they may be sent.
+    fn reserve_global(&self, len: usize) -> Option<Instant> {
+        let rate = self.limits.global?;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        Some(state.global.reserve(now, rate, len))
+    }
+}
+
+/// Rate limiting for the response to a single request.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Throttle {
+    limiter: RateLimiter,
+    peer: Option<NodeId>,
+    /// Total time spent waiting, in microseconds.
+    waited: Arc<AtomicU64>,
+}
+
+impl Throttle {
+    pub fn new(limiter: RateLimiter, peer: Option<NodeId>) -> Self {
+        Self {
+            limiter,
+            peer,
+            waited: Default::default(),
+        }
+    }
+
+    /// Total time spent waiting for the rate limits.
+    pub fn waited(&self) -> Duration {
+        Duration::from_micros(self.waited.load(Ordering::Relaxed))
+    }
+
+    async fn wait(&self, len: usize) {
+        // the global budget is only reserved once the peer budget allows sending, so that
+        // it is not spent on data that is still held back
+        if let Some(send_at) = self.limiter.reserve_peer(self.peer, len) {
+            self.sleep_until(send_at).await;
+        }
+        if let Some(send_at) = self.limiter.reserve_global(len) {
+            self.sleep_until(send_at).await;
+        }
+    }
+
+    async fn sleep_until(&self, send_at: Instant) {
+        let now = Instant::now();
+        if send_at > now {
+            tokio::time::sleep_until(send_at).await;
+            let waited = (send_at - now).as_micros() as u64;
+            self.waited.fetch_add(waited, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A writer that waits for the rate limits before writing.
+#[derive(Debug)]
+pub(super) struct ThrottledWriter<W> {
+    inner: W,
+    throttle: Throttle,
+}
+
+impl<W> ThrottledWriter<W> {
+    pub fn new(inner: W, throttle: Throttle) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<W: AsyncStreamWriter> AsyncStreamWriter for ThrottledWriter<W> {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.throttle.wait(data.len()).await;
+        self.inner.write(data).await
+    }
+
+    async fn write_bytes(&mut self, data: Bytes) -> io::Result<()> {
+        self.throttle.wait(data.len()).await;
+        self.inner.write_bytes(data).await
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        self.inner.sync().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limit() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: Some(1024 * 1024),
+            per_peer: None,
+        });
+        let throttle = Throttle::new(limiter, None);
+        let t0 = Instant::now();
+        for _ in 0..16 {
+            throttle.wait(1024 * 1024 / 8).await;
+        }
+        // 2 MiB at 1 MiB/s, minus the burst
+        let elapsed = t0.elapsed();
+        assert_eq!(elapsed, Duration::from_secs(2) - BURST);
+        assert_eq!(throttle.waited(), elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn per_peer_limit() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: None,
+            per_peer: Some(1024 * 1024),
+        });
+        let a = iroh_base::key::SecretKey::generate().public();
+        let b = iroh_base::key::SecretKey::generate().public();
+        let throttle_a = Throttle::new(limiter.clone(), Some(a));
+        let throttle_b = Throttle::new(limiter.clone(), Some(b));
+        let unknown = Throttle::new(limiter, None);
+        let t0 = Instant::now();
+        let send = |throttle: Throttle| async move {
+            for _ in 0..8 {
+                throttle.wait(1024 * 1024 / 8).await;
+            }
+        };
+        futures_lite::future::zip(send(throttle_a), send(throttle_b)).await;
+        // the peers do not slow each other down
+        assert_eq!(t0.elapsed(), Duration::from_secs(1) - BURST);
+        send(unknown.clone()).await;
+        assert_eq!(unknown.waited(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttled_peer_keeps_global_budget() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: Some(1024 * 1024),
+            per_peer: Some(256 * 1024),
+        });
+        let throttled = Throttle::new(
+            limiter.clone(),
+            Some(iroh_base::key::SecretKey::generate().public()),
+        );
+        // connections without a known peer are only subject to the global limit
+        let unthrottled = Throttle::new(limiter, None);
+        let t0 = Instant::now();
+        let held_back = async {
+            throttled.wait(256 * 1024).await;
+            t0.elapsed()
+        };
+        let other = async {
+            for _ in 0..64 {
+                unthrottled.wait(16 * 1024).await;
+            }
+            t0.elapsed()
+        };
+        let (held_back, other) = futures_lite::future::zip(held_back, other).await;
+        // the throttled peer waits for its own limit
+        assert!(held_back >= Duration::from_secs(1) - BURST, "{held_back:?}");
+        // while it waits, the other peer gets the full global rate
+        assert_eq!(other, Duration::from_secs(1) - BURST);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fair_share() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: Some(1024 * 1024),
+            per_peer: None,
+        });
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let send = |id: usize| {
+            let throttle = Throttle::new(limiter.clone(), None);
+            let sent = sent.clone();
+            async move {
+                for _ in 0..32 {
+                    throttle.wait(16 * 1024).await;
+                    sent.lock().unwrap().push(id);
+                }
+            }
+        };
+        futures_lite::future::zip(send(0), send(1)).await;
+        // once the first transfer has used up the burst, the transfers take turns
+        let sent = sent.lock().unwrap();
+        let shared = &sent[8..48];
+        assert!(shared.windows(2).all(|w| w[0] != w[1]), "{sent:?}");
+    }
+}
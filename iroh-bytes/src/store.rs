@@ -6,10 +6,14 @@ mod bao_file;
 pub mod mem;
 mod mutable_mem_storage;
 pub mod readonly_mem;
+pub mod tiered;
 
 #[cfg(feature = "fs-store")]
 pub mod fs;
 
+#[cfg(feature = "object-store")]
+pub mod object;
+
 mod traits;
 pub use traits::*;
 
@@ -30,7 +30,10 @@ use derive_more::Debug;
 use iroh_io::AsyncSliceReader;
 
 use crate::{
-    store::BaoBatchWriter,
+    store::{
+        fs::encryption::{CryptFile, EncryptionKey},
+        BaoBatchWriter,
+    },
     util::{get_limited_slice, MemOrFile, SparseMemFile},
     IROH_BLOCK_SIZE,
 };
@@ -87,10 +90,10 @@ struct DataPaths {
 pub struct CompleteStorage {
     /// data part, which can be in memory or on disk.
     #[debug("{:?}", data.as_ref().map_mem(|x| x.len()))]
-    pub data: MemOrFile<Bytes, (File, u64)>,
+    pub data: MemOrFile<Bytes, (CryptFile, u64)>,
     /// outboard part, which can be in memory or on disk.
     #[debug("{:?}", outboard.as_ref().map_mem(|x| x.len()))]
-    pub outboard: MemOrFile<Bytes, (File, u64)>,
+    pub outboard: MemOrFile<Bytes, (CryptFile, u64)>,
 }
 
 impl CompleteStorage {
@@ -179,14 +182,14 @@ fn max_offset(batch: &[BaoContentItem]) -> u64 {
 /// A file storage for an incomplete bao file.
 #[derive(Debug)]
 pub struct FileStorage {
-    data: std::fs::File,
-    outboard: std::fs::File,
+    data: CryptFile,
+    outboard: CryptFile,
     sizes: std::fs::File,
 }
 
 impl FileStorage {
     /// Split into data, outboard and sizes files.
-    pub fn into_parts(self) -> (File, File, File) {
+    pub fn into_parts(self) -> (CryptFile, CryptFile, File) {
         (self.data, self.outboard, self.sizes)
     }
 
@@ -353,15 +356,23 @@ pub struct BaoFileConfig {
     /// Todo: make this async.
     #[debug("{:?}", on_file_create.as_ref().map(|_| ()))]
     on_file_create: Option<CreateCb>,
+    /// Key to encrypt the data and outboard files with.
+    encryption: Option<EncryptionKey>,
 }
 
 impl BaoFileConfig {
     /// Create a new deferred batch writer configuration.
-    pub fn new(dir: Arc<PathBuf>, max_mem: usize, on_file_create: Option<CreateCb>) -> Self {
+    pub fn new(
+        dir: Arc<PathBuf>,
+        max_mem: usize,
+        on_file_create: Option<CreateCb>,
+        encryption: Option<EncryptionKey>,
+    ) -> Self {
         Self {
             dir,
             max_mem,
             on_file_create,
+            encryption,
         }
     }
 
@@ -373,6 +384,25 @@ impl BaoFileConfig {
             sizes: self.dir.join(format!("{}.sizes4", hash.to_hex())),
         }
     }
+
+    /// Create or open the files for a hash.
+    ///
+    /// This allocates 3 pathbufs, so we do it only when we need to.
+    fn create_files(&self, hash: &Hash) -> io::Result<FileStorage> {
+        let paths = self.paths(hash);
+        let key = self.encryption.as_ref();
+        Ok(FileStorage {
+            data: CryptFile::new(
+                create_read_write(&paths.data)?,
+                key.map(|key| key.data_keystream(hash)),
+            ),
+            outboard: CryptFile::new(
+                create_read_write(&paths.outboard)?,
+                key.map(|key| key.outboard_keystream(hash)),
+            ),
+            sizes: create_read_write(&paths.sizes)?,
+        })
+    }
 }
 
 /// A reader for a bao file, reading just the data.
@@ -494,12 +524,7 @@ impl BaoFileHandle {
 
     /// Create a new bao file handle with a partial file.
     pub fn incomplete_file(config: Arc<BaoFileConfig>, hash: Hash) -> io::Result<Self> {
-        let paths = config.paths(&hash);
-        let storage = BaoFileStorage::IncompleteFile(FileStorage {
-            data: create_read_write(&paths.data)?,
-            outboard: create_read_write(&paths.outboard)?,
-            sizes: create_read_write(&paths.sizes)?,
-        });
+        let storage = BaoFileStorage::IncompleteFile(config.create_files(&hash)?);
         Ok(Self(Arc::new(BaoFileHandleInner {
             storage: RwLock::new(storage),
             config,
@@ -511,8 +536,8 @@ impl BaoFileHandle {
     pub fn new_complete(
         config: Arc<BaoFileConfig>,
         hash: Hash,
-        data: MemOrFile<Bytes, (File, u64)>,
-        outboard: MemOrFile<Bytes, (File, u64)>,
+        data: MemOrFile<Bytes, (CryptFile, u64)>,
+        outboard: MemOrFile<Bytes, (CryptFile, u64)>,
     ) -> Self {
         let storage = BaoFileStorage::Complete(CompleteStorage { data, outboard });
         Self(Arc::new(BaoFileHandleInner {
@@ -600,14 +625,12 @@ impl BaoFileHandle {
                     mem.write_batch(size, batch)?;
                     Ok(HandleChange::None)
                 } else {
-                    // create the paths. This allocates 3 pathbufs, so we do it
-                    // only when we need to.
-                    let paths = self.config.paths(&self.hash);
+                    let files = self.config.create_files(&self.hash)?;
                     // *first* switch to file mode, *then* write the batch.
                     //
                     // otherwise we might allocate a lot of memory if we get
                     // a write at the end of a very large file.
-                    let mut file_batch = mem.persist(paths)?;
+                    let mut file_batch = mem.persist(files)?;
                     file_batch.write_batch(size, batch)?;
                     *storage = BaoFileStorage::IncompleteFile(file_batch);
                     Ok(HandleChange::MemToFile)
@@ -649,22 +672,15 @@ impl SizeInfo {
 }
 
 impl MutableMemStorage {
-    /// Persist the batch to disk, creating a FileBatch.
-    fn persist(&self, paths: DataPaths) -> io::Result<FileStorage> {
-        let mut data = create_read_write(&paths.data)?;
-        let mut outboard = create_read_write(&paths.outboard)?;
-        let mut sizes = create_read_write(&paths.sizes)?;
-        self.data.persist(&mut data)?;
-        self.outboard.persist(&mut outboard)?;
-        self.sizes.persist(&mut sizes)?;
-        data.sync_all()?;
-        outboard.sync_all()?;
-        sizes.sync_all()?;
-        Ok(FileStorage {
-            data,
-            outboard,
-            sizes,
-        })
+    /// Persist the batch to disk, into the given files.
+    fn persist(&self, mut files: FileStorage) -> io::Result<FileStorage> {
+        self.data.persist(&mut files.data)?;
+        self.outboard.persist(&mut files.outboard)?;
+        self.sizes.persist(&mut files.sizes)?;
+        files.data.sync_all()?;
+        files.outboard.sync_all()?;
+        files.sizes.sync_all()?;
+        Ok(files)
     }
 
     /// Get the parts data, outboard and sizes
@@ -895,6 +911,7 @@ mod tests {
                     Arc::new(temp_dir.as_ref().to_owned()),
                     1024 * 16,
                     None,
+                    None,
                 )),
                 hash.into(),
             );
@@ -955,6 +972,7 @@ mod tests {
                 Arc::new(temp_dir.as_ref().to_owned()),
                 1024 * 16,
                 None,
+                None,
             )),
             hash.into(),
         );
@@ -1014,6 +1032,7 @@ mod tests {
                 Arc::new(temp_dir.as_ref().to_owned()),
                 1024 * 16,
                 None,
+                None,
             )),
             hash,
         );
@@ -49,6 +49,8 @@
 //! The inline_data table contains the actual data for complete entries.
 //! The inline_outboard table contains the actual outboard for complete entries.
 //! The tags table contains a mapping from tag to hash.
+//! The encrypted_tags table contains the tags of an encrypted store.
+//! The blob_meta table contains the MIME type and annotations of blobs.
 //!
 //! Design:
 //!
@@ -65,7 +67,7 @@
 //! OuterError is an enum containing all the actor errors and in addition
 //! errors when communicating with the actor.
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{btree_map, BTreeMap, BTreeSet},
     io::{self, BufReader, Read},
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
@@ -88,6 +90,7 @@ use smallvec::SmallVec;
 use tokio::{io::AsyncWriteExt, sync::oneshot};
 use tracing::trace_span;
 
+pub(super) mod encryption;
 mod import_flat_store;
 mod migrate_redb_v1_v2;
 mod tables;
@@ -99,14 +102,17 @@ mod util;
 mod validate;
 
 use crate::{
+    hashseq::HashSeq,
     store::{
         bao_file::{BaoFileStorage, CompleteStorage},
         fs::{
             tables::BaoFilePart,
-            util::{overwrite_and_sync, read_and_remove, ProgressReader},
+            util::{link_file, read_and_remove, Link, ProgressReader},
         },
     },
     util::{
+        chunker::Chunker,
+        mime,
         progress::{
             BoxedProgressSender, IdGenerator, IgnoreProgressSender, ProgressSendError,
             ProgressSender,
@@ -121,10 +127,14 @@ use self::{tables::DeleteSet, util::PeekableFlumeReceiver};
 
 use self::test_support::EntryData;
 
+pub use self::encryption::EncryptionKey;
+use self::encryption::{apply_inline, copy_with_keystream, CryptFile, Keystream};
+
 use super::{
     bao_file::{BaoFileConfig, BaoFileHandle, BaoFileHandleWeak, CreateCb},
-    temp_name, BaoBatchWriter, BaoBlobSize, ConsistencyCheckProgress, EntryStatus, ExportMode,
-    ExportProgressCb, ImportMode, ImportProgress, Map, TempCounterMap,
+    temp_name, BaoBatchWriter, BaoBlobSize, BlobAccess, BlobMeta, BlobMetaUpdate,
+    ConsistencyCheckProgress, EntryStatus, ExportMode, ExportProgressCb, ImportMode,
+    ImportProgress, Map, Quota, TempCounterMap,
 };
 
 /// Location of the data.
@@ -141,6 +151,12 @@ pub(crate) enum DataLocation<I = (), E = ()> {
     Owned(E),
     /// Data is in several external locations. This should be a non-empty list.
     External(Vec<PathBuf>, E),
+    /// Data is in the canonical location in the data directory, but the file is
+    /// a read-only hard link that shares its content with a file outside of the
+    /// store.
+    ///
+    /// Deleting the entry only removes the link of the store.
+    Linked(E),
 }
 
 impl<X> DataLocation<X, u64> {
@@ -161,12 +177,12 @@ impl<X> DataLocation<X, u64> {
                 paths.dedup();
                 DataLocation::External(paths, a_size)
             }
-            (_, b @ DataLocation::Owned(_)) => {
+            (_, b @ (DataLocation::Owned(_) | DataLocation::Linked(_))) => {
                 // owned needs to win, since it has an associated file. Choosing
                 // external would orphan the file.
                 b
             }
-            (a @ DataLocation::Owned(_), _) => {
+            (a @ (DataLocation::Owned(_) | DataLocation::Linked(_)), _) => {
                 // owned needs to win, since it has an associated file. Choosing
                 // external would orphan the file.
                 a
@@ -191,6 +207,7 @@ impl<I, E> DataLocation<I, E> {
             DataLocation::Inline(_) => DataLocation::Inline(()),
             DataLocation::Owned(x) => DataLocation::Owned(x),
             DataLocation::External(paths, x) => DataLocation::External(paths, x),
+            DataLocation::Linked(x) => DataLocation::Linked(x),
         }
     }
 }
@@ -429,7 +446,11 @@ impl Default for BatchOptions {
 }
 
 /// Options for the file store.
+///
+/// Use [`Options::new`] and the `with_` methods to create options, so that new
+/// options can be added without breaking existing code.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Options {
     /// Path options.
     pub path: PathOptions,
@@ -437,11 +458,73 @@ pub struct Options {
     pub inline: InlineOptions,
     /// Transaction batching options.
     pub batch: BatchOptions,
+    /// Key to encrypt the store at rest.
+    ///
+    /// If set, data and outboard files, inline data and outboards and tags are
+    /// encrypted. An existing unencrypted store is encrypted in place when it
+    /// is opened with a key. An encrypted store can only be opened with the
+    /// key it was encrypted with. The encryption provides confidentiality
+    /// only, tampering with the encrypted files is not detected.
+    pub encryption: Option<EncryptionKey>,
+    /// Limit on the total size of the blobs in the store.
+    pub quota: Option<Quota>,
+}
+
+impl Options {
+    /// Create options for a store with its files in `root`.
+    ///
+    /// Inline storage and transaction batching use their defaults. The store is
+    /// not encrypted and has no quota.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            path: PathOptions::new(root.as_ref()),
+            inline: Default::default(),
+            batch: Default::default(),
+            encryption: None,
+            quota: None,
+        }
+    }
+
+    /// Set the inline storage options.
+    pub fn with_inline(mut self, inline: InlineOptions) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Set the transaction batching options.
+    pub fn with_batch(mut self, batch: BatchOptions) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Encrypt the store at rest with `key`, see [`Options::encryption`].
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    /// Limit the total size of the blobs in the store, see [`Options::quota`].
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    fn data_keystream(&self, hash: &Hash) -> Option<Keystream> {
+        self.encryption.as_ref().map(|key| key.data_keystream(hash))
+    }
+
+    fn outboard_keystream(&self, hash: &Hash) -> Option<Keystream> {
+        self.encryption
+            .as_ref()
+            .map(|key| key.outboard_keystream(hash))
+    }
 }
 
 #[derive(derive_more::Debug)]
 pub(crate) enum ImportSource {
     TempFile(PathBuf),
+    /// A hard link in the temp directory to a read-only file outside of the store.
+    TempLink(PathBuf),
     External(PathBuf),
     Memory(#[debug(skip)] Bytes),
 }
@@ -450,6 +533,7 @@ impl ImportSource {
     fn content(&self) -> MemOrFile<&[u8], &Path> {
         match self {
             Self::TempFile(path) => MemOrFile::File(path.as_path()),
+            Self::TempLink(path) => MemOrFile::File(path.as_path()),
             Self::External(path) => MemOrFile::File(path.as_path()),
             Self::Memory(data) => MemOrFile::Mem(data.as_ref()),
         }
@@ -458,10 +542,26 @@ impl ImportSource {
     fn len(&self) -> io::Result<u64> {
         match self {
             Self::TempFile(path) => std::fs::metadata(path).map(|m| m.len()),
+            Self::TempLink(path) => std::fs::metadata(path).map(|m| m.len()),
             Self::External(path) => std::fs::metadata(path).map(|m| m.len()),
             Self::Memory(data) => Ok(data.len() as u64),
         }
     }
+
+    /// Detect the MIME type of the data, from `name` or else from the first bytes.
+    fn mime_type(&self, name: Option<&str>) -> io::Result<String> {
+        let mime_type = match self.content() {
+            MemOrFile::Mem(data) => mime::detect_content_type(name, data),
+            MemOrFile::File(path) => {
+                let mut head = Vec::with_capacity(mime::SNIFF_LEN);
+                std::fs::File::open(path)?
+                    .take(mime::SNIFF_LEN as u64)
+                    .read_to_end(&mut head)?;
+                mime::detect_content_type(name, &head)
+            }
+        };
+        Ok(mime_type.to_string())
+    }
 }
 
 /// Use BaoFileHandle as the entry type for the map.
@@ -508,6 +608,8 @@ pub(crate) struct Import {
     /// Outboard without length prefix
     #[debug("{:?}", outboard.as_ref().map(|x| x.len()))]
     outboard: Option<Vec<u8>>,
+    /// MIME type detected during the import, kept unless the blob already has one
+    mime_type: Option<String>,
 }
 
 #[derive(derive_more::Debug)]
@@ -564,6 +666,29 @@ pub(crate) enum ActorMessage {
         hash: Hash,
         tx: oneshot::Sender<ActorResult<BaoFileHandle>>,
     },
+    /// Query method: get the access times of a blob.
+    BlobAccess {
+        hash: Hash,
+        tx: oneshot::Sender<ActorResult<Option<BlobAccess>>>,
+    },
+    /// Modification method: record that a blob was served to a peer.
+    OnServed { hash: Hash },
+    /// Query method: get the metadata of a blob.
+    BlobMeta {
+        hash: Hash,
+        tx: oneshot::Sender<ActorResult<Option<BlobMeta>>>,
+    },
+    /// Bulk query method: get the entire blob metadata table.
+    BlobMetas {
+        #[allow(clippy::type_complexity)]
+        tx: oneshot::Sender<ActorResult<Vec<std::result::Result<(Hash, BlobMeta), StorageError>>>>,
+    },
+    /// Modification method: update the metadata of a blob.
+    UpdateBlobMeta {
+        hash: Hash,
+        update: BlobMetaUpdate,
+        tx: oneshot::Sender<ActorResult<()>>,
+    },
     /// Modification method: inline size was exceeded for a partial entry.
     /// If the entry is complete, this is a no-op. If the entry is partial and in
     /// memory, it will be written to a file and created in redb.
@@ -609,10 +734,9 @@ pub(crate) enum ActorMessage {
             ActorResult<Vec<std::result::Result<(Hash, EntryState), StorageError>>>,
         >,
     },
-    /// Bulk query method: get the entire tags table
+    /// Bulk query method: get the entire tags table, or the tags starting with a prefix
     Tags {
-        #[debug(skip)]
-        filter: FilterPredicate<Tag, HashAndFormat>,
+        prefix: Option<Tag>,
         #[allow(clippy::type_complexity)]
         tx: oneshot::Sender<
             ActorResult<Vec<std::result::Result<(Tag, HashAndFormat), StorageError>>>,
@@ -624,6 +748,20 @@ pub(crate) enum ActorMessage {
         value: Option<HashAndFormat>,
         tx: oneshot::Sender<ActorResult<()>>,
     },
+    /// Modification method: rename a tag, if the new name does not exist yet.
+    RenameTag {
+        from: Tag,
+        to: Tag,
+        tx: oneshot::Sender<ActorResult<()>>,
+    },
+    /// Modification method: set a tag to a value, or remove it, if it has the expected value.
+    CompareAndSwapTag {
+        tag: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+        #[allow(clippy::type_complexity)]
+        tx: oneshot::Sender<ActorResult<std::result::Result<(), Option<HashAndFormat>>>>,
+    },
     /// Modification method: create a new unique tag and set it to a value.
     CreateTag {
         hash: HashAndFormat,
@@ -669,12 +807,19 @@ impl ActorMessage {
             | Self::Tags { .. }
             | Self::GcStart { .. }
             | Self::GetFullEntryState { .. }
+            | Self::BlobAccess { .. }
+            | Self::BlobMeta { .. }
+            | Self::BlobMetas { .. }
             | Self::Dump => MessageCategory::ReadOnly,
             Self::Import { .. }
             | Self::Export { .. }
             | Self::OnMemSizeExceeded { .. }
             | Self::OnComplete { .. }
+            | Self::OnServed { .. }
+            | Self::UpdateBlobMeta { .. }
             | Self::SetTag { .. }
+            | Self::CompareAndSwapTag { .. }
+            | Self::RenameTag { .. }
             | Self::CreateTag { .. }
             | Self::SetFullEntryState { .. }
             | Self::Delete { .. } => MessageCategory::ReadWrite,
@@ -720,15 +865,13 @@ impl Store {
     pub async fn load(root: impl AsRef<Path>) -> io::Result<Self> {
         let path = root.as_ref();
         let db_path = path.join("blobs.db");
-        let options = Options {
-            path: PathOptions::new(path),
-            inline: Default::default(),
-            batch: Default::default(),
-        };
-        Self::new(db_path, options).await
+        Self::new(db_path, Options::new(path)).await
     }
 
     /// Create a new store with custom options.
+    ///
+    /// If [`Options::encryption`] is set and the store is not encrypted yet,
+    /// this encrypts the existing content in place before returning.
     pub async fn new(path: PathBuf, options: Options) -> io::Result<Self> {
         // spawn_blocking because StoreInner::new creates directories
         let rt = tokio::runtime::Handle::try_current()
@@ -777,6 +920,7 @@ struct StoreInner {
     temp: Arc<RwLock<TempCounterMap>>,
     handle: Option<std::thread::JoinHandle<()>>,
     path_options: Arc<PathOptions>,
+    quota: Option<Quota>,
 }
 
 impl LivenessTracker for RwLock<TempCounterMap> {
@@ -807,6 +951,7 @@ impl StoreInner {
         );
         std::fs::create_dir_all(path.parent().unwrap())?;
         let temp: Arc<RwLock<TempCounterMap>> = Default::default();
+        let quota = options.quota.clone();
         let (actor, tx) = Actor::new(&path, options.clone(), temp.clone(), rt)?;
         let handle = std::thread::Builder::new()
             .name("redb-actor".to_string())
@@ -821,6 +966,7 @@ impl StoreInner {
             temp,
             handle: Some(handle),
             path_options: Arc::new(options.path),
+            quota,
         })
     }
 
@@ -886,12 +1032,13 @@ impl StoreInner {
         Ok(res)
     }
 
-    async fn tags(&self) -> OuterResult<Vec<io::Result<(Tag, HashAndFormat)>>> {
+    async fn tags(
+        &self,
+        prefix: Option<Tag>,
+    ) -> OuterResult<Vec<io::Result<(Tag, HashAndFormat)>>> {
         let (tx, rx) = oneshot::channel();
-        let filter: FilterPredicate<Tag, HashAndFormat> =
-            Box::new(|_i, k, v| Some((k.value(), v.value())));
         self.tx
-            .send_async(ActorMessage::Tags { filter, tx })
+            .send_async(ActorMessage::Tags { prefix, tx })
             .await?;
         let tags = rx.await?;
         // transform the internal error type into io::Error
@@ -910,6 +1057,32 @@ impl StoreInner {
         Ok(rx.await??)
     }
 
+    async fn compare_and_swap_tag(
+        &self,
+        tag: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> OuterResult<std::result::Result<(), Option<HashAndFormat>>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send_async(ActorMessage::CompareAndSwapTag {
+                tag,
+                expected,
+                value,
+                tx,
+            })
+            .await?;
+        Ok(rx.await??)
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> OuterResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send_async(ActorMessage::RenameTag { from, to, tx })
+            .await?;
+        Ok(rx.await??)
+    }
+
     async fn create_tag(&self, hash: HashAndFormat) -> OuterResult<Tag> {
         let (tx, rx) = oneshot::channel();
         self.tx
@@ -926,6 +1099,46 @@ impl StoreInner {
         Ok(rx.await??)
     }
 
+    async fn blob_access(&self, hash: Hash) -> OuterResult<Option<BlobAccess>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send_async(ActorMessage::BlobAccess { hash, tx })
+            .await?;
+        Ok(rx.await??)
+    }
+
+    async fn record_served(&self, hash: Hash) -> OuterResult<()> {
+        self.tx.send_async(ActorMessage::OnServed { hash }).await?;
+        Ok(())
+    }
+
+    async fn blob_meta(&self, hash: Hash) -> OuterResult<Option<BlobMeta>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send_async(ActorMessage::BlobMeta { hash, tx })
+            .await?;
+        Ok(rx.await??)
+    }
+
+    async fn blob_metas(&self) -> OuterResult<Vec<io::Result<(Hash, BlobMeta)>>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send_async(ActorMessage::BlobMetas { tx }).await?;
+        let metas = rx
+            .await??
+            .into_iter()
+            .map(|r| r.map_err(|e| ActorError::from(e).into()))
+            .collect();
+        Ok(metas)
+    }
+
+    async fn update_blob_meta(&self, hash: Hash, update: BlobMetaUpdate) -> OuterResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send_async(ActorMessage::UpdateBlobMeta { hash, update, tx })
+            .await?;
+        Ok(rx.await??)
+    }
+
     async fn gc_start(&self) -> OuterResult<()> {
         let (tx, rx) = oneshot::channel();
         self.tx.send_async(ActorMessage::GcStart { tx }).await?;
@@ -1076,7 +1289,11 @@ impl StoreInner {
             id,
             name: path.to_string_lossy().to_string(),
         })?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
         let file = match mode {
+            ImportMode::Chunked => return self.import_chunked_sync(path, id, progress),
             ImportMode::TryReference => ImportSource::External(path),
             ImportMode::Copy => {
                 if std::fs::metadata(&path)?.len() < 16 * 1024 {
@@ -1089,17 +1306,76 @@ impl StoreInner {
                     let temp_path = self.temp_file_name();
                     // copy the data, since it is not stable
                     progress.try_send(ImportProgress::CopyProgress { id, offset: 0 })?;
-                    if reflink_copy::reflink_or_copy(&path, &temp_path)?.is_none() {
-                        tracing::debug!("reflinked {} to {}", path.display(), temp_path.display());
-                    } else {
-                        tracing::debug!("copied {} to {}", path.display(), temp_path.display());
-                    }
+                    let link = link_file(&path, &temp_path);
+                    tracing::debug!(
+                        "{} {} to {}",
+                        match link {
+                            Some(Link::Reflink) => "reflinked",
+                            Some(Link::Hardlink) => "hard linked",
+                            None => "copied",
+                        },
+                        path.display(),
+                        temp_path.display()
+                    );
                     // copy progress for size will be called in finalize_import_sync
-                    ImportSource::TempFile(temp_path)
+                    match link {
+                        Some(Link::Reflink) => ImportSource::TempFile(temp_path),
+                        Some(Link::Hardlink) => ImportSource::TempLink(temp_path),
+                        None => {
+                            std::fs::copy(&path, &temp_path)?;
+                            ImportSource::TempFile(temp_path)
+                        }
+                    }
                 }
             }
         };
-        let (tag, size) = self.finalize_import_sync(file, format, id, progress)?;
+        let mime_type = file.mime_type(name.as_deref())?;
+        let (tag, size) = self.finalize_import_sync(file, format, Some(mime_type), id, progress)?;
+        Ok((tag, size))
+    }
+
+    fn import_chunked_sync(
+        &self,
+        path: PathBuf,
+        id: u64,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> OuterResult<(TempTag, u64)> {
+        let file = std::fs::File::open(&path)?;
+        let size = file.metadata()?.len();
+        progress.blocking_send(ImportProgress::Size { id, size })?;
+        let mut hashes = Vec::new();
+        // protect the chunks until the hash seq is stored
+        let mut tags = BTreeMap::new();
+        let mut offset = 0;
+        let mut stored = 0;
+        for chunk in Chunker::new(BufReader::new(file)) {
+            let chunk = chunk?;
+            let len = chunk.len() as u64;
+            let hash = Hash::new(&chunk);
+            hashes.push(hash);
+            if let btree_map::Entry::Vacant(e) = tags.entry(hash) {
+                let tag = self.temp_tag(HashAndFormat::raw(hash));
+                if !matches!(self.entry_status_sync(&hash)?, EntryStatus::Complete) {
+                    self.import_bytes_sync(chunk, BlobFormat::Raw)?;
+                    stored += len;
+                }
+                e.insert(tag);
+            }
+            offset += len;
+            progress.try_send(ImportProgress::CopyProgress { id, offset })?;
+        }
+        progress.blocking_send(ImportProgress::ChunkingDone {
+            id,
+            chunks: hashes.len() as u64,
+            size,
+            stored,
+        })?;
+        let hash_seq = hashes.into_iter().collect::<HashSeq>().into_inner();
+        let tag = self.import_bytes_sync(hash_seq, BlobFormat::HashSeq)?;
+        progress.blocking_send(ImportProgress::OutboardDone {
+            id,
+            hash: *tag.hash(),
+        })?;
         Ok((tag, size))
     }
 
@@ -1107,7 +1383,7 @@ impl StoreInner {
         let id = 0;
         let file = ImportSource::Memory(data);
         let progress = IgnoreProgressSender::default();
-        let (tag, _size) = self.finalize_import_sync(file, format, id, progress)?;
+        let (tag, _size) = self.finalize_import_sync(file, format, None, id, progress)?;
         Ok(tag)
     }
 
@@ -1115,6 +1391,7 @@ impl StoreInner {
         &self,
         file: ImportSource,
         format: BlobFormat,
+        mime_type: Option<String>,
         id: u64,
         progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
     ) -> OuterResult<(TempTag, u64)> {
@@ -1151,6 +1428,7 @@ impl StoreInner {
                 source: file,
                 outboard,
                 data_size,
+                mime_type,
             },
             tx,
         })?;
@@ -1283,6 +1561,10 @@ impl super::Map for Store {
     async fn get(&self, hash: &Hash) -> io::Result<Option<Self::Entry>> {
         Ok(self.0.get(*hash).await?.map(From::from))
     }
+
+    async fn record_served(&self, hash: &Hash) -> io::Result<()> {
+        Ok(self.0.record_served(*hash).await?)
+    }
 }
 
 impl super::MapMut for Store {
@@ -1319,7 +1601,14 @@ impl super::ReadableStore for Store {
     }
 
     async fn tags(&self) -> io::Result<super::DbIter<(Tag, HashAndFormat)>> {
-        Ok(Box::new(self.0.tags().await?.into_iter()))
+        Ok(Box::new(self.0.tags(None).await?.into_iter()))
+    }
+
+    async fn tags_with_prefix(
+        &self,
+        prefix: Tag,
+    ) -> io::Result<super::DbIter<(Tag, HashAndFormat)>> {
+        Ok(Box::new(self.0.tags(Some(prefix)).await?.into_iter()))
     }
 
     fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
@@ -1400,7 +1689,9 @@ impl super::Store for Store {
         drop(writer);
         let file = ImportSource::TempFile(temp_data_path);
         Ok(tokio::task::spawn_blocking(move || {
-            this.0.finalize_import_sync(file, format, id, progress)
+            let mime_type = file.mime_type(None)?;
+            this.0
+                .finalize_import_sync(file, format, Some(mime_type), id, progress)
         })
         .await??)
     }
@@ -1413,6 +1704,19 @@ impl super::Store for Store {
         Ok(self.0.create_tag(hash).await?)
     }
 
+    async fn compare_and_swap_tag(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> io::Result<std::result::Result<(), Option<HashAndFormat>>> {
+        Ok(self.0.compare_and_swap_tag(name, expected, value).await?)
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        Ok(self.0.rename_tag(from, to).await?)
+    }
+
     async fn delete(&self, hashes: Vec<Hash>) -> io::Result<()> {
         Ok(self.0.delete(hashes).await?)
     }
@@ -1426,6 +1730,26 @@ impl super::Store for Store {
         self.0.temp_tag(value)
     }
 
+    fn quota(&self) -> Option<Quota> {
+        self.0.quota.clone()
+    }
+
+    async fn blob_access(&self, hash: &Hash) -> io::Result<Option<BlobAccess>> {
+        Ok(self.0.blob_access(*hash).await?)
+    }
+
+    async fn blob_meta(&self, hash: &Hash) -> io::Result<Option<BlobMeta>> {
+        Ok(self.0.blob_meta(*hash).await?)
+    }
+
+    async fn update_blob_meta(&self, hash: Hash, update: BlobMetaUpdate) -> io::Result<()> {
+        Ok(self.0.update_blob_meta(hash, update).await?)
+    }
+
+    async fn blob_metas(&self) -> io::Result<super::DbIter<(Hash, BlobMeta)>> {
+        Ok(Box::new(self.0.blob_metas().await?.into_iter()))
+    }
+
     async fn shutdown(&self) {
         self.0.shutdown().await;
     }
@@ -1452,6 +1776,7 @@ impl Actor {
         let tables = Tables::new(&txn, &mut t)?;
         drop(tables);
         txn.commit()?;
+        encryption::init(&db, &options.path, options.encryption.as_ref())?;
         // make the channel relatively large. there are some messages that don't
         // require a response, it's fine if they pile up a bit.
         let (tx, rx) = flume::bounded(1024);
@@ -1466,6 +1791,7 @@ impl Actor {
             Arc::new(options.path.data_path.clone()),
             16 * 1024,
             Some(on_file_create),
+            options.encryption.clone(),
         );
         Ok((
             Self {
@@ -1574,14 +1900,9 @@ impl ActorState {
                 data_location,
                 outboard_location,
             } => {
-                let data = load_data(tables, &self.options.path, data_location, &hash)?;
-                let outboard = load_outboard(
-                    tables,
-                    &self.options.path,
-                    outboard_location,
-                    data.size(),
-                    &hash,
-                )?;
+                let data = load_data(tables, &self.options, data_location, &hash)?;
+                let outboard =
+                    load_outboard(tables, &self.options, outboard_location, data.size(), &hash)?;
                 BaoFileHandle::new_complete(config, hash, data, outboard)
             }
             EntryState::Partial { .. } => BaoFileHandle::incomplete_file(config, hash)?,
@@ -1617,18 +1938,28 @@ impl ActorState {
                     let data = tables.inline_data.get(temp_tag.hash())?.ok_or_else(|| {
                         ActorError::Inconsistent("inline data not found".to_owned())
                     })?;
+                    let keystream = self.options.data_keystream(temp_tag.hash());
+                    let data = apply_inline(keystream.as_ref(), data.value());
                     tracing::trace!("exporting inline data to {}", target.display());
-                    tx.send(std::fs::write(&target, data.value()).map_err(|e| e.into()))
+                    tx.send(std::fs::write(&target, data).map_err(|e| e.into()))
                         .ok();
                 }
-                DataLocation::Owned(size) => {
+                DataLocation::Owned(size) | DataLocation::Linked(size) => {
                     let path = self.options.path.owned_data_path(temp_tag.hash());
+                    let keystream = self.options.data_keystream(temp_tag.hash());
+                    // encrypted data can not be referenced, so it is always copied
+                    let mode = match keystream {
+                        Some(_) => ExportMode::Copy,
+                        None => mode,
+                    };
                     match mode {
                         ExportMode::Copy => {
                             // copy in an external thread
                             self.rt.spawn_blocking(move || {
-                                tx.send(export_file_copy(temp_tag, path, size, target, progress))
-                                    .ok();
+                                tx.send(export_file_copy(
+                                    temp_tag, path, size, target, progress, keystream,
+                                ))
+                                .ok();
                             });
                         }
                         ExportMode::TryReference => match std::fs::rename(&path, &target) {
@@ -1692,8 +2023,10 @@ impl ActorState {
                     } else {
                         // copy in an external thread
                         self.rt.spawn_blocking(move || {
-                            tx.send(export_file_copy(temp_tag, path, size, target, progress))
-                                .ok();
+                            tx.send(export_file_copy(
+                                temp_tag, path, size, target, progress, None,
+                            ))
+                            .ok();
                         });
                     }
                 }
@@ -1711,6 +2044,7 @@ impl ActorState {
             source: file,
             outboard,
             data_size,
+            mime_type,
         } = cmd;
         let outboard_size = outboard.as_ref().map(|x| x.len() as u64).unwrap_or(0);
         let inline_data = data_size <= self.options.inline.max_data_inlined;
@@ -1720,6 +2054,7 @@ impl ActorState {
         let tag = TempTag::new(content_id, Some(self.temp.clone()));
         let hash = *tag.hash();
         self.protected.insert(hash);
+        let linked = matches!(file, ImportSource::TempLink(_));
         // move the data file into place, or create a reference to it
         let data_location = match file {
             ImportSource::External(external_path) => {
@@ -1735,7 +2070,7 @@ impl ActorState {
                     DataLocation::External(vec![external_path], data_size)
                 }
             }
-            ImportSource::TempFile(temp_data_path) => {
+            ImportSource::TempFile(temp_data_path) | ImportSource::TempLink(temp_data_path) => {
                 if inline_data {
                     tracing::debug!(
                         "reading and deleting temp file to inline it: {}",
@@ -1745,9 +2080,24 @@ impl ActorState {
                     DataLocation::Inline(data)
                 } else {
                     let data_path = self.options.path.owned_data_path(&hash);
-                    std::fs::rename(&temp_data_path, &data_path)?;
-                    tracing::debug!("created file {}", data_path.display());
-                    DataLocation::Owned(data_size)
+                    match self.options.data_keystream(&hash) {
+                        Some(keystream) => {
+                            // encrypting the data breaks the link
+                            copy_with_keystream(&temp_data_path, &data_path, &keystream)?;
+                            std::fs::remove_file(&temp_data_path)?;
+                            tracing::debug!("created file {}", data_path.display());
+                            DataLocation::Owned(data_size)
+                        }
+                        None => {
+                            std::fs::rename(&temp_data_path, &data_path)?;
+                            tracing::debug!("created file {}", data_path.display());
+                            if linked {
+                                DataLocation::Linked(data_size)
+                            } else {
+                                DataLocation::Owned(data_size)
+                            }
+                        }
+                    }
                 }
             }
             ImportSource::Memory(data) => {
@@ -1755,7 +2105,11 @@ impl ActorState {
                     DataLocation::Inline(data)
                 } else {
                     let data_path = self.options.path.owned_data_path(&hash);
-                    overwrite_and_sync(&data_path, &data)?;
+                    CryptFile::overwrite_and_sync(
+                        &data_path,
+                        &data,
+                        self.options.data_keystream(&hash),
+                    )?;
                     tracing::debug!("created file {}", data_path.display());
                     DataLocation::Owned(data_size)
                 }
@@ -1767,19 +2121,27 @@ impl ActorState {
             } else {
                 let outboard_path = self.options.path.owned_outboard_path(&hash);
                 // todo: this blocks the actor when writing a large outboard
-                overwrite_and_sync(&outboard_path, &outboard)?;
+                CryptFile::overwrite_and_sync(
+                    &outboard_path,
+                    &outboard,
+                    self.options.outboard_keystream(&hash),
+                )?;
                 OutboardLocation::Owned
             }
         } else {
             OutboardLocation::NotNeeded
         };
         if let DataLocation::Inline(data) = &data_location {
+            let keystream = self.options.data_keystream(&hash);
+            let data = apply_inline(keystream.as_ref(), data);
             tables.inline_data.insert(hash, data.as_ref())?;
         }
         if let OutboardLocation::Inline(outboard) = &outboard_location {
+            let keystream = self.options.outboard_keystream(&hash);
+            let outboard = apply_inline(keystream.as_ref(), outboard);
             tables.inline_outboard.insert(hash, outboard.as_ref())?;
         }
-        if let DataLocation::Owned(_) = &data_location {
+        if let DataLocation::Owned(_) | DataLocation::Linked(_) = &data_location {
             tables.delete_after_commit.remove(hash, [BaoFilePart::Data]);
         }
         if let OutboardLocation::Owned = &outboard_location {
@@ -1796,6 +2158,14 @@ impl ActorState {
             outboard_location,
         })?;
         tables.blobs.insert(hash, entry)?;
+        on_imported(tables, hash)?;
+        if let Some(mime_type) = mime_type {
+            let mut meta = self.blob_meta(tables, hash)?.unwrap_or_default();
+            if meta.mime_type.is_none() {
+                meta.mime_type = Some(mime_type);
+                self.put_blob_meta(tables, hash, &meta)?;
+            }
+        }
         Ok((tag, data_size))
     }
 
@@ -1817,10 +2187,10 @@ impl ActorState {
                     outboard_location,
                     ..
                 } => {
-                    let data = load_data(tables, &self.options.path, data_location, &hash)?;
+                    let data = load_data(tables, &self.options, data_location, &hash)?;
                     let outboard = load_outboard(
                         tables,
-                        &self.options.path,
+                        &self.options,
                         outboard_location,
                         data.size(),
                         &hash,
@@ -1865,39 +2235,65 @@ impl ActorState {
         Ok(res)
     }
 
-    /// Read the entire tags table. Callers can then sift through the results to find what they need
+    /// Read the entire tags table, or the tags starting with `prefix`. Callers can then sift
+    /// through the results to find what they need
     fn tags(
         &mut self,
         tables: &impl ReadableTables,
-        filter: FilterPredicate<Tag, HashAndFormat>,
+        prefix: Option<Tag>,
     ) -> ActorResult<Vec<std::result::Result<(Tag, HashAndFormat), StorageError>>> {
+        let matches = |tag: &Tag| match &prefix {
+            Some(prefix) => tag.0.starts_with(&prefix.0),
+            None => true,
+        };
+        let Some(key) = &self.options.encryption else {
+            let items = match &prefix {
+                Some(prefix) => tables.tags().range(prefix.clone()..)?,
+                None => tables.tags().iter()?,
+            };
+            // the tags table is ordered by tag, so the matching tags are a contiguous range
+            return Ok(items
+                .map(|item| item.map(|(k, v)| (k.value(), v.value())))
+                .take_while(|item| item.as_ref().map_or(true, |(tag, _)| matches(tag)))
+                .collect());
+        };
         let mut res = Vec::new();
-        let mut index = 0u64;
-        #[allow(clippy::explicit_counter_loop)]
-        for item in tables.tags().iter()? {
-            match item {
-                Ok((k, v)) => {
-                    if let Some(item) = filter(index, k, v) {
-                        res.push(Ok(item));
-                    }
-                }
-                Err(e) => {
-                    res.push(Err(e));
+        for item in tables.encrypted_tags().iter()? {
+            let item = item.and_then(|(k, v)| {
+                let (tag, value) = key
+                    .open_tag(v.value())
+                    .map_err(|e| StorageError::Corrupted(e.to_string()))?;
+                // a sealed tag copied to the row of another tag would be listed twice
+                if key.tag_id(&tag) != k.value() {
+                    return Err(StorageError::Corrupted("tag stored under wrong id".into()));
                 }
+                Ok((tag, value))
+            });
+            if item.as_ref().map_or(true, |(tag, _)| matches(tag)) {
+                res.push(item);
             }
-            index += 1;
         }
+        // the encrypted tags table is ordered by tag id, not by tag
+        res.sort_by(|a, b| match (a, b) {
+            (Ok((a, _)), Ok((b, _))) => a.cmp(b),
+            (a, b) => a.is_ok().cmp(&b.is_ok()),
+        });
         Ok(res)
     }
 
     fn create_tag(&mut self, tables: &mut Tables, content: HashAndFormat) -> ActorResult<Tag> {
-        let tag = {
-            let tag = Tag::auto(SystemTime::now(), |x| {
-                matches!(tables.tags.get(Tag(Bytes::copy_from_slice(x))), Ok(Some(_)))
-            });
-            tables.tags.insert(tag.clone(), content)?;
-            tag
-        };
+        let tag = Tag::auto(SystemTime::now(), |x| {
+            let tag = Tag(Bytes::copy_from_slice(x));
+            let existing = match &self.options.encryption {
+                Some(key) => tables
+                    .encrypted_tags
+                    .get(key.tag_id(&tag))
+                    .map(|x| x.is_some()),
+                None => tables.tags.get(tag).map(|x| x.is_some()),
+            };
+            matches!(existing, Ok(true))
+        });
+        self.set_tag(tables, tag.clone(), Some(content))?;
         Ok(tag)
     }
 
@@ -1907,17 +2303,171 @@ impl ActorState {
         tag: Tag,
         value: Option<HashAndFormat>,
     ) -> ActorResult<()> {
-        match value {
-            Some(value) => {
+        match (&self.options.encryption, value) {
+            (Some(key), Some(value)) => {
+                let sealed = key.seal_tag(&tag, &value);
+                tables
+                    .encrypted_tags
+                    .insert(key.tag_id(&tag), sealed.as_slice())?;
+            }
+            (Some(key), None) => {
+                tables.encrypted_tags.remove(key.tag_id(&tag))?;
+            }
+            (None, Some(value)) => {
                 tables.tags.insert(tag, value)?;
             }
-            None => {
+            (None, None) => {
                 tables.tags.remove(tag)?;
             }
         }
         Ok(())
     }
 
+    fn get_tag(
+        &self,
+        tables: &impl ReadableTables,
+        tag: &Tag,
+    ) -> ActorResult<Option<HashAndFormat>> {
+        let Some(key) = &self.options.encryption else {
+            return Ok(tables.tags().get(tag)?.map(|x| x.value()));
+        };
+        let Some(sealed) = tables.encrypted_tags().get(key.tag_id(tag))? else {
+            return Ok(None);
+        };
+        let (stored, value) = key.open_tag(sealed.value())?;
+        // the sealed value authenticates the tag name, so a value moved from the row of
+        // another tag is detected here
+        if &stored != tag {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("encrypted tag {tag} contains the value of another tag"),
+            )
+            .into());
+        }
+        Ok(Some(value))
+    }
+
+    fn rename_tag(&self, tables: &mut Tables, from: Tag, to: Tag) -> ActorResult<()> {
+        let Some(value) = self.get_tag(tables, &from)? else {
+            return Err(super::tag_not_found(&from).into());
+        };
+        if self.get_tag(tables, &to)?.is_some() {
+            return Err(super::tag_exists(&to).into());
+        }
+        self.set_tag(tables, to, Some(value))?;
+        self.set_tag(tables, from, None)?;
+        Ok(())
+    }
+
+    fn compare_and_swap_tag(
+        &self,
+        tables: &mut Tables,
+        tag: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> ActorResult<std::result::Result<(), Option<HashAndFormat>>> {
+        let current = self.get_tag(tables, &tag)?;
+        if current != expected {
+            return Ok(Err(current));
+        }
+        self.set_tag(tables, tag, value)?;
+        Ok(Ok(()))
+    }
+
+    fn blob_access(
+        &mut self,
+        tables: &impl ReadableTables,
+        hash: Hash,
+    ) -> ActorResult<Option<BlobAccess>> {
+        let Some(access) = tables.blob_access().get(hash)? else {
+            return Ok(None);
+        };
+        let (imported, served) = access.value();
+        Ok(Some(BlobAccess {
+            imported: from_millis(imported),
+            served: (served != 0).then(|| from_millis(served)),
+        }))
+    }
+
+    fn on_served(&mut self, tables: &mut Tables, hash: Hash) -> ActorResult<()> {
+        if tables.blobs.get(hash)?.is_none() {
+            return Ok(());
+        }
+        let imported = match tables.blob_access.get(hash)? {
+            Some(access) => access.value().0,
+            None => 0,
+        };
+        let served = to_millis(SystemTime::now());
+        tables.blob_access.insert(hash, (imported, served))?;
+        Ok(())
+    }
+
+    /// Decode a value of the blob meta table, decrypting it if needed.
+    fn decode_blob_meta(&self, value: &[u8]) -> std::result::Result<BlobMeta, StorageError> {
+        let corrupted = |e: &dyn std::fmt::Display| StorageError::Corrupted(e.to_string());
+        let plaintext = match &self.options.encryption {
+            Some(key) => std::borrow::Cow::Owned(key.open_meta(value).map_err(|e| corrupted(&e))?),
+            None => std::borrow::Cow::Borrowed(value),
+        };
+        postcard::from_bytes(&plaintext).map_err(|e| corrupted(&e))
+    }
+
+    fn blob_meta(
+        &mut self,
+        tables: &impl ReadableTables,
+        hash: Hash,
+    ) -> ActorResult<Option<BlobMeta>> {
+        let Some(value) = tables.blob_meta().get(hash)? else {
+            return Ok(None);
+        };
+        Ok(Some(self.decode_blob_meta(value.value())?))
+    }
+
+    fn blob_metas(
+        &mut self,
+        tables: &impl ReadableTables,
+    ) -> ActorResult<Vec<std::result::Result<(Hash, BlobMeta), StorageError>>> {
+        let mut res = Vec::new();
+        for item in tables.blob_meta().iter()? {
+            res.push(item.and_then(|(k, v)| Ok((k.value(), self.decode_blob_meta(v.value())?))));
+        }
+        Ok(res)
+    }
+
+    fn update_blob_meta(
+        &mut self,
+        tables: &mut Tables,
+        hash: Hash,
+        update: BlobMetaUpdate,
+    ) -> ActorResult<()> {
+        // metadata is removed with the blob, so it must not exist without one
+        if tables.blobs.get(hash)?.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("blob {} is not in the store", hash.to_hex()),
+            )
+            .into());
+        }
+        let mut meta = self.blob_meta(tables, hash)?.unwrap_or_default();
+        meta.apply(update);
+        self.put_blob_meta(tables, hash, &meta)
+    }
+
+    /// Write the metadata of a blob, or remove it if it is empty.
+    fn put_blob_meta(&self, tables: &mut Tables, hash: Hash, meta: &BlobMeta) -> ActorResult<()> {
+        if meta.is_empty() {
+            tables.blob_meta.remove(hash)?;
+            return Ok(());
+        }
+        let value = postcard::to_stdvec(meta).expect("blob meta can be serialized");
+        let value = match &self.options.encryption {
+            Some(key) => key.seal_meta(value),
+            None => value,
+        };
+        tables.blob_meta.insert(hash, value.as_slice())?;
+        Ok(())
+    }
+
     fn on_mem_size_exceeded(&mut self, tables: &mut Tables, hash: Hash) -> ActorResult<()> {
         let entry = tables
             .blobs
@@ -1992,6 +2542,17 @@ impl ActorState {
                                     (DataLocation::Inline(()), size, false)
                                 }
                             }
+                            DataLocation::Linked(size) => {
+                                if size <= self.options.inline.max_data_inlined {
+                                    let path = self.options.path.owned_data_path(&hash);
+                                    let data = std::fs::read(&path)?;
+                                    tables.delete_after_commit.insert(hash, [BaoFilePart::Data]);
+                                    tables.inline_data.insert(hash, data.as_slice())?;
+                                    (DataLocation::Inline(()), size, true)
+                                } else {
+                                    (DataLocation::Linked(size), size, false)
+                                }
+                            }
                             DataLocation::External(paths, size) => {
                                 (DataLocation::External(paths, size), size, false)
                             }
@@ -2055,6 +2616,8 @@ impl ActorState {
             }
             tracing::debug!("deleting {}", &hash.to_hex()[..8]);
             self.handles.remove(&hash);
+            tables.blob_access.remove(hash)?;
+            tables.blob_meta.remove(hash)?;
             if let Some(entry) = tables.blobs.remove(hash)? {
                 match entry.value() {
                     EntryState::Complete {
@@ -2065,8 +2628,9 @@ impl ActorState {
                             DataLocation::Inline(_) => {
                                 tables.inline_data.remove(hash)?;
                             }
-                            DataLocation::Owned(_) => {
-                                // mark the data for deletion
+                            DataLocation::Owned(_) | DataLocation::Linked(_) => {
+                                // mark the data for deletion. For linked data this
+                                // only removes the link of the store.
                                 tables.delete_after_commit.insert(hash, [BaoFilePart::Data]);
                             }
                             DataLocation::External(_, _) => {}
@@ -2103,28 +2667,23 @@ impl ActorState {
         tracing::trace!("on_complete({})", hash.to_hex());
         entry.transform(|state| {
             tracing::trace!("on_complete transform {:?}", state);
-            let entry = match complete_storage(
-                state,
-                &hash,
-                &self.options.path,
-                &self.options.inline,
-                tables.delete_after_commit,
-            )? {
-                Ok(entry) => {
-                    // store the info so we can insert it into the db later
-                    info = Some((
-                        entry.data_size(),
-                        entry.data.mem().cloned(),
-                        entry.outboard_size(),
-                        entry.outboard.mem().cloned(),
-                    ));
-                    entry
-                }
-                Err(entry) => {
-                    // the entry was already complete, nothing to do
-                    entry
-                }
-            };
+            let entry =
+                match complete_storage(state, &hash, &self.options, tables.delete_after_commit)? {
+                    Ok(entry) => {
+                        // store the info so we can insert it into the db later
+                        info = Some((
+                            entry.data_size(),
+                            entry.data.mem().cloned(),
+                            entry.outboard_size(),
+                            entry.outboard.mem().cloned(),
+                        ));
+                        entry
+                    }
+                    Err(entry) => {
+                        // the entry was already complete, nothing to do
+                        entry
+                    }
+                };
             Ok(BaoFileStorage::Complete(entry))
         })?;
         if let Some((data_size, data, outboard_size, outboard)) = info {
@@ -2156,10 +2715,15 @@ impl ActorState {
                     outboard_location,
                 })?;
                 tables.blobs.insert(hash, entry)?;
+                on_imported(tables, hash)?;
                 if let Some(data) = data {
+                    let keystream = self.options.data_keystream(&hash);
+                    let data = apply_inline(keystream.as_ref(), &data);
                     tables.inline_data.insert(hash, data.as_ref())?;
                 }
                 if let Some(outboard) = outboard {
+                    let keystream = self.options.outboard_keystream(&hash);
+                    let outboard = apply_inline(keystream.as_ref(), &outboard);
                     tables.inline_outboard.insert(hash, outboard.as_ref())?;
                 }
             }
@@ -2224,8 +2788,8 @@ impl ActorState {
                 let res = self.blobs(tables, filter);
                 tx.send(res).ok();
             }
-            ActorMessage::Tags { filter, tx } => {
-                let res = self.tags(tables, filter);
+            ActorMessage::Tags { prefix, tx } => {
+                let res = self.tags(tables, prefix);
                 tx.send(res).ok();
             }
             ActorMessage::GcStart { tx } => {
@@ -2244,6 +2808,18 @@ impl ActorState {
                 let res = self.get_full_entry_state(tables, hash);
                 tx.send(res).ok();
             }
+            ActorMessage::BlobAccess { hash, tx } => {
+                let res = self.blob_access(tables, hash);
+                tx.send(res).ok();
+            }
+            ActorMessage::BlobMeta { hash, tx } => {
+                let res = self.blob_meta(tables, hash);
+                tx.send(res).ok();
+            }
+            ActorMessage::BlobMetas { tx } => {
+                let res = self.blob_metas(tables);
+                tx.send(res).ok();
+            }
             x => return Ok(Err(x)),
         }
         Ok(Ok(()))
@@ -2263,6 +2839,19 @@ impl ActorState {
                 let res = self.set_tag(tables, tag, value);
                 tx.send(res).ok();
             }
+            ActorMessage::RenameTag { from, to, tx } => {
+                let res = self.rename_tag(tables, from, to);
+                tx.send(res).ok();
+            }
+            ActorMessage::CompareAndSwapTag {
+                tag,
+                expected,
+                value,
+                tx,
+            } => {
+                let res = self.compare_and_swap_tag(tables, tag, expected, value);
+                tx.send(res).ok();
+            }
             ActorMessage::CreateTag { hash, tx } => {
                 let res = self.create_tag(tables, hash);
                 tx.send(res).ok();
@@ -2282,6 +2871,14 @@ impl ActorState {
                 let res = self.on_mem_size_exceeded(tables, hash);
                 res.ok();
             }
+            ActorMessage::OnServed { hash } => {
+                let res = self.on_served(tables, hash);
+                res.ok();
+            }
+            ActorMessage::UpdateBlobMeta { hash, update, tx } => {
+                let res = self.update_blob_meta(tables, hash, update);
+                tx.send(res).ok();
+            }
             ActorMessage::Dump => {
                 let res = dump(tables);
                 res.ok();
@@ -2301,17 +2898,52 @@ impl ActorState {
     }
 }
 
+/// Record that a blob was completed, keeping the time it was last served.
+fn on_imported(tables: &mut Tables, hash: Hash) -> ActorResult<()> {
+    let served = match tables.blob_access.get(hash)? {
+        Some(access) => access.value().1,
+        None => 0,
+    };
+    let imported = to_millis(SystemTime::now());
+    tables.blob_access.insert(hash, (imported, served))?;
+    Ok(())
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+}
+
 /// Export a file by copying out its content to a new location
+///
+/// If possible, the new file is a reflink, or a hard link for read-only files,
+/// see [`link_file`].
+///
+/// If the file is encrypted, it is decrypted with the keystream while copying.
 fn export_file_copy(
     temp_tag: TempTag,
     path: PathBuf,
     size: u64,
     target: PathBuf,
     progress: ExportProgressCb,
+    keystream: Option<Keystream>,
 ) -> ActorResult<()> {
     progress(0)?;
     // todo: fine grained copy progress
-    reflink_copy::reflink_or_copy(path, target)?;
+    match keystream {
+        Some(keystream) => copy_with_keystream(&path, &target, &keystream)?,
+        None => match link_file(&path, &target) {
+            Some(link) => tracing::debug!("exported {} as {:?}", path.display(), link),
+            None => {
+                std::fs::copy(&path, &target)?;
+            }
+        },
+    }
     progress(size)?;
     drop(temp_tag);
     Ok(())
@@ -2380,10 +3012,10 @@ fn dump(tables: &impl ReadableTables) -> ActorResult<()> {
 
 fn load_data(
     tables: &impl ReadableTables,
-    options: &PathOptions,
+    options: &Options,
     location: DataLocation<(), u64>,
     hash: &Hash,
-) -> ActorResult<MemOrFile<Bytes, (std::fs::File, u64)>> {
+) -> ActorResult<MemOrFile<Bytes, (CryptFile, u64)>> {
     Ok(match location {
         DataLocation::Inline(()) => {
             let Some(data) = tables.inline_data().get(hash)? else {
@@ -2392,10 +3024,12 @@ fn load_data(
                     hash.to_hex()
                 )));
             };
-            MemOrFile::Mem(Bytes::copy_from_slice(data.value()))
+            let keystream = options.data_keystream(hash);
+            let data = apply_inline(keystream.as_ref(), data.value());
+            MemOrFile::Mem(Bytes::copy_from_slice(&data))
         }
-        DataLocation::Owned(data_size) => {
-            let path = options.owned_data_path(hash);
+        DataLocation::Owned(data_size) | DataLocation::Linked(data_size) => {
+            let path = options.path.owned_data_path(hash);
             let Ok(file) = std::fs::File::open(&path) else {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -2403,6 +3037,7 @@ fn load_data(
                 )
                 .into());
             };
+            let file = CryptFile::new(file, options.data_keystream(hash));
             MemOrFile::File((file, data_size))
         }
         DataLocation::External(paths, data_size) => {
@@ -2419,18 +3054,19 @@ fn load_data(
                 )
                 .into());
             };
-            MemOrFile::File((file, data_size))
+            // external files are not owned by the store, so they are never encrypted
+            MemOrFile::File((CryptFile::new(file, None), data_size))
         }
     })
 }
 
 fn load_outboard(
     tables: &impl ReadableTables,
-    options: &PathOptions,
+    options: &Options,
     location: OutboardLocation,
     size: u64,
     hash: &Hash,
-) -> ActorResult<MemOrFile<Bytes, (std::fs::File, u64)>> {
+) -> ActorResult<MemOrFile<Bytes, (CryptFile, u64)>> {
     Ok(match location {
         OutboardLocation::NotNeeded => MemOrFile::Mem(Bytes::new()),
         OutboardLocation::Inline(_) => {
@@ -2440,11 +3076,13 @@ fn load_outboard(
                     hash.to_hex()
                 )));
             };
-            MemOrFile::Mem(Bytes::copy_from_slice(outboard.value()))
+            let keystream = options.outboard_keystream(hash);
+            let outboard = apply_inline(keystream.as_ref(), outboard.value());
+            MemOrFile::Mem(Bytes::copy_from_slice(&outboard))
         }
         OutboardLocation::Owned => {
             let outboard_size = raw_outboard_size(size);
-            let path = options.owned_outboard_path(hash);
+            let path = options.path.owned_outboard_path(hash);
             let Ok(file) = std::fs::File::open(&path) else {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -2452,6 +3090,7 @@ fn load_outboard(
                 )
                 .into());
             };
+            let file = CryptFile::new(file, options.outboard_keystream(hash));
             MemOrFile::File((file, outboard_size))
         }
     })
@@ -2461,10 +3100,11 @@ fn load_outboard(
 fn complete_storage(
     storage: BaoFileStorage,
     hash: &Hash,
-    path_options: &PathOptions,
-    inline_options: &InlineOptions,
+    options: &Options,
     delete_after_commit: &mut DeleteSet,
 ) -> ActorResult<std::result::Result<CompleteStorage, CompleteStorage>> {
+    let path_options = &options.path;
+    let inline_options = &options.inline;
     let (data, outboard, _sizes) = match storage {
         BaoFileStorage::Complete(c) => return Ok(Err(c)),
         BaoFileStorage::IncompleteMem(storage) => {
@@ -2506,7 +3146,8 @@ fn complete_storage(
         match data {
             MemOrFile::Mem(data) => {
                 let path = path_options.owned_data_path(hash);
-                let file = overwrite_and_sync(&path, &data)?;
+                let file =
+                    CryptFile::overwrite_and_sync(&path, &data, options.data_keystream(hash))?;
                 MemOrFile::File((file, data_size))
             }
             MemOrFile::File(data) => MemOrFile::File((data, data_size)),
@@ -2533,7 +3174,11 @@ fn complete_storage(
         match outboard {
             MemOrFile::Mem(outboard) => {
                 let path = path_options.owned_outboard_path(hash);
-                let file = overwrite_and_sync(&path, &outboard)?;
+                let file = CryptFile::overwrite_and_sync(
+                    &path,
+                    &outboard,
+                    options.outboard_keystream(hash),
+                )?;
                 MemOrFile::File((file, outboard_size))
             }
             MemOrFile::File(outboard) => MemOrFile::File((outboard, outboard_size)),
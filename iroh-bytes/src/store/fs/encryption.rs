@@ -0,0 +1,493 @@
+//! At-rest encryption for the file system store.
+//!
+//! Data and outboard files as well as inline data and outboards are encrypted
+//! by xoring them with a keystream. The keystream is the extended output of a
+//! blake3 keyed hash, with a key derived from the store key, the part (data or
+//! outboard) and the hash of the entry. The content of a part of an entry at a
+//! given offset never changes, so using the same keystream for every write
+//! does not leak anything. Encryption preserves sizes and offsets, so the store
+//! can keep reading and writing ranges of files.
+//!
+//! This only provides confidentiality. The encryption of data and outboards is
+//! malleable: flipping a bit of an encrypted file flips the same bit of the
+//! plaintext, and the store does not verify the data it reads against the
+//! outboard. Tampering with encrypted data is therefore not detected locally,
+//! only by peers that fetch the data and verify it against its hash.
+//!
+//! Tags can be overwritten, so they are encrypted with a random nonce and
+//! authenticated. They are stored in a separate table, keyed by a keyed hash of
+//! the tag name so they can still be looked up. Blob metadata is encrypted the
+//! same way, but keyed by the hash of the blob.
+//!
+//! Not encrypted are the file names, which contain the hash, the sizes files of
+//! partial entries, files in the temp directory while an import is in progress,
+//! and externally referenced files, which are not owned by the store.
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::{File, Metadata, OpenOptions},
+    io,
+    path::Path,
+};
+
+use bao_tree::{
+    blake3,
+    io::sync::{ReadAt, Size, WriteAt},
+};
+use iroh_base::hash::{Hash, HashAndFormat};
+use redb::ReadableTable;
+
+use super::{
+    tables::{Tables, ENCRYPTION_TABLE},
+    util::overwrite_and_sync,
+    ActorResult, DataLocation, EntryState, OutboardLocation, PathOptions,
+};
+use crate::Tag;
+
+/// Suffix of files that were encrypted by the migration but not yet moved into place.
+const MIGRATION_SUFFIX: &str = ".encrypted";
+
+/// Length of the random nonce of an encrypted tag or blob metadata.
+const NONCE_LEN: usize = 16;
+
+/// A key to encrypt the content of a [`Store`](super::Store) at rest.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl EncryptionKey {
+    /// Generate a new random key.
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    /// The raw key material.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn subkey(&self, purpose: &[u8]) -> [u8; 32] {
+        blake3::keyed_hash(&self.0, purpose).into()
+    }
+
+    fn part_keystream(&self, part: &[u8], hash: &Hash) -> Keystream {
+        let mut hasher = blake3::Hasher::new_keyed(&self.0);
+        hasher.update(part);
+        hasher.update(hash.as_bytes());
+        Keystream(hasher.finalize().into())
+    }
+
+    /// The keystream for the data of an entry, in a file or inline.
+    pub(crate) fn data_keystream(&self, hash: &Hash) -> Keystream {
+        self.part_keystream(b"data", hash)
+    }
+
+    /// The keystream for the outboard of an entry, in a file or inline.
+    pub(crate) fn outboard_keystream(&self, hash: &Hash) -> Keystream {
+        self.part_keystream(b"outboard", hash)
+    }
+
+    /// A value to check that a store is opened with the key it was encrypted with.
+    fn check_value(&self) -> [u8; 32] {
+        self.subkey(b"check")
+    }
+
+    /// The key of a tag in the encrypted tags table.
+    pub(super) fn tag_id(&self, tag: &Tag) -> [u8; 32] {
+        blake3::keyed_hash(&self.subkey(b"tag id"), &tag.0).into()
+    }
+
+    /// Encrypt and authenticate a tag and its value.
+    pub(super) fn seal_tag(&self, tag: &Tag, value: &HashAndFormat) -> Vec<u8> {
+        let plaintext = postcard::to_stdvec(&(tag, value)).expect("tags can be serialized");
+        self.seal(b"tag", b"tag mac", plaintext)
+    }
+
+    /// Decrypt a tag and its value sealed with [`Self::seal_tag`].
+    pub(super) fn open_tag(&self, sealed: &[u8]) -> io::Result<(Tag, HashAndFormat)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid encrypted tag");
+        let plaintext = self.open(b"tag", b"tag mac", sealed).ok_or_else(invalid)?;
+        postcard::from_bytes(&plaintext).map_err(|_| invalid())
+    }
+
+    /// Encrypt and authenticate the serialized metadata of a blob.
+    pub(super) fn seal_meta(&self, meta: Vec<u8>) -> Vec<u8> {
+        self.seal(b"meta", b"meta mac", meta)
+    }
+
+    /// Decrypt the serialized metadata of a blob sealed with [`Self::seal_meta`].
+    pub(super) fn open_meta(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        self.open(b"meta", b"meta mac", sealed)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid encrypted meta"))
+    }
+
+    /// Encrypt `plaintext` with a random nonce and append a mac.
+    fn seal(&self, purpose: &[u8], mac_purpose: &[u8], mut plaintext: Vec<u8>) -> Vec<u8> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let keystream = Keystream(blake3::keyed_hash(&self.subkey(purpose), &nonce).into());
+        keystream.apply(0, &mut plaintext);
+        let mut res = nonce.to_vec();
+        res.extend(plaintext);
+        let mac = blake3::keyed_hash(&self.subkey(mac_purpose), &res);
+        res.extend_from_slice(mac.as_bytes());
+        res
+    }
+
+    /// Check the mac of a value sealed with [`Self::seal`] and decrypt it.
+    fn open(&self, purpose: &[u8], mac_purpose: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let split = sealed.len().checked_sub(blake3::OUT_LEN)?;
+        let (body, mac) = sealed.split_at(split);
+        let mac: [u8; blake3::OUT_LEN] = mac.try_into().expect("just checked the length");
+        // comparing blake3 hashes is constant time
+        if body.len() < NONCE_LEN
+            || blake3::keyed_hash(&self.subkey(mac_purpose), body) != blake3::Hash::from(mac)
+        {
+            return None;
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let keystream = Keystream(blake3::keyed_hash(&self.subkey(purpose), nonce).into());
+        let mut plaintext = ciphertext.to_vec();
+        keystream.apply(0, &mut plaintext);
+        Some(plaintext)
+    }
+}
+
+/// A keystream to encrypt or decrypt one part of an entry.
+#[derive(Clone)]
+pub struct Keystream([u8; 32]);
+
+impl fmt::Debug for Keystream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Keystream(..)")
+    }
+}
+
+impl Keystream {
+    /// Encrypt or decrypt `buf`, which starts at `offset` within the part.
+    pub fn apply(&self, offset: u64, buf: &mut [u8]) {
+        let mut reader = blake3::Hasher::new_keyed(&self.0).finalize_xof();
+        reader.set_position(offset);
+        let mut block = [0u8; 4096];
+        for chunk in buf.chunks_mut(block.len()) {
+            let block = &mut block[..chunk.len()];
+            reader.fill(block);
+            for (byte, key) in chunk.iter_mut().zip(block.iter()) {
+                *byte ^= key;
+            }
+        }
+    }
+}
+
+/// Encrypt or decrypt an inline value if there is a keystream.
+pub(crate) fn apply_inline<'a>(keystream: Option<&Keystream>, data: &'a [u8]) -> Cow<'a, [u8]> {
+    match keystream {
+        Some(keystream) => {
+            let mut data = data.to_vec();
+            keystream.apply(0, &mut data);
+            Cow::Owned(data)
+        }
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// A data or outboard file, transparently encrypted if it has a keystream.
+#[derive(derive_more::Debug)]
+pub struct CryptFile {
+    file: File,
+    #[debug(skip)]
+    keystream: Option<Keystream>,
+}
+
+impl CryptFile {
+    /// Wrap a file whose content is encrypted with `keystream`, if set.
+    pub fn new(file: File, keystream: Option<Keystream>) -> Self {
+        Self { file, keystream }
+    }
+
+    /// Overwrite the file at `path` with `data` and sync it, see [`overwrite_and_sync`].
+    pub fn overwrite_and_sync(
+        path: &Path,
+        data: &[u8],
+        keystream: Option<Keystream>,
+    ) -> io::Result<Self> {
+        let file = overwrite_and_sync(path, &apply_inline(keystream.as_ref(), data))?;
+        Ok(Self::new(file, keystream))
+    }
+
+    /// Metadata of the underlying file.
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.file.metadata()
+    }
+
+    /// Sync the underlying file to disk.
+    pub fn sync_all(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+impl ReadAt for CryptFile {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read_at(pos, buf)?;
+        if let Some(keystream) = &self.keystream {
+            keystream.apply(pos, &mut buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl WriteAt for CryptFile {
+    fn write_at(&mut self, pos: u64, buf: &[u8]) -> io::Result<usize> {
+        match &self.keystream {
+            Some(keystream) => {
+                let mut encrypted = buf.to_vec();
+                keystream.apply(pos, &mut encrypted);
+                self.file.write_all_at(pos, &encrypted)?;
+                Ok(buf.len())
+            }
+            None => self.file.write_at(pos, buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        WriteAt::flush(&mut self.file)
+    }
+}
+
+impl Size for CryptFile {
+    fn size(&self) -> io::Result<Option<u64>> {
+        self.file.size()
+    }
+}
+
+/// Copy a file, encrypting or decrypting it with the keystream on the way.
+pub(super) fn copy_with_keystream(
+    source: &Path,
+    target: &Path,
+    keystream: &Keystream,
+) -> io::Result<()> {
+    let source = File::open(source)?;
+    let mut target = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(target)?;
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = source.read_at(offset, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        keystream.apply(offset, &mut buf[..n]);
+        target.write_all_at(offset, &buf[..n])?;
+        offset += n as u64;
+    }
+    target.sync_all()
+}
+
+/// Check that the store is opened with the right key, if it is encrypted.
+///
+/// If a key is given for a store that is not encrypted yet, the store is
+/// encrypted in place.
+pub(super) fn init(
+    db: &redb::Database,
+    options: &PathOptions,
+    key: Option<&EncryptionKey>,
+) -> ActorResult<()> {
+    let txn = db.begin_read()?;
+    let check = match txn.open_table(ENCRYPTION_TABLE) {
+        Ok(table) => table.get(())?.map(|x| x.value()),
+        Err(redb::TableError::TableDoesNotExist(_)) => None,
+        Err(cause) => return Err(cause.into()),
+    };
+    drop(txn);
+    match (check, key) {
+        (None, None) => Ok(()),
+        (Some(_), None) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the store is encrypted, but no encryption key was given",
+        )
+        .into()),
+        (Some(check), Some(key)) => {
+            if check != key.check_value() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "wrong encryption key for the store",
+                )
+                .into());
+            }
+            finish_migration(options)?;
+            Ok(())
+        }
+        (None, Some(key)) => migrate(db, options, key),
+    }
+}
+
+/// Encrypt an unencrypted store in place.
+///
+/// Files are first encrypted into the temp directory, then the database is
+/// updated in a single transaction, and only then the encrypted files are moved
+/// into place. If this is interrupted before the commit, it will start over the
+/// next time the store is opened with a key. If it is interrupted after the
+/// commit, the remaining files are moved into place the next time.
+///
+/// This needs as much free space as the data and outboard files of the store.
+fn migrate(db: &redb::Database, options: &PathOptions, key: &EncryptionKey) -> ActorResult<()> {
+    tracing::info!("encrypting store in place");
+    // remove leftovers of an earlier attempt that was interrupted before the commit
+    remove_migration_files(options)?;
+    let mut delete_after_commit = Default::default();
+    let txn = db.begin_write()?;
+    {
+        let mut tables = Tables::new(&txn, &mut delete_after_commit)?;
+        let mut inline_data = Vec::new();
+        let mut inline_outboard = Vec::new();
+        let mut linked = Vec::new();
+        for item in tables.blobs.iter()? {
+            let (hash, entry) = item?;
+            let hash = hash.value();
+            let (data, outboard) = match entry.value() {
+                EntryState::Complete {
+                    data_location,
+                    outboard_location,
+                } => (
+                    match data_location {
+                        DataLocation::Inline(()) => {
+                            inline_data.push(hash);
+                            false
+                        }
+                        DataLocation::Owned(_) => true,
+                        DataLocation::Linked(size) => {
+                            // the encrypted copy replaces the link
+                            linked.push((hash, size));
+                            true
+                        }
+                        // external files are not owned by the store
+                        DataLocation::External(_, _) => false,
+                    },
+                    match outboard_location {
+                        OutboardLocation::Inline(()) => {
+                            inline_outboard.push(hash);
+                            false
+                        }
+                        OutboardLocation::Owned => true,
+                        OutboardLocation::NotNeeded => false,
+                    },
+                ),
+                EntryState::Partial { .. } => (true, true),
+            };
+            if data {
+                let path = options.owned_data_path(&hash);
+                encrypt_into_temp(options, &path, &key.data_keystream(&hash))?;
+            }
+            if outboard {
+                let path = options.owned_outboard_path(&hash);
+                encrypt_into_temp(options, &path, &key.outboard_keystream(&hash))?;
+            }
+        }
+        for (hash, size) in linked {
+            let entry = tables.blobs.get(hash)?.map(|x| x.value());
+            if let Some(EntryState::Complete {
+                outboard_location, ..
+            }) = entry
+            {
+                let entry = EntryState::Complete {
+                    data_location: DataLocation::Owned(size),
+                    outboard_location,
+                };
+                tables.blobs.insert(hash, entry)?;
+            }
+        }
+        for hash in inline_data {
+            let data = tables.inline_data.get(hash)?.map(|x| x.value().to_vec());
+            if let Some(mut data) = data {
+                key.data_keystream(&hash).apply(0, &mut data);
+                tables.inline_data.insert(hash, data.as_slice())?;
+            }
+        }
+        for hash in inline_outboard {
+            let outboard = tables
+                .inline_outboard
+                .get(hash)?
+                .map(|x| x.value().to_vec());
+            if let Some(mut outboard) = outboard {
+                key.outboard_keystream(&hash).apply(0, &mut outboard);
+                tables.inline_outboard.insert(hash, outboard.as_slice())?;
+            }
+        }
+        let tags = tables
+            .tags
+            .iter()?
+            .map(|item| item.map(|(k, v)| (k.value(), v.value())))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (tag, value) in tags {
+            tables
+                .encrypted_tags
+                .insert(key.tag_id(&tag), key.seal_tag(&tag, &value).as_slice())?;
+            tables.tags.remove(tag)?;
+        }
+        let mut check = txn.open_table(ENCRYPTION_TABLE)?;
+        check.insert((), key.check_value())?;
+    }
+    txn.commit()?;
+    finish_migration(options)?;
+    tracing::info!("encrypting store done");
+    Ok(())
+}
+
+/// Write an encrypted copy of an owned file to the temp directory.
+fn encrypt_into_temp(options: &PathOptions, path: &Path, keystream: &Keystream) -> io::Result<()> {
+    let name = path.file_name().expect("owned files have a name");
+    let mut target = name.to_owned();
+    target.push(MIGRATION_SUFFIX);
+    match copy_with_keystream(path, &options.temp_path.join(target), keystream) {
+        // partial entries might not have all of their files yet
+        Err(cause) if cause.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// Paths of encrypted files left in the temp directory by the migration, and their targets.
+fn migration_files(options: &PathOptions) -> io::Result<Vec<(std::path::PathBuf, String)>> {
+    let mut res = Vec::new();
+    for entry in std::fs::read_dir(&options.temp_path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(target) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(MIGRATION_SUFFIX))
+        else {
+            continue;
+        };
+        res.push((entry.path(), target.to_owned()));
+    }
+    Ok(res)
+}
+
+fn remove_migration_files(options: &PathOptions) -> io::Result<()> {
+    for (path, _) in migration_files(options)? {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Move the files encrypted by the migration into place.
+fn finish_migration(options: &PathOptions) -> ActorResult<()> {
+    for (path, target) in migration_files(options)? {
+        let target = options.data_path.join(target);
+        tracing::debug!("moving encrypted file into place: {}", target.display());
+        std::fs::rename(&path, &target)?;
+    }
+    Ok(())
+}
@@ -124,6 +124,15 @@ impl ActorState {
             partial: partial_path,
             meta: meta_path,
         } = &paths;
+        if self.options.encryption.is_some() {
+            // the flat store is not encrypted, and importing it would add unencrypted files
+            if complete_path.exists() || partial_path.exists() || meta_path.exists() {
+                tracing::warn!(
+                    "not importing flat store into an encrypted store, import it before enabling encryption"
+                );
+            }
+            return Ok(false);
+        }
         let mut index = BTreeMap::<Hash, EntryPaths>::new();
         let mut have_partial = false;
         let mut have_complete = false;
@@ -12,6 +12,21 @@ pub(super) const BLOBS_TABLE: TableDefinition<Hash, EntryState> = TableDefinitio
 
 pub(super) const TAGS_TABLE: TableDefinition<Tag, HashAndFormat> = TableDefinition::new("tags-0");
 
+pub(super) const ENCRYPTED_TAGS_TABLE: TableDefinition<[u8; 32], &[u8]> =
+    TableDefinition::new("encrypted-tags-0");
+
+pub(super) const ENCRYPTION_TABLE: TableDefinition<(), [u8; 32]> =
+    TableDefinition::new("encryption-0");
+
+/// Milliseconds since the unix epoch at which a blob was last imported and
+/// served, 0 if it was never served.
+pub(super) const BLOB_ACCESS_TABLE: TableDefinition<Hash, (u64, u64)> =
+    TableDefinition::new("blob-access-0");
+
+/// Metadata of blobs, serialized with postcard and sealed if the store is encrypted.
+pub(super) const BLOB_META_TABLE: TableDefinition<Hash, &[u8]> =
+    TableDefinition::new("blob-meta-0");
+
 pub(super) const INLINE_DATA_TABLE: TableDefinition<Hash, &[u8]> =
     TableDefinition::new("inline-data-0");
 
@@ -24,8 +39,11 @@ pub(super) const INLINE_OUTBOARD_TABLE: TableDefinition<Hash, &[u8]> =
 pub(super) trait ReadableTables {
     fn blobs(&self) -> &impl ReadableTable<Hash, EntryState>;
     fn tags(&self) -> &impl ReadableTable<Tag, HashAndFormat>;
+    fn encrypted_tags(&self) -> &impl ReadableTable<[u8; 32], &'static [u8]>;
     fn inline_data(&self) -> &impl ReadableTable<Hash, &'static [u8]>;
     fn inline_outboard(&self) -> &impl ReadableTable<Hash, &'static [u8]>;
+    fn blob_access(&self) -> &impl ReadableTable<Hash, (u64, u64)>;
+    fn blob_meta(&self) -> &impl ReadableTable<Hash, &'static [u8]>;
 }
 
 /// A struct similar to [`redb::Table`] but for all tables that make up the
@@ -33,8 +51,11 @@ pub(super) trait ReadableTables {
 pub(super) struct Tables<'a> {
     pub blobs: redb::Table<'a, Hash, EntryState>,
     pub tags: redb::Table<'a, Tag, HashAndFormat>,
+    pub encrypted_tags: redb::Table<'a, [u8; 32], &'static [u8]>,
     pub inline_data: redb::Table<'a, Hash, &'static [u8]>,
     pub inline_outboard: redb::Table<'a, Hash, &'static [u8]>,
+    pub blob_access: redb::Table<'a, Hash, (u64, u64)>,
+    pub blob_meta: redb::Table<'a, Hash, &'static [u8]>,
     pub delete_after_commit: &'a mut DeleteSet,
 }
 
@@ -53,8 +74,11 @@ impl<'txn> Tables<'txn> {
         Ok(Self {
             blobs: tx.open_table(BLOBS_TABLE)?,
             tags: tx.open_table(TAGS_TABLE)?,
+            encrypted_tags: tx.open_table(ENCRYPTED_TAGS_TABLE)?,
             inline_data: tx.open_table(INLINE_DATA_TABLE)?,
             inline_outboard: tx.open_table(INLINE_OUTBOARD_TABLE)?,
+            blob_access: tx.open_table(BLOB_ACCESS_TABLE)?,
+            blob_meta: tx.open_table(BLOB_META_TABLE)?,
             delete_after_commit,
         })
     }
@@ -67,12 +91,21 @@ impl ReadableTables for Tables<'_> {
     fn tags(&self) -> &impl ReadableTable<Tag, HashAndFormat> {
         &self.tags
     }
+    fn encrypted_tags(&self) -> &impl ReadableTable<[u8; 32], &'static [u8]> {
+        &self.encrypted_tags
+    }
     fn inline_data(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
         &self.inline_data
     }
     fn inline_outboard(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
         &self.inline_outboard
     }
+    fn blob_access(&self) -> &impl ReadableTable<Hash, (u64, u64)> {
+        &self.blob_access
+    }
+    fn blob_meta(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
+        &self.blob_meta
+    }
 }
 
 /// A struct similar to [`redb::ReadOnlyTable`] but for all tables that make up
@@ -80,8 +113,11 @@ impl ReadableTables for Tables<'_> {
 pub(super) struct ReadOnlyTables {
     pub blobs: redb::ReadOnlyTable<Hash, EntryState>,
     pub tags: redb::ReadOnlyTable<Tag, HashAndFormat>,
+    pub encrypted_tags: redb::ReadOnlyTable<[u8; 32], &'static [u8]>,
     pub inline_data: redb::ReadOnlyTable<Hash, &'static [u8]>,
     pub inline_outboard: redb::ReadOnlyTable<Hash, &'static [u8]>,
+    pub blob_access: redb::ReadOnlyTable<Hash, (u64, u64)>,
+    pub blob_meta: redb::ReadOnlyTable<Hash, &'static [u8]>,
 }
 
 impl<'txn> ReadOnlyTables {
@@ -89,8 +125,11 @@ impl<'txn> ReadOnlyTables {
         Ok(Self {
             blobs: tx.open_table(BLOBS_TABLE)?,
             tags: tx.open_table(TAGS_TABLE)?,
+            encrypted_tags: tx.open_table(ENCRYPTED_TAGS_TABLE)?,
             inline_data: tx.open_table(INLINE_DATA_TABLE)?,
             inline_outboard: tx.open_table(INLINE_OUTBOARD_TABLE)?,
+            blob_access: tx.open_table(BLOB_ACCESS_TABLE)?,
+            blob_meta: tx.open_table(BLOB_META_TABLE)?,
         })
     }
 }
@@ -102,12 +141,21 @@ impl ReadableTables for ReadOnlyTables {
     fn tags(&self) -> &impl ReadableTable<Tag, HashAndFormat> {
         &self.tags
     }
+    fn encrypted_tags(&self) -> &impl ReadableTable<[u8; 32], &'static [u8]> {
+        &self.encrypted_tags
+    }
     fn inline_data(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
         &self.inline_data
     }
     fn inline_outboard(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
         &self.inline_outboard
     }
+    fn blob_access(&self) -> &impl ReadableTable<Hash, (u64, u64)> {
+        &self.blob_access
+    }
+    fn blob_meta(&self) -> &impl ReadableTable<Hash, &'static [u8]> {
+        &self.blob_meta
+    }
 }
 
 /// Helper to keep track of files to delete after a transaction is committed.
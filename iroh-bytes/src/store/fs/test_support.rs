@@ -183,7 +183,7 @@ impl ActorState {
                             }
                             res
                         }
-                        DataLocation::Owned(size) => {
+                        DataLocation::Owned(size) | DataLocation::Linked(size) => {
                             let res = std::fs::read(data_path)?;
                             if res.len() != size as usize {
                                 return Err(ActorError::Inconsistent(
@@ -320,6 +320,7 @@ impl ActorState {
                             }
                             DataLocation::Owned(x) => DataLocation::Owned(x),
                             DataLocation::External(p, s) => DataLocation::External(p, s),
+                            DataLocation::Linked(x) => DataLocation::Linked(x),
                         };
                         let outboard_location = match outboard_location {
                             OutboardLocation::Inline(()) => {
@@ -1,11 +1,14 @@
-use bao_tree::ChunkRanges;
+use bao_tree::{ChunkNum, ChunkRanges};
 use iroh_io::AsyncSliceReaderExt;
 use std::io::Cursor;
 
 use crate::store::bao_file::test_support::{
     decode_response_into_batch, make_wire_data, random_test_data, simulate_remote, validate,
 };
+use crate::store::ExportFormat;
 use crate::store::{Map as _, MapEntryMut, MapMut, ReadableStore, Store as _};
+use crate::util::chunker::MAX_CHUNK_SIZE;
+use crate::util::progress::FlumeProgressSender;
 use crate::util::raw_outboard;
 
 macro_rules! assert_matches {
@@ -52,6 +55,8 @@ async fn create_test_db() -> (tempfile::TempDir, Store) {
         path: PathOptions::new(testdir.path()),
         batch: Default::default(),
         inline: Default::default(),
+        encryption: None,
+        quota: None,
     };
     let db = Store::new(db_path, options).await.unwrap();
     (testdir, db)
@@ -472,6 +477,58 @@ async fn import_file_reference_cases() {
     }
 }
 
+#[tokio::test]
+async fn import_file_chunked() {
+    let (tempdir, db) = create_test_db().await;
+    let import = |data: Vec<u8>| {
+        let db = db.clone();
+        let path = tempdir.path().join("chunked.data");
+        std::fs::write(&path, data).unwrap();
+        async move {
+            let (tx, rx) = flume::unbounded();
+            let progress = FlumeProgressSender::new(tx);
+            let (tt, size) = db
+                .import_file(path, ImportMode::Chunked, BlobFormat::Raw, progress)
+                .await
+                .unwrap();
+            let stored = rx
+                .drain()
+                .find_map(|msg| match msg {
+                    ImportProgress::ChunkingDone { stored, .. } => Some(stored),
+                    _ => None,
+                })
+                .unwrap();
+            (tt, size, stored)
+        }
+    };
+    let data = random_test_data(LARGE_SIZE as usize).to_vec();
+    let (tt, size, stored) = import(data.clone()).await;
+    assert_eq!(tt.format(), BlobFormat::HashSeq);
+    assert_eq!(size, LARGE_SIZE);
+    assert_eq!(stored, LARGE_SIZE);
+
+    // a modified version only stores the changed chunks, the change can move a boundary
+    let mut modified = data;
+    modified[LARGE_SIZE as usize / 2] ^= 0xff;
+    let (tt, size, stored) = import(modified.clone()).await;
+    assert_eq!(size, LARGE_SIZE);
+    assert!(stored <= 2 * MAX_CHUNK_SIZE as u64, "{stored}");
+
+    // exporting reassembles the file
+    let path = tempdir.path().join("exported.data");
+    crate::export::export(
+        &db,
+        *tt.hash(),
+        path.clone(),
+        ExportFormat::Chunked,
+        ExportMode::Copy,
+        IgnoreProgressSender::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(modified, std::fs::read(path).unwrap());
+}
+
 #[tokio::test]
 async fn import_file_error_cases() {
     let np = IgnoreProgressSender::<ImportProgress>::default;
@@ -633,6 +690,60 @@ async fn import_file_overwrite() {
     }
 }
 
+/// tests that read-only files are linked instead of copied, and that deleting
+/// the entry leaves the linked file alone
+#[tokio::test]
+async fn import_export_link() {
+    let np = IgnoreProgressSender::<ImportProgress>::default;
+    let (tempdir, db) = create_test_db().await;
+    let path = tempdir.path().join("large.data");
+    let data = random_test_data(LARGE_SIZE as usize);
+    std::fs::write(&path, &data).unwrap();
+    let mut permissions = path.metadata().unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    let (tt, _) = db
+        .import_file(path.clone(), ImportMode::Copy, BlobFormat::Raw, np())
+        .await
+        .unwrap();
+    let hash = *tt.hash();
+    let data_path = PathOptions::new(tempdir.path()).owned_data_path(&hash);
+    // depending on the file system, this is a reflink or a hard link
+    let state = db.entry_state(hash).await.unwrap();
+    let linked = match state.db {
+        Some(EntryState::Complete {
+            data_location: DataLocation::Linked(LARGE_SIZE),
+            ..
+        }) => true,
+        Some(EntryState::Complete {
+            data_location: DataLocation::Owned(LARGE_SIZE),
+            ..
+        }) => false,
+        state => panic!("unexpected entry state {state:?}"),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let ino = |path: &Path| path.metadata().unwrap().ino();
+        assert_eq!(ino(&path) == ino(&data_path), linked);
+    }
+
+    let target = tempdir.path().join("export.data");
+    db.export(hash, target.clone(), ExportMode::Copy, Box::new(|_| Ok(())))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), data);
+
+    drop(tt);
+    // entries imported since the last gc start are protected from deletion
+    db.gc_start().await.unwrap();
+    db.delete(vec![hash]).await.unwrap();
+    db.sync().await.unwrap();
+    assert!(!data_path.exists());
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert_eq!(std::fs::read(&target).unwrap(), data);
+}
+
 /// tests that export works in copy mode
 #[tokio::test]
 async fn export_copy_cases() {
@@ -773,6 +884,479 @@ async fn export_reference_cases() {
     );
 }
 
+async fn open_test_db(dir: &Path, encryption: Option<EncryptionKey>) -> io::Result<Store> {
+    let options = Options {
+        path: PathOptions::new(dir),
+        batch: Default::default(),
+        inline: Default::default(),
+        encryption,
+        quota: None,
+    };
+    Store::new(dir.join("db.redb"), options).await
+}
+
+/// Import small (data inline), mid (data file, outboard inline) and large (data file, outboard file) blobs and tag them
+async fn import_tagged_cases(db: &Store) -> Vec<(Hash, Bytes)> {
+    let mut res = Vec::new();
+    for (name, size) in [
+        ("small", SMALL_SIZE),
+        ("mid", MID_SIZE),
+        ("large", LARGE_SIZE),
+    ] {
+        let data = Bytes::from(random_test_data(size as usize));
+        let tt = db
+            .import_bytes(data.clone(), BlobFormat::Raw)
+            .await
+            .unwrap();
+        db.set_tag(Tag::from(name), Some(*tt.inner()))
+            .await
+            .unwrap();
+        res.push((*tt.hash(), data));
+    }
+    res
+}
+
+async fn assert_cases_readable(db: &Store, cases: &[(Hash, Bytes)]) {
+    for (hash, data) in cases {
+        let entry = db.get(hash).await.unwrap().expect("entry not found");
+        let actual = entry.data_reader().read_to_end().await.unwrap();
+        assert_eq!(&actual, data);
+        // the outboard is decrypted correctly
+        let valid = crate::get::db::entry_valid_ranges(&entry).await.unwrap();
+        let size = data.len() as u64;
+        assert_eq!(valid, ChunkRanges::from(..ChunkNum::full_chunks(size)));
+    }
+    let tags = db
+        .tags()
+        .await
+        .unwrap()
+        .map(|x| x.map(|(tag, value)| (tag, value.hash)))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    let expected = [("large", 2), ("mid", 1), ("small", 0)]
+        .into_iter()
+        .map(|(name, i)| (Tag::from(name), cases[i].0))
+        .collect::<Vec<_>>();
+    assert_eq!(tags, expected);
+}
+
+/// Check that no data or outboard of the cases is stored in plaintext
+async fn assert_cases_encrypted(db: &Store, cases: &[(Hash, Bytes)]) {
+    let [(small_hash, small), (mid_hash, mid), (large_hash, large)] = cases else {
+        panic!("unexpected cases");
+    };
+    let small_state = db.entry_state(*small_hash).await.unwrap().db;
+    let Some(EntryState::Complete {
+        data_location: DataLocation::Inline(small_inline),
+        ..
+    }) = small_state
+    else {
+        panic!("small data is not inline");
+    };
+    assert_eq!(small_inline.len(), small.len());
+    assert_ne!(&small_inline, small);
+    let mid_state = db.entry_state(*mid_hash).await.unwrap().db;
+    let Some(EntryState::Complete {
+        outboard_location: OutboardLocation::Inline(mid_outboard),
+        ..
+    }) = mid_state
+    else {
+        panic!("mid outboard is not inline");
+    };
+    assert_ne!(mid_outboard, raw_outboard(mid).0);
+    for (hash, data) in [(mid_hash, mid), (large_hash, large)] {
+        let on_disk = std::fs::read(db.owned_data_path(hash)).unwrap();
+        assert_eq!(on_disk.len(), data.len());
+        assert_ne!(&on_disk, data);
+    }
+    let on_disk = std::fs::read(db.owned_outboard_path(large_hash)).unwrap();
+    assert_ne!(on_disk, raw_outboard(large).0);
+}
+
+#[tokio::test]
+async fn encrypted_store() {
+    let np = || Box::new(|_: u64| io::Result::Ok(()));
+    let _ = tracing_subscriber::fmt::try_init();
+    let testdir = tempfile::tempdir().unwrap();
+    let key = EncryptionKey::generate();
+    let db = open_test_db(testdir.path(), Some(key.clone()))
+        .await
+        .unwrap();
+    let cases = import_tagged_cases(&db).await;
+    assert_cases_encrypted(&db, &cases).await;
+    assert_cases_readable(&db, &cases).await;
+
+    // exports are decrypted, and encrypted files are copied even in reference mode
+    let (large_hash, large) = &cases[2];
+    for (name, mode) in [
+        ("copy", ExportMode::Copy),
+        ("ref", ExportMode::TryReference),
+    ] {
+        let target = testdir.path().join(format!("large.{name}"));
+        db.export(*large_hash, target.clone(), mode, np())
+            .await
+            .unwrap();
+        assert_eq!(&std::fs::read(&target).unwrap(), large);
+    }
+    let state = db.entry_state(*large_hash).await.unwrap().db;
+    assert_matches!(
+        state,
+        Some(EntryState::Complete {
+            data_location: DataLocation::Owned(LARGE_SIZE),
+            ..
+        })
+    );
+
+    // data received from a remote is encrypted as well
+    let data = random_test_data(1024 * 1024);
+    #[allow(clippy::single_range_in_vec_init)]
+    let ranges = [0..data.len() as u64];
+    let (hash, chunk_ranges, wire_data) = make_wire_data(&data, &ranges);
+    let handle = db.get_or_create(hash, 0).await.unwrap();
+    decode_response_into_batch(
+        hash,
+        IROH_BLOCK_SIZE,
+        chunk_ranges,
+        Cursor::new(wire_data.as_slice()),
+        handle.batch_writer().await.unwrap(),
+    )
+    .await
+    .unwrap();
+    validate(&handle, &data, &ranges).await;
+    db.insert_complete(handle).await.unwrap();
+    assert_ne!(std::fs::read(db.owned_data_path(&hash)).unwrap(), data);
+    db.shutdown().await;
+    drop(db);
+
+    // the store can only be opened with the right key
+    let res = open_test_db(testdir.path(), None).await;
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    let res = open_test_db(testdir.path(), Some(EncryptionKey::generate())).await;
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    let db = open_test_db(testdir.path(), Some(key)).await.unwrap();
+    assert_cases_readable(&db, &cases).await;
+    let entry = db.get(&hash).await.unwrap().expect("entry not found");
+    assert_eq!(entry.data_reader().read_to_end().await.unwrap(), data);
+}
+
+#[tokio::test]
+async fn encrypt_existing_store() {
+    let _ = tracing_subscriber::fmt::try_init();
+    let testdir = tempfile::tempdir().unwrap();
+    let db = open_test_db(testdir.path(), None).await.unwrap();
+    let cases = import_tagged_cases(&db).await;
+    db.shutdown().await;
+    drop(db);
+
+    let key = EncryptionKey::generate();
+    let db = open_test_db(testdir.path(), Some(key)).await.unwrap();
+    assert_cases_encrypted(&db, &cases).await;
+    assert_cases_readable(&db, &cases).await;
+    // all encrypted files were moved into place
+    let temp_files = std::fs::read_dir(testdir.path().join("temp"))
+        .unwrap()
+        .count();
+    assert_eq!(temp_files, 0);
+}
+
+#[tokio::test]
+async fn compare_and_swap_tag() {
+    let _ = tracing_subscriber::fmt::try_init();
+    for encryption in [None, Some(EncryptionKey::generate())] {
+        let testdir = tempfile::tempdir().unwrap();
+        let db = open_test_db(testdir.path(), encryption).await.unwrap();
+        let tag = Tag::from("builds/latest");
+        let a = HashAndFormat::raw(Hash::new(b"a"));
+        let b = HashAndFormat::raw(Hash::new(b"b"));
+        // create only if absent
+        assert_eq!(
+            db.compare_and_swap_tag(tag.clone(), None, Some(a))
+                .await
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            db.compare_and_swap_tag(tag.clone(), None, Some(b))
+                .await
+                .unwrap(),
+            Err(Some(a))
+        );
+        // replace the expected value
+        assert_eq!(
+            db.compare_and_swap_tag(tag.clone(), Some(b), Some(a))
+                .await
+                .unwrap(),
+            Err(Some(a))
+        );
+        assert_eq!(
+            db.compare_and_swap_tag(tag.clone(), Some(a), Some(b))
+                .await
+                .unwrap(),
+            Ok(())
+        );
+        let tags = db
+            .tags()
+            .await
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(tags, vec![(tag.clone(), b)]);
+        // delete the expected value
+        assert_eq!(
+            db.compare_and_swap_tag(tag.clone(), Some(b), None)
+                .await
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            db.compare_and_swap_tag(tag, Some(b), None).await.unwrap(),
+            Err(None)
+        );
+        assert_eq!(db.tags().await.unwrap().count(), 0);
+    }
+}
+
+#[tokio::test]
+async fn rename_and_list_tags() {
+    let _ = tracing_subscriber::fmt::try_init();
+    for encryption in [None, Some(EncryptionKey::generate())] {
+        let testdir = tempfile::tempdir().unwrap();
+        let db = open_test_db(testdir.path(), encryption).await.unwrap();
+        let a = HashAndFormat::raw(Hash::new(b"a"));
+        let b = HashAndFormat::raw(Hash::new(b"b"));
+        db.set_tag(Tag::from("builds/1"), Some(a)).await.unwrap();
+        db.set_tag(Tag::from("builds/2"), Some(b)).await.unwrap();
+        db.set_tag(Tag::from("buildsx"), Some(b)).await.unwrap();
+        db.set_tag(Tag::from("other"), Some(a)).await.unwrap();
+        let list = |prefix: &'static str| {
+            let db = db.clone();
+            async move {
+                db.tags_with_prefix(Tag::from(prefix))
+                    .await
+                    .unwrap()
+                    .map(|item| item.map(|(name, _)| name))
+                    .collect::<io::Result<Vec<_>>>()
+                    .unwrap()
+            }
+        };
+        assert_eq!(
+            list("builds/").await,
+            vec![Tag::from("builds/1"), Tag::from("builds/2")]
+        );
+        assert_eq!(list("none").await, vec![]);
+        // renaming onto an existing tag or from a missing tag fails
+        let err = db
+            .rename_tag(Tag::from("builds/1"), Tag::from("builds/2"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = db
+            .rename_tag(Tag::from("missing"), Tag::from("builds/3"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        db.rename_tag(Tag::from("builds/1"), Tag::from("builds/3"))
+            .await
+            .unwrap();
+        assert_eq!(
+            list("builds/").await,
+            vec![Tag::from("builds/2"), Tag::from("builds/3")]
+        );
+        let tags = db
+            .tags()
+            .await
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert!(tags.contains(&(Tag::from("builds/3"), a)));
+        assert_eq!(tags.len(), 4);
+    }
+}
+
+/// tests that swapping the sealed values of two encrypted tags is detected
+#[tokio::test]
+async fn encrypted_tags_swapped() {
+    let _ = tracing_subscriber::fmt::try_init();
+    let testdir = tempfile::tempdir().unwrap();
+    let key = EncryptionKey::generate();
+    let db = open_test_db(testdir.path(), Some(key.clone()))
+        .await
+        .unwrap();
+    let a = HashAndFormat::raw(Hash::new(b"a"));
+    let b = HashAndFormat::raw(Hash::new(b"b"));
+    db.set_tag(Tag::from("a"), Some(a)).await.unwrap();
+    db.set_tag(Tag::from("b"), Some(b)).await.unwrap();
+    db.shutdown().await;
+    drop(db);
+
+    // swap the rows directly in the database
+    {
+        let redb = redb::Database::open(testdir.path().join("db.redb")).unwrap();
+        let tx = redb.begin_write().unwrap();
+        {
+            let mut table = tx.open_table(tables::ENCRYPTED_TAGS_TABLE).unwrap();
+            let id_a = key.tag_id(&Tag::from("a"));
+            let id_b = key.tag_id(&Tag::from("b"));
+            let sealed_a = table.get(id_a).unwrap().unwrap().value().to_vec();
+            let sealed_b = table.get(id_b).unwrap().unwrap().value().to_vec();
+            table.insert(id_a, sealed_b.as_slice()).unwrap();
+            table.insert(id_b, sealed_a.as_slice()).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    let db = open_test_db(testdir.path(), Some(key)).await.unwrap();
+    // row "a" now holds the value of "b", which must not be accepted as the value of "a"
+    let err = db
+        .compare_and_swap_tag(Tag::from("a"), Some(b), None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = db
+        .rename_tag(Tag::from("a"), Tag::from("c"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let tags = db.tags().await.unwrap().collect::<Vec<_>>();
+    assert_eq!(tags.len(), 2);
+    assert!(tags.iter().all(|item| item.is_err()));
+}
+
+#[tokio::test]
+async fn blob_meta() {
+    let _ = tracing_subscriber::fmt::try_init();
+    let testdir = tempfile::tempdir().unwrap();
+    let key = EncryptionKey::generate();
+    let db = open_test_db(testdir.path(), Some(key.clone()))
+        .await
+        .unwrap();
+    let tt = db
+        .import_bytes(Bytes::from_static(b"hello"), BlobFormat::Raw)
+        .await
+        .unwrap();
+    let hash = *tt.hash();
+    assert_eq!(db.blob_meta(&hash).await.unwrap(), None);
+    let annotate = |key: &str, value: Option<&str>| BlobMetaUpdate::Annotation {
+        key: key.to_string(),
+        value: value.map(ToString::to_string),
+    };
+    db.update_blob_meta(hash, BlobMetaUpdate::MimeType(Some("text/plain".into())))
+        .await
+        .unwrap();
+    db.update_blob_meta(hash, annotate("author", Some("alice")))
+        .await
+        .unwrap();
+    db.update_blob_meta(hash, annotate("draft", Some("yes")))
+        .await
+        .unwrap();
+    db.update_blob_meta(hash, annotate("draft", None))
+        .await
+        .unwrap();
+    // a MIME type detected when importing the blob again does not replace the one set
+    let (tt2, _) = db
+        .import_stream(
+            futures_lite::stream::once(Ok(Bytes::from_static(b"hello"))),
+            BlobFormat::Raw,
+            IgnoreProgressSender::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(*tt2.hash(), hash);
+    drop(tt2);
+    let expected = BlobMeta {
+        mime_type: Some("text/plain".into()),
+        annotations: [("author".to_string(), "alice".to_string())].into(),
+    };
+    assert_eq!(db.blob_meta(&hash).await.unwrap(), Some(expected.clone()));
+
+    // metadata can not be set for blobs that are not in the store
+    let missing = Hash::new(b"missing");
+    let err = db
+        .update_blob_meta(missing, annotate("author", Some("alice")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(db.blob_meta(&missing).await.unwrap(), None);
+    db.shutdown().await;
+    drop(db);
+
+    // metadata is persisted, and listed
+    let db = open_test_db(testdir.path(), Some(key)).await.unwrap();
+    let metas = db
+        .blob_metas()
+        .await
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(metas, vec![(hash, expected)]);
+
+    // clearing all metadata removes the entry, and so does deleting the blob
+    db.update_blob_meta(hash, BlobMetaUpdate::MimeType(None))
+        .await
+        .unwrap();
+    db.update_blob_meta(hash, annotate("author", None))
+        .await
+        .unwrap();
+    assert_eq!(db.blob_meta(&hash).await.unwrap(), None);
+    db.update_blob_meta(hash, annotate("author", Some("bob")))
+        .await
+        .unwrap();
+    drop(tt);
+    db.delete(vec![hash]).await.unwrap();
+    assert_eq!(db.blob_meta(&hash).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn blob_meta_detect_mime_type() {
+    let _ = tracing_subscriber::fmt::try_init();
+    let testdir = tempfile::tempdir().unwrap();
+    let db = open_test_db(testdir.path(), None).await.unwrap();
+    let mime_type = |hash: Hash| {
+        let db = &db;
+        async move { db.blob_meta(&hash).await.unwrap().and_then(|m| m.mime_type) }
+    };
+
+    // from the file name, for small and large files
+    for (name, size) in [("small.html", 100), ("large.html", 1024 * 1024)] {
+        let path = testdir.path().join(name);
+        std::fs::write(&path, vec![0u8; size]).unwrap();
+        let (tt, _) = db
+            .import_file(
+                path,
+                ImportMode::Copy,
+                BlobFormat::Raw,
+                IgnoreProgressSender::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            mime_type(*tt.hash()).await.as_deref(),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    // from the content of a stream
+    let (tt, _) = db
+        .import_stream(
+            futures_lite::stream::once(Ok(Bytes::from_static(b"%PDF-1.7"))),
+            BlobFormat::Raw,
+            IgnoreProgressSender::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        mime_type(*tt.hash()).await.as_deref(),
+        Some("application/pdf")
+    );
+
+    // but not for bytes, which are mostly internal data
+    let tt = db
+        .import_bytes(Bytes::from_static(b"%PDF-1.6"), BlobFormat::Raw)
+        .await
+        .unwrap();
+    assert_eq!(mime_type(*tt.hash()).await, None);
+}
+
 #[tokio::test]
 async fn actor_store_smoke() {
     let testdir = tempfile::tempdir().unwrap();
@@ -781,6 +1365,8 @@ async fn actor_store_smoke() {
         path: PathOptions::new(testdir.path()),
         batch: Default::default(),
         inline: Default::default(),
+        encryption: None,
+        quota: None,
     };
     let db = Store::new(db_path, options).await.unwrap();
     db.dump().await.unwrap();
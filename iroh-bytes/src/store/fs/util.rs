@@ -60,6 +60,41 @@ pub fn overwrite_and_sync(path: &Path, data: &[u8]) -> io::Result<std::fs::File>
     Ok(file)
 }
 
+/// How [`link_file`] created a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Link {
+    /// The file is a reflink, which shares data on disk until either file is modified.
+    Reflink,
+    /// The file is a hard link to the same inode.
+    Hardlink,
+}
+
+/// Create `target` with the content of `source` without copying the data.
+///
+/// This tries a reflink first, e.g. `FICLONE` on btrfs or XFS. If that is not
+/// supported and `source` is read-only, it tries a hard link. Hard links are
+/// limited to read-only files, since modifying either path in place would
+/// modify both.
+///
+/// Returns `None` if neither worked, e.g. because the two paths are on different
+/// file systems. The caller then has to copy the data.
+pub(super) fn link_file(source: &Path, target: &Path) -> Option<Link> {
+    match reflink_copy::reflink(source, target) {
+        Ok(()) => return Some(Link::Reflink),
+        Err(cause) => tracing::trace!("reflink {} failed: {}", source.display(), cause),
+    }
+    let readonly = source
+        .metadata()
+        .is_ok_and(|metadata| metadata.permissions().readonly());
+    if readonly {
+        match std::fs::hard_link(source, target) {
+            Ok(()) => return Some(Link::Hardlink),
+            Err(cause) => tracing::trace!("hard link {} failed: {}", source.display(), cause),
+        }
+    }
+    None
+}
+
 /// Read a file into memory and then delete it.
 pub fn read_and_remove(path: &Path) -> io::Result<Vec<u8>> {
     let data = std::fs::read(path)?;
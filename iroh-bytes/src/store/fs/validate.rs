@@ -229,7 +229,7 @@ impl ActorState {
                                         };
                                         inline_data.value().len() as u64
                                     }
-                                    DataLocation::Owned(size) => {
+                                    DataLocation::Owned(size) | DataLocation::Linked(size) => {
                                         let path = self.options.path.owned_data_path(&hash);
                                         let Ok(metadata) = path.metadata() else {
                                             entry_error!(hash, "owned data file does not exist");
@@ -10,7 +10,7 @@ use futures_lite::{Stream, StreamExt};
 use iroh_base::hash::{BlobFormat, Hash, HashAndFormat};
 use iroh_io::AsyncSliceReader;
 use std::{
-    collections::BTreeMap,
+    collections::{btree_map, BTreeMap},
     io,
     path::PathBuf,
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
@@ -18,10 +18,14 @@ use std::{
 };
 
 use crate::{
+    hashseq::HashSeq,
     store::{
-        mutable_mem_storage::MutableMemStorage, BaoBlobSize, MapEntry, MapEntryMut, ReadableStore,
+        mutable_mem_storage::MutableMemStorage, BaoBlobSize, BlobAccess, BlobMeta, BlobMetaUpdate,
+        MapEntry, MapEntryMut, Quota, ReadableStore,
     },
     util::{
+        chunker::Chunker,
+        mime,
         progress::{BoxedProgressSender, IdGenerator, IgnoreProgressSender, ProgressSender},
         LivenessTracker,
     },
@@ -63,6 +67,14 @@ impl Store {
         Self::default()
     }
 
+    /// Limit the total size of the blobs in the store.
+    ///
+    /// See [`Quota`] for how the quota is enforced.
+    pub fn with_quota(self, quota: Quota) -> Self {
+        self.write_lock().quota = Some(quota);
+        self
+    }
+
     /// Take a write lock on the store
     fn write_lock(&self) -> RwLockWriteGuard<'_, StateInner> {
         self.inner.0.write().unwrap()
@@ -78,6 +90,7 @@ impl Store {
         id: u64,
         bytes: Bytes,
         format: BlobFormat,
+        mime_type: Option<&str>,
         progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
     ) -> io::Result<TempTag> {
         progress.blocking_send(ImportProgress::OutboardProgress { id, offset: 0 })?;
@@ -92,10 +105,65 @@ impl Store {
             }),
             complete: true,
         };
-        self.write_lock().entries.insert(hash, entry);
+        let mut state = self.write_lock();
+        state.entries.insert(hash, entry);
+        state.on_imported(hash);
+        if let Some(mime_type) = mime_type {
+            let meta = state.meta.entry(hash).or_default();
+            meta.mime_type.get_or_insert_with(|| mime_type.to_string());
+        }
         Ok(tag)
     }
 
+    fn import_chunked_sync(
+        &self,
+        id: u64,
+        bytes: Bytes,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<TempTag> {
+        let mut hashes = Vec::new();
+        // protect the chunks until the hash seq is stored
+        let mut tags = BTreeMap::new();
+        let mut offset = 0;
+        let mut stored = 0;
+        for chunk in Chunker::new(&bytes[..]) {
+            let chunk = chunk?;
+            let len = chunk.len() as u64;
+            let hash = Hash::new(&chunk);
+            hashes.push(hash);
+            if let btree_map::Entry::Vacant(e) = tags.entry(hash) {
+                use super::Store;
+                let tag = self.temp_tag(HashAndFormat::raw(hash));
+                let exists = self
+                    .read_lock()
+                    .entries
+                    .get(&hash)
+                    .is_some_and(|entry| entry.complete);
+                if !exists {
+                    self.import_bytes_sync(
+                        id,
+                        chunk,
+                        BlobFormat::Raw,
+                        None,
+                        IgnoreProgressSender::default(),
+                    )?;
+                    stored += len;
+                }
+                e.insert(tag);
+            }
+            offset += len;
+            progress.try_send(ImportProgress::CopyProgress { id, offset })?;
+        }
+        progress.blocking_send(ImportProgress::ChunkingDone {
+            id,
+            chunks: hashes.len() as u64,
+            size: bytes.len() as u64,
+            stored,
+        })?;
+        let hash_seq = hashes.into_iter().collect::<HashSeq>().into_inner();
+        self.import_bytes_sync(id, hash_seq, BlobFormat::HashSeq, None, progress)
+    }
+
     fn export_sync(
         &self,
         hash: Hash,
@@ -142,7 +210,7 @@ impl super::Store for Store {
     async fn import_file(
         &self,
         path: std::path::PathBuf,
-        _mode: ImportMode,
+        mode: ImportMode,
         format: BlobFormat,
         progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
     ) -> io::Result<(TempTag, u64)> {
@@ -155,10 +223,17 @@ impl super::Store for Store {
             })?;
             progress.try_send(ImportProgress::CopyProgress { id, offset: 0 })?;
             // todo: provide progress for reading into mem
-            let bytes: Bytes = std::fs::read(path)?.into();
+            let bytes: Bytes = std::fs::read(&path)?.into();
             let size = bytes.len() as u64;
             progress.blocking_send(ImportProgress::Size { id, size })?;
-            let tag = this.import_bytes_sync(id, bytes, format, progress)?;
+            let tag = match mode {
+                ImportMode::Chunked => this.import_chunked_sync(id, bytes, progress)?,
+                _ => {
+                    let name = path.file_name().and_then(|name| name.to_str());
+                    let mime_type = mime::detect_content_type(name, &bytes);
+                    this.import_bytes_sync(id, bytes, format, Some(mime_type), progress)?
+                }
+            };
             Ok((tag, size))
         })
         .await?
@@ -187,14 +262,15 @@ impl super::Store for Store {
         let bytes = bytes.freeze();
         let size = bytes.len() as u64;
         progress.blocking_send(ImportProgress::Size { id, size })?;
-        let tag = this.import_bytes_sync(id, bytes, format, progress)?;
+        let mime_type = mime::detect_content_type(None, &bytes);
+        let tag = this.import_bytes_sync(id, bytes, format, Some(mime_type), progress)?;
         Ok((tag, size))
     }
 
     async fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> io::Result<TempTag> {
         let this = self.clone();
         tokio::task::spawn_blocking(move || {
-            this.import_bytes_sync(0, bytes, format, IgnoreProgressSender::default())
+            this.import_bytes_sync(0, bytes, format, None, IgnoreProgressSender::default())
         })
         .await?
     }
@@ -216,10 +292,76 @@ impl super::Store for Store {
         Ok(tag)
     }
 
+    async fn compare_and_swap_tag(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> io::Result<Result<(), Option<HashAndFormat>>> {
+        let mut state = self.write_lock();
+        let current = state.tags.get(&name).copied();
+        if current != expected {
+            return Ok(Err(current));
+        }
+        if let Some(value) = value {
+            state.tags.insert(name, value);
+        } else {
+            state.tags.remove(&name);
+        }
+        Ok(Ok(()))
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        let mut state = self.write_lock();
+        if state.tags.contains_key(&to) {
+            return Err(super::tag_exists(&to));
+        }
+        let value = state
+            .tags
+            .remove(&from)
+            .ok_or_else(|| super::tag_not_found(&from))?;
+        state.tags.insert(to, value);
+        Ok(())
+    }
+
     fn temp_tag(&self, tag: HashAndFormat) -> TempTag {
         TempTag::new(tag, Some(self.inner.clone()))
     }
 
+    fn quota(&self) -> Option<Quota> {
+        self.read_lock().quota.clone()
+    }
+
+    async fn blob_access(&self, hash: &Hash) -> io::Result<Option<BlobAccess>> {
+        Ok(self.read_lock().access.get(hash).copied())
+    }
+
+    async fn blob_meta(&self, hash: &Hash) -> io::Result<Option<BlobMeta>> {
+        Ok(self.read_lock().meta.get(hash).cloned())
+    }
+
+    async fn update_blob_meta(&self, hash: Hash, update: BlobMetaUpdate) -> io::Result<()> {
+        let mut state = self.write_lock();
+        // metadata is removed with the blob, so it must not exist without one
+        if !state.entries.contains_key(&hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("blob {} is not in the store", hash.to_hex()),
+            ));
+        }
+        let meta = state.meta.entry(hash).or_default();
+        meta.apply(update);
+        if meta.is_empty() {
+            state.meta.remove(&hash);
+        }
+        Ok(())
+    }
+
+    async fn blob_metas(&self) -> io::Result<crate::store::DbIter<(Hash, BlobMeta)>> {
+        let meta = self.read_lock().meta.clone();
+        Ok(Box::new(meta.into_iter().map(Ok)))
+    }
+
     async fn gc_start(&self) -> io::Result<()> {
         Ok(())
     }
@@ -229,6 +371,8 @@ impl super::Store for Store {
         for hash in hashes {
             if !state.temp.contains(&hash) {
                 state.entries.remove(&hash);
+                state.access.remove(&hash);
+                state.meta.remove(&hash);
             }
         }
         Ok(())
@@ -242,6 +386,16 @@ struct StateInner {
     entries: BTreeMap<Hash, Entry>,
     tags: BTreeMap<Tag, HashAndFormat>,
     temp: TempCounterMap,
+    access: BTreeMap<Hash, BlobAccess>,
+    meta: BTreeMap<Hash, BlobMeta>,
+    quota: Option<Quota>,
+}
+
+impl StateInner {
+    fn on_imported(&mut self, hash: Hash) {
+        let access = self.access.entry(hash).or_default();
+        access.imported = SystemTime::now();
+    }
 }
 
 /// An in memory entry
@@ -337,6 +491,15 @@ impl super::Map for Store {
     async fn get(&self, hash: &Hash) -> std::io::Result<Option<Self::Entry>> {
         Ok(self.inner.0.read().unwrap().entries.get(hash).cloned())
     }
+
+    async fn record_served(&self, hash: &Hash) -> io::Result<()> {
+        let mut state = self.write_lock();
+        if state.entries.contains_key(hash) {
+            let access = state.access.entry(*hash).or_default();
+            access.served = Some(SystemTime::now());
+        }
+        Ok(())
+    }
 }
 
 impl super::MapMut for Store {
@@ -347,14 +510,16 @@ impl super::MapMut for Store {
     }
 
     async fn get_or_create(&self, hash: Hash, _size: u64) -> std::io::Result<Entry> {
-        let entry = Entry {
+        let mut inner = self.inner.0.write().unwrap();
+        // share partial entries, so concurrent downloads of the same blob write to the same data
+        let entry = inner.entries.entry(hash).or_insert_with(|| Entry {
             inner: Arc::new(EntryInner {
                 hash,
                 data: RwLock::new(MutableMemStorage::default()),
             }),
             complete: false,
-        };
-        Ok(entry)
+        });
+        Ok(entry.clone())
     }
 
     async fn entry_status(&self, hash: &Hash) -> std::io::Result<crate::store::EntryStatus> {
@@ -385,6 +550,7 @@ impl super::MapMut for Store {
         if !complete {
             entry.complete = true;
             inner.entries.insert(hash, entry);
+            inner.on_imported(hash);
         }
         Ok(())
     }
@@ -445,3 +611,91 @@ impl ReadableStore for Store {
         tokio::task::spawn_blocking(move || this.export_sync(hash, target, mode, progress)).await?
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MapMut, ReadableStore, Store as _};
+
+    #[tokio::test]
+    async fn get_or_create_shares_partial_entries() -> io::Result<()> {
+        let store = Store::new();
+        let hash = Hash::new(b"partial");
+        let a = store.get_or_create(hash, 7).await?;
+        let b = store.get_or_create(hash, 7).await?;
+        // concurrent downloads of the same blob write to the same data
+        assert!(Arc::ptr_eq(&a.inner, &b.inner));
+        assert!(matches!(
+            store.entry_status(&hash).await?,
+            crate::store::EntryStatus::Partial
+        ));
+        let partial = store
+            .partial_blobs()
+            .await?
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(partial, vec![hash]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn partial_last_chunk_group() -> anyhow::Result<()> {
+        use crate::store::bao_file::test_support::{
+            decode_response_into_batch, make_wire_data, random_test_data,
+        };
+        use bao_tree::{ChunkNum, ChunkRanges};
+        let store = Store::new();
+        // a bit more than two chunk groups, so the last chunk group is not full
+        let data = random_test_data(1024 * 40);
+        let size = data.len() as u64;
+        // just the last chunk, as requested to verify the size of a blob
+        let range = 1024 * 39..size;
+        let (hash, ranges, wire) = make_wire_data(&data, &[range]);
+        let entry = store.get_or_create(hash, size).await?;
+        let wire = std::io::Cursor::new(wire.as_slice());
+        decode_response_into_batch(
+            hash,
+            IROH_BLOCK_SIZE,
+            ranges,
+            wire,
+            entry.batch_writer().await?,
+        )
+        .await?;
+        // the hash of the last chunk group is known, but most of its data is missing
+        let valid = crate::get::db::valid_ranges::<Store>(&entry).await?;
+        assert!(valid.is_empty());
+        let range = 0..size;
+        let (_, ranges, wire) = make_wire_data(&data, &[range]);
+        let wire = std::io::Cursor::new(wire.as_slice());
+        decode_response_into_batch(
+            hash,
+            IROH_BLOCK_SIZE,
+            ranges,
+            wire,
+            entry.batch_writer().await?,
+        )
+        .await?;
+        let valid = crate::get::db::valid_ranges::<Store>(&entry).await?;
+        assert_eq!(valid, ChunkRanges::from(..ChunkNum(40)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_tag() -> io::Result<()> {
+        let store = Store::new();
+        let value = HashAndFormat::raw(Hash::new(b"value"));
+        store.set_tag(Tag::from("a/1"), Some(value)).await?;
+        store.set_tag(Tag::from("b"), Some(value)).await?;
+        let err = store
+            .rename_tag(Tag::from("a/1"), Tag::from("b"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        store.rename_tag(Tag::from("a/1"), Tag::from("a/2")).await?;
+        let tags = store
+            .tags_with_prefix(Tag::from("a/"))
+            .await?
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(tags, vec![(Tag::from("a/2"), value)]);
+        Ok(())
+    }
+}
@@ -47,7 +47,7 @@ impl SizeInfo {
     }
 
     /// Write a size at the given offset. The size at the highest offset is going to be kept.
-    fn write(&mut self, offset: u64, size: u64) {
+    pub(super) fn write(&mut self, offset: u64, size: u64) {
         // >= instead of > because we want to be able to update size 0, the initial value.
         if offset >= self.offset {
             self.offset = offset;
@@ -98,26 +98,44 @@ impl MutableMemStorage {
         size: u64,
         batch: &[BaoContentItem],
     ) -> std::io::Result<()> {
-        let tree = BaoTree::new(size, IROH_BLOCK_SIZE);
-        for item in batch {
-            match item {
-                BaoContentItem::Parent(parent) => {
-                    if let Some(offset) = tree.pre_order_offset(parent.node) {
-                        let o0 = offset
-                            .checked_mul(64)
-                            .expect("u64 overflow multiplying to hash pair offset");
-                        let o1 = o0.checked_add(32).expect("u64 overflow");
-                        let outboard = &mut self.outboard;
-                        outboard.write_all_at(o0, parent.pair.0.as_bytes().as_slice())?;
-                        outboard.write_all_at(o1, parent.pair.1.as_bytes().as_slice())?;
-                    }
-                }
-                BaoContentItem::Leaf(leaf) => {
-                    self.sizes.write(leaf.offset, size);
-                    self.data.write_all_at(leaf.offset, leaf.data.as_ref())?;
+        write_batch(
+            &mut self.data,
+            &mut self.outboard,
+            &mut self.sizes,
+            size,
+            batch,
+        )
+    }
+}
+
+/// Write a batch of bao content items to a data and an outboard file.
+///
+/// The sizes are tracked in `sizes`, so the most precise size is kept.
+pub(super) fn write_batch(
+    mut data: impl WriteAt,
+    mut outboard: impl WriteAt,
+    sizes: &mut SizeInfo,
+    size: u64,
+    batch: &[BaoContentItem],
+) -> std::io::Result<()> {
+    let tree = BaoTree::new(size, IROH_BLOCK_SIZE);
+    for item in batch {
+        match item {
+            BaoContentItem::Parent(parent) => {
+                if let Some(offset) = tree.pre_order_offset(parent.node) {
+                    let o0 = offset
+                        .checked_mul(64)
+                        .expect("u64 overflow multiplying to hash pair offset");
+                    let o1 = o0.checked_add(32).expect("u64 overflow");
+                    outboard.write_all_at(o0, parent.pair.0.as_bytes().as_slice())?;
+                    outboard.write_all_at(o1, parent.pair.1.as_bytes().as_slice())?;
                 }
             }
+            BaoContentItem::Leaf(leaf) => {
+                sizes.write(leaf.offset, size);
+                data.write_all_at(leaf.offset, leaf.data.as_ref())?;
+            }
         }
-        Ok(())
     }
+    Ok(())
 }
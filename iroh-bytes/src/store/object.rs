@@ -0,0 +1,1318 @@
+//! A blob store that keeps data and outboards in an object store, such as S3.
+//!
+//! Main entry point is [Store].
+//!
+//! Complete blobs are stored as two objects below a common prefix,
+//! `<prefix>/data/<hash>` for the data and `<prefix>/outboard/<hash>` for the
+//! outboard. Blobs that fit into a single chunk group have an empty outboard,
+//! so no outboard object is stored for them.
+//!
+//! Tags and the list of complete blobs live in a small local redb database, so
+//! listing blobs and tags does not need to list the bucket. Partial blobs, i.e.
+//! blobs that are currently being downloaded, and imported streams are staged
+//! in local files next to the database and uploaded once they are complete.
+//! Partial blobs do not survive a restart.
+//!
+//! Data is first uploaded to a staging object below `<prefix>/staging/` and
+//! moved to its final location once its hash is known. Staging objects of
+//! interrupted uploads are not cleaned up automatically.
+//!
+//! Any [ObjectStore] can be used, e.g. an S3 compatible store created with
+//! [`object_store::aws::AmazonS3Builder`], or [`object_store::memory::InMemory`]
+//! for tests.
+use std::{
+    collections::{btree_map, BTreeMap},
+    fs::OpenOptions,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
+
+use bao_tree::{
+    io::{
+        fsm::{BaoContentItem, Outboard},
+        outboard::PreOrderOutboard,
+        sync::{CreateOutboard, ReadAt},
+    },
+    BaoTree,
+};
+use bytes::Bytes;
+use futures_lite::{Stream, StreamExt};
+use iroh_base::hash::{BlobFormat, Hash, HashAndFormat};
+use iroh_io::AsyncSliceReader;
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use redb::{ReadableTable, TableDefinition};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    store::{
+        mutable_mem_storage::{write_batch, SizeInfo},
+        BaoBlobSize, DbIter, EntryStatus, MapEntry, MapEntryMut, ReadableStore,
+    },
+    util::{
+        limited_range,
+        progress::{BoxedProgressSender, IdGenerator, ProgressSender},
+        raw_outboard, raw_outboard_size, LivenessTracker,
+    },
+    Tag, TempTag, IROH_BLOCK_SIZE,
+};
+
+use super::{
+    temp_name, BaoBatchWriter, ConsistencyCheckProgress, ExportMode, ExportProgressCb, ImportMode,
+    ImportProgress, Map, MapMut, ReportLevel, TempCounterMap,
+};
+
+/// Sizes of all complete blobs in the object store.
+const BLOBS_TABLE: TableDefinition<Hash, u64> = TableDefinition::new("blobs-0");
+
+const TAGS_TABLE: TableDefinition<Tag, HashAndFormat> = TableDefinition::new("tags-0");
+
+/// Files up to this size are uploaded with a single put, larger files are
+/// uploaded in parts.
+const MAX_SINGLE_PUT: u64 = 1024 * 1024 * 8;
+
+/// A blob store that keeps data and outboards in an [ObjectStore].
+///
+/// See the [module docs](self) for the layout of the objects.
+#[derive(Debug, Clone)]
+pub struct Store {
+    inner: Arc<StoreInner>,
+}
+
+#[derive(derive_more::Debug)]
+struct StoreInner {
+    objects: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// Local directory for partial blobs and imported streams.
+    staging: PathBuf,
+    #[debug(skip)]
+    db: redb::Database,
+    state: RwLock<State>,
+    /// Serializes adding blobs to the index with deleting them, so a delete
+    /// can not remove the objects of a blob that is re-imported concurrently.
+    commit: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Blobs that are currently being downloaded.
+    partial: BTreeMap<Hash, Arc<RwLock<PartialStorage>>>,
+    temp: TempCounterMap,
+}
+
+impl LivenessTracker for StoreInner {
+    fn on_clone(&self, inner: &HashAndFormat) {
+        tracing::trace!("temp tagging: {:?}", inner);
+        let mut state = self.state.write().unwrap();
+        state.temp.inc(inner);
+    }
+
+    fn on_drop(&self, inner: &HashAndFormat) {
+        tracing::trace!("temp tag drop: {:?}", inner);
+        let mut state = self.state.write().unwrap();
+        state.temp.dec(inner);
+    }
+}
+
+/// Content of a blob to upload.
+enum Upload {
+    Bytes(Bytes),
+    File(PathBuf),
+}
+
+/// Local files for a blob that is being downloaded.
+///
+/// The files are removed when the storage is dropped.
+#[derive(Debug)]
+struct PartialStorage {
+    data_path: PathBuf,
+    outboard_path: PathBuf,
+    data: std::fs::File,
+    outboard: std::fs::File,
+    sizes: SizeInfo,
+}
+
+impl PartialStorage {
+    fn create(dir: &Path) -> io::Result<Self> {
+        let name = temp_name();
+        let data_path = dir.join(format!("{name}.data"));
+        let outboard_path = dir.join(format!("{name}.obao4"));
+        let create = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(path)
+        };
+        Ok(Self {
+            data: create(&data_path)?,
+            outboard: create(&outboard_path)?,
+            data_path,
+            outboard_path,
+            sizes: SizeInfo::default(),
+        })
+    }
+
+    fn current_size(&self) -> u64 {
+        self.sizes.current_size()
+    }
+
+    fn read_data_at(&self, offset: u64, len: usize) -> io::Result<Bytes> {
+        read_limited(&self.data, offset, len)
+    }
+
+    fn read_outboard_at(&self, offset: u64, len: usize) -> io::Result<Bytes> {
+        read_limited(&self.outboard, offset, len)
+    }
+
+    fn write_batch(&mut self, size: u64, batch: &[BaoContentItem]) -> io::Result<()> {
+        write_batch(
+            &mut self.data,
+            &mut self.outboard,
+            &mut self.sizes,
+            size,
+            batch,
+        )
+    }
+}
+
+impl Drop for PartialStorage {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.data_path).ok();
+        std::fs::remove_file(&self.outboard_path).ok();
+    }
+}
+
+/// Read from a file at the given offset, until end of file or `len` bytes.
+fn read_limited(file: &std::fs::File, offset: u64, len: usize) -> io::Result<Bytes> {
+    let file_len = usize::try_from(file.metadata()?.len()).unwrap_or(usize::MAX);
+    let mut buf = vec![0u8; limited_range(offset, len, file_len).len()];
+    file.read_exact_at(offset, &mut buf)?;
+    Ok(buf.into())
+}
+
+/// A reader that sends everything it reads to an upload.
+struct TeeReader<R> {
+    inner: R,
+    send: tokio::sync::mpsc::Sender<Bytes>,
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.send
+                .blocking_send(Bytes::copy_from_slice(&buf[..n]))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload failed"))?;
+        }
+        Ok(n)
+    }
+}
+
+fn to_io_err(e: impl Into<redb::Error>) -> io::Error {
+    io::Error::other(e.into())
+}
+
+impl Store {
+    /// Create a new store that keeps blobs in `objects` below `prefix`.
+    ///
+    /// Tags and the index of complete blobs are kept in a redb database at
+    /// `db_path`, which is created if it does not exist. Partial blobs are
+    /// staged in a directory next to it, named like the database with a
+    /// `.staging` suffix.
+    pub async fn new(
+        objects: Arc<dyn ObjectStore>,
+        prefix: ObjectPath,
+        db_path: PathBuf,
+    ) -> io::Result<Self> {
+        let mut staging = db_path.clone().into_os_string();
+        staging.push(".staging");
+        let staging = PathBuf::from(staging);
+        let staging2 = staging.clone();
+        let db = tokio::task::spawn_blocking(move || {
+            if let Some(parent) = db_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // partial blobs are not persisted, so leftovers can be removed
+            match std::fs::remove_dir_all(&staging2) {
+                Err(cause) if cause.kind() != io::ErrorKind::NotFound => return Err(cause),
+                _ => {}
+            }
+            std::fs::create_dir_all(&staging2)?;
+            let db = redb::Database::create(db_path).map_err(to_io_err)?;
+            let tx = db.begin_write().map_err(to_io_err)?;
+            tx.open_table(BLOBS_TABLE).map_err(to_io_err)?;
+            tx.open_table(TAGS_TABLE).map_err(to_io_err)?;
+            tx.commit().map_err(to_io_err)?;
+            io::Result::Ok(db)
+        })
+        .await??;
+        Ok(Self {
+            inner: Arc::new(StoreInner {
+                objects,
+                prefix,
+                staging,
+                db,
+                state: Default::default(),
+                commit: Default::default(),
+            }),
+        })
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, State> {
+        self.inner.state.read().unwrap()
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, State> {
+        self.inner.state.write().unwrap()
+    }
+
+    fn data_path(&self, hash: &Hash) -> ObjectPath {
+        self.inner.prefix.child("data").child(hash.to_hex())
+    }
+
+    fn outboard_path(&self, hash: &Hash) -> ObjectPath {
+        self.inner.prefix.child("outboard").child(hash.to_hex())
+    }
+
+    fn staging_path(&self) -> ObjectPath {
+        self.inner.prefix.child("staging").child(temp_name())
+    }
+
+    /// Size of a complete blob, or `None` if the blob is not in the index.
+    fn blob_size(&self, hash: &Hash) -> io::Result<Option<u64>> {
+        let tx = self.inner.db.begin_read().map_err(to_io_err)?;
+        let blobs = tx.open_table(BLOBS_TABLE).map_err(to_io_err)?;
+        let size = blobs.get(*hash).map_err(to_io_err)?.map(|x| x.value());
+        Ok(size)
+    }
+
+    /// Run a write transaction on the local database.
+    async fn write<T, F>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&redb::WriteTransaction) -> Result<T, redb::Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let tx = inner.db.begin_write()?;
+            let res = f(&tx)?;
+            tx.commit()?;
+            Result::<T, redb::Error>::Ok(res)
+        })
+        .await?
+        .map_err(to_io_err)
+    }
+
+    fn complete_entry(&self, hash: Hash, size: u64) -> Entry {
+        Entry {
+            hash,
+            state: EntryState::Complete {
+                size,
+                objects: self.inner.objects.clone(),
+                data: self.data_path(&hash),
+                outboard: self.outboard_path(&hash),
+            },
+        }
+    }
+
+    /// Upload data and outboard of a blob and add it to the index.
+    ///
+    /// Callers that import data must hold a temp tag for the blob, so it is
+    /// not deleted concurrently.
+    async fn upload(
+        &self,
+        hash: Hash,
+        size: u64,
+        data: Upload,
+        outboard: Vec<u8>,
+    ) -> io::Result<()> {
+        {
+            // check under the commit lock, so a concurrent delete has either
+            // completed or will see the temp tag of the caller
+            let _guard = self.inner.commit.lock().await;
+            if self.blob_size(&hash)?.is_some() {
+                return Ok(());
+            }
+        }
+        let objects = &self.inner.objects;
+        let staging = self.staging_path();
+        match data {
+            Upload::Bytes(bytes) => {
+                objects.put(&staging, bytes).await?;
+            }
+            Upload::File(source) => {
+                let (id, mut writer) = objects.put_multipart(&staging).await?;
+                let res = async {
+                    let mut file = tokio::fs::File::open(source).await?;
+                    tokio::io::copy(&mut file, &mut writer).await?;
+                    writer.shutdown().await
+                }
+                .await;
+                if let Err(cause) = res {
+                    objects.abort_multipart(&staging, &id).await.ok();
+                    return Err(cause);
+                }
+            }
+        }
+        self.commit(hash, size, staging, outboard).await
+    }
+
+    /// Upload a file to a staging object while computing its outboard.
+    ///
+    /// The file is read only once. The outboard is computed on a blocking
+    /// thread, which sends the data it reads on to the upload.
+    async fn stage_file(
+        &self,
+        id: u64,
+        path: PathBuf,
+        size: u64,
+        progress: &impl ProgressSender<Msg = ImportProgress>,
+    ) -> io::Result<(Hash, Vec<u8>, ObjectPath)> {
+        let objects = &self.inner.objects;
+        let staging = self.staging_path();
+        let (upload_id, mut writer) = objects.put_multipart(&staging).await?;
+        let (send, mut recv) = tokio::sync::mpsc::channel(4);
+        let hasher = tokio::task::spawn_blocking(move || {
+            // only read as much as we hash, even if the file grows
+            let inner = std::fs::File::open(path)?.take(size);
+            let reader = TeeReader { inner, send };
+            let reader = std::io::BufReader::with_capacity(1024 * 1024, reader);
+            let outboard =
+                PreOrderOutboard::<Vec<u8>>::create_sized(reader, size, IROH_BLOCK_SIZE)?;
+            io::Result::Ok((outboard.data, Hash::from(outboard.root)))
+        });
+        let res = async move {
+            let mut offset = 0u64;
+            while let Some(chunk) = recv.recv().await {
+                writer.write_all(&chunk).await?;
+                offset += chunk.len() as u64;
+                progress
+                    .try_send(ImportProgress::OutboardProgress { id, offset })
+                    .ok();
+            }
+            let (outboard, hash) = hasher.await??;
+            writer.shutdown().await?;
+            io::Result::Ok((hash, outboard))
+        }
+        .await;
+        match res {
+            Ok((hash, outboard)) => Ok((hash, outboard, staging)),
+            Err(cause) => {
+                objects.abort_multipart(&staging, &upload_id).await.ok();
+                Err(cause)
+            }
+        }
+    }
+
+    /// Add a blob whose data was uploaded to the `staging` object to the store.
+    ///
+    /// The data is moved to its final location and the outboard is uploaded,
+    /// and the blob is only added to the index once both succeeded.
+    async fn commit(
+        &self,
+        hash: Hash,
+        size: u64,
+        staging: ObjectPath,
+        outboard: Vec<u8>,
+    ) -> io::Result<()> {
+        let objects = self.inner.objects.as_ref();
+        let _guard = self.inner.commit.lock().await;
+        if self.blob_size(&hash)?.is_some() {
+            return delete_object(objects, &staging).await;
+        }
+        let res = async {
+            objects.rename(&staging, &self.data_path(&hash)).await?;
+            if !outboard.is_empty() {
+                objects
+                    .put(&self.outboard_path(&hash), outboard.into())
+                    .await?;
+            }
+            self.write(move |tx| {
+                tx.open_table(BLOBS_TABLE)?.insert(hash, size)?;
+                Ok(())
+            })
+            .await
+        }
+        .await;
+        if res.is_err() {
+            delete_object(objects, &staging).await.ok();
+        }
+        res
+    }
+
+    async fn import_bytes_impl(
+        &self,
+        id: u64,
+        bytes: Bytes,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<TempTag> {
+        progress
+            .send(ImportProgress::OutboardProgress { id, offset: 0 })
+            .await?;
+        let size = bytes.len() as u64;
+        let (bytes, (outboard, hash)) = tokio::task::spawn_blocking(move || {
+            let res = raw_outboard(&bytes);
+            (bytes, res)
+        })
+        .await?;
+        use super::Store;
+        let tag = self.temp_tag(HashAndFormat { hash, format });
+        self.upload(hash, size, Upload::Bytes(bytes), outboard)
+            .await?;
+        progress
+            .send(ImportProgress::OutboardDone { id, hash })
+            .await?;
+        Ok(tag)
+    }
+
+    /// Import a file that is too large to be uploaded with a single put.
+    ///
+    /// The file is hashed and uploaded in a single pass.
+    async fn import_large_file(
+        &self,
+        id: u64,
+        path: PathBuf,
+        size: u64,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<TempTag> {
+        progress
+            .send(ImportProgress::OutboardProgress { id, offset: 0 })
+            .await?;
+        let (hash, outboard, staging) = self.stage_file(id, path, size, &progress).await?;
+        use super::Store;
+        // protect the blob before it is committed, so a concurrent delete skips it
+        let tag = self.temp_tag(HashAndFormat { hash, format });
+        self.commit(hash, size, staging, outboard).await?;
+        progress
+            .send(ImportProgress::OutboardDone { id, hash })
+            .await?;
+        Ok(tag)
+    }
+
+    /// Import a file, with a single put if it is small enough.
+    async fn import_path(
+        &self,
+        id: u64,
+        path: PathBuf,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        let size = tokio::fs::metadata(&path).await?.len();
+        progress.send(ImportProgress::Size { id, size }).await?;
+        if size > MAX_SINGLE_PUT {
+            let tag = self
+                .import_large_file(id, path, size, format, progress)
+                .await?;
+            return Ok((tag, size));
+        }
+        let bytes: Bytes = tokio::fs::read(&path).await?.into();
+        // the file might have changed since we got the size
+        let size = bytes.len() as u64;
+        let tag = self.import_bytes_impl(id, bytes, format, progress).await?;
+        Ok((tag, size))
+    }
+
+    async fn check_blob(
+        &self,
+        hash: Hash,
+        size: u64,
+        tx: &BoxedProgressSender<ConsistencyCheckProgress>,
+    ) -> io::Result<bool> {
+        let objects = &self.inner.objects;
+        let mut ok = true;
+        match objects.head(&self.data_path(&hash)).await {
+            Ok(meta) if meta.size as u64 == size => {}
+            Ok(meta) => {
+                ok = false;
+                let message = format!("data size is {} but expected {}", meta.size, size);
+                report(tx, hash, ReportLevel::Error, message).await?;
+            }
+            Err(cause) => {
+                ok = false;
+                let message = format!("data object can not be read: {cause}");
+                report(tx, hash, ReportLevel::Error, message).await?;
+            }
+        }
+        let outboard_size = raw_outboard_size(size);
+        if outboard_size > 0 {
+            match objects.head(&self.outboard_path(&hash)).await {
+                Ok(meta) if meta.size as u64 == outboard_size => {}
+                Ok(meta) => {
+                    ok = false;
+                    let message = format!(
+                        "outboard size is {} but expected {}",
+                        meta.size, outboard_size
+                    );
+                    report(tx, hash, ReportLevel::Error, message).await?;
+                }
+                Err(cause) => {
+                    ok = false;
+                    let message = format!("outboard object can not be read: {cause}");
+                    report(tx, hash, ReportLevel::Error, message).await?;
+                }
+            }
+        }
+        Ok(ok)
+    }
+}
+
+async fn report(
+    tx: &BoxedProgressSender<ConsistencyCheckProgress>,
+    hash: Hash,
+    level: ReportLevel,
+    message: String,
+) -> io::Result<()> {
+    tx.send(ConsistencyCheckProgress::Update {
+        message,
+        entry: Some(hash),
+        level,
+    })
+    .await?;
+    Ok(())
+}
+
+/// Delete an object, ignoring objects that do not exist.
+async fn delete_object(objects: &dyn ObjectStore, path: &ObjectPath) -> io::Result<()> {
+    match objects.delete(path).await {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(cause) => Err(cause.into()),
+    }
+}
+
+impl super::Store for Store {
+    async fn import_file(
+        &self,
+        path: PathBuf,
+        mode: ImportMode,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        if mode == ImportMode::Chunked {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "chunked import is not supported by the object store",
+            ));
+        }
+        let id = progress.new_id();
+        progress
+            .send(ImportProgress::Found {
+                id,
+                name: path.to_string_lossy().to_string(),
+            })
+            .await?;
+        self.import_path(id, path, format, progress).await
+    }
+
+    async fn import_stream(
+        &self,
+        mut data: impl Stream<Item = io::Result<Bytes>> + Unpin + Send + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        let id = progress.new_id();
+        let name = temp_name();
+        progress
+            .send(ImportProgress::Found {
+                id,
+                name: name.clone(),
+            })
+            .await?;
+        // spool the stream to a local file, so it does not need to fit in memory
+        let path = self.inner.staging.join(name);
+        let res = async {
+            let mut file = tokio::fs::File::create(&path).await?;
+            let mut offset = 0u64;
+            while let Some(chunk) = data.next().await {
+                let chunk = chunk?;
+                file.write_all(&chunk).await?;
+                offset += chunk.len() as u64;
+                progress
+                    .try_send(ImportProgress::CopyProgress { id, offset })
+                    .ok();
+            }
+            file.sync_all().await?;
+            drop(file);
+            self.import_path(id, path.clone(), format, progress).await
+        }
+        .await;
+        tokio::fs::remove_file(&path).await.ok();
+        res
+    }
+
+    async fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> io::Result<TempTag> {
+        let progress = crate::util::progress::IgnoreProgressSender::default();
+        self.import_bytes_impl(0, bytes, format, progress).await
+    }
+
+    async fn set_tag(&self, name: Tag, value: Option<HashAndFormat>) -> io::Result<()> {
+        self.write(move |tx| {
+            let mut tags = tx.open_table(TAGS_TABLE)?;
+            match value {
+                Some(value) => tags.insert(name, value)?,
+                None => tags.remove(name)?,
+            };
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_tag(&self, value: HashAndFormat) -> io::Result<Tag> {
+        self.write(move |tx| {
+            let mut tags = tx.open_table(TAGS_TABLE)?;
+            let tag = Tag::auto(SystemTime::now(), |x| {
+                matches!(tags.get(Tag(Bytes::copy_from_slice(x))), Ok(Some(_)))
+            });
+            tags.insert(tag.clone(), value)?;
+            Ok(tag)
+        })
+        .await
+    }
+
+    async fn compare_and_swap_tag(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> io::Result<Result<(), Option<HashAndFormat>>> {
+        self.write(move |tx| {
+            let mut tags = tx.open_table(TAGS_TABLE)?;
+            let current = tags.get(&name)?.map(|x| x.value());
+            if current != expected {
+                return Ok(Err(current));
+            }
+            match value {
+                Some(value) => tags.insert(name, value)?,
+                None => tags.remove(name)?,
+            };
+            Ok(Ok(()))
+        })
+        .await
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        self.write(move |tx| {
+            let mut tags = tx.open_table(TAGS_TABLE)?;
+            let Some(value) = tags.get(&from)?.map(|x| x.value()) else {
+                return Ok(Err(super::tag_not_found(&from)));
+            };
+            if tags.get(&to)?.is_some() {
+                return Ok(Err(super::tag_exists(&to)));
+            }
+            tags.insert(to, value)?;
+            tags.remove(from)?;
+            Ok(Ok(()))
+        })
+        .await?
+    }
+
+    fn temp_tag(&self, tag: HashAndFormat) -> TempTag {
+        TempTag::new(tag, Some(self.inner.clone()))
+    }
+
+    async fn gc_start(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn delete(&self, hashes: Vec<Hash>) -> io::Result<()> {
+        let _guard = self.inner.commit.lock().await;
+        let hashes = {
+            let mut state = self.write_lock();
+            let hashes = hashes
+                .into_iter()
+                .filter(|hash| !state.temp.contains(hash))
+                .collect::<Vec<_>>();
+            for hash in &hashes {
+                state.partial.remove(hash);
+            }
+            hashes
+        };
+        let deleted = hashes.clone();
+        self.write(move |tx| {
+            let mut blobs = tx.open_table(BLOBS_TABLE)?;
+            for hash in deleted {
+                blobs.remove(hash)?;
+            }
+            Ok(())
+        })
+        .await?;
+        // remove the objects only after they are no longer in the index, so a
+        // concurrent reader never sees an indexed blob without data
+        for hash in hashes {
+            delete_object(self.inner.objects.as_ref(), &self.data_path(&hash)).await?;
+            delete_object(self.inner.objects.as_ref(), &self.outboard_path(&hash)).await?;
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&self) {}
+}
+
+/// An entry in the object store
+#[derive(Debug, Clone)]
+pub struct Entry {
+    hash: Hash,
+    state: EntryState,
+}
+
+#[derive(Debug, Clone)]
+enum EntryState {
+    /// A complete blob, stored as objects.
+    Complete {
+        size: u64,
+        objects: Arc<dyn ObjectStore>,
+        data: ObjectPath,
+        outboard: ObjectPath,
+    },
+    /// A blob that is being downloaded, staged in local files until it is complete.
+    Partial(Arc<RwLock<PartialStorage>>),
+}
+
+impl MapEntry for Entry {
+    fn hash(&self) -> Hash {
+        self.hash
+    }
+
+    fn size(&self) -> BaoBlobSize {
+        match &self.state {
+            EntryState::Complete { size, .. } => BaoBlobSize::new(*size, true),
+            EntryState::Partial(storage) => {
+                BaoBlobSize::new(storage.read().unwrap().current_size(), false)
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, EntryState::Complete { .. })
+    }
+
+    async fn outboard(&self) -> io::Result<impl Outboard> {
+        let (size, data) = match &self.state {
+            EntryState::Complete {
+                size,
+                objects,
+                outboard,
+                ..
+            } => (
+                *size,
+                Reader::Object {
+                    objects: objects.clone(),
+                    path: outboard.clone(),
+                    size: raw_outboard_size(*size),
+                },
+            ),
+            EntryState::Partial(storage) => (
+                storage.read().unwrap().current_size(),
+                Reader::Outboard(storage.clone()),
+            ),
+        };
+        Ok(PreOrderOutboard {
+            root: self.hash.into(),
+            tree: BaoTree::new(size, IROH_BLOCK_SIZE),
+            data,
+        })
+    }
+
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(match &self.state {
+            EntryState::Complete {
+                size,
+                objects,
+                data,
+                ..
+            } => Reader::Object {
+                objects: objects.clone(),
+                path: data.clone(),
+                size: *size,
+            },
+            EntryState::Partial(storage) => Reader::Data(storage.clone()),
+        })
+    }
+}
+
+impl MapEntryMut for Entry {
+    async fn batch_writer(&self) -> io::Result<impl BaoBatchWriter> {
+        Ok(match &self.state {
+            EntryState::Complete { .. } => BatchWriter(None),
+            EntryState::Partial(storage) => BatchWriter(Some(storage.clone())),
+        })
+    }
+}
+
+/// Reader for the data or outboard of an entry.
+#[derive(Debug)]
+enum Reader {
+    /// An object of known size.
+    Object {
+        objects: Arc<dyn ObjectStore>,
+        path: ObjectPath,
+        size: u64,
+    },
+    /// The data of a partial entry.
+    Data(Arc<RwLock<PartialStorage>>),
+    /// The outboard of a partial entry.
+    Outboard(Arc<RwLock<PartialStorage>>),
+}
+
+impl AsyncSliceReader for Reader {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        match self {
+            Self::Object {
+                objects,
+                path,
+                size,
+            } => {
+                let size = usize::try_from(*size).unwrap_or(usize::MAX);
+                let range = limited_range(offset, len, size);
+                if range.is_empty() {
+                    return Ok(Bytes::new());
+                }
+                Ok(objects.get_range(path, range).await?)
+            }
+            Self::Data(storage) => storage.read().unwrap().read_data_at(offset, len),
+            Self::Outboard(storage) => storage.read().unwrap().read_outboard_at(offset, len),
+        }
+    }
+
+    async fn size(&mut self) -> io::Result<u64> {
+        Ok(match self {
+            Self::Object { size, .. } => *size,
+            Self::Data(storage) => storage.read().unwrap().data.metadata()?.len(),
+            Self::Outboard(storage) => storage.read().unwrap().outboard.metadata()?.len(),
+        })
+    }
+}
+
+/// Batch writer for an entry.
+///
+/// Writes to complete entries are ignored, since they already contain all data.
+struct BatchWriter(Option<Arc<RwLock<PartialStorage>>>);
+
+impl BaoBatchWriter for BatchWriter {
+    async fn write_batch(&mut self, size: u64, batch: Vec<BaoContentItem>) -> io::Result<()> {
+        match &self.0 {
+            Some(storage) => storage.write().unwrap().write_batch(size, &batch),
+            None => Ok(()),
+        }
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Map for Store {
+    type Entry = Entry;
+
+    async fn get(&self, hash: &Hash) -> io::Result<Option<Self::Entry>> {
+        if let Some(size) = self.blob_size(hash)? {
+            return Ok(Some(self.complete_entry(*hash, size)));
+        }
+        Ok(self.read_lock().partial.get(hash).map(|storage| Entry {
+            hash: *hash,
+            state: EntryState::Partial(storage.clone()),
+        }))
+    }
+}
+
+impl MapMut for Store {
+    type EntryMut = Entry;
+
+    async fn get_mut(&self, hash: &Hash) -> io::Result<Option<Self::EntryMut>> {
+        self.get(hash).await
+    }
+
+    async fn get_or_create(&self, hash: Hash, _size: u64) -> io::Result<Entry> {
+        if let Some(size) = self.blob_size(&hash)? {
+            return Ok(self.complete_entry(hash, size));
+        }
+        // share partial entries, so concurrent downloads of the same blob write to the same data
+        let mut state = self.write_lock();
+        let storage = match state.partial.entry(hash) {
+            btree_map::Entry::Occupied(entry) => entry.get().clone(),
+            btree_map::Entry::Vacant(entry) => entry
+                .insert(Arc::new(RwLock::new(PartialStorage::create(
+                    &self.inner.staging,
+                )?)))
+                .clone(),
+        };
+        Ok(Entry {
+            hash,
+            state: EntryState::Partial(storage),
+        })
+    }
+
+    async fn entry_status(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        self.entry_status_sync(hash)
+    }
+
+    fn entry_status_sync(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        Ok(if self.blob_size(hash)?.is_some() {
+            EntryStatus::Complete
+        } else if self.read_lock().partial.contains_key(hash) {
+            EntryStatus::Partial
+        } else {
+            EntryStatus::NotFound
+        })
+    }
+
+    async fn insert_complete(&self, entry: Entry) -> io::Result<()> {
+        let EntryState::Partial(storage) = entry.state else {
+            return Ok(());
+        };
+        let hash = entry.hash;
+        let (size, data, outboard) = {
+            let storage = storage.read().unwrap();
+            let size = storage.current_size();
+            let outboard_size = raw_outboard_size(size) as usize;
+            (
+                size,
+                storage.data_path.clone(),
+                storage.read_outboard_at(0, outboard_size)?,
+            )
+        };
+        self.upload(hash, size, Upload::File(data), outboard.to_vec())
+            .await?;
+        self.write_lock().partial.remove(&hash);
+        Ok(())
+    }
+}
+
+impl ReadableStore for Store {
+    async fn blobs(&self) -> io::Result<DbIter<Hash>> {
+        let tx = self.inner.db.begin_read().map_err(to_io_err)?;
+        let blobs = tx.open_table(BLOBS_TABLE).map_err(to_io_err)?;
+        let hashes = blobs
+            .iter()
+            .map_err(to_io_err)?
+            .map(|item| item.map(|(hash, _)| hash.value()).map_err(to_io_err))
+            .collect::<Vec<_>>();
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    async fn partial_blobs(&self) -> io::Result<DbIter<Hash>> {
+        let hashes = self.read_lock().partial.keys().copied().collect::<Vec<_>>();
+        Ok(Box::new(hashes.into_iter().map(Ok)))
+    }
+
+    async fn tags(&self) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        let tx = self.inner.db.begin_read().map_err(to_io_err)?;
+        let tags = tx.open_table(TAGS_TABLE).map_err(to_io_err)?;
+        let tags = tags
+            .iter()
+            .map_err(to_io_err)?
+            .map(|item| {
+                item.map(|(name, value)| (name.value(), value.value()))
+                    .map_err(to_io_err)
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(tags.into_iter()))
+    }
+
+    async fn tags_with_prefix(&self, prefix: Tag) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        let tx = self.inner.db.begin_read().map_err(to_io_err)?;
+        let tags = tx.open_table(TAGS_TABLE).map_err(to_io_err)?;
+        let tags = tags
+            .range(prefix.clone()..)
+            .map_err(to_io_err)?
+            .map(|item| {
+                item.map(|(name, value)| (name.value(), value.value()))
+                    .map_err(to_io_err)
+            })
+            .take_while(|item| {
+                item.as_ref()
+                    .map_or(true, |(name, _)| name.0.starts_with(&prefix.0))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(tags.into_iter()))
+    }
+
+    fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
+        Box::new(self.read_lock().temp.keys())
+    }
+
+    async fn consistency_check(
+        &self,
+        repair: bool,
+        tx: BoxedProgressSender<ConsistencyCheckProgress>,
+    ) -> io::Result<()> {
+        let mut invalid = Vec::new();
+        for item in self.blobs().await? {
+            let hash = item?;
+            let Some(size) = self.blob_size(&hash)? else {
+                continue;
+            };
+            if !self.check_blob(hash, size, &tx).await? {
+                invalid.push(hash);
+            }
+        }
+        if repair && !invalid.is_empty() {
+            let message = format!("removing {} invalid blobs from the index", invalid.len());
+            tx.send(ConsistencyCheckProgress::Update {
+                message,
+                entry: None,
+                level: ReportLevel::Info,
+            })
+            .await?;
+            self.write(move |tx| {
+                let mut blobs = tx.open_table(BLOBS_TABLE)?;
+                for hash in invalid {
+                    blobs.remove(hash)?;
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn export(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        _mode: ExportMode,
+        progress: ExportProgressCb,
+    ) -> io::Result<()> {
+        tracing::trace!("exporting {} to {}", hash, target.display());
+
+        if !target.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "target path must be absolute",
+            ));
+        }
+        let parent = target.parent().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "target path has no parent directory",
+            )
+        })?;
+        if self.blob_size(&hash)?.is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "hash not found"));
+        }
+        // create the directory in which the target file is
+        tokio::fs::create_dir_all(parent).await?;
+        let mut stream = self
+            .inner
+            .objects
+            .get(&self.data_path(&hash))
+            .await?
+            .into_stream();
+        let mut offset = 0u64;
+        let mut file = tokio::fs::File::create(&target).await?;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            progress(offset)?;
+            file.write_all(&chunk).await?;
+            offset += chunk.len() as u64;
+        }
+        file.sync_all().await?;
+        drop(file);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "fs-store"))]
+mod tests {
+    use iroh_io::TokioStreamReader;
+    use object_store::memory::InMemory;
+
+    use super::*;
+    use crate::store::{
+        bao_file::test_support::{decode_response_into_batch, make_wire_data, random_test_data},
+        Store as _,
+    };
+
+    async fn create(objects: &Arc<InMemory>, dir: &std::path::Path) -> io::Result<Store> {
+        Store::new(
+            objects.clone(),
+            ObjectPath::from("blobs"),
+            dir.join("index.redb"),
+        )
+        .await
+    }
+
+    /// The ranges that [`crate::get::db::entry_valid_ranges`] reports for a complete blob.
+    fn full_chunks(size: u64) -> bao_tree::ChunkRanges {
+        bao_tree::ChunkRanges::from(..bao_tree::ChunkNum::full_chunks(size))
+    }
+
+    async fn read_all(entry: &Entry) -> io::Result<Bytes> {
+        let mut reader = entry.data_reader().await?;
+        let size = reader.size().await?;
+        reader.read_at(0, size as usize).await
+    }
+
+    #[tokio::test]
+    async fn import_and_read() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = Arc::new(InMemory::new());
+        let store = create(&objects, dir.path()).await?;
+        for size in [0, 1, 1024 * 16, 1024 * 100 + 17] {
+            let data = Bytes::from(random_test_data(size));
+            let tag = store.import_bytes(data.clone(), BlobFormat::Raw).await?;
+            let hash = *tag.hash();
+            assert_eq!(store.entry_status(&hash).await?, EntryStatus::Complete);
+            let entry = store.get(&hash).await?.unwrap();
+            assert!(entry.is_complete());
+            assert_eq!(entry.size().value(), size as u64);
+            assert_eq!(read_all(&entry).await?, data);
+            let valid = crate::get::db::entry_valid_ranges(&entry).await.unwrap();
+            assert_eq!(valid, full_chunks(size as u64));
+        }
+        // only blobs larger than a chunk group of 16 KiB have an outboard object
+        let outboards = objects
+            .list(Some(&ObjectPath::from("blobs/outboard")))
+            .try_collect::<_, _, Vec<_>>()
+            .await?;
+        assert_eq!(outboards.len(), 1);
+
+        // import a file and export it again
+        let data = random_test_data(100000);
+        let source = dir.path().join("source");
+        std::fs::write(&source, &data)?;
+        let progress = crate::util::progress::IgnoreProgressSender::default();
+        let (tag, size) = store
+            .import_file(source, ImportMode::Copy, BlobFormat::Raw, progress)
+            .await?;
+        assert_eq!(size, data.len() as u64);
+        let target = dir.path().join("target");
+        store
+            .export(
+                *tag.hash(),
+                target.clone(),
+                ExportMode::Copy,
+                Box::new(|_| Ok(())),
+            )
+            .await?;
+        assert_eq!(std::fs::read(target)?, data);
+
+        // deleted blobs are gone from the index and the object store
+        let hash = *tag.hash();
+        drop(tag);
+        store.delete(vec![hash]).await?;
+        assert_eq!(store.entry_status(&hash).await?, EntryStatus::NotFound);
+        let res = objects.head(&store.data_path(&hash)).await;
+        assert!(matches!(res, Err(object_store::Error::NotFound { .. })));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tags_persist() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = Arc::new(InMemory::new());
+        let store = create(&objects, dir.path()).await?;
+        let hash = *store
+            .import_bytes(Bytes::from_static(b"hello"), BlobFormat::Raw)
+            .await?
+            .hash();
+        let value = HashAndFormat::raw(hash);
+        let name = Tag::from("a");
+        store.set_tag(name.clone(), Some(value)).await?;
+        let auto = store.create_tag(value).await?;
+        let res = store
+            .compare_and_swap_tag(name.clone(), None, Some(value))
+            .await?;
+        assert_eq!(res, Err(Some(value)));
+        drop(store);
+
+        let store = create(&objects, dir.path()).await?;
+        let tags = store.tags().await?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(tags.len(), 2);
+        assert!(tags.contains(&(name, value)));
+        assert!(tags.contains(&(auto, value)));
+        let entry = store.get(&hash).await?.unwrap();
+        assert_eq!(read_all(&entry).await?, Bytes::from_static(b"hello"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn partial_download() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = Arc::new(InMemory::new());
+        let store = create(&objects, dir.path()).await?;
+        let data = random_test_data(1024 * 64);
+        let range = 0..data.len() as u64;
+        let (hash, ranges, wire) = make_wire_data(&data, &[range]);
+        let entry = store.get_or_create(hash, data.len() as u64).await?;
+        assert_eq!(store.entry_status(&hash).await?, EntryStatus::Partial);
+        let writer = entry.batch_writer().await?;
+        let wire = TokioStreamReader::new(std::io::Cursor::new(wire));
+        decode_response_into_batch(hash, IROH_BLOCK_SIZE, ranges, wire, writer).await?;
+        store.insert_complete(entry).await?;
+        assert_eq!(store.entry_status(&hash).await?, EntryStatus::Complete);
+        assert_eq!(store.partial_blobs().await?.count(), 0);
+        // the staged files are removed once the blob is uploaded
+        assert_eq!(std::fs::read_dir(&store.inner.staging)?.count(), 0);
+        let entry = store.get(&hash).await?.unwrap();
+        assert_eq!(read_all(&entry).await?, Bytes::from(data.clone()));
+        let valid = crate::get::db::entry_valid_ranges(&entry).await.unwrap();
+        assert_eq!(valid, full_chunks(data.len() as u64));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_large() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = Arc::new(InMemory::new());
+        let store = create(&objects, dir.path()).await?;
+        let data = Bytes::from(random_test_data(MAX_SINGLE_PUT as usize + 1024 * 17));
+        let source = dir.path().join("source");
+        std::fs::write(&source, &data)?;
+        let progress = crate::util::progress::IgnoreProgressSender::default();
+        let (file_tag, size) = store
+            .import_file(source, ImportMode::Copy, BlobFormat::Raw, progress.clone())
+            .await?;
+        assert_eq!(size, data.len() as u64);
+        let stream = futures_lite::stream::iter(
+            data.chunks(1024 * 1024)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>(),
+        );
+        let (stream_tag, size) = store
+            .import_stream(stream, BlobFormat::Raw, progress)
+            .await?;
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(file_tag.hash(), stream_tag.hash());
+        let entry = store.get(file_tag.hash()).await?.unwrap();
+        assert_eq!(read_all(&entry).await?, data);
+        let valid = crate::get::db::entry_valid_ranges(&entry).await.unwrap();
+        assert_eq!(valid, full_chunks(data.len() as u64));
+        // neither local staging files nor staging objects are left behind
+        assert_eq!(std::fs::read_dir(&store.inner.staging)?.count(), 0);
+        let staging = objects
+            .list(Some(&ObjectPath::from("blobs/staging")))
+            .try_collect::<_, _, Vec<_>>()
+            .await?;
+        assert!(staging.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_while_importing() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = Arc::new(InMemory::new());
+        let store = create(&objects, dir.path()).await?;
+        let data = Bytes::from(random_test_data(1024 * 100));
+        let hash = *store
+            .import_bytes(data.clone(), BlobFormat::Raw)
+            .await?
+            .hash();
+        for _ in 0..20 {
+            let (deleted, imported) = tokio::join!(
+                store.delete(vec![hash]),
+                store.import_bytes(data.clone(), BlobFormat::Raw)
+            );
+            deleted?;
+            let _tag = imported?;
+            // the import holds a temp tag, so the blob must be intact
+            assert_eq!(store.entry_status(&hash).await?, EntryStatus::Complete);
+            let entry = store.get(&hash).await?.unwrap();
+            assert_eq!(read_all(&entry).await?, data);
+        }
+        Ok(())
+    }
+}
@@ -320,6 +320,15 @@ impl super::Store for Store {
         Err(io::Error::new(io::ErrorKind::Other, "not implemented"))
     }
 
+    async fn compare_and_swap_tag(
+        &self,
+        _name: Tag,
+        _expected: Option<HashAndFormat>,
+        _value: Option<HashAndFormat>,
+    ) -> io::Result<Result<(), Option<HashAndFormat>>> {
+        Err(io::Error::new(io::ErrorKind::Other, "not implemented"))
+    }
+
     fn temp_tag(&self, inner: HashAndFormat) -> TempTag {
         TempTag::new(inner, None)
     }
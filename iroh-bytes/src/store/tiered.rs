@@ -0,0 +1,725 @@
+//! A store that combines a small, fast cache store with a large backing store.
+//!
+//! Main entry point is [Store].
+//!
+//! The backing store holds all blobs and is the authority for tags, blob
+//! metadata and partial blobs. Imports and downloads always go to the backing
+//! store. The cache holds copies of hot blobs, and reads are served from the
+//! cache if it has a complete copy.
+//!
+//! Blobs are promoted into the cache once they were served to peers from the
+//! backing store often enough, see [`TierPolicy`]. When the cache grows beyond
+//! its size, the least recently served blobs are demoted, i.e. removed from the
+//! cache. Since the backing store keeps all blobs, demotion never loses data.
+//!
+//! Tags are written to both stores, so each of them stays usable on its own.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use bao_tree::{blake3, io::fsm::Outboard, BaoTree, TreeNode};
+use bytes::{Bytes, BytesMut};
+use futures_lite::Stream;
+use iroh_base::hash::{BlobFormat, Hash, HashAndFormat};
+use iroh_io::AsyncSliceReader;
+use tokio_util::task::LocalPoolHandle;
+
+use crate::{
+    store::{
+        BaoBlobSize, BlobAccess, BlobMeta, BlobMetaUpdate, DbIter, EntryStatus, MapEntry, Quota,
+        ReadableStore,
+    },
+    util::progress::{BoxedProgressSender, IdGenerator, ProgressSender},
+    Tag, TempTag,
+};
+
+use super::{
+    ConsistencyCheckProgress, ExportMode, ExportProgressCb, ImportMode, ImportProgress, Map, MapMut,
+};
+
+/// Size of the reads when copying a blob into the cache.
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// When blobs are moved between the tiers of a [Store].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierPolicy {
+    /// The maximum total size of the blobs in the cache, in bytes.
+    ///
+    /// When a promotion makes the cache larger, the least recently served
+    /// blobs are demoted until it is within this size again.
+    pub cache_size: u64,
+    /// Promote a blob after it was served this many times from the backing store.
+    ///
+    /// 0 disables automatic promotion, blobs are then only promoted with
+    /// [`Store::promote`].
+    pub promote_after: u32,
+    /// Blobs larger than this are never promoted.
+    ///
+    /// Promoted blobs are copied through memory, so this also limits the
+    /// memory used by a promotion.
+    pub max_promote_size: u64,
+}
+
+/// A store that serves reads from a fast cache store, and falls back to a
+/// large backing store.
+///
+/// See the [module docs](self) for details.
+#[derive(Debug, Clone)]
+pub struct Store<C, B> {
+    inner: Arc<Inner<C, B>>,
+}
+
+#[derive(derive_more::Debug)]
+struct Inner<C, B> {
+    cache: C,
+    backing: B,
+    policy: TierPolicy,
+    state: Mutex<State>,
+    /// Pool for copying blobs into the cache, since readers are not `Send`.
+    #[debug(skip)]
+    pool: LocalPoolHandle,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Blobs in the cache.
+    cached: BTreeMap<Hash, Cached>,
+    /// Number of times blobs that are not in the cache were served.
+    served: BTreeMap<Hash, u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cached {
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl<C: super::Store, B: super::Store> Store<C, B> {
+    /// Create a new tiered store from a cache and a backing store.
+    ///
+    /// Complete blobs that are already in the cache are kept, ordered for
+    /// demotion by when they were last imported or served.
+    pub async fn new(cache: C, backing: B, policy: TierPolicy) -> io::Result<Self> {
+        let mut cached = BTreeMap::new();
+        for hash in cache.blobs().await? {
+            let hash = hash?;
+            let Some(entry) = cache.get(&hash).await? else {
+                continue;
+            };
+            let access = cache.blob_access(&hash).await?.unwrap_or_default();
+            let cached_entry = Cached {
+                size: entry.size().value(),
+                last_used: access.served.unwrap_or(access.imported),
+            };
+            cached.insert(hash, cached_entry);
+        }
+        let this = Self {
+            inner: Arc::new(Inner {
+                cache,
+                backing,
+                policy,
+                state: Mutex::new(State {
+                    cached,
+                    served: Default::default(),
+                }),
+                pool: LocalPoolHandle::new(1),
+            }),
+        };
+        this.shrink_cache(None).await?;
+        Ok(this)
+    }
+
+    /// The cache store.
+    pub fn cache(&self) -> &C {
+        &self.inner.cache
+    }
+
+    /// The backing store.
+    pub fn backing(&self) -> &B {
+        &self.inner.backing
+    }
+
+    /// The total size of the blobs in the cache.
+    pub fn cache_size(&self) -> u64 {
+        self.state().cached.values().map(|x| x.size).sum()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap()
+    }
+
+    /// Copy a complete blob from the backing store into the cache.
+    ///
+    /// Returns false if the blob is not complete in the backing store, is
+    /// larger than [`TierPolicy::max_promote_size`] or is already cached.
+    pub async fn promote(&self, hash: Hash) -> io::Result<bool> {
+        let this = self.clone();
+        self.inner
+            .pool
+            .spawn_pinned(move || async move { this.promote_local(hash).await })
+            .await?
+    }
+
+    /// Remove a blob from the cache.
+    ///
+    /// The blob stays in the backing store.
+    pub async fn demote(&self, hash: Hash) -> io::Result<()> {
+        self.inner.cache.delete(vec![hash]).await?;
+        self.state().cached.remove(&hash);
+        Ok(())
+    }
+
+    async fn promote_local(&self, hash: Hash) -> io::Result<bool> {
+        if self.state().cached.contains_key(&hash) {
+            return Ok(false);
+        }
+        let Some(entry) = self.inner.backing.get(&hash).await? else {
+            return Ok(false);
+        };
+        let size = entry.size().value();
+        if !entry.is_complete() || size > self.inner.policy.max_promote_size {
+            return Ok(false);
+        }
+        let mut reader = entry.data_reader().await?;
+        let mut data = BytesMut::with_capacity(size as usize);
+        while (data.len() as u64) < size {
+            let chunk = reader.read_at(data.len() as u64, COPY_CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let tag = self
+            .inner
+            .cache
+            .import_bytes(data.freeze(), BlobFormat::Raw)
+            .await?;
+        if tag.hash() != &hash {
+            let actual = *tag.hash();
+            drop(tag);
+            self.inner.cache.delete(vec![actual]).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("backing store returned wrong data for {hash}"),
+            ));
+        }
+        let deleted = {
+            let mut state = self.state();
+            // the blob might have been deleted while it was copied. A delete removes blobs from
+            // the backing store before it removes them from the state, so checking the backing
+            // store under the lock makes sure a deleted blob is not added back to the cache.
+            let deleted = self.inner.backing.entry_status_sync(&hash)? != EntryStatus::Complete;
+            if !deleted {
+                state.served.remove(&hash);
+                let cached = Cached {
+                    size,
+                    last_used: SystemTime::now(),
+                };
+                state.cached.insert(hash, cached);
+            }
+            deleted
+        };
+        drop(tag);
+        if deleted {
+            self.inner.cache.delete(vec![hash]).await?;
+            return Ok(false);
+        }
+        self.shrink_cache(Some(hash)).await?;
+        Ok(true)
+    }
+
+    /// Demote the least recently used blobs until the cache is within its size.
+    async fn shrink_cache(&self, keep: Option<Hash>) -> io::Result<()> {
+        let demote = {
+            let state = self.state();
+            let mut total = state.cached.values().map(|x| x.size).sum::<u64>();
+            let mut candidates = state
+                .cached
+                .iter()
+                .filter(|(hash, _)| Some(**hash) != keep)
+                .map(|(hash, cached)| (cached.last_used, *hash, cached.size))
+                .collect::<Vec<_>>();
+            candidates.sort();
+            let mut demote = Vec::new();
+            for (_, hash, size) in candidates {
+                if total <= self.inner.policy.cache_size {
+                    break;
+                }
+                total -= size;
+                demote.push(hash);
+            }
+            demote
+        };
+        if demote.is_empty() {
+            return Ok(());
+        }
+        tracing::debug!("demoting {} blobs from the cache", demote.len());
+        self.inner.cache.delete(demote.clone()).await?;
+        let mut state = self.state();
+        for hash in demote {
+            state.cached.remove(&hash);
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a tiered [Store], from either the cache or the backing store.
+#[derive(Debug, Clone)]
+pub enum Entry<C, B> {
+    /// A complete entry from the cache.
+    Cache(C),
+    /// An entry from the backing store.
+    Backing(B),
+}
+
+impl<C: MapEntry, B: MapEntry> MapEntry for Entry<C, B> {
+    fn hash(&self) -> Hash {
+        match self {
+            Self::Cache(entry) => entry.hash(),
+            Self::Backing(entry) => entry.hash(),
+        }
+    }
+
+    fn size(&self) -> BaoBlobSize {
+        match self {
+            Self::Cache(entry) => entry.size(),
+            Self::Backing(entry) => entry.size(),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self {
+            Self::Cache(entry) => entry.is_complete(),
+            Self::Backing(entry) => entry.is_complete(),
+        }
+    }
+
+    async fn outboard(&self) -> io::Result<impl Outboard> {
+        Ok(match self {
+            Self::Cache(entry) => Either::Cache(entry.outboard().await?),
+            Self::Backing(entry) => Either::Backing(entry.outboard().await?),
+        })
+    }
+
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(match self {
+            Self::Cache(entry) => Either::Cache(entry.data_reader().await?),
+            Self::Backing(entry) => Either::Backing(entry.data_reader().await?),
+        })
+    }
+}
+
+/// A reader or outboard from either tier.
+#[derive(Debug)]
+enum Either<C, B> {
+    Cache(C),
+    Backing(B),
+}
+
+impl<C: AsyncSliceReader, B: AsyncSliceReader> AsyncSliceReader for Either<C, B> {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        match self {
+            Self::Cache(reader) => reader.read_at(offset, len).await,
+            Self::Backing(reader) => reader.read_at(offset, len).await,
+        }
+    }
+
+    async fn size(&mut self) -> io::Result<u64> {
+        match self {
+            Self::Cache(reader) => reader.size().await,
+            Self::Backing(reader) => reader.size().await,
+        }
+    }
+}
+
+impl<C: Outboard, B: Outboard> Outboard for Either<C, B> {
+    fn root(&self) -> blake3::Hash {
+        match self {
+            Self::Cache(outboard) => outboard.root(),
+            Self::Backing(outboard) => outboard.root(),
+        }
+    }
+
+    fn tree(&self) -> BaoTree {
+        match self {
+            Self::Cache(outboard) => outboard.tree(),
+            Self::Backing(outboard) => outboard.tree(),
+        }
+    }
+
+    async fn load(&mut self, node: TreeNode) -> io::Result<Option<(blake3::Hash, blake3::Hash)>> {
+        match self {
+            Self::Cache(outboard) => outboard.load(node).await,
+            Self::Backing(outboard) => outboard.load(node).await,
+        }
+    }
+}
+
+impl<C: super::Store, B: super::Store> Map for Store<C, B> {
+    type Entry = Entry<C::Entry, B::Entry>;
+
+    async fn get(&self, hash: &Hash) -> io::Result<Option<Self::Entry>> {
+        if self.state().cached.contains_key(hash) {
+            match self.inner.cache.get(hash).await? {
+                Some(entry) if entry.is_complete() => return Ok(Some(Entry::Cache(entry))),
+                // removed from the cache behind our back
+                _ => {
+                    self.state().cached.remove(hash);
+                }
+            }
+        }
+        Ok(self.inner.backing.get(hash).await?.map(Entry::Backing))
+    }
+
+    async fn record_served(&self, hash: &Hash) -> io::Result<()> {
+        self.inner.backing.record_served(hash).await?;
+        let (cached, promote) = {
+            let mut state = self.state();
+            if let Some(cached) = state.cached.get_mut(hash) {
+                cached.last_used = SystemTime::now();
+                (true, false)
+            } else {
+                let served = state.served.entry(*hash).or_default();
+                *served = served.saturating_add(1);
+                let promote_after = self.inner.policy.promote_after;
+                (false, promote_after != 0 && *served >= promote_after)
+            }
+        };
+        if cached {
+            self.inner.cache.record_served(hash).await?;
+        }
+        if promote {
+            let this = self.clone();
+            let hash = *hash;
+            self.inner.pool.spawn_pinned(move || async move {
+                if let Err(cause) = this.promote_local(hash).await {
+                    tracing::warn!("failed to promote {hash}: {cause}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<C: super::Store, B: super::Store> MapMut for Store<C, B> {
+    type EntryMut = B::EntryMut;
+
+    async fn get_mut(&self, hash: &Hash) -> io::Result<Option<Self::EntryMut>> {
+        self.inner.backing.get_mut(hash).await
+    }
+
+    async fn get_or_create(&self, hash: Hash, size: u64) -> io::Result<Self::EntryMut> {
+        self.inner.backing.get_or_create(hash, size).await
+    }
+
+    async fn entry_status(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        self.inner.backing.entry_status(hash).await
+    }
+
+    fn entry_status_sync(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        self.inner.backing.entry_status_sync(hash)
+    }
+
+    async fn insert_complete(&self, entry: Self::EntryMut) -> io::Result<()> {
+        self.inner.backing.insert_complete(entry).await
+    }
+}
+
+impl<C: super::Store, B: super::Store> ReadableStore for Store<C, B> {
+    async fn blobs(&self) -> io::Result<DbIter<Hash>> {
+        self.inner.backing.blobs().await
+    }
+
+    async fn partial_blobs(&self) -> io::Result<DbIter<Hash>> {
+        self.inner.backing.partial_blobs().await
+    }
+
+    async fn tags(&self) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        self.inner.backing.tags().await
+    }
+
+    async fn tags_with_prefix(&self, prefix: Tag) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        self.inner.backing.tags_with_prefix(prefix).await
+    }
+
+    fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
+        self.inner.backing.temp_tags()
+    }
+
+    async fn consistency_check(
+        &self,
+        repair: bool,
+        tx: BoxedProgressSender<ConsistencyCheckProgress>,
+    ) -> io::Result<()> {
+        self.inner
+            .cache
+            .consistency_check(repair, tx.clone())
+            .await?;
+        self.inner.backing.consistency_check(repair, tx).await
+    }
+
+    async fn export(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        mode: ExportMode,
+        progress: ExportProgressCb,
+    ) -> io::Result<()> {
+        if self.state().cached.contains_key(&hash) {
+            self.inner.cache.export(hash, target, mode, progress).await
+        } else {
+            self.inner
+                .backing
+                .export(hash, target, mode, progress)
+                .await
+        }
+    }
+}
+
+impl<C: super::Store, B: super::Store> super::Store for Store<C, B> {
+    async fn import_file(
+        &self,
+        path: PathBuf,
+        mode: ImportMode,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        self.inner
+            .backing
+            .import_file(path, mode, format, progress)
+            .await
+    }
+
+    async fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> io::Result<TempTag> {
+        self.inner.backing.import_bytes(bytes, format).await
+    }
+
+    async fn import_stream(
+        &self,
+        data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        self.inner
+            .backing
+            .import_stream(data, format, progress)
+            .await
+    }
+
+    async fn set_tag(&self, name: Tag, value: Option<HashAndFormat>) -> io::Result<()> {
+        self.inner.backing.set_tag(name.clone(), value).await?;
+        self.inner.cache.set_tag(name, value).await
+    }
+
+    async fn create_tag(&self, value: HashAndFormat) -> io::Result<Tag> {
+        let tag = self.inner.backing.create_tag(value).await?;
+        self.inner.cache.set_tag(tag.clone(), Some(value)).await?;
+        Ok(tag)
+    }
+
+    async fn compare_and_swap_tag(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> io::Result<Result<(), Option<HashAndFormat>>> {
+        let res = self
+            .inner
+            .backing
+            .compare_and_swap_tag(name.clone(), expected, value)
+            .await?;
+        if res.is_ok() {
+            self.inner.cache.set_tag(name, value).await?;
+        }
+        Ok(res)
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        self.inner
+            .backing
+            .rename_tag(from.clone(), to.clone())
+            .await?;
+        self.inner.cache.rename_tag(from, to).await
+    }
+
+    fn temp_tag(&self, value: HashAndFormat) -> TempTag {
+        self.inner.backing.temp_tag(value)
+    }
+
+    fn quota(&self) -> Option<Quota> {
+        self.inner.backing.quota()
+    }
+
+    async fn blob_access(&self, hash: &Hash) -> io::Result<Option<BlobAccess>> {
+        self.inner.backing.blob_access(hash).await
+    }
+
+    async fn blob_meta(&self, hash: &Hash) -> io::Result<Option<BlobMeta>> {
+        self.inner.backing.blob_meta(hash).await
+    }
+
+    async fn update_blob_meta(&self, hash: Hash, update: BlobMetaUpdate) -> io::Result<()> {
+        self.inner.backing.update_blob_meta(hash, update).await
+    }
+
+    async fn blob_metas(&self) -> io::Result<DbIter<(Hash, BlobMeta)>> {
+        self.inner.backing.blob_metas().await
+    }
+
+    async fn gc_start(&self) -> io::Result<()> {
+        self.inner.cache.gc_start().await?;
+        self.inner.backing.gc_start().await
+    }
+
+    async fn delete(&self, hashes: Vec<Hash>) -> io::Result<()> {
+        // delete from the backing store first, a concurrent promotion relies on this order
+        self.inner.backing.delete(hashes.clone()).await?;
+        // the backing store keeps temp tagged blobs, so the cache keeps them as well
+        let temp = self
+            .inner
+            .backing
+            .temp_tags()
+            .map(|x| x.hash)
+            .collect::<BTreeSet<_>>();
+        let hashes = hashes
+            .into_iter()
+            .filter(|hash| !temp.contains(hash))
+            .collect::<Vec<_>>();
+        {
+            let mut state = self.state();
+            for hash in &hashes {
+                state.cached.remove(hash);
+                state.served.remove(hash);
+            }
+        }
+        self.inner.cache.delete(hashes).await
+    }
+
+    async fn shutdown(&self) {
+        self.inner.cache.shutdown().await;
+        self.inner.backing.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{mem, Store as _};
+
+    type Tiered = Store<mem::Store, mem::Store>;
+
+    async fn create(cache_size: u64) -> io::Result<Tiered> {
+        let policy = TierPolicy {
+            cache_size,
+            promote_after: 0,
+            max_promote_size: 1024 * 1024,
+        };
+        Store::new(mem::Store::new(), mem::Store::new(), policy).await
+    }
+
+    async fn read_all(entry: &impl MapEntry) -> io::Result<Bytes> {
+        let mut reader = entry.data_reader().await?;
+        let size = reader.size().await?;
+        reader.read_at(0, size as usize).await
+    }
+
+    #[tokio::test]
+    async fn promote_and_demote() -> io::Result<()> {
+        let store = create(2048).await?;
+        let mut hashes = Vec::new();
+        for i in 1..=3u8 {
+            let tag = store
+                .import_bytes(vec![i; 1024].into(), BlobFormat::Raw)
+                .await?;
+            hashes.push(*tag.hash());
+        }
+        let [a, b, c] = hashes[..] else {
+            unreachable!()
+        };
+        // imports go to the backing store only
+        assert_eq!(store.cache().blobs().await?.count(), 0);
+        assert!(matches!(store.get(&a).await?, Some(Entry::Backing(_))));
+
+        assert!(store.promote(a).await?);
+        assert!(!store.promote(a).await?);
+        let entry = store.get(&a).await?.unwrap();
+        assert!(matches!(entry, Entry::Cache(_)));
+        assert_eq!(read_all(&entry).await?, Bytes::from(vec![1u8; 1024]));
+        let valid = crate::get::db::entry_valid_ranges(&entry).await.unwrap();
+        assert_eq!(valid, bao_tree::ChunkRanges::from(..bao_tree::ChunkNum(1)));
+
+        // promoting a third blob demotes the least recently served one
+        assert!(store.promote(b).await?);
+        store.record_served(&a).await?;
+        assert!(store.promote(c).await?);
+        assert_eq!(store.cache_size(), 2048);
+        assert!(matches!(store.get(&a).await?, Some(Entry::Cache(_))));
+        assert!(matches!(store.get(&b).await?, Some(Entry::Backing(_))));
+        assert!(matches!(store.get(&c).await?, Some(Entry::Cache(_))));
+        assert_eq!(store.cache().entry_status(&b).await?, EntryStatus::NotFound);
+
+        // deleting removes the blob from both tiers
+        store.delete(vec![a]).await?;
+        assert!(store.get(&a).await?.is_none());
+        assert_eq!(store.cache().entry_status(&a).await?, EntryStatus::NotFound);
+
+        // blobs over the size limit are never promoted
+        let large = store
+            .import_bytes(vec![4u8; 1024 * 1024 + 1].into(), BlobFormat::Raw)
+            .await?;
+        assert!(!store.promote(*large.hash()).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn promote_after_served() -> io::Result<()> {
+        let policy = TierPolicy {
+            cache_size: 1024 * 1024,
+            promote_after: 2,
+            max_promote_size: 1024 * 1024,
+        };
+        let store = Store::new(mem::Store::new(), mem::Store::new(), policy).await?;
+        let tag = store
+            .import_bytes(vec![1u8; 1024].into(), BlobFormat::Raw)
+            .await?;
+        let hash = *tag.hash();
+        store.record_served(&hash).await?;
+        assert!(matches!(store.get(&hash).await?, Some(Entry::Backing(_))));
+        store.record_served(&hash).await?;
+        // promotion happens in the background
+        for _ in 0..100 {
+            if matches!(store.get(&hash).await?, Some(Entry::Cache(_))) {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("blob was not promoted");
+    }
+
+    #[tokio::test]
+    async fn tags_in_both_tiers() -> io::Result<()> {
+        let store = create(1024).await?;
+        let tag = store
+            .import_bytes(vec![1u8; 10].into(), BlobFormat::Raw)
+            .await?;
+        let value = *tag.inner();
+        let name = Tag::from("a");
+        store.set_tag(name.clone(), Some(value)).await?;
+        let auto = store.create_tag(value).await?;
+        let res = store
+            .compare_and_swap_tag(name.clone(), Some(value), None)
+            .await?;
+        assert_eq!(res, Ok(()));
+        let res = store.compare_and_swap_tag(auto.clone(), None, None).await?;
+        assert_eq!(res, Err(Some(value)));
+        for tags in [store.backing().tags().await?, store.cache().tags().await?] {
+            let tags = tags.collect::<io::Result<Vec<_>>>()?;
+            assert_eq!(tags, vec![(auto.clone(), value)]);
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,11 @@
 //! Traits for in-memory or persistent maps of blob with bao encoded outboards.
-use std::{collections::BTreeSet, future::Future, io, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    io,
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use bao_tree::{
     io::fsm::{BaoContentItem, Outboard},
@@ -114,6 +120,15 @@ pub trait Map: Clone + Send + Sync + 'static {
     ///
     /// It is not guaranteed that the entry is complete.
     fn get(&self, hash: &Hash) -> impl Future<Output = io::Result<Option<Self::Entry>>> + Send;
+
+    /// Record that the blob for `hash` was served to a peer.
+    ///
+    /// Stores with a [`Quota`] use this to find the least recently served
+    /// blobs. The default implementation does nothing.
+    fn record_served(&self, hash: &Hash) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = hash;
+        async { Ok(()) }
+    }
 }
 
 /// A partial entry
@@ -266,6 +281,22 @@ pub trait ReadableStore: Map {
     /// list all tags (collections or other explicitly added things) in the database
     fn tags(&self) -> impl Future<Output = io::Result<DbIter<(Tag, HashAndFormat)>>> + Send;
 
+    /// list all tags starting with `prefix`, in order
+    ///
+    /// The default implementation filters the result of [`ReadableStore::tags`].
+    fn tags_with_prefix(
+        &self,
+        prefix: Tag,
+    ) -> impl Future<Output = io::Result<DbIter<(Tag, HashAndFormat)>>> + Send {
+        async move {
+            let tags = self.tags().await?.filter(move |item| match item {
+                Ok((tag, _)) => tag.0.starts_with(&prefix.0),
+                Err(_) => true,
+            });
+            Ok(Box::new(tags) as DbIter<_>)
+        }
+    }
+
     /// Temp tags
     fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static>;
 
@@ -353,9 +384,121 @@ pub trait Store: ReadableStore + MapMut {
     /// Create a new tag
     fn create_tag(&self, hash: HashAndFormat) -> impl Future<Output = io::Result<Tag>> + Send;
 
+    /// Atomically set the tag `name` to `value`, if its current value is `expected`.
+    ///
+    /// A value of `None` means that the tag does not exist, so `expected: None` only creates
+    /// the tag if it is absent, and `value: None` deletes it. If the current value differs
+    /// from `expected`, the tag is left unchanged and the current value is returned as the
+    /// error, so the caller can retry.
+    ///
+    /// The default implementation reads the tag and then sets it, which is not atomic. Stores
+    /// that can change their tags in a transaction should override it.
+    fn compare_and_swap_tag(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> impl Future<Output = io::Result<Result<(), Option<HashAndFormat>>>> + Send {
+        async move {
+            let current = get_tag(self, &name).await?;
+            if current != expected {
+                return Ok(Err(current));
+            }
+            self.set_tag(name, value).await?;
+            Ok(Ok(()))
+        }
+    }
+
+    /// Rename the tag `from` to `to`, keeping its value.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if `from` does not exist, and with
+    /// [`io::ErrorKind::AlreadyExists`] if `to` exists.
+    ///
+    /// The default implementation creates `to` before it deletes `from`, so the content is
+    /// protected at all times, but the rename is not atomic. Stores that can change their tags
+    /// in a transaction should override it.
+    fn rename_tag(&self, from: Tag, to: Tag) -> impl Future<Output = io::Result<()>> + Send {
+        async move {
+            let value = get_tag(self, &from)
+                .await?
+                .ok_or_else(|| tag_not_found(&from))?;
+            if self
+                .compare_and_swap_tag(to.clone(), None, Some(value))
+                .await?
+                .is_err()
+            {
+                return Err(tag_exists(&to));
+            }
+            if self
+                .compare_and_swap_tag(from.clone(), Some(value), None)
+                .await?
+                .is_err()
+            {
+                // the old tag was changed concurrently, undo the rename
+                self.compare_and_swap_tag(to, Some(value), None).await?.ok();
+                return Err(io::Error::other(format!(
+                    "tag {from} was changed during the rename"
+                )));
+            }
+            Ok(())
+        }
+    }
+
     /// Create a temporary pin for this store
     fn temp_tag(&self, value: HashAndFormat) -> TempTag;
 
+    /// The storage quota of this store, if any.
+    ///
+    /// The quota is enforced by evicting blobs during [`Store::gc_sweep`].
+    fn quota(&self) -> Option<Quota> {
+        None
+    }
+
+    /// When the blob for `hash` was last imported and served.
+    ///
+    /// Returns `None` if the store does not track this for the blob.
+    fn blob_access(
+        &self,
+        hash: &Hash,
+    ) -> impl Future<Output = io::Result<Option<BlobAccess>>> + Send {
+        let _ = hash;
+        async { Ok(None) }
+    }
+
+    /// The metadata of the blob for `hash`, if it has any.
+    ///
+    /// Stores that support metadata detect the MIME type of blobs added with
+    /// [`Store::import_file`] and [`Store::import_stream`], from the file name or the content.
+    fn blob_meta(&self, hash: &Hash) -> impl Future<Output = io::Result<Option<BlobMeta>>> + Send {
+        let _ = hash;
+        async { Ok(None) }
+    }
+
+    /// Apply `update` to the metadata of the blob for `hash`.
+    ///
+    /// The update is atomic, so concurrent updates of different annotations do not overwrite
+    /// each other. Metadata belongs to a blob: it can only be set while the blob is in the
+    /// store, failing with [`io::ErrorKind::NotFound`] otherwise, and is removed together with
+    /// the blob.
+    fn update_blob_meta(
+        &self,
+        hash: Hash,
+        update: BlobMetaUpdate,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (hash, update);
+        async {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "blob metadata is not supported by this store",
+            ))
+        }
+    }
+
+    /// List the metadata of all blobs that have any.
+    fn blob_metas(&self) -> impl Future<Output = io::Result<DbIter<(Hash, BlobMeta)>>> + Send {
+        async { Ok(Box::new(std::iter::empty()) as DbIter<_>) }
+    }
+
     /// Notify the store that a new gc phase is about to start.
     ///
     /// This should not fail unless the store is shut down or otherwise in a
@@ -364,6 +507,9 @@ pub trait Store: ReadableStore + MapMut {
 
     /// Traverse all roots recursively and mark them as live.
     ///
+    /// If the store has a [`Quota`], tags in its evictable class are not
+    /// roots. Blobs held only by them are kept or evicted by [`Store::gc_sweep`].
+    ///
     /// Poll this stream to completion to perform a full gc mark phase.
     ///
     /// Not polling this stream to completion is dangerous, since it might lead
@@ -382,6 +528,10 @@ pub trait Store: ReadableStore + MapMut {
 
     /// Remove all blobs that are not marked as live.
     ///
+    /// If the store has a [`Quota`] and is over it afterwards, this also evicts
+    /// blobs held only by evictable tags, in the order given by the quota, and
+    /// reports each eviction as [`GcSweepEvent::Evicted`].
+    ///
     /// Poll this stream to completion to perform a full gc sweep. Not polling this stream
     /// to completion just means that some garbage will remain in the database.
     ///
@@ -560,9 +710,17 @@ async fn gc_mark_task<'a>(
         };
     }
     let mut roots = BTreeSet::new();
+    let quota = store.quota();
     debug!("traversing tags");
     for item in store.tags().await? {
         let (name, haf) = item?;
+        if quota
+            .as_ref()
+            .is_some_and(|quota| quota.is_evictable(&name))
+        {
+            debug!("skipping evictable root {:?} {:?}", name, haf);
+            continue;
+        }
         debug!("adding root {:?} {:?}", name, haf);
         roots.insert(haf);
     }
@@ -614,12 +772,28 @@ async fn gc_sweep_task<'a>(
     live: &BTreeSet<Hash>,
     co: &Co<GcSweepEvent>,
 ) -> anyhow::Result<()> {
+    let quota = store.quota();
+    // blobs held by evictable tags are kept unless the store is over its quota
+    let mut evictable = Vec::new();
+    if let Some(quota) = &quota {
+        for item in store.tags().await? {
+            let (tag, content) = item?;
+            if quota.is_evictable(&tag) {
+                let hashes = content_hashes(store, content).await?;
+                evictable.push((tag, content, hashes));
+            }
+        }
+    }
+    let held = evictable
+        .iter()
+        .flat_map(|(_, _, hashes)| hashes.iter().copied())
+        .collect::<BTreeSet<_>>();
     let blobs = store.blobs().await?.chain(store.partial_blobs().await?);
     let mut count = 0;
     let mut batch = Vec::new();
     for hash in blobs {
         let hash = hash?;
-        if !live.contains(&hash) {
+        if !live.contains(&hash) && !held.contains(&hash) {
             batch.push(hash);
             count += 1;
         }
@@ -636,9 +810,135 @@ async fn gc_sweep_task<'a>(
         count
     )))
     .await;
+    if let Some(quota) = quota {
+        evict_task(store, live, &quota, evictable, co).await?;
+    }
     Ok(())
 }
 
+/// Evict blobs held only by evictable tags until the store is within its quota.
+async fn evict_task(
+    store: &impl Store,
+    live: &BTreeSet<Hash>,
+    quota: &Quota,
+    evictable: Vec<(Tag, HashAndFormat, BTreeSet<Hash>)>,
+    co: &Co<GcSweepEvent>,
+) -> anyhow::Result<()> {
+    let mut sizes = BTreeMap::new();
+    let mut total = 0u64;
+    for hash in store.blobs().await?.chain(store.partial_blobs().await?) {
+        let hash = hash?;
+        if let Some(entry) = store.get(&hash).await? {
+            let size = entry.size().value();
+            sizes.insert(hash, size);
+            total += size;
+        }
+    }
+    if total <= quota.max_size {
+        return Ok(());
+    }
+    // number of evictable tags holding each blob
+    let mut holders = BTreeMap::<Hash, usize>::new();
+    let mut candidates = Vec::new();
+    for (tag, content, hashes) in evictable {
+        for hash in &hashes {
+            *holders.entry(*hash).or_default() += 1;
+        }
+        // tags whose content is also held elsewhere would free nothing
+        if hashes.iter().all(|hash| live.contains(hash)) {
+            continue;
+        }
+        let access = store.blob_access(&content.hash).await?.unwrap_or_default();
+        candidates.push((access.last_used(quota.order), tag, content, hashes));
+    }
+    candidates.sort_by_key(|(last_used, ..)| *last_used);
+    for (_, tag, content, hashes) in candidates {
+        if total <= quota.max_size {
+            break;
+        }
+        // the tag may have been set to other content since it was listed, in which case its
+        // blobs are not ours to evict
+        if store
+            .compare_and_swap_tag(tag.clone(), Some(content), None)
+            .await?
+            .is_err()
+        {
+            continue;
+        }
+        let mut size = 0;
+        let mut batch = Vec::new();
+        for hash in hashes {
+            let holders = holders.get_mut(&hash).expect("counted above");
+            *holders -= 1;
+            if *holders == 0 && !live.contains(&hash) {
+                size += sizes.get(&hash).copied().unwrap_or_default();
+                batch.push(hash);
+            }
+        }
+        store.delete(batch).await?;
+        total = total.saturating_sub(size);
+        co.yield_(GcSweepEvent::Evicted { tag, content, size })
+            .await;
+    }
+    if total > quota.max_size {
+        co.yield_(GcSweepEvent::CustomWarning(
+            format!(
+                "store uses {} bytes, more than its quota of {} bytes, but nothing is left to evict",
+                total, quota.max_size
+            ),
+            None,
+        ))
+        .await;
+    }
+    Ok(())
+}
+
+/// Get the value of the tag `name`.
+async fn get_tag(store: &impl ReadableStore, name: &Tag) -> io::Result<Option<HashAndFormat>> {
+    for item in store.tags_with_prefix(name.clone()).await? {
+        let (tag, value) = item?;
+        if &tag == name {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// The error for renaming a tag that does not exist.
+pub(crate) fn tag_not_found(tag: &Tag) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("tag {tag} not found"))
+}
+
+/// The error for renaming a tag to a name that already exists.
+pub(crate) fn tag_exists(tag: &Tag) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("tag {tag} already exists"),
+    )
+}
+
+/// The hashes of a blob, and of its children if it is a complete hash sequence.
+async fn content_hashes(store: &impl Store, content: HashAndFormat) -> io::Result<BTreeSet<Hash>> {
+    let mut hashes = BTreeSet::new();
+    hashes.insert(content.hash);
+    if content.format.is_raw() {
+        return Ok(hashes);
+    }
+    let Some(entry) = store.get(&content.hash).await? else {
+        return Ok(hashes);
+    };
+    if !entry.is_complete() {
+        return Ok(hashes);
+    }
+    let Ok((mut stream, _)) = parse_hash_seq(entry.data_reader().await?).await else {
+        return Ok(hashes);
+    };
+    while let Ok(Some(hash)) = stream.next().await {
+        hashes.insert(hash);
+    }
+    Ok(hashes)
+}
+
 /// An event related to GC
 #[derive(Debug)]
 pub enum GcMarkEvent {
@@ -657,10 +957,133 @@ pub enum GcSweepEvent {
     CustomDebug(String),
     /// A custom non critical error
     CustomWarning(String, Option<anyhow::Error>),
+    /// A tag was removed and the blobs only it held were deleted to get the
+    /// store within its [`Quota`].
+    Evicted {
+        /// The removed tag
+        tag: Tag,
+        /// The content the tag pointed to
+        content: HashAndFormat,
+        /// The total size of the deleted blobs
+        size: u64,
+    },
     /// An unrecoverable error during GC
     Error(anyhow::Error),
 }
 
+/// A limit on the total size of the blobs in a store.
+///
+/// The quota is enforced during gc. Once the store is over the quota, blobs
+/// that are held only by evictable tags are evicted until it is within the
+/// quota again. Blobs held by other tags, temp tags or documents are never
+/// evicted, so the store can stay over its quota.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// The maximum total size of all blobs in the store, in bytes.
+    ///
+    /// Partial blobs count with their full size.
+    pub max_size: u64,
+    /// Tags starting with this prefix are evictable.
+    ///
+    /// An empty prefix makes all tags evictable.
+    pub evictable_prefix: Tag,
+    /// The order in which blobs are evicted.
+    pub order: EvictionOrder,
+}
+
+impl Quota {
+    /// Whether the blobs held by `tag` can be evicted.
+    pub fn is_evictable(&self, tag: &Tag) -> bool {
+        tag.0.starts_with(&self.evictable_prefix.0)
+    }
+}
+
+/// The order in which blobs are evicted when a store is over its [`Quota`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionOrder {
+    /// Evict the blobs that were served least recently first.
+    ///
+    /// Blobs that were never served count as served when they were imported.
+    #[default]
+    LeastRecentlyServed,
+    /// Evict the blobs that were imported least recently first.
+    LeastRecentlyImported,
+}
+
+/// When a blob was last imported into and served from a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobAccess {
+    /// When the blob was last completed, by an import or a download.
+    pub imported: SystemTime,
+    /// When the blob was last served to a peer, if ever.
+    pub served: Option<SystemTime>,
+}
+
+impl Default for BlobAccess {
+    fn default() -> Self {
+        Self {
+            imported: SystemTime::UNIX_EPOCH,
+            served: None,
+        }
+    }
+}
+
+impl BlobAccess {
+    /// The time used to order the blob for eviction.
+    pub fn last_used(&self, order: EvictionOrder) -> SystemTime {
+        match order {
+            EvictionOrder::LeastRecentlyServed => self.served.unwrap_or(self.imported),
+            EvictionOrder::LeastRecentlyImported => self.imported,
+        }
+    }
+}
+
+/// Metadata of a blob in a store.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobMeta {
+    /// The MIME type of the blob, if known.
+    pub mime_type: Option<String>,
+    /// User defined key/value annotations.
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl BlobMeta {
+    /// Whether there is neither a MIME type nor any annotation.
+    pub fn is_empty(&self) -> bool {
+        self.mime_type.is_none() && self.annotations.is_empty()
+    }
+
+    /// Apply an update to this metadata.
+    pub fn apply(&mut self, update: BlobMetaUpdate) {
+        match update {
+            BlobMetaUpdate::MimeType(mime_type) => self.mime_type = mime_type,
+            BlobMetaUpdate::Annotation { key, value: None } => {
+                self.annotations.remove(&key);
+            }
+            BlobMetaUpdate::Annotation {
+                key,
+                value: Some(value),
+            } => {
+                self.annotations.insert(key, value);
+            }
+        }
+    }
+}
+
+/// An update of the [`BlobMeta`] of a blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlobMetaUpdate {
+    /// Set or clear the MIME type.
+    MimeType(Option<String>),
+    /// Set or remove an annotation.
+    Annotation {
+        /// The key of the annotation.
+        key: String,
+        /// The new value, or `None` to remove the annotation.
+        value: Option<String>,
+    },
+}
+
 /// Progress messages for an import operation
 ///
 /// An import operation involves computing the outboard of a file, and then
@@ -692,6 +1115,19 @@ pub enum ImportProgress {
     ///
     /// This comes after `Size` and zero or more `OutboardProgress` messages
     OutboardDone { id: u64, hash: Hash },
+    /// Done splitting the file into chunks
+    ///
+    /// This is only sent for [`ImportMode::Chunked`], after `Size` and zero or more
+    /// `CopyProgress` messages, and before `OutboardDone` for the hash seq of the chunks.
+    ///
+    /// `size` is the size of the file, `stored` is the size of the chunks that were not
+    /// in the store before, so the dedup ratio is `size / stored`.
+    ChunkingDone {
+        id: u64,
+        chunks: u64,
+        size: u64,
+        stored: u64,
+    },
 }
 
 /// The import mode describes how files will be imported.
@@ -706,6 +1142,11 @@ pub enum ImportMode {
     ///
     /// This is the safe default because the file can not be accidentally modified
     /// after it has been imported.
+    ///
+    /// Stores may avoid copying the data where that keeps this guarantee, e.g.
+    /// the fs store uses a reflink if the file system supports it, or a hard
+    /// link if the file is read-only. A hard linked file shares its data with
+    /// the store, so it must stay read-only for as long as it is in the store.
     #[default]
     Copy,
     /// This mode will try to reference the file in place and assume it is unchanged after import.
@@ -716,6 +1157,14 @@ pub enum ImportMode {
     /// Stores are allowed to ignore this mode and always copy the file, e.g.
     /// if the file is very small or if the store does not support referencing files.
     TryReference,
+    /// This mode will split the file into content-defined chunks, see
+    /// [`crate::util::chunker`].
+    ///
+    /// Each chunk is stored as a raw blob, and the result is a [`BlobFormat::HashSeq`] of
+    /// the chunks, regardless of the requested format. Chunks that are already in the store
+    /// are not stored again, so importing a slightly modified version of a file only
+    /// stores the changed chunks. Use [`ExportFormat::Chunked`] to reassemble the file.
+    Chunked,
 }
 /// The import mode describes how files will be imported.
 ///
@@ -729,6 +1178,9 @@ pub enum ExportMode {
     ///
     /// This is the safe default because the file can not be accidentally modified
     /// after it has been exported.
+    ///
+    /// Like for [`ImportMode::Copy`], stores may use reflinks or hard links to
+    /// read-only files instead of copying the data.
     #[default]
     Copy,
     /// This mode will try to move the file to the target directory and then reference it from
@@ -758,6 +1210,21 @@ pub enum ExportFormat {
     ///
     /// If the blob cannot be parsed as a collection, the operation will fail.
     Collection,
+    /// The hash refers to a [`BlobFormat::HashSeq`] of chunks, as created by
+    /// [`ImportMode::Chunked`], and the chunks shall be concatenated into a single file.
+    ///
+    /// If the blob cannot be parsed as a hash seq, or any chunk is missing, the operation
+    /// will fail.
+    Chunked,
+    /// The hash refers to a [`crate::format::tree::Tree`], which shall be exported as a
+    /// directory, recursively.
+    ///
+    /// Files, subdirectories including empty ones, and symlinks are recreated below the
+    /// export destination path. Where the platform supports it, the file modes, modification
+    /// times and extended attributes of the entries are restored.
+    ///
+    /// If the blob cannot be parsed as a tree, the operation will fail.
+    Tree,
 }
 
 #[allow(missing_docs)]
@@ -8,8 +8,10 @@ use std::{borrow::Borrow, fmt, sync::Arc, time::SystemTime};
 
 use crate::{store::Store, BlobFormat, Hash, HashAndFormat, IROH_BLOCK_SIZE};
 
+pub mod chunker;
 pub mod io;
 mod mem_or_file;
+pub mod mime;
 pub mod progress;
 pub use mem_or_file::MemOrFile;
 mod sparse_mem_file;
@@ -0,0 +1,174 @@
+//! Content-defined chunking for deduplicating imports.
+//!
+//! Data is split at positions determined by a gear hash over the last 64 bytes, so an
+//! insertion or modification only changes the chunks around it. Identical regions of two
+//! versions of a file therefore end up in identical chunks, which are stored only once.
+//!
+//! Chunk sizes are normalized around [`AVG_CHUNK_SIZE`]: below it, a boundary is harder to
+//! find, above it, it is easier.
+use std::io::{self, Read};
+
+use bytes::{Bytes, BytesMut};
+
+/// The minimum size of a chunk, except for the last chunk of the data.
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// The average size of a chunk.
+pub const AVG_CHUNK_SIZE: usize = 64 * 1024;
+/// The maximum size of a chunk.
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Mask for finding a boundary before the average chunk size, 18 bits.
+const MASK_SMALL: u64 = !(u64::MAX >> 18);
+/// Mask for finding a boundary after the average chunk size, 14 bits.
+const MASK_LARGE: u64 = !(u64::MAX >> 14);
+
+/// Random values for each byte, generated from a fixed seed so chunk boundaries are stable.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x6972_6f68_6364_6321u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Find the end of the first chunk of `data`.
+///
+/// `data` must contain at least [`MAX_CHUNK_SIZE`] bytes unless it is the end of the input.
+fn find_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = end.min(AVG_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(normal).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & MASK_SMALL == 0 {
+            return i + 1;
+        }
+    }
+    for (i, byte) in data.iter().enumerate().take(end).skip(normal) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & MASK_LARGE == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Splits the data of a reader into content-defined chunks.
+#[derive(Debug)]
+pub struct Chunker<R> {
+    reader: R,
+    buffer: BytesMut,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    /// Create a new chunker for the data of `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    /// Fill the buffer up to the maximum chunk size, or until the end of the input.
+    fn fill(&mut self) -> io::Result<()> {
+        while !self.eof && self.buffer.len() < MAX_CHUNK_SIZE {
+            let len = self.buffer.len();
+            self.buffer.resize(MAX_CHUNK_SIZE, 0);
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(n) => {
+                    self.buffer.truncate(len + n);
+                    self.eof = n == 0;
+                }
+                Err(err) => {
+                    self.buffer.truncate(len);
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill() {
+            return Some(Err(err));
+        }
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let end = find_boundary(&self.buffer);
+        Some(Ok(self.buffer.split_to(end).freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn chunks(data: &[u8]) -> Vec<Bytes> {
+        Chunker::new(data).collect::<io::Result<_>>().unwrap()
+    }
+
+    fn random_data(size: usize) -> Vec<u8> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        (0..size).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let data = random_data(4 * 1024 * 1024);
+        let chunks = chunks(&data);
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(!last.is_empty() && last.len() <= MAX_CHUNK_SIZE);
+        for chunk in rest {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len()));
+        }
+        // the sizes are spread around the average
+        let avg = data.len() / chunks.len();
+        assert!(
+            (AVG_CHUNK_SIZE / 2..AVG_CHUNK_SIZE * 2).contains(&avg),
+            "{avg}"
+        );
+    }
+
+    #[test]
+    fn small_data() {
+        assert!(chunks(&[]).is_empty());
+        let data = random_data(1000);
+        assert_eq!(chunks(&data), vec![Bytes::from(data)]);
+    }
+
+    #[test]
+    fn insertion_changes_few_chunks() {
+        let data = random_data(4 * 1024 * 1024);
+        let mut modified = data.clone();
+        modified.splice(1_000_000..1_000_000, [1, 2, 3]);
+        let before = chunks(&data).into_iter().collect::<HashSet<_>>();
+        let after = chunks(&modified);
+        let changed = after.iter().filter(|c| !before.contains(*c)).count();
+        assert!(changed <= 2, "{changed} of {} chunks changed", after.len());
+    }
+}
@@ -0,0 +1,122 @@
+//! Detection of the MIME type of blobs.
+//!
+//! The type is guessed from the extension of a file name, or sniffed from the first
+//! [`SNIFF_LEN`] bytes of the content. Only common types used on the web are detected.
+
+/// Number of bytes at the start of a blob used to sniff its content type.
+pub const SNIFF_LEN: usize = 512;
+
+/// Guess the content type from the extension of a file name.
+pub fn content_type_from_name(name: &str) -> Option<&'static str> {
+    let (_, ext) = name.rsplit_once('.')?;
+    let content_type = match ext.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// Detect the content type of a blob from its file name, or else from the first bytes of its
+/// content.
+pub fn detect_content_type(name: Option<&str>, head: &[u8]) -> &'static str {
+    name.and_then(content_type_from_name)
+        .unwrap_or_else(|| sniff_content_type(&head[..head.len().min(SNIFF_LEN)]))
+}
+
+/// Guess the content type from the first bytes of the content.
+pub fn sniff_content_type(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return content_type;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the head might end in the middle of a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).expect("valid up to here")
+        }
+        Err(_) => return "application/octet-stream",
+    };
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            content_type_from_name("dir/index.HTML"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(content_type_from_name("no_extension"), None);
+        assert_eq!(detect_content_type(Some("logo.png"), b"<svg"), "image/png");
+        assert_eq!(detect_content_type(Some("logo"), b"<svg"), "image/svg+xml");
+        assert_eq!(sniff_content_type(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff_content_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(
+            sniff_content_type(b"  <!DOCTYPE html><html>"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            sniff_content_type("caf\u{e9}".as_bytes()),
+            "text/plain; charset=utf-8"
+        );
+        // truncated in the middle of a character
+        assert_eq!(
+            sniff_content_type(&"caf\u{e9}".as_bytes()[..4]),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            sniff_content_type(b"\xff\xfe\xfd"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -6,6 +6,7 @@ use std::{
 };
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
+use bao_tree::{io::round_up_to_chunks, ByteRanges, ChunkNum, ChunkRanges};
 use clap::Subcommand;
 use console::{style, Emoji};
 use futures_lite::{Stream, StreamExt};
@@ -17,10 +18,13 @@ use iroh::net::{key::PublicKey, relay::RelayUrl, NodeAddr};
 use iroh::{
     base::node_addr::AddrInfoOptions,
     bytes::{
+        format::diff::{Change, ChangeKind},
         get::{db::DownloadProgress, progress::BlobProgress, Stats},
+        protocol::RangeSpecSeq,
         provider::AddProgress,
         store::{
-            ConsistencyCheckProgress, ExportFormat, ExportMode, ReportLevel, ValidateProgress,
+            BlobMeta, ConsistencyCheckProgress, ExportFormat, ExportMode, ReportLevel,
+            ValidateProgress,
         },
         BlobFormat, Hash, HashAndFormat, Tag,
     },
@@ -29,7 +33,8 @@ use iroh::{
     client::{BlobStatus, Iroh},
     rpc_protocol::{
         BlobDownloadRequest, BlobListCollectionsResponse, BlobListIncompleteResponse,
-        BlobListResponse, DownloadMode, ProviderService, SetTagOption, WrapOption,
+        BlobListMetaResponse, BlobListResponse, DownloadMode, ProviderService, SetTagOption,
+        WrapOption,
     },
     ticket::BlobTicket,
 };
@@ -92,6 +97,13 @@ pub enum BlobCommands {
         /// downloads running concurrently.
         #[clap(long)]
         queued: bool,
+        /// Only download a byte range, e.g. `0..4096` or `1048576..`. Can be used multiple times.
+        ///
+        /// For hash sequences, prefix the range with the offset of the blob in the sequence,
+        /// e.g. `1:0..4096`. Offset 0 is the hash sequence itself. Ranges are rounded up to
+        /// whole chunks.
+        #[clap(long = "range")]
+        ranges: Vec<ByteRangeArg>,
     },
     /// Export a blob from the internal blob store to the local filesystem.
     Export {
@@ -105,6 +117,14 @@ pub enum BlobCommands {
         /// the collection.
         #[clap(long, default_value_t = false)]
         recursive: bool,
+        /// Set to true if the hash refers to a tree and you want to export it as a directory,
+        /// restoring the metadata of the entries.
+        #[clap(long, default_value_t = false, conflicts_with = "recursive")]
+        tree: bool,
+        /// Set to true if the hash refers to a file that was added with `--chunked`, and you
+        /// want to reassemble the file from its chunks.
+        #[clap(long, default_value_t = false, conflicts_with_all = ["recursive", "tree"])]
+        chunked: bool,
         /// If set, the data will be moved to the output directory, and iroh will assume that it
         /// will not change.
         #[clap(long, default_value_t = false)]
@@ -142,6 +162,12 @@ pub enum BlobCommands {
     /// Delete content on the node.
     #[clap(subcommand)]
     Delete(DeleteCommands),
+    /// Show or edit the MIME type and annotations of blobs.
+    #[clap(subcommand)]
+    Meta(MetaCommands),
+    /// Export blobs to, or import blobs from, an offline bundle file.
+    #[clap(subcommand)]
+    Bundle(BundleCommands),
     /// Get a ticket to share this blob.
     Share {
         /// Hash of the blob to share.
@@ -158,6 +184,31 @@ pub enum BlobCommands {
         #[clap(long, hide = true)]
         debug: bool,
     },
+    /// Show the changes between two collections or two trees.
+    Diff {
+        /// Hash of the old collection or tree.
+        from: Hash,
+        /// Hash of the new collection or tree.
+        to: Hash,
+    },
+    /// Push content of this node to another node.
+    ///
+    /// The data is sent over a connection opened by this node, so the other node does not need
+    /// to be able to dial this node. It only stores the data if its push policy accepts it.
+    Push {
+        /// Ticket for the blob or collection to push.
+        ///
+        /// Only the hash and format of the ticket are used, the data is pushed from this node.
+        ticket: BlobTicket,
+        /// NodeId of the node to push to.
+        node: PublicKey,
+        /// Additional socket address to use to contact the node. Can be used multiple times.
+        #[clap(long)]
+        address: Vec<SocketAddr>,
+        /// Override the relay URL to use to contact the node.
+        #[clap(long)]
+        relay_url: Option<RelayUrl>,
+    },
 }
 
 #[derive(Debug, Clone, derive_more::Display)]
@@ -180,6 +231,71 @@ impl std::str::FromStr for TicketOrHash {
     }
 }
 
+/// A byte range of a blob, optionally at an offset in a hash sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteRangeArg {
+    /// Offset of the blob in the hash sequence.
+    offset: u64,
+    /// Start of the byte range.
+    start: u64,
+    /// End of the byte range, open if `None`.
+    end: Option<u64>,
+}
+
+impl ByteRangeArg {
+    /// Convert a list of byte ranges into the [`RangeSpecSeq`] of chunks to download.
+    fn to_range_spec_seq(ranges: &[ByteRangeArg], format: BlobFormat) -> Result<RangeSpecSeq> {
+        if ranges.is_empty() {
+            return Ok(RangeSpecSeq::all());
+        }
+        let mut by_offset = BTreeMap::<u64, ChunkRanges>::new();
+        for range in ranges {
+            ensure!(
+                range.offset == 0 || format == BlobFormat::HashSeq,
+                "Offsets are only supported for hash sequences"
+            );
+            let bytes = match range.end {
+                Some(end) => ByteRanges::from(range.start..end),
+                None => ByteRanges::from(range.start..),
+            };
+            *by_offset
+                .entry(range.offset)
+                .or_insert_with(ChunkRanges::empty) |= round_up_to_chunks(&bytes);
+        }
+        let count = by_offset
+            .keys()
+            .last()
+            .map(|offset| offset + 1)
+            .unwrap_or_default();
+        let ranges =
+            (0..count).map(|offset| by_offset.remove(&offset).unwrap_or_else(ChunkRanges::empty));
+        Ok(RangeSpecSeq::from_ranges(ranges))
+    }
+}
+
+impl std::str::FromStr for ByteRangeArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (offset, range) = match s.split_once(':') {
+            Some((offset, range)) => (offset.parse().context("invalid offset")?, range),
+            None => (0, s),
+        };
+        let (start, end) = range
+            .split_once("..")
+            .ok_or_else(|| anyhow!("expected a range like START..END"))?;
+        let start = start.parse().context("invalid range start")?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse().context("invalid range end")?),
+        };
+        if let Some(end) = end {
+            ensure!(start < end, "range must not be empty");
+        }
+        Ok(Self { offset, start, end })
+    }
+}
+
 impl BlobCommands {
     pub async fn run<C>(self, iroh: &Iroh<C>) -> Result<()>
     where
@@ -197,6 +313,7 @@ impl BlobCommands {
                 stable,
                 tag,
                 queued,
+                ranges,
             } => {
                 let (node_addr, hash, format) = match ticket {
                     TicketOrHash::Ticket(ticket) => {
@@ -250,6 +367,12 @@ impl BlobCommands {
                     return Err(anyhow::anyhow!("The input arguments refer to a collection of blobs and output is set to STDOUT. Only single blobs may be passed in this case."));
                 }
 
+                ensure!(
+                    ranges.is_empty() || out.is_none(),
+                    "Exporting is not supported when only downloading ranges"
+                );
+                let ranges = ByteRangeArg::to_range_spec_seq(&ranges, format)?;
+
                 let tag = match tag {
                     Some(tag) => SetTagOption::Named(Tag::from(tag)),
                     None => SetTagOption::Auto,
@@ -265,6 +388,7 @@ impl BlobCommands {
                     .download(BlobDownloadRequest {
                         hash,
                         format,
+                        ranges,
                         nodes: vec![node_addr],
                         tag,
                         mode,
@@ -309,29 +433,33 @@ impl BlobCommands {
                 hash,
                 out,
                 recursive,
+                tree,
+                chunked,
                 stable,
             } => {
                 match out {
                     OutputTarget::Stdout => {
                         ensure!(
-                            !recursive,
-                            "Recursive option is not supported when exporting to STDOUT"
+                            !recursive && !tree && !chunked,
+                            "Only single blobs can be exported to STDOUT"
                         );
                         let mut blob_read = iroh.blobs.read(hash).await?;
                         tokio::io::copy(&mut blob_read, &mut tokio::io::stdout()).await?;
                     }
                     OutputTarget::Path(path) => {
                         let absolute = std::env::current_dir()?.join(&path);
-                        if !recursive {
+                        if !recursive && !tree {
                             ensure!(!absolute.is_dir(), "output must not be a directory");
                         }
                         let mode = match stable {
                             true => ExportMode::TryReference,
                             false => ExportMode::Copy,
                         };
-                        let format = match recursive {
-                            true => ExportFormat::Collection,
-                            false => ExportFormat::Blob,
+                        let format = match (recursive, tree) {
+                            _ if chunked => ExportFormat::Chunked,
+                            (_, true) => ExportFormat::Tree,
+                            (true, false) => ExportFormat::Collection,
+                            (false, false) => ExportFormat::Blob,
                         };
                         tracing::info!(
                             "exporting {hash} to {} -> {}",
@@ -347,6 +475,8 @@ impl BlobCommands {
             }
             Self::List(cmd) => cmd.run(iroh).await,
             Self::Delete(cmd) => cmd.run(iroh).await,
+            Self::Meta(cmd) => cmd.run(iroh).await,
+            Self::Bundle(cmd) => cmd.run(iroh).await,
             Self::Validate { verbose, repair } => validate(iroh, verbose, repair).await,
             Self::ConsistencyCheck { verbose, repair } => {
                 consistency_check(iroh, verbose, repair).await
@@ -387,6 +517,33 @@ impl BlobCommands {
                 }
                 Ok(())
             }
+            Self::Diff { from, to } => {
+                let changes = iroh.blobs.diff(from, to).await?;
+                for Change { path, kind } in &changes {
+                    let marker = match kind {
+                        ChangeKind::Added => "+",
+                        ChangeKind::Removed => "-",
+                        ChangeKind::Modified => "~",
+                    };
+                    println!("{marker} {path}");
+                }
+                if changes.is_empty() {
+                    println!("No changes");
+                }
+                Ok(())
+            }
+            Self::Push {
+                ticket,
+                node,
+                address,
+                relay_url,
+            } => {
+                let (_, hash, format) = ticket.into_parts();
+                let node_addr = NodeAddr::from_parts(node, relay_url, address);
+                iroh.blobs.push(hash, format, node_addr).await?;
+                println!("Pushed {hash} to {}", node.fmt_short());
+                Ok(())
+            }
         }
     }
 }
@@ -428,17 +585,72 @@ pub struct BlobAddOptions {
     #[clap(long, requires = "wrap")]
     pub filename: Option<String>,
 
+    /// Add the file or directory as a tree instead of a collection.
+    ///
+    /// A tree preserves empty directories, symlinks, file modes, modification times and
+    /// extended attributes, which are restored by `blob export --tree`. Subdirectories are
+    /// trees of their own, so they can be shared on their own.
+    #[clap(long, conflicts_with = "wrap")]
+    pub tree: bool,
+
+    /// Hash of a tree from an earlier `--tree` import of the same path.
+    ///
+    /// Files whose size and modification time did not change since the earlier import are not
+    /// hashed again. Use `blob diff` to see what changed.
+    ///
+    /// Only supported with `--tree`: a collection does not record modification times, so
+    /// unchanged files can not be told apart from files that changed but kept their size.
+    #[clap(long, requires = "tree")]
+    pub previous: Option<Hash>,
+
+    /// Split the file into content-defined chunks, and add a hash sequence of the chunks.
+    ///
+    /// Chunks that are already stored are not stored again, so adding a slightly modified
+    /// version of a large file only stores the changed chunks. Use `blob export --chunked` to
+    /// reassemble the file.
+    #[clap(long, conflicts_with_all = ["wrap", "tree", "in_place"])]
+    pub chunked: bool,
+
     /// Do not print the all-in-one ticket to get the added data from this node.
     #[clap(long)]
     pub no_ticket: bool,
 }
 
+/// Describe how much of a blob of `size` bytes is covered by the root ranges of `ranges`.
+fn describe_available(ranges: &RangeSpecSeq, size: u64) -> String {
+    let blob = ChunkRanges::from(..ChunkNum::chunks(size));
+    let available = ranges
+        .iter()
+        .next()
+        .map(|spec| spec.to_chunk_ranges())
+        .unwrap_or_else(ChunkRanges::empty);
+    let available: ChunkRanges = available.intersection(&blob);
+    if available == blob {
+        return "complete".to_string();
+    }
+    let chunks: u64 = available
+        .boundaries()
+        .chunks(2)
+        .map(|range| range[1].0 - range[0].0)
+        .sum();
+    match chunks {
+        0 => "not available".to_string(),
+        chunks => format!("{} of {} chunks", chunks, ChunkNum::chunks(size).0),
+    }
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum ListCommands {
     /// List the available blobs on the running provider.
     Blobs,
-    /// List the available blobs on the running provider.
-    IncompleteBlobs,
+    /// List the incomplete blobs on the running provider.
+    IncompleteBlobs {
+        /// Ask this node which part of each blob it has, and show the answer.
+        ///
+        /// This only queries the node, it does not download any of the missing data.
+        #[clap(long)]
+        remote: Option<PublicKey>,
+    },
     /// List the available collections on the running provider.
     Collections,
 }
@@ -456,11 +668,30 @@ impl ListCommands {
                     println!("{} {} ({})", path, hash, HumanBytes(size));
                 }
             }
-            Self::IncompleteBlobs => {
+            Self::IncompleteBlobs { remote } => {
                 let mut response = iroh.blobs.list_incomplete().await?;
                 while let Some(item) = response.next().await {
-                    let BlobListIncompleteResponse { hash, size, .. } = item?;
-                    println!("{} ({})", hash, HumanBytes(size));
+                    let BlobListIncompleteResponse {
+                        hash,
+                        size,
+                        expected_size,
+                    } = item?;
+                    let Some(node) = remote else {
+                        println!("{} ({})", hash, HumanBytes(size));
+                        continue;
+                    };
+                    let ranges = iroh
+                        .blobs
+                        .available_ranges(hash, BlobFormat::Raw, node.into())
+                        .await?;
+                    let remote_status = describe_available(&ranges, expected_size);
+                    println!(
+                        "{} ({}) {}: {}",
+                        hash,
+                        HumanBytes(size),
+                        node.fmt_short(),
+                        remote_status
+                    );
                 }
             }
             Self::Collections => {
@@ -520,6 +751,149 @@ impl DeleteCommands {
     }
 }
 
+#[derive(Subcommand, Debug, Clone)]
+pub enum MetaCommands {
+    /// Show the metadata of a blob.
+    Get {
+        /// Hash of the blob.
+        hash: Hash,
+    },
+    /// Set an annotation of a blob.
+    Set {
+        /// Hash of the blob.
+        hash: Hash,
+        /// Key of the annotation.
+        key: String,
+        /// Value of the annotation.
+        value: String,
+    },
+    /// Remove an annotation of a blob.
+    Rm {
+        /// Hash of the blob.
+        hash: Hash,
+        /// Key of the annotation.
+        key: String,
+    },
+    /// Set the MIME type of a blob, or clear it if no type is given.
+    Mime {
+        /// Hash of the blob.
+        hash: Hash,
+        /// The MIME type, e.g. `text/html`.
+        mime_type: Option<String>,
+    },
+    /// List the metadata of all blobs that have any.
+    List,
+}
+
+impl MetaCommands {
+    pub async fn run<C>(self, iroh: &Iroh<C>) -> Result<()>
+    where
+        C: ServiceConnection<ProviderService>,
+    {
+        match self {
+            Self::Get { hash } => {
+                let meta = iroh.blobs.meta(hash).await?;
+                print_meta(&meta, "");
+            }
+            Self::Set { hash, key, value } => {
+                iroh.blobs.set_annotation(hash, key, Some(value)).await?;
+            }
+            Self::Rm { hash, key } => {
+                iroh.blobs.set_annotation(hash, key, None).await?;
+            }
+            Self::Mime { hash, mime_type } => {
+                iroh.blobs.set_mime_type(hash, mime_type).await?;
+            }
+            Self::List => {
+                let mut response = iroh.blobs.list_meta().await?;
+                while let Some(item) = response.next().await {
+                    let BlobListMetaResponse { hash, meta } = item?;
+                    println!("{hash}");
+                    print_meta(&meta, "  ");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum BundleCommands {
+    /// Write blobs, their outboards and the tags pointing to them to a bundle file.
+    Export {
+        /// Hashes of raw blobs to include.
+        hashes: Vec<Hash>,
+        /// Hashes of hash sequences to include together with all their children.
+        #[clap(long)]
+        hash_seq: Vec<Hash>,
+        /// Path of the bundle file to write.
+        #[clap(long, short)]
+        out: PathBuf,
+    },
+    /// Verify a bundle file and import its blobs and tags.
+    Import {
+        /// Path of the bundle file to read.
+        path: PathBuf,
+    },
+}
+
+impl BundleCommands {
+    pub async fn run<C>(self, iroh: &Iroh<C>) -> Result<()>
+    where
+        C: ServiceConnection<ProviderService>,
+    {
+        match self {
+            Self::Export {
+                hashes,
+                hash_seq,
+                out,
+            } => {
+                let roots: Vec<_> = hashes
+                    .into_iter()
+                    .map(HashAndFormat::raw)
+                    .chain(hash_seq.into_iter().map(HashAndFormat::hash_seq))
+                    .map(|root| (root, RangeSpecSeq::all()))
+                    .collect();
+                ensure!(!roots.is_empty(), "no blobs to export");
+                let out = std::env::current_dir()?.join(out);
+                let header = iroh.blobs.export_bundle(roots, out.clone()).await?;
+                println!(
+                    "Exported {} root(s) and {} tag(s) to {}",
+                    header.roots.len(),
+                    header.tags.len(),
+                    out.display()
+                );
+            }
+            Self::Import { path } => {
+                let path = path.canonicalize()?;
+                let res = iroh.blobs.import_bundle(path).await?;
+                for (root, _) in &res.header.roots {
+                    println!("{root}");
+                }
+                for (tag, root) in &res.header.tags {
+                    println!("{tag}: {root}");
+                }
+                for tag in &res.created_tags {
+                    println!("{}: {}", style("created").bold(), tag);
+                }
+                for tag in &res.conflicting_tags {
+                    println!("{}: {}", style("skipped existing tag").bold(), tag);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_meta(meta: &BlobMeta, indent: &str) {
+    if let Some(mime_type) = &meta.mime_type {
+        println!("{indent}{}: {mime_type}", style("mime").bold());
+    }
+    for (key, value) in &meta.annotations {
+        println!("{indent}{key}: {value}");
+    }
+}
+
 fn get_report_level(verbose: u8) -> ReportLevel {
     match verbose {
         0 => ReportLevel::Warn,
@@ -825,6 +1199,16 @@ pub async fn add_with_opts<C: ServiceConnection<ProviderService>>(
         },
     };
     let wrap = match (opts.wrap, opts.filename) {
+        _ if opts.chunked => WrapOption::Chunked,
+        _ if opts.tree => {
+            ensure!(
+                !matches!(source, BlobSourceIroh::Stdin),
+                "`--tree` may not be used when adding from STDIN"
+            );
+            WrapOption::Tree {
+                previous: opts.previous,
+            }
+        }
         (true, None) => WrapOption::Wrap { name: None },
         (true, Some(filename)) => WrapOption::Wrap {
             name: Some(filename),
@@ -897,6 +1281,7 @@ pub async fn aggregate_add_response(
 ) -> Result<(Hash, BlobFormat, Vec<ProvideResponseEntry>)> {
     let mut hash_and_format = None;
     let mut collections = BTreeMap::<u64, (String, u64, Option<Hash>)>::new();
+    let mut chunking = None;
     let mut mp = Some(ProvideProgressState::new());
     while let Some(item) = stream.next().await {
         match item? {
@@ -927,6 +1312,15 @@ pub async fn aggregate_add_response(
                     }
                 }
             }
+            AddProgress::ChunkingDone {
+                id,
+                chunks,
+                size,
+                stored,
+            } => {
+                tracing::trace!("ChunkingDone({id},{chunks},{size},{stored})");
+                chunking = Some((chunks, size, stored));
+            }
             AddProgress::AllDone { hash, format, .. } => {
                 tracing::trace!("AllDone({hash:?})");
                 if let Some(mp) = mp.take() {
@@ -945,6 +1339,13 @@ pub async fn aggregate_add_response(
     }
     let HashAndFormat { hash, format } =
         hash_and_format.context("Missing hash for collection or blob")?;
+    if let Some((chunks, size, stored)) = chunking {
+        println!(
+            "Split into {chunks} chunks, stored {} of {}",
+            HumanBytes(stored),
+            HumanBytes(size)
+        );
+    }
     let entries = collections
         .into_iter()
         .map(|(_, (name, size, hash))| {
@@ -1179,6 +1580,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_byte_range_arg() {
+        let range: ByteRangeArg = "1:0..2048".parse().unwrap();
+        assert_eq!(
+            range,
+            ByteRangeArg {
+                offset: 1,
+                start: 0,
+                end: Some(2048)
+            }
+        );
+        let range: ByteRangeArg = "4096..".parse().unwrap();
+        assert_eq!(
+            range,
+            ByteRangeArg {
+                offset: 0,
+                start: 4096,
+                end: None
+            }
+        );
+        assert!("10..10".parse::<ByteRangeArg>().is_err());
+        assert!("10".parse::<ByteRangeArg>().is_err());
+
+        let spec =
+            ByteRangeArg::to_range_spec_seq(std::slice::from_ref(&range), BlobFormat::Raw).unwrap();
+        assert_eq!(
+            spec,
+            RangeSpecSeq::from_ranges([ChunkRanges::from(bao_tree::ChunkNum(4)..)])
+        );
+        assert!(
+            ByteRangeArg::to_range_spec_seq(&["1:0..1".parse().unwrap()], BlobFormat::Raw).is_err()
+        );
+        assert_eq!(
+            ByteRangeArg::to_range_spec_seq(&[], BlobFormat::HashSeq).unwrap(),
+            RangeSpecSeq::all()
+        );
+    }
+
     #[test]
     fn test_output_target() {
         assert_eq!(
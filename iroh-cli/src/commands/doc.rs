@@ -3,10 +3,10 @@ use std::{
     collections::BTreeMap,
     path::{Path, PathBuf},
     rc::Rc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::Parser;
 use colored::Colorize;
 use dialoguer::Confirm;
@@ -21,11 +21,11 @@ use tokio::io::AsyncReadExt;
 use iroh::bytes::{provider::AddProgress, Hash, Tag};
 use iroh::sync::{
     store::{DownloadPolicy, FilterKind, Query, SortDirection},
-    AuthorId, NamespaceId,
+    AuthorId, NamespaceId, WriteRestrictions,
 };
 use iroh::{
     client::{Doc, Entry, Iroh, LiveEvent},
-    rpc_protocol::{DocTicket, ProviderService, SetTagOption, WrapOption},
+    rpc_protocol::{DocImportProgress, DocTicket, ProviderService, SetTagOption, WrapOption},
     sync_engine::Origin,
     util::fs::{path_content_info, path_to_key, PathContent},
 };
@@ -94,6 +94,11 @@ pub enum DocCommands {
         /// Switch to the created document (only in the Iroh console).
         #[clap(long)]
         switch: bool,
+        /// Encrypt the keys and content of the document.
+        ///
+        /// Peers joining with a `relay` ticket can sync the document, but cannot read it.
+        #[clap(long)]
+        encrypted: bool,
     },
     /// Join a document from a ticket.
     Join {
@@ -118,6 +123,77 @@ pub enum DocCommands {
         /// Use `relay-and-addresses` in networks with no internet connectivity.
         #[clap(long, default_value_t = AddrInfoOptions::Id)]
         addr_options: AddrInfoOptions,
+        /// Only allow writes to keys with this prefix (delegate mode only).
+        #[clap(long)]
+        prefix: Option<String>,
+        /// Only allow writes by these authors (delegate mode only).
+        #[clap(long = "author")]
+        authors: Vec<AuthorId>,
+        /// Only allow writes for this many seconds from now (delegate mode only).
+        #[clap(long)]
+        expires_in: Option<u64>,
+    },
+    /// Rotate a document to a new keypair.
+    ///
+    /// Entries by authors on this node are carried forward into a new document, and the old
+    /// document gets a signed pointer to the new one. The old document becomes read-only on this
+    /// node. Tickets for the old document do not grant access to the new one.
+    Rotate {
+        /// Document to operate on.
+        ///
+        /// Required unless the document is set through the IROH_DOC environment variable.
+        /// Within the Iroh console, the active document can also set with `doc switch`.
+        #[clap(short, long)]
+        doc: Option<NamespaceId>,
+        /// Author of the rotation pointer.
+        ///
+        /// Required unless the author is set through the IROH_AUTHOR environment variable.
+        /// Within the Iroh console, the active author can also set with `author switch`.
+        #[clap(long)]
+        author: Option<AuthorId>,
+        /// Switch to the new document (only in the Iroh console).
+        #[clap(long)]
+        switch: bool,
+    },
+    /// Show whether a document was rotated to a new keypair.
+    Rotation {
+        /// Document to operate on.
+        ///
+        /// Required unless the document is set through the IROH_DOC environment variable.
+        /// Within the Iroh console, the active document can also set with `doc switch`.
+        #[clap(short, long)]
+        doc: Option<NamespaceId>,
+        /// Check whether this document is the one the rotation points to.
+        #[clap(long)]
+        successor: Option<NamespaceId>,
+    },
+    /// Store a snapshot of a document as a blob.
+    ///
+    /// Prints the hash of the snapshot, which can be shared with `blob share` and imported with
+    /// `doc restore`.
+    Snapshot {
+        /// Document to operate on.
+        ///
+        /// Required unless the document is set through the IROH_DOC environment variable.
+        /// Within the Iroh console, the active document can also set with `doc switch`.
+        #[clap(short, long)]
+        doc: Option<NamespaceId>,
+        /// Take the snapshot as of this time, in microseconds since the unix epoch.
+        ///
+        /// Only works as far as the history of the document reaches.
+        #[clap(long)]
+        as_of: Option<u64>,
+        /// Tag to tag the snapshot with.
+        #[clap(long)]
+        tag: Option<String>,
+    },
+    /// Import a document from a snapshot blob.
+    Restore {
+        /// Hash of the snapshot blob.
+        hash: Hash,
+        /// Switch to the restored document (only in the Iroh console).
+        #[clap(long)]
+        switch: bool,
     },
     /// Set an entry in a document.
     Set {
@@ -289,15 +365,10 @@ pub enum ShareMode {
     Read,
     /// Write access
     Write,
-}
-
-impl From<ShareMode> for iroh::rpc_protocol::ShareMode {
-    fn from(value: ShareMode) -> Self {
-        match value {
-            ShareMode::Read => iroh::rpc_protocol::ShareMode::Read,
-            ShareMode::Write => iroh::rpc_protocol::ShareMode::Write,
-        }
-    }
+    /// Read-only access without the encryption key, to store and sync an encrypted document
+    Relay,
+    /// Write access restricted by `--prefix`, `--author` and `--expires-in`
+    Delegate,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug, Default, strum::Display)]
@@ -328,12 +399,15 @@ impl DocCommands {
                 env.set_doc(doc)?;
                 println!("Active doc is now {}", fmt_short(doc.as_bytes()));
             }
-            Self::New { switch } => {
+            Self::New { switch, encrypted } => {
                 if switch && !env.is_console() {
                     bail!("The --switch flag is only supported within the Iroh console.");
                 }
 
-                let doc = iroh.docs.create().await?;
+                let doc = match encrypted {
+                    true => iroh.docs.create_encrypted().await?,
+                    false => iroh.docs.create().await?,
+                };
                 println!("{}", doc.id());
 
                 if switch {
@@ -364,11 +438,114 @@ impl DocCommands {
                 doc,
                 mode,
                 addr_options,
+                prefix,
+                authors,
+                expires_in,
             } => {
                 let doc = get_doc(iroh, env, doc).await?;
-                let ticket = doc.share(mode.into(), addr_options).await?;
+                let restricted = prefix.is_some() || !authors.is_empty() || expires_in.is_some();
+                if restricted && !matches!(mode, ShareMode::Delegate) {
+                    bail!("--prefix, --author and --expires-in require the delegate mode");
+                }
+                let mode = match mode {
+                    ShareMode::Read => iroh::rpc_protocol::ShareMode::Read,
+                    ShareMode::Write => iroh::rpc_protocol::ShareMode::Write,
+                    ShareMode::Relay => iroh::rpc_protocol::ShareMode::Relay,
+                    ShareMode::Delegate => {
+                        let mut restrictions = WriteRestrictions::default();
+                        if let Some(prefix) = prefix {
+                            restrictions = restrictions.with_prefix(prefix.into_bytes());
+                        }
+                        if !authors.is_empty() {
+                            restrictions = restrictions.with_authors(authors);
+                        }
+                        if let Some(expires_in) = expires_in {
+                            let expires_at = SystemTime::now() + Duration::from_secs(expires_in);
+                            let expires_at = expires_at
+                                .duration_since(SystemTime::UNIX_EPOCH)?
+                                .as_micros();
+                            restrictions = restrictions.with_expiry(expires_at as u64);
+                        }
+                        iroh::rpc_protocol::ShareMode::Delegate(restrictions)
+                    }
+                };
+                let ticket = doc.share(mode, addr_options).await?;
                 println!("{}", ticket);
             }
+            Self::Rotate {
+                doc,
+                author,
+                switch,
+            } => {
+                if switch && !env.is_console() {
+                    bail!("The --switch flag is only supported within the Iroh console.");
+                }
+
+                let doc = get_doc(iroh, env, doc).await?;
+                let author = env.author(author)?;
+                let res = doc.rotate(author).await?;
+                println!("{}", res.doc_id);
+                println!("Carried {} entries forward.", res.carried);
+                if res.skipped > 0 {
+                    println!(
+                        "Skipped {} entries by authors not on this node.",
+                        res.skipped
+                    );
+                }
+
+                if switch {
+                    env.set_doc(res.doc_id)?;
+                    println!("Active doc is now {}", fmt_short(res.doc_id.as_bytes()));
+                }
+            }
+            Self::Rotation { doc, successor } => {
+                let doc = get_doc(iroh, env, doc).await?;
+                match doc.rotation().await? {
+                    None => println!("Doc {} has not been rotated.", fmt_short(doc.id())),
+                    Some(rotation) => {
+                        let rotated_at =
+                            SystemTime::UNIX_EPOCH + Duration::from_micros(rotation.timestamp());
+                        let ago = SystemTime::now()
+                            .duration_since(rotated_at)
+                            .unwrap_or_default();
+                        println!(
+                            "Doc {} was rotated {} ago.",
+                            fmt_short(doc.id()),
+                            HumanDuration(ago)
+                        );
+                        if let Some(successor) = successor {
+                            if rotation.is_successor(&successor) {
+                                println!("{} is its successor.", fmt_short(successor));
+                            } else {
+                                println!("{} is not its successor.", fmt_short(successor));
+                            }
+                        }
+                    }
+                }
+            }
+            Self::Snapshot { doc, as_of, tag } => {
+                let doc = get_doc(iroh, env, doc).await?;
+                let tag = match tag {
+                    Some(tag) => SetTagOption::Named(Tag::from(tag)),
+                    None => SetTagOption::Auto,
+                };
+                let res = doc.snapshot(as_of, tag).await?;
+                println!("{}", res.hash);
+                println!("Stored {} entries with tag {}.", res.entries, res.tag);
+            }
+            Self::Restore { hash, switch } => {
+                if switch && !env.is_console() {
+                    bail!("The --switch flag is only supported within the Iroh console.");
+                }
+
+                let doc = iroh.docs.import_snapshot(hash).await?;
+                println!("{}", doc.id());
+
+                if switch {
+                    env.set_doc(doc.id())?;
+                    println!("Active doc is now {}", fmt_short(doc.id().as_bytes()));
+                }
+            }
             Self::Set {
                 doc,
                 author,
@@ -498,21 +675,32 @@ impl DocCommands {
                     }
                 }
 
-                let stream = iroh
-                    .blobs
-                    .add_from_path(
-                        root.clone(),
-                        in_place,
-                        SetTagOption::Named(tag.clone()),
-                        WrapOption::NoWrap,
-                    )
-                    .await?;
                 let root_prefix = match root.parent() {
                     Some(p) => p.to_path_buf(),
                     None => PathBuf::new(),
                 };
                 let start = Instant::now();
-                import_coordinator(doc, author, root_prefix, prefix, stream, size, files).await?;
+                if doc.encryption_key().await?.is_some() {
+                    // the content of encrypted docs has to be encrypted by the node while
+                    // importing, so the files cannot go through the blob store first
+                    ensure!(
+                        !in_place,
+                        "encrypted documents cannot import files in place"
+                    );
+                    import_encrypted(doc, author, root, root_prefix, prefix, size, files).await?;
+                } else {
+                    let stream = iroh
+                        .blobs
+                        .add_from_path(
+                            root.clone(),
+                            in_place,
+                            SetTagOption::Named(tag.clone()),
+                            WrapOption::NoWrap,
+                        )
+                        .await?;
+                    import_coordinator(doc, author, root_prefix, prefix, stream, size, files)
+                        .await?;
+                }
                 println!("Success! ({})", HumanDuration(start.elapsed()));
             }
             Self::Export { doc, key, out } => {
@@ -873,6 +1061,8 @@ where
                         }
                     }
                 }
+                // files are never imported in chunks into a document
+                AddProgress::ChunkingDone { .. } => None,
                 AddProgress::AllDone { hash, .. } => {
                     imp.add_done();
                     tracing::info!("AddProgress::AllDone({hash:?})");
@@ -907,6 +1097,61 @@ where
     Ok(())
 }
 
+/// Import the files under `root` into an encrypted document, one file at a time.
+///
+/// Every file goes through [`Doc::import_file`], which encrypts the content on the node.
+async fn import_encrypted<C>(
+    doc: Doc<C>,
+    author_id: AuthorId,
+    root: PathBuf,
+    root_prefix: PathBuf,
+    prefix: String,
+    expected_size: u64,
+    expected_entries: u64,
+) -> Result<()>
+where
+    C: ServiceConnection<ProviderService>,
+{
+    let imp = ImportProgressBar::new(
+        &root.display().to_string(),
+        doc.id(),
+        expected_size,
+        expected_entries,
+    );
+    for entry in walkdir::WalkDir::new(&root) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.into_path();
+        let key = path_to_key(&path, Some(prefix.clone()), Some(root_prefix.clone()))?;
+        let mut progress = doc.import_file(author_id, key, &path, false).await?;
+        let mut last_offset = 0;
+        while let Some(msg) = progress.try_next().await? {
+            match msg {
+                DocImportProgress::Found { name, size, .. } => imp.add_found(name, size),
+                DocImportProgress::Progress { offset, .. } => {
+                    imp.add_progress(offset.saturating_sub(last_offset));
+                    last_offset = offset;
+                }
+                DocImportProgress::IngestDone { .. } => {
+                    imp.import_found(path.display().to_string())
+                }
+                DocImportProgress::AllDone { .. } => {
+                    imp.import_progress();
+                    break;
+                }
+                DocImportProgress::Abort(err) => {
+                    bail!("failed to import {}: {err}", path.display())
+                }
+            }
+        }
+    }
+    imp.add_done();
+    imp.all_done();
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 struct ImportProgressBar {
     mp: MultiProgress,
@@ -1014,4 +1259,48 @@ mod tests {
         iroh.node.shutdown(false).await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_doc_import_encrypted() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let foo = temp_dir.path().join("foo");
+        tokio::fs::write(foo, "foo").await.context("write foo")?;
+
+        let data_dir = tempfile::tempdir()?;
+
+        let node = crate::commands::start::start_node(data_dir.path(), None).await?;
+        let client = node.client();
+        let doc = client.docs.create_encrypted().await.context("doc create")?;
+        let author = client.authors.create().await.context("author create")?;
+
+        let cli = ConsoleEnv::for_console(data_dir.path()).context("ConsoleEnv")?;
+        let iroh = iroh::client::quic::Iroh::connect(data_dir.path())
+            .await
+            .context("rpc connect")?;
+
+        let command = DocCommands::Import {
+            doc: Some(doc.id()),
+            author: Some(author),
+            prefix: None,
+            path: temp_dir.path().to_string_lossy().into(),
+            in_place: false,
+            no_prompt: true,
+        };
+
+        command.run(&iroh, &cli).await.context("DocCommands run")?;
+
+        let entries: Vec<_> = doc
+            .get_many(Query::all())
+            .await
+            .context("doc get many")?
+            .try_collect()
+            .await?;
+        assert_eq!(1, entries.len());
+        // the stored content is encrypted, reading it through the doc decrypts it
+        assert_ne!(3, entries[0].content_len());
+        assert_eq!(&entries[0].content_bytes(&doc).await?[..], b"foo");
+
+        iroh.node.shutdown(false).await?;
+        Ok(())
+    }
 }
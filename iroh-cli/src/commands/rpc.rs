@@ -1,5 +1,12 @@
+#[cfg(feature = "gateway")]
+use std::net::SocketAddr;
+#[cfg(feature = "fuse")]
+use std::path::PathBuf;
+
 use anyhow::Result;
 use clap::Subcommand;
+#[cfg(feature = "fuse")]
+use iroh::net::{key::PublicKey, NodeAddr};
 use iroh::{client::Iroh, rpc_protocol::ProviderService};
 use quic_rpc::ServiceConnection;
 
@@ -55,6 +62,45 @@ pub enum RpcCommands {
         #[clap(subcommand)]
         command: TagCommands,
     },
+    /// Mount tagged collections and documents as a read-only file system
+    ///
+    /// The mount point contains a `tags` directory with an entry per tag, and a `docs`
+    /// directory with a directory per document. File contents are read on demand, and
+    /// missing data is downloaded when it is read.
+    ///
+    /// The file system is unmounted when the command is stopped with Ctrl-C.
+    #[cfg(feature = "fuse")]
+    Mount {
+        /// Directory to mount the file system at.
+        mountpoint: PathBuf,
+        /// Node to download missing data from. Can be used multiple times.
+        ///
+        /// Nodes are given by their id and dialed through node discovery. Document content is
+        /// also downloaded from the sync peers of the document. Without any node to download
+        /// from, only data that is already stored can be read.
+        #[clap(long)]
+        node: Vec<PublicKey>,
+    },
+    /// Serve blobs, collections and documents over HTTP
+    ///
+    /// Content is served at `/blob/<hash>`, `/collection/<hash>/<name>`,
+    /// `/doc/<namespace>/<key>` and, with `--remote-fetch`, `/ticket/<ticket>`, with support
+    /// for range requests.
+    ///
+    /// The gateway runs until the command is stopped with Ctrl-C.
+    #[cfg(feature = "gateway")]
+    Gateway {
+        /// Address to listen on.
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Download content that is not on this node from other nodes.
+        ///
+        /// This enables `/ticket/<ticket>`, and downloads missing document content from the
+        /// peers of the document. Anyone who can reach the gateway can then make this node
+        /// connect to other nodes.
+        #[clap(long)]
+        remote_fetch: bool,
+    },
 }
 
 impl RpcCommands {
@@ -68,6 +114,29 @@ impl RpcCommands {
             Self::Doc { command } => command.run(iroh, env).await,
             Self::Author { command } => command.run(iroh, env).await,
             Self::Tag { command } => command.run(iroh).await,
+            #[cfg(feature = "fuse")]
+            Self::Mount { mountpoint, node } => {
+                let nodes = node.into_iter().map(NodeAddr::new).collect();
+                let mount = iroh::mount::mount(iroh, &mountpoint, nodes).await?;
+                println!(
+                    "Mounted at {}, press Ctrl-C to unmount",
+                    mountpoint.display()
+                );
+                tokio::signal::ctrl_c().await?;
+                tokio::task::spawn_blocking(move || mount.unmount()).await?;
+                Ok(())
+            }
+            #[cfg(feature = "gateway")]
+            Self::Gateway { addr, remote_fetch } => {
+                let options = iroh::gateway::Options::default().with_remote_fetch(remote_fetch);
+                let gateway = iroh::gateway::Gateway::spawn(iroh.clone(), addr, options).await?;
+                println!(
+                    "Serving on http://{}, press Ctrl-C to stop",
+                    gateway.local_addr()
+                );
+                tokio::signal::ctrl_c().await?;
+                Ok(())
+            }
         }
     }
 }
@@ -1,8 +1,8 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
 use clap::Subcommand;
 use futures_lite::StreamExt;
-use iroh::bytes::Tag;
+use iroh::bytes::{BlobFormat, Hash, HashAndFormat, Tag};
 use iroh::{client::Iroh, rpc_protocol::ProviderService};
 use quic_rpc::ServiceConnection;
 
@@ -10,7 +10,36 @@ use quic_rpc::ServiceConnection;
 #[allow(clippy::large_enum_variant)]
 pub enum TagCommands {
     /// List all tags
-    List,
+    List {
+        /// Only list tags starting with this prefix, e.g. `builds/`
+        prefix: Option<String>,
+        #[clap(long, default_value_t = false)]
+        hex: bool,
+    },
+    /// Set a tag, creating it or replacing its value
+    Set {
+        tag: String,
+        /// Hash of the content to tag
+        hash: Hash,
+        /// Whether the content is a hash sequence, such as a collection
+        #[clap(long, default_value_t = false)]
+        hash_seq: bool,
+        /// Only update the tag if it currently points to this hash
+        #[clap(long, conflicts_with = "if_absent")]
+        expected: Option<Hash>,
+        /// Only create the tag if it does not exist yet
+        #[clap(long, default_value_t = false)]
+        if_absent: bool,
+        #[clap(long, default_value_t = false)]
+        hex: bool,
+    },
+    /// Rename a tag
+    Rename {
+        from: String,
+        to: String,
+        #[clap(long, default_value_t = false)]
+        hex: bool,
+    },
     /// Delete a tag
     Delete {
         tag: String,
@@ -25,22 +54,90 @@ impl TagCommands {
         C: ServiceConnection<ProviderService>,
     {
         match self {
-            Self::List => {
-                let mut response = iroh.tags.list().await?;
+            Self::List { prefix, hex } => {
+                let mut response = match prefix {
+                    Some(prefix) => iroh
+                        .tags
+                        .list_prefix(parse_tag(prefix, hex)?)
+                        .await?
+                        .boxed(),
+                    None => iroh.tags.list().await?.boxed(),
+                };
                 while let Some(res) = response.next().await {
                     let res = res?;
                     println!("{}: {} ({:?})", res.name, res.hash, res.format,);
                 }
             }
-            Self::Delete { tag, hex } => {
-                let tag = if hex {
-                    Tag::from(Bytes::from(hex::decode(tag)?))
-                } else {
-                    Tag::from(tag)
+            Self::Set {
+                tag,
+                hash,
+                hash_seq,
+                expected,
+                if_absent,
+                hex,
+            } => {
+                let tag = parse_tag(tag, hex)?;
+                let format = match hash_seq {
+                    true => BlobFormat::HashSeq,
+                    false => BlobFormat::Raw,
                 };
+                let value = HashAndFormat { hash, format };
+                if expected.is_none() && !if_absent {
+                    iroh.tags.set(tag, value).await?;
+                    return Ok(());
+                }
+                // the format of the expected value is not known, so compare with the current
+                // value and only use the swap to detect concurrent changes
+                let current = match expected {
+                    Some(expected) => {
+                        let current = iroh
+                            .tags
+                            .list_prefix(tag.clone())
+                            .await?
+                            .find(|res| match res {
+                                Ok(res) => res.name == tag,
+                                Err(_) => true,
+                            })
+                            .await
+                            .transpose()?
+                            .map(|res| HashAndFormat::new(res.hash, res.format));
+                        match current {
+                            Some(current) if current.hash == expected => Some(current),
+                            Some(current) => bail!("tag {tag} points to {}", current.hash),
+                            None => bail!("tag {tag} does not exist"),
+                        }
+                    }
+                    None => None,
+                };
+                if let Err(current) = iroh
+                    .tags
+                    .compare_and_swap(tag.clone(), current, Some(value))
+                    .await?
+                {
+                    match current {
+                        Some(current) => bail!("tag {tag} points to {}", current.hash),
+                        None => bail!("tag {tag} does not exist"),
+                    }
+                }
+            }
+            Self::Rename { from, to, hex } => {
+                let from = parse_tag(from, hex)?;
+                let to = parse_tag(to, hex)?;
+                iroh.tags.rename(from, to).await?;
+            }
+            Self::Delete { tag, hex } => {
+                let tag = parse_tag(tag, hex)?;
                 iroh.tags.delete(tag).await?;
             }
         }
         Ok(())
     }
 }
+
+fn parse_tag(tag: String, hex: bool) -> Result<Tag> {
+    Ok(if hex {
+        Tag::from(Bytes::from(hex::decode(tag)?))
+    } else {
+        Tag::from(tag)
+    })
+}
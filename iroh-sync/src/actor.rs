@@ -19,11 +19,11 @@ use crate::{
     ranger::Message,
     store::{
         fs::{ContentHashesIterator, StoreInstance},
-        DownloadPolicy, ImportNamespaceOutcome, Query, Store,
+        DownloadPolicy, HistoryPolicy, ImportNamespaceOutcome, Query, RotateOutcome, Store,
     },
     Author, AuthorHeads, AuthorId, Capability, CapabilityKind, ContentStatus,
-    ContentStatusCallback, Event, NamespaceId, NamespaceSecret, PeerIdBytes, Replica, ReplicaInfo,
-    SignedEntry, SyncOutcome,
+    ContentStatusCallback, Delegate, EncryptionKey, Event, NamespaceId, NamespaceSecret,
+    PeerIdBytes, Replica, ReplicaInfo, SignedEntry, Snapshot, SyncOutcome, WriteRestrictions,
 };
 
 const ACTION_CAP: usize = 1024;
@@ -55,6 +55,12 @@ enum Action {
         #[debug("reply")]
         reply: oneshot::Sender<Result<NamespaceId>>,
     },
+    #[display("ImportSnapshot")]
+    ImportSnapshot {
+        snapshot: Snapshot,
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<usize>>,
+    },
     #[display("ListAuthors")]
     ListAuthors {
         #[debug("reply")]
@@ -166,6 +172,21 @@ enum ReplicaAction {
     ExportSecretKey {
         reply: oneshot::Sender<Result<NamespaceSecret>>,
     },
+    Delegate {
+        restrictions: WriteRestrictions,
+        reply: oneshot::Sender<Result<Delegate>>,
+    },
+    Rotate {
+        reply: oneshot::Sender<Result<RotateOutcome>>,
+    },
+    MakeReadOnly {
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Snapshot {
+        as_of: Option<u64>,
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<Snapshot>>,
+    },
     HasNewsForUs {
         heads: AuthorHeads,
         #[debug("reply")]
@@ -180,6 +201,24 @@ enum ReplicaAction {
         #[debug("reply")]
         reply: oneshot::Sender<Result<DownloadPolicy>>,
     },
+    SetEncryptionKey {
+        key: EncryptionKey,
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetEncryptionKey {
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<Option<EncryptionKey>>>,
+    },
+    SetHistoryPolicy {
+        policy: Option<HistoryPolicy>,
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetHistoryPolicy {
+        #[debug("reply")]
+        reply: oneshot::Sender<Result<Option<HistoryPolicy>>>,
+    },
 }
 
 /// The state for an open replica.
@@ -465,6 +504,45 @@ impl SyncHandle {
         rx.await?
     }
 
+    pub async fn delegate(
+        &self,
+        namespace: NamespaceId,
+        restrictions: WriteRestrictions,
+    ) -> Result<Delegate> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::Delegate {
+            restrictions,
+            reply,
+        };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn rotate(&self, namespace: NamespaceId) -> Result<RotateOutcome> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::Rotate { reply };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn make_read_only(&self, namespace: NamespaceId) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::MakeReadOnly { reply };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn export_snapshot(
+        &self,
+        namespace: NamespaceId,
+        as_of: Option<u64>,
+    ) -> Result<Snapshot> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::Snapshot { as_of, reply };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
     pub async fn get_state(&self, namespace: NamespaceId) -> Result<OpenState> {
         let (reply, rx) = oneshot::channel();
         let action = ReplicaAction::GetState { reply };
@@ -515,6 +593,13 @@ impl SyncHandle {
         rx.await?
     }
 
+    pub async fn import_snapshot(&self, snapshot: Snapshot) -> Result<usize> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Action::ImportSnapshot { snapshot, reply })
+            .await?;
+        rx.await?
+    }
+
     pub async fn get_download_policy(&self, namespace: NamespaceId) -> Result<DownloadPolicy> {
         let (reply, rx) = oneshot::channel();
         let action = ReplicaAction::GetDownloadPolicy { reply };
@@ -533,6 +618,48 @@ impl SyncHandle {
         rx.await?
     }
 
+    pub async fn get_encryption_key(
+        &self,
+        namespace: NamespaceId,
+    ) -> Result<Option<EncryptionKey>> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::GetEncryptionKey { reply };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn set_encryption_key(
+        &self,
+        namespace: NamespaceId,
+        key: EncryptionKey,
+    ) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::SetEncryptionKey { reply, key };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn get_history_policy(
+        &self,
+        namespace: NamespaceId,
+    ) -> Result<Option<HistoryPolicy>> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::GetHistoryPolicy { reply };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
+    pub async fn set_history_policy(
+        &self,
+        namespace: NamespaceId,
+        policy: Option<HistoryPolicy>,
+    ) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        let action = ReplicaAction::SetHistoryPolicy { reply, policy };
+        self.send_replica(namespace, action).await?;
+        rx.await?
+    }
+
     pub async fn content_hashes(&self) -> Result<ContentHashesIterator> {
         let (reply, rx) = oneshot::channel();
         self.send(Action::ContentHashes { reply }).await?;
@@ -636,6 +763,9 @@ impl Actor {
                 }
                 Ok(id)
             }),
+            Action::ImportSnapshot { snapshot, reply } => {
+                send_reply(reply, self.store.import_snapshot(&snapshot))
+            }
             Action::ListAuthors { reply } => iter_to_channel(
                 reply,
                 self.store
@@ -772,6 +902,34 @@ impl Actor {
                     .and_then(|state| Ok(state.info.capability.secret_key()?.clone()));
                 send_reply(reply, res)
             }
+            ReplicaAction::Delegate {
+                restrictions,
+                reply,
+            } => {
+                let res = self.states.get_mut(&namespace).and_then(|state| {
+                    let delegate = state
+                        .info
+                        .capability
+                        .delegate(restrictions, &mut rand::rngs::OsRng {})?;
+                    Ok(delegate)
+                });
+                send_reply(reply, res)
+            }
+            ReplicaAction::Rotate { reply } => send_reply_with(reply, self, |this| {
+                let state = this.states.get_mut(&namespace)?;
+                let secret = state.info.capability.secret_key()?.clone();
+                this.store
+                    .rotate_namespace(&secret, &mut rand::rngs::OsRng {})
+            }),
+            ReplicaAction::MakeReadOnly { reply } => send_reply_with(reply, self, |this| {
+                let state = this.states.get_mut(&namespace)?;
+                this.store.make_read_only(&namespace)?;
+                state.info.capability = Capability::Read(namespace);
+                Ok(())
+            }),
+            ReplicaAction::Snapshot { as_of, reply } => {
+                send_reply(reply, self.store.export_snapshot(&namespace, as_of))
+            }
             ReplicaAction::GetState { reply } => send_reply_with(reply, self, move |this| {
                 let state = this.states.get_mut(&namespace)?;
                 let handles = state.handles;
@@ -793,6 +951,18 @@ impl Actor {
             ReplicaAction::GetDownloadPolicy { reply } => {
                 send_reply(reply, self.store.get_download_policy(&namespace))
             }
+            ReplicaAction::SetEncryptionKey { key, reply } => {
+                send_reply(reply, self.store.set_encryption_key(&namespace, &key))
+            }
+            ReplicaAction::GetEncryptionKey { reply } => {
+                send_reply(reply, self.store.get_encryption_key(&namespace))
+            }
+            ReplicaAction::SetHistoryPolicy { policy, reply } => {
+                send_reply(reply, self.store.set_history_policy(&namespace, policy))
+            }
+            ReplicaAction::GetHistoryPolicy { reply } => {
+                send_reply(reply, self.store.get_history_policy(&namespace))
+            }
         }
     }
 
@@ -0,0 +1,466 @@
+//! Delegated write capabilities for documents.
+//!
+//! The holder of a [`NamespaceSecret`] can issue a [`WriteCapability`] to a [`DelegatePublicKey`].
+//! The capability restricts which keys may be written, which authors may sign entries and until
+//! when entries may be created. A delegate can pass its capability on to further delegates, but
+//! each step in the chain may only narrow the [`WriteRestrictions`] of its issuer.
+//!
+//! Entries created by a delegate are signed with the [`DelegateSecret`] in place of the namespace
+//! key, and carry the full [`WriteCapability`], so that every peer can verify them without any
+//! further information.
+
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use ed25519_dalek::Signature;
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    store::PublicKeyStore, AuthorId, DelegatePublicKey, DelegateSecret, Entry, NamespaceId,
+    NamespaceSecret,
+};
+
+/// Domain separator for the signatures over a [`Delegation`].
+const DELEGATION_DOMAIN: &[u8] = b"iroh-sync:delegation:v1";
+
+/// Maximum number of delegations after the root of a [`WriteCapability`].
+pub const MAX_DELEGATION_DEPTH: usize = 16;
+
+/// Restrictions on the entries a delegate may write.
+///
+/// A field that is `None` does not restrict writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteRestrictions {
+    /// Only keys that start with this prefix may be written.
+    pub prefix: Option<Bytes>,
+    /// Only entries signed by one of these authors may be written.
+    pub authors: Option<BTreeSet<AuthorId>>,
+    /// Only entries with a timestamp at or before this time may be written.
+    ///
+    /// The time is in microseconds since the unix epoch, like entry timestamps. The delegate
+    /// cannot create entries after this time by its own clock, see
+    /// [`WriteCapability::check_expiry`]. Entries received from other peers are only checked
+    /// against their timestamp, so that replicas syncing after the expiry still agree on the
+    /// entries written before it. A delegate that backdates its entries is not detected.
+    pub expires_at: Option<u64>,
+}
+
+impl WriteRestrictions {
+    /// Restrict writes to keys that start with `prefix`.
+    pub fn with_prefix(mut self, prefix: impl Into<Bytes>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Restrict writes to entries signed by one of `authors`.
+    pub fn with_authors(mut self, authors: impl IntoIterator<Item = AuthorId>) -> Self {
+        self.authors = Some(authors.into_iter().collect());
+        self
+    }
+
+    /// Restrict writes to entries with a timestamp at or before `expires_at`.
+    pub fn with_expiry(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Check that these restrictions allow `entry`.
+    pub fn allows(&self, entry: &Entry) -> Result<(), DelegationError> {
+        if let Some(prefix) = &self.prefix {
+            if !entry.key().starts_with(prefix) {
+                return Err(DelegationError::KeyOutsidePrefix);
+            }
+        }
+        if let Some(authors) = &self.authors {
+            if !authors.contains(&entry.author()) {
+                return Err(DelegationError::AuthorNotAllowed);
+            }
+        }
+        if let Some(expires_at) = self.expires_at {
+            if entry.timestamp() > expires_at {
+                return Err(DelegationError::Expired);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if everything allowed by `self` is also allowed by `other`.
+    pub fn is_within(&self, other: &WriteRestrictions) -> bool {
+        let prefix = match (&self.prefix, &other.prefix) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(ours), Some(theirs)) => ours.starts_with(theirs),
+        };
+        let authors = match (&self.authors, &other.authors) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(ours), Some(theirs)) => ours.is_subset(theirs),
+        };
+        let expires_at = match (self.expires_at, other.expires_at) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(ours), Some(theirs)) => ours <= theirs,
+        };
+        prefix && authors && expires_at
+    }
+}
+
+/// A single step in a [`WriteCapability`], signed by its issuer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    delegate: DelegatePublicKey,
+    restrictions: WriteRestrictions,
+    signature: Signature,
+}
+
+impl Delegation {
+    /// The key this delegation was issued to.
+    pub fn delegate(&self) -> DelegatePublicKey {
+        self.delegate
+    }
+
+    /// The restrictions of this delegation.
+    pub fn restrictions(&self) -> &WriteRestrictions {
+        &self.restrictions
+    }
+}
+
+/// A signed, delegatable capability to write to a namespace within some [`WriteRestrictions`].
+///
+/// The capability is a chain of [`Delegation`]s. The root is signed by the [`NamespaceSecret`],
+/// each further delegation by the delegate of the previous one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteCapability {
+    namespace: NamespaceId,
+    root: Delegation,
+    delegations: Vec<Delegation>,
+}
+
+impl WriteCapability {
+    /// Issue a new capability for `delegate`, signed by the `namespace` key.
+    pub fn new(
+        namespace: &NamespaceSecret,
+        delegate: DelegatePublicKey,
+        restrictions: WriteRestrictions,
+    ) -> Self {
+        let id = namespace.id();
+        let bytes = signing_bytes(&id, None, &delegate, &restrictions);
+        let signature = namespace.sign(&bytes);
+        WriteCapability {
+            namespace: id,
+            root: Delegation {
+                delegate,
+                restrictions,
+                signature,
+            },
+            delegations: Vec::new(),
+        }
+    }
+
+    /// Pass this capability on to `delegate`.
+    ///
+    /// `issuer` must be the secret of the current holder, and `restrictions` must be within the
+    /// restrictions of this capability.
+    pub fn delegate(
+        &self,
+        issuer: &DelegateSecret,
+        delegate: DelegatePublicKey,
+        restrictions: WriteRestrictions,
+    ) -> Result<Self, DelegationError> {
+        let last = self.last();
+        if issuer.public_key() != last.delegate {
+            return Err(DelegationError::KeyMismatch);
+        }
+        if !restrictions.is_within(&last.restrictions) {
+            return Err(DelegationError::Widened);
+        }
+        if self.delegations.len() >= MAX_DELEGATION_DEPTH {
+            return Err(DelegationError::TooDeep);
+        }
+        let bytes = signing_bytes(
+            &self.namespace,
+            Some(&last.signature),
+            &delegate,
+            &restrictions,
+        );
+        let signature = issuer.sign(&bytes);
+        let mut capability = self.clone();
+        capability.delegations.push(Delegation {
+            delegate,
+            restrictions,
+            signature,
+        });
+        Ok(capability)
+    }
+
+    /// The namespace this capability grants write access to.
+    pub fn namespace(&self) -> NamespaceId {
+        self.namespace
+    }
+
+    /// The key of the current holder of this capability.
+    pub fn holder(&self) -> DelegatePublicKey {
+        self.last().delegate
+    }
+
+    /// The restrictions that apply to the current holder.
+    pub fn restrictions(&self) -> &WriteRestrictions {
+        &self.last().restrictions
+    }
+
+    /// Iterate over the delegations, starting at the one signed by the namespace key.
+    pub fn chain(&self) -> impl Iterator<Item = &Delegation> {
+        std::iter::once(&self.root).chain(self.delegations.iter())
+    }
+
+    /// Verify the signatures of the delegation chain, and that no step widens the restrictions.
+    pub fn verify<S: PublicKeyStore>(&self, store: &S) -> Result<(), DelegationError> {
+        if self.delegations.len() > MAX_DELEGATION_DEPTH {
+            return Err(DelegationError::TooDeep);
+        }
+        let namespace = self
+            .namespace
+            .public_key(store)
+            .map_err(|_| DelegationError::BadSignature)?;
+        let root = &self.root;
+        let bytes = signing_bytes(&self.namespace, None, &root.delegate, &root.restrictions);
+        namespace
+            .verify(&bytes, &root.signature)
+            .map_err(|_| DelegationError::BadSignature)?;
+        let mut parent = root;
+        for delegation in &self.delegations {
+            if !delegation.restrictions.is_within(&parent.restrictions) {
+                return Err(DelegationError::Widened);
+            }
+            let bytes = signing_bytes(
+                &self.namespace,
+                Some(&parent.signature),
+                &delegation.delegate,
+                &delegation.restrictions,
+            );
+            parent
+                .delegate
+                .verify(&bytes, &delegation.signature)
+                .map_err(|_| DelegationError::BadSignature)?;
+            parent = delegation;
+        }
+        Ok(())
+    }
+
+    /// Check that the restrictions of this capability allow `entry`.
+    ///
+    /// This does not verify the delegation chain, see [`Self::verify`].
+    pub fn authorize(&self, entry: &Entry) -> Result<(), DelegationError> {
+        if entry.namespace() != self.namespace {
+            return Err(DelegationError::NamespaceMismatch);
+        }
+        self.restrictions().allows(entry)
+    }
+
+    /// Check that this capability has not expired at `now`.
+    ///
+    /// `now` is the local time at which an entry is created, in microseconds since the unix
+    /// epoch. This is only checked for local entries, entries from other peers are accepted
+    /// if their timestamp is within the expiry.
+    pub fn check_expiry(&self, now: u64) -> Result<(), DelegationError> {
+        match self.restrictions().expires_at {
+            Some(expires_at) if now > expires_at => Err(DelegationError::Expired),
+            _ => Ok(()),
+        }
+    }
+
+    fn last(&self) -> &Delegation {
+        self.delegations.last().unwrap_or(&self.root)
+    }
+}
+
+/// A [`WriteCapability`] together with the secret key of its holder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delegate {
+    capability: WriteCapability,
+    secret: DelegateSecret,
+}
+
+impl Delegate {
+    /// Create a delegate from a capability and the secret key it was issued to.
+    pub fn new(
+        capability: WriteCapability,
+        secret: DelegateSecret,
+    ) -> Result<Self, DelegationError> {
+        if secret.public_key() != capability.holder() {
+            return Err(DelegationError::KeyMismatch);
+        }
+        Ok(Delegate { capability, secret })
+    }
+
+    /// Issue a capability with `restrictions` to a new random delegate key.
+    pub fn issue<R: CryptoRngCore + ?Sized>(
+        namespace: &NamespaceSecret,
+        restrictions: WriteRestrictions,
+        rng: &mut R,
+    ) -> Self {
+        let secret = DelegateSecret::new(rng);
+        let capability = WriteCapability::new(namespace, secret.public_key(), restrictions);
+        Delegate { capability, secret }
+    }
+
+    /// Pass the capability on to a new random delegate key, with narrower `restrictions`.
+    pub fn delegate<R: CryptoRngCore + ?Sized>(
+        &self,
+        restrictions: WriteRestrictions,
+        rng: &mut R,
+    ) -> Result<Self, DelegationError> {
+        let secret = DelegateSecret::new(rng);
+        let capability =
+            self.capability
+                .delegate(&self.secret, secret.public_key(), restrictions)?;
+        Ok(Delegate { capability, secret })
+    }
+
+    /// The namespace this delegate may write to.
+    pub fn id(&self) -> NamespaceId {
+        self.capability.namespace()
+    }
+
+    /// The capability of this delegate.
+    pub fn capability(&self) -> &WriteCapability {
+        &self.capability
+    }
+
+    /// The secret key of this delegate.
+    pub fn secret(&self) -> &DelegateSecret {
+        &self.secret
+    }
+}
+
+/// Error for invalid or insufficient delegated capabilities.
+#[derive(Debug, thiserror::Error)]
+pub enum DelegationError {
+    /// The capability is for a different namespace.
+    #[error("Capability is for a different namespace")]
+    NamespaceMismatch,
+    /// A signature in the delegation chain is invalid.
+    #[error("Invalid signature in delegation chain")]
+    BadSignature,
+    /// A delegation has wider restrictions than its issuer.
+    #[error("Delegation widens the restrictions of its issuer")]
+    Widened,
+    /// The delegation chain is longer than [`MAX_DELEGATION_DEPTH`].
+    #[error("Delegation chain is too long")]
+    TooDeep,
+    /// The secret key does not belong to the holder of the capability.
+    #[error("Key does not match the holder of the capability")]
+    KeyMismatch,
+    /// The entry key is outside of the delegated prefix.
+    #[error("Entry key is outside of the delegated prefix")]
+    KeyOutsidePrefix,
+    /// The entry author is not allowed by the delegation.
+    #[error("Entry author is not allowed by the delegation")]
+    AuthorNotAllowed,
+    /// The entry timestamp is after the expiry of the delegation.
+    #[error("Entry timestamp is after the expiry of the delegation")]
+    Expired,
+}
+
+fn signing_bytes(
+    namespace: &NamespaceId,
+    parent: Option<&Signature>,
+    delegate: &DelegatePublicKey,
+    restrictions: &WriteRestrictions,
+) -> Vec<u8> {
+    let mut bytes = DELEGATION_DOMAIN.to_vec();
+    bytes.extend_from_slice(namespace.as_bytes());
+    if let Some(parent) = parent {
+        bytes.extend_from_slice(&parent.to_bytes());
+    }
+    bytes.extend_from_slice(delegate.as_bytes());
+    let restrictions = postcard::to_stdvec(restrictions).expect("serialization never fails");
+    bytes.extend_from_slice(&restrictions);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Author, Record, RecordIdentifier};
+
+    fn entry(namespace: NamespaceId, author: &Author, key: &[u8], timestamp: u64) -> Entry {
+        let id = RecordIdentifier::new(namespace, author.id(), key);
+        Entry::new(
+            id,
+            Record::new(iroh_base::hash::Hash::new(b"x"), 1, timestamp),
+        )
+    }
+
+    #[test]
+    fn delegation_chain() -> anyhow::Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = NamespaceSecret::new(&mut rng);
+        let alice = Author::new(&mut rng);
+        let bob = Author::new(&mut rng);
+
+        let root = Delegate::issue(
+            &namespace,
+            WriteRestrictions::default().with_prefix(&b"/uploads/"[..]),
+            &mut rng,
+        );
+        root.capability().verify(&())?;
+        root.capability()
+            .authorize(&entry(namespace.id(), &alice, b"/uploads/a", 1))?;
+        assert!(matches!(
+            root.capability()
+                .authorize(&entry(namespace.id(), &alice, b"/other", 1)),
+            Err(DelegationError::KeyOutsidePrefix)
+        ));
+
+        let restrictions = WriteRestrictions::default()
+            .with_prefix(&b"/uploads/bob/"[..])
+            .with_authors([bob.id()])
+            .with_expiry(100);
+        let sub = root.delegate(restrictions, &mut rng)?;
+        sub.capability().verify(&())?;
+        assert_eq!(sub.capability().chain().count(), 2);
+        sub.capability()
+            .authorize(&entry(namespace.id(), &bob, b"/uploads/bob/a", 100))?;
+        assert!(matches!(
+            sub.capability()
+                .authorize(&entry(namespace.id(), &alice, b"/uploads/bob/a", 1)),
+            Err(DelegationError::AuthorNotAllowed)
+        ));
+        assert!(matches!(
+            sub.capability()
+                .authorize(&entry(namespace.id(), &bob, b"/uploads/bob/a", 101)),
+            Err(DelegationError::Expired)
+        ));
+
+        // a delegate cannot widen its restrictions
+        assert!(matches!(
+            sub.delegate(WriteRestrictions::default(), &mut rng),
+            Err(DelegationError::Widened)
+        ));
+
+        // the expiry is also checked against the local time
+        sub.capability().check_expiry(100)?;
+        assert!(matches!(
+            sub.capability().check_expiry(101),
+            Err(DelegationError::Expired)
+        ));
+        root.capability().check_expiry(u64::MAX)?;
+
+        // tampering with the restrictions invalidates the chain
+        let mut tampered = sub.capability().clone();
+        tampered.delegations[0].restrictions.expires_at = None;
+        assert!(tampered.verify(&()).is_err());
+
+        // a tampered capability is not merged into an existing one
+        let mut ours = crate::Capability::Delegated(sub.clone());
+        let forged = Delegate::new(tampered, sub.secret().clone())?;
+        assert!(ours.merge(crate::Capability::Delegated(forged)).is_err());
+        assert!(matches!(
+            ours,
+            crate::Capability::Delegated(ref delegate) if delegate.capability() == sub.capability()
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! Encryption of document entries and content.
+//!
+//! An encrypted document has an [`EncryptionKey`], which is shared with every peer that may read
+//! it. Peers without the key can still store and sync the document, but cannot read it.
+//!
+//! Entry keys are encrypted deterministically, so that the same key always maps to the same
+//! ciphertext. Set reconciliation and the replacement of older entries therefore work on the
+//! ciphertext just like on plaintext keys. Key prefixes are not preserved, so prefix queries and
+//! prefix deletions only match complete keys.
+//!
+//! Content is encrypted in segments of [`CONTENT_SEGMENT_SIZE`] bytes, so that it can be
+//! encrypted while it is streamed. The nonce of a segment is made of a random prefix, which is
+//! stored in front of the first segment, the index of the segment and whether it is the last
+//! one, so reordered, truncated or extended content fails to decrypt.
+
+use std::{fmt, sync::Arc};
+
+use iroh_base::{
+    base32,
+    key::{SecretKey, SharedSecret, NONCE_LEN, TAG_LEN},
+};
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    store::{KeyFilter, Query},
+    Entry, RecordIdentifier, SignedEntry,
+};
+
+/// Context for deriving the cipher key from an [`EncryptionKey`].
+const CIPHER_CONTEXT: &str = "iroh-sync 2024-05 document encryption cipher";
+
+/// Context for deriving the key for the nonces of entry keys from an [`EncryptionKey`].
+const NONCE_CONTEXT: &str = "iroh-sync 2024-05 document encryption key nonce";
+
+/// Size of the segments that content is encrypted in.
+pub const CONTENT_SEGMENT_SIZE: usize = 64 * 1024;
+
+/// Length of the random prefix of the content nonces.
+///
+/// The rest of the nonce is the index of the segment and a flag for the last segment.
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - 9;
+
+/// Symmetric key to encrypt the entry keys and content of a document.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "[u8; 32]", into = "[u8; 32]")]
+pub struct EncryptionKey {
+    bytes: [u8; 32],
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cipher: SharedSecret,
+    nonce_key: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// Create a new random [`EncryptionKey`].
+    pub fn new<R: CryptoRngCore + ?Sized>(rng: &mut R) -> Self {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        Self::from_bytes(&bytes)
+    }
+
+    /// Create an [`EncryptionKey`] from a byte array.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let secret = SecretKey::from_bytes(&blake3::derive_key(CIPHER_CONTEXT, bytes));
+        let cipher = secret.shared(&secret.public());
+        let nonce_key = blake3::derive_key(NONCE_CONTEXT, bytes);
+        EncryptionKey {
+            bytes: *bytes,
+            inner: Arc::new(Inner { cipher, nonce_key }),
+        }
+    }
+
+    /// Returns the [`EncryptionKey`] byte representation.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.bytes
+    }
+
+    /// Encrypt an entry key.
+    ///
+    /// The same key always results in the same ciphertext.
+    pub fn encrypt_key(&self, key: &[u8]) -> Vec<u8> {
+        let hash = blake3::keyed_hash(&self.inner.nonce_key, key);
+        let nonce: [u8; NONCE_LEN] = hash.as_bytes()[..NONCE_LEN]
+            .try_into()
+            .expect("hash is longer than nonce");
+        let mut buffer = key.to_vec();
+        self.inner.cipher.seal_with_nonce(&nonce, &mut buffer);
+        buffer
+    }
+
+    /// Decrypt an entry key that was encrypted with [`Self::encrypt_key`].
+    pub fn decrypt_key(&self, key: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+        let mut buffer = key.to_vec();
+        self.inner
+            .cipher
+            .open(&mut buffer)
+            .map_err(|_| DecryptionError)?;
+        Ok(buffer)
+    }
+
+    /// Encrypt the key filter of a [`Query`].
+    ///
+    /// A prefix filter is encrypted like a key, so it only matches the complete key.
+    pub fn encrypt_query(&self, mut query: Query) -> Query {
+        match query.key_filter_mut() {
+            KeyFilter::Any => {}
+            KeyFilter::Exact(key) | KeyFilter::Prefix(key) => {
+                *key = self.encrypt_key(key).into();
+            }
+        }
+        query
+    }
+
+    /// Create a [`ContentEncryptor`], to encrypt content while it is streamed.
+    pub fn content_encryptor(&self) -> ContentEncryptor {
+        ContentEncryptor {
+            key: self.clone(),
+            prefix: rand::random(),
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Encrypt the content of an entry.
+    pub fn encrypt_content(&self, content: &[u8]) -> Vec<u8> {
+        let mut encryptor = self.content_encryptor();
+        let mut encrypted = encryptor.header().to_vec();
+        let mut segments = content.chunks(CONTENT_SEGMENT_SIZE).peekable();
+        loop {
+            // empty content is encrypted as a single empty segment
+            let mut segment = segments.next().unwrap_or_default().to_vec();
+            let last = segments.peek().is_none();
+            encryptor.seal_segment(&mut segment, last);
+            encrypted.extend_from_slice(&segment);
+            if last {
+                return encrypted;
+            }
+        }
+    }
+
+    /// Decrypt content that was encrypted with [`Self::encrypt_content`] or a
+    /// [`ContentEncryptor`].
+    pub fn decrypt_content(&self, content: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+        if content.len() < NONCE_PREFIX_LEN {
+            return Err(DecryptionError);
+        }
+        let (prefix, mut rest) = content.split_at(NONCE_PREFIX_LEN);
+        let prefix = prefix.try_into().expect("prefix has the right length");
+        let mut decrypted = Vec::with_capacity(rest.len());
+        let mut index = 0;
+        loop {
+            let (segment, tail) = rest.split_at(rest.len().min(CONTENT_SEGMENT_SIZE + TAG_LEN));
+            let last = tail.is_empty();
+            let mut buffer = segment.to_vec();
+            self.inner
+                .cipher
+                .open_detached(&segment_nonce(prefix, index, last), &mut buffer)
+                .map_err(|_| DecryptionError)?;
+            decrypted.extend_from_slice(&buffer);
+            if last {
+                return Ok(decrypted);
+            }
+            rest = tail;
+            index += 1;
+        }
+    }
+
+    /// Decrypt the key of an [`Entry`].
+    pub fn decrypt_entry(&self, entry: &Entry) -> Result<Entry, DecryptionError> {
+        let key = self.decrypt_key(entry.key())?;
+        let id = RecordIdentifier::new(entry.namespace(), entry.author(), key);
+        Ok(Entry::new(id, entry.record().clone()))
+    }
+
+    /// Decrypt the key of a [`SignedEntry`].
+    ///
+    /// The signatures are those of the encrypted entry, so the returned entry does not verify.
+    pub fn decrypt_signed_entry(
+        &self,
+        entry: &SignedEntry,
+    ) -> Result<SignedEntry, DecryptionError> {
+        let decrypted = self.decrypt_entry(entry.entry())?;
+        Ok(SignedEntry::new(
+            entry.signature().clone(),
+            decrypted,
+            entry.delegation().cloned(),
+        ))
+    }
+}
+
+/// Encrypts the content of an entry segment by segment.
+///
+/// The encrypted content is the [`Self::header`], followed by the sealed segments. It can be
+/// decrypted with [`EncryptionKey::decrypt_content`].
+#[derive(Debug)]
+pub struct ContentEncryptor {
+    key: EncryptionKey,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    index: u64,
+    done: bool,
+}
+
+impl ContentEncryptor {
+    /// The header of the encrypted content, which precedes the first segment.
+    pub fn header(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Encrypt the next segment of the content in place.
+    ///
+    /// All segments but the last must be [`CONTENT_SEGMENT_SIZE`] bytes long. The last segment
+    /// may be shorter, and is empty for empty content.
+    ///
+    /// # Panics
+    ///
+    /// If the segment has the wrong size, or if the last segment was already sealed.
+    pub fn seal_segment(&mut self, segment: &mut Vec<u8>, last: bool) {
+        assert!(!self.done, "the last segment was already sealed");
+        assert!(
+            segment.len() == CONTENT_SEGMENT_SIZE || (last && segment.len() < CONTENT_SEGMENT_SIZE),
+            "invalid segment size {}",
+            segment.len()
+        );
+        let nonce = segment_nonce(&self.prefix, self.index, last);
+        self.key.inner.cipher.seal_detached(&nonce, segment);
+        self.index += 1;
+        self.done = last;
+    }
+}
+
+/// The nonce of the segment at `index` of content with the nonce prefix `prefix`.
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u64, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl From<EncryptionKey> for [u8; 32] {
+    fn from(key: EncryptionKey) -> Self {
+        key.bytes
+    }
+}
+
+impl PartialEq for EncryptionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for EncryptionKey {}
+
+impl fmt::Display for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base32::fmt(self.bytes))
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // do not leak the key into logs
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Error for ciphertexts that cannot be decrypted with an [`EncryptionKey`].
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to decrypt")]
+pub struct DecryptionError;
+
+#[cfg(test)]
+mod tests {
+    use iroh_base::hash::Hash;
+
+    use super::*;
+    use crate::{Author, NamespaceSecret, Record};
+
+    #[test]
+    fn encryption_roundtrip() -> anyhow::Result<()> {
+        let mut rng = rand::thread_rng();
+        let key = EncryptionKey::new(&mut rng);
+        let other = EncryptionKey::new(&mut rng);
+
+        let encrypted = key.encrypt_key(b"/a/b");
+        assert_eq!(encrypted, key.encrypt_key(b"/a/b"));
+        assert_ne!(encrypted, key.encrypt_key(b"/a/c"));
+        assert_ne!(encrypted, other.encrypt_key(b"/a/b"));
+        assert_eq!(key.decrypt_key(&encrypted)?, b"/a/b");
+        assert!(other.decrypt_key(&encrypted).is_err());
+
+        let content = key.encrypt_content(b"hello");
+        assert_ne!(content, key.encrypt_content(b"hello"));
+        assert_eq!(key.decrypt_content(&content)?, b"hello");
+        assert!(other.decrypt_content(&content).is_err());
+
+        let empty = key.encrypt_content(b"");
+        assert_eq!(key.decrypt_content(&empty)?, b"");
+
+        let decoded = EncryptionKey::from_bytes(&key.to_bytes());
+        assert_eq!(decoded.encrypt_key(b"/a/b"), encrypted);
+
+        let namespace = NamespaceSecret::new(&mut rng);
+        let author = Author::new(&mut rng);
+        let record = Record::new_current(Hash::new(&content), content.len() as u64);
+        let entry = SignedEntry::from_parts(&namespace, &author, &encrypted, record);
+        let decrypted = key.decrypt_signed_entry(&entry)?;
+        assert_eq!(decrypted.key(), b"/a/b");
+        assert_eq!(decrypted.content_hash(), entry.content_hash());
+        assert!(other.decrypt_signed_entry(&entry).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn content_segments() -> anyhow::Result<()> {
+        let key = EncryptionKey::new(&mut rand::thread_rng());
+        for len in [
+            CONTENT_SEGMENT_SIZE - 1,
+            CONTENT_SEGMENT_SIZE,
+            2 * CONTENT_SEGMENT_SIZE + 1,
+        ] {
+            let content = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let encrypted = key.encrypt_content(&content);
+            assert_eq!(key.decrypt_content(&encrypted)?, content);
+        }
+
+        let content = vec![1u8; 2 * CONTENT_SEGMENT_SIZE];
+        let encrypted = key.encrypt_content(&content);
+        let segment = CONTENT_SEGMENT_SIZE + TAG_LEN;
+        // dropping the last segment is detected
+        let truncated = &encrypted[..NONCE_PREFIX_LEN + segment];
+        assert!(key.decrypt_content(truncated).is_err());
+        // swapping segments is detected
+        let mut swapped = encrypted[..NONCE_PREFIX_LEN].to_vec();
+        swapped.extend_from_slice(&encrypted[NONCE_PREFIX_LEN + segment..]);
+        swapped.extend_from_slice(&encrypted[NONCE_PREFIX_LEN..NONCE_PREFIX_LEN + segment]);
+        assert!(key.decrypt_content(&swapped).is_err());
+
+        // streamed content decrypts like content encrypted at once
+        let mut encryptor = key.content_encryptor();
+        let mut streamed = encryptor.header().to_vec();
+        for (i, segment) in content.chunks(CONTENT_SEGMENT_SIZE).enumerate() {
+            let mut segment = segment.to_vec();
+            encryptor.seal_segment(&mut segment, i == 1);
+            streamed.extend_from_slice(&segment);
+        }
+        assert_eq!(key.decrypt_content(&streamed)?, content);
+        Ok(())
+    }
+}
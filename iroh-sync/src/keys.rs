@@ -158,6 +158,88 @@ impl NamespacePublicKey {
     }
 }
 
+/// Key of a delegate holding a [`crate::WriteCapability`].
+///
+/// A delegate signs entries in place of the [`NamespaceSecret`], within the restrictions of
+/// the capability that was issued to its public key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DelegateSecret {
+    signing_key: SigningKey,
+}
+
+impl DelegateSecret {
+    /// Create a new [`DelegateSecret`] with a random key.
+    pub fn new<R: CryptoRngCore + ?Sized>(rng: &mut R) -> Self {
+        let signing_key = SigningKey::generate(rng);
+        DelegateSecret { signing_key }
+    }
+
+    /// Create a [`DelegateSecret`] from a byte array.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        DelegateSecret {
+            signing_key: SigningKey::from_bytes(bytes),
+        }
+    }
+
+    /// Returns the [`DelegateSecret`] byte representation.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Get the [`DelegatePublicKey`] for this delegate.
+    pub fn public_key(&self) -> DelegatePublicKey {
+        DelegatePublicKey(self.signing_key.verifying_key())
+    }
+
+    /// Sign a message with this [`DelegateSecret`] key.
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        self.signing_key.sign(msg)
+    }
+}
+
+/// The corresponding [`VerifyingKey`] for a [`DelegateSecret`].
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, derive_more::From)]
+pub struct DelegatePublicKey(VerifyingKey);
+
+impl DelegatePublicKey {
+    /// Verify that a signature matches the `msg` bytes and was created with the
+    /// [`DelegateSecret`] that corresponds to this key.
+    pub fn verify(&self, msg: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        self.0.verify_strict(msg, signature)
+    }
+
+    /// Get the byte representation of this [`DelegatePublicKey`].
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.0.as_bytes()
+    }
+
+    /// Create from a slice of bytes.
+    ///
+    /// Will return an error if the input bytes do not represent a valid [`ed25519_dalek`]
+    /// curve point.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, SignatureError> {
+        Ok(DelegatePublicKey(VerifyingKey::from_bytes(bytes)?))
+    }
+}
+
+impl fmt::Display for DelegatePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base32::fmt(self.as_bytes()))
+    }
+}
+
+impl fmt::Debug for DelegatePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DelegatePublicKey({})", self)
+    }
+}
+
+impl fmt::Debug for DelegateSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DelegateSecret({})", self.public_key())
+    }
+}
+
 impl fmt::Display for Author {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", base32::fmt(self.to_bytes()))
@@ -13,6 +13,19 @@
 //! * The [Author] key, as a proof of authorship. Any number of authors may be created, and
 //!   their semantic meaning is application-specific. The public key of an author is the [AuthorId].
 //!
+//! Instead of the namespace key, entries may also be signed by a [`DelegateSecret`] that holds a
+//! [`WriteCapability`]. Such capabilities are issued by the namespace key and restrict writes
+//! to a key prefix, a set of authors and a point in time.
+//!
+//! Documents can be encrypted with an [`EncryptionKey`]. Entry keys and content are then only
+//! readable for peers that hold the key, while all other peers can still store and sync them.
+//!
+//! Capabilities cannot be revoked. Instead, a namespace can be rotated to a new keypair, which
+//! carries its entries forward and leaves a [`NamespaceRotation`] pointer in the old namespace.
+//!
+//! The state of a namespace can be frozen into a content-addressed [`Snapshot`], which can be
+//! stored as a blob and imported into a replica on another node.
+//!
 //! Replicas can be synchronized between peers by exchanging messages. The synchronization algorithm
 //! is based on a technique called *range-based set reconciliation*, based on [this paper][paper] by
 //! Aljoscha Meyer:
@@ -34,6 +47,8 @@
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 
 pub mod actor;
+mod delegation;
+mod encryption;
 mod heads;
 mod keys;
 #[cfg(feature = "metrics")]
@@ -41,9 +56,15 @@ pub mod metrics;
 #[cfg(feature = "net")]
 pub mod net;
 mod ranger;
+mod rotation;
+mod snapshot;
 pub mod store;
 pub mod sync;
 
+pub use self::delegation::*;
+pub use self::encryption::*;
 pub use self::heads::*;
 pub use self::keys::*;
+pub use self::rotation::*;
+pub use self::snapshot::*;
 pub use self::sync::*;
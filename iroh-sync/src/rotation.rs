@@ -0,0 +1,140 @@
+//! Rotation of a namespace to a new keypair.
+//!
+//! Once a write capability for a namespace has leaked, it cannot be taken back. Instead, the
+//! namespace is rotated: entries are carried forward into a new namespace, and the old namespace
+//! gets a [`NamespaceRotation`] entry at [`ROTATION_KEY`] that points to its successor.
+//!
+//! The pointer does not contain the [`NamespaceId`] of the new namespace, since knowing it grants
+//! read access. It only contains a commitment to it, so that a peer which was given the new
+//! namespace by other means can check that it is the successor of the old one. Note that the
+//! pointer is signed with the old namespace key, so anyone who holds that key can create one.
+
+use ed25519_dalek::Signature;
+use serde::{Deserialize, Serialize};
+
+use crate::{store::PublicKeyStore, NamespaceId, NamespaceSecret};
+
+/// Domain separator for the signature over a [`NamespaceRotation`].
+const ROTATION_DOMAIN: &[u8] = b"iroh-sync:rotation:v1";
+
+/// Domain separator for the commitment to the successor namespace.
+const SUCCESSOR_DOMAIN: &[u8] = b"iroh-sync:rotation-successor:v1";
+
+/// The key at which the [`NamespaceRotation`] of a rotated namespace is stored.
+pub const ROTATION_KEY: &[u8] = b"\0iroh-sync/rotation";
+
+/// A signed pointer from a namespace to the namespace it was rotated to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceRotation {
+    namespace: NamespaceId,
+    successor: [u8; 32],
+    timestamp: u64,
+    signature: Signature,
+}
+
+impl NamespaceRotation {
+    /// Create a pointer from `namespace` to `successor`, signed by the old namespace key.
+    ///
+    /// The `timestamp` is in microseconds since the unix epoch, like entry timestamps.
+    pub fn new(namespace: &NamespaceSecret, successor: &NamespaceId, timestamp: u64) -> Self {
+        let id = namespace.id();
+        let successor = commitment(successor);
+        let signature = namespace.sign(&signing_bytes(&id, &successor, timestamp));
+        NamespaceRotation {
+            namespace: id,
+            successor,
+            timestamp,
+            signature,
+        }
+    }
+
+    /// The namespace that was rotated.
+    pub fn namespace(&self) -> NamespaceId {
+        self.namespace
+    }
+
+    /// The time of the rotation, in microseconds since the unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Returns `true` if `namespace` is the successor this pointer commits to.
+    pub fn is_successor(&self, namespace: &NamespaceId) -> bool {
+        commitment(namespace) == self.successor
+    }
+
+    /// Verify the signature of the old namespace key.
+    pub fn verify<S: PublicKeyStore>(&self, store: &S) -> Result<(), RotationError> {
+        let bytes = signing_bytes(&self.namespace, &self.successor, self.timestamp);
+        self.namespace
+            .public_key(store)
+            .and_then(|key| key.verify(&bytes, &self.signature))
+            .map_err(|_| RotationError::BadSignature)
+    }
+
+    /// Encode this pointer as the content of the entry at [`ROTATION_KEY`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("serialization never fails")
+    }
+
+    /// Decode a pointer from the content of the entry at [`ROTATION_KEY`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RotationError> {
+        postcard::from_bytes(bytes).map_err(|_| RotationError::Malformed)
+    }
+}
+
+/// Error for invalid rotation pointers.
+#[derive(Debug, thiserror::Error)]
+pub enum RotationError {
+    /// The pointer could not be decoded.
+    #[error("Malformed rotation pointer")]
+    Malformed,
+    /// The signature of the old namespace key is invalid.
+    #[error("Invalid signature on rotation pointer")]
+    BadSignature,
+}
+
+fn commitment(successor: &NamespaceId) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(SUCCESSOR_DOMAIN);
+    hasher.update(successor.as_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+fn signing_bytes(namespace: &NamespaceId, successor: &[u8; 32], timestamp: u64) -> Vec<u8> {
+    let mut bytes = ROTATION_DOMAIN.to_vec();
+    bytes.extend_from_slice(namespace.as_bytes());
+    bytes.extend_from_slice(successor);
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_pointer() -> anyhow::Result<()> {
+        let mut rng = rand::thread_rng();
+        let old = NamespaceSecret::new(&mut rng);
+        let new = NamespaceSecret::new(&mut rng);
+        let other = NamespaceSecret::new(&mut rng);
+
+        let rotation = NamespaceRotation::new(&old, &new.id(), 42);
+        rotation.verify(&())?;
+        assert_eq!(rotation.namespace(), old.id());
+        assert!(rotation.is_successor(&new.id()));
+        assert!(!rotation.is_successor(&other.id()));
+
+        let decoded = NamespaceRotation::from_bytes(&rotation.to_bytes())?;
+        assert_eq!(decoded, rotation);
+
+        let mut forged = rotation.clone();
+        forged.successor = commitment(&other.id());
+        assert!(matches!(
+            forged.verify(&()),
+            Err(RotationError::BadSignature)
+        ));
+        Ok(())
+    }
+}
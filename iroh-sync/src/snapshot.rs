@@ -0,0 +1,133 @@
+//! Snapshots of the state of a namespace.
+//!
+//! A [`Snapshot`] contains the entries of a namespace at one point in time, sorted by their
+//! [`RecordIdentifier`](crate::RecordIdentifier). It is encoded deterministically, so the same
+//! state always results in the same bytes and the same [`Hash`]. Stored as a blob, a snapshot can
+//! be shared like any other content, and imported into a fresh replica elsewhere.
+//!
+//! Deletions are part of the state, so the empty entries of a namespace are included. The
+//! snapshot of an encrypted document contains the encrypted entries, and is only readable with
+//! the [`EncryptionKey`](crate::EncryptionKey) of the document.
+
+use iroh_base::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::{store::PublicKeyStore, NamespaceId, SignedEntry, ValidationFailure};
+
+/// The entries of a namespace at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    namespace: NamespaceId,
+    entries: Vec<SignedEntry>,
+}
+
+impl Snapshot {
+    /// Create a snapshot of `namespace` from its entries.
+    ///
+    /// The entries are sorted, so their order does not matter.
+    pub fn new(namespace: NamespaceId, mut entries: Vec<SignedEntry>) -> Self {
+        entries.sort_by(|a, b| a.id().cmp(b.id()));
+        Snapshot { namespace, entries }
+    }
+
+    /// The namespace this snapshot was taken of.
+    pub fn namespace(&self) -> NamespaceId {
+        self.namespace
+    }
+
+    /// The entries of this snapshot, sorted by their record identifier.
+    pub fn entries(&self) -> &[SignedEntry] {
+        &self.entries
+    }
+
+    /// The hash of this snapshot, as a blob.
+    pub fn hash(&self) -> Hash {
+        Hash::new(self.to_bytes())
+    }
+
+    /// Verify that the entries belong to the namespace, are sorted, and are correctly signed.
+    pub fn verify<S: PublicKeyStore>(&self, store: &S) -> Result<(), SnapshotError> {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.namespace() != self.namespace {
+                return Err(SnapshotError::InvalidNamespace);
+            }
+            if i > 0 && self.entries[i - 1].id() >= entry.id() {
+                return Err(SnapshotError::Unsorted);
+            }
+            entry.verify(store)?;
+        }
+        Ok(())
+    }
+
+    /// Encode this snapshot as the content of a blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("serialization never fails")
+    }
+
+    /// Decode a snapshot from the content of a blob.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        postcard::from_bytes(bytes).map_err(|_| SnapshotError::Malformed)
+    }
+}
+
+/// Error for invalid snapshots.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    /// The snapshot could not be decoded.
+    #[error("Malformed snapshot")]
+    Malformed,
+    /// An entry belongs to another namespace.
+    #[error("Snapshot entry belongs to another namespace")]
+    InvalidNamespace,
+    /// The entries are not sorted, or contain the same record twice.
+    #[error("Snapshot entries are not sorted")]
+    Unsorted,
+    /// An entry failed to verify.
+    #[error("Invalid snapshot entry: {0}")]
+    InvalidEntry(#[from] ValidationFailure),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Author, NamespaceSecret, Record};
+
+    #[test]
+    fn snapshot_roundtrip() -> anyhow::Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = NamespaceSecret::new(&mut rng);
+        let other = NamespaceSecret::new(&mut rng);
+        let author = Author::new(&mut rng);
+        let entry = |namespace: &NamespaceSecret, key: &str| {
+            let record = Record::new_current(Hash::new(key), key.len() as u64);
+            SignedEntry::from_parts(namespace, &author, key, record)
+        };
+
+        let a = entry(&namespace, "/a");
+        let b = entry(&namespace, "/b");
+        let snapshot = Snapshot::new(namespace.id(), vec![b.clone(), a.clone()]);
+        snapshot.verify(&())?;
+        assert_eq!(snapshot.entries(), &[a.clone(), b.clone()]);
+        assert_eq!(snapshot, Snapshot::new(namespace.id(), vec![a.clone(), b]));
+
+        let decoded = Snapshot::from_bytes(&snapshot.to_bytes())?;
+        assert_eq!(decoded, snapshot);
+        assert_eq!(decoded.hash(), snapshot.hash());
+        assert!(matches!(
+            Snapshot::from_bytes(b"garbage"),
+            Err(SnapshotError::Malformed)
+        ));
+
+        let foreign = Snapshot::new(namespace.id(), vec![entry(&other, "/c")]);
+        assert!(matches!(
+            foreign.verify(&()),
+            Err(SnapshotError::InvalidNamespace)
+        ));
+        let duplicate = Snapshot::new(namespace.id(), vec![a.clone(), a]);
+        assert!(matches!(
+            duplicate.verify(&()),
+            Err(SnapshotError::Unsorted)
+        ));
+        Ok(())
+    }
+}
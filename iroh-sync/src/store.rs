@@ -1,11 +1,11 @@
 //! Storage trait and implementation for iroh-sync documents
-use std::num::NonZeroUsize;
+use std::{num::NonZeroUsize, time::Duration};
 
 use anyhow::Result;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
-use crate::{AuthorId, Entry, NamespaceId};
+use crate::{AuthorId, Entry, NamespaceId, NamespaceRotation};
 
 pub mod fs;
 mod pubkeys;
@@ -60,6 +60,47 @@ pub enum ImportNamespaceOutcome {
     NoChange,
 }
 
+/// Outcome of [`Store::rotate_namespace`]
+#[derive(Debug, Clone)]
+pub struct RotateOutcome {
+    /// The namespace the entries were carried forward to.
+    pub namespace: NamespaceId,
+    /// The pointer from the old namespace to the new one.
+    pub rotation: NamespaceRotation,
+    /// The number of entries carried forward.
+    pub carried: u64,
+    /// The number of entries whose author key is not in the store, and which were not carried
+    /// forward.
+    pub skipped: u64,
+}
+
+/// Retention policy for the history of a document.
+///
+/// A document with a history policy keeps the entries that were superseded by newer entries,
+/// either for the same key or for a prefix of it. Without a policy, superseded entries are
+/// dropped. The history is local to a node and not synced.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoryPolicy {
+    /// Maximum number of superseded versions to keep per author and key.
+    pub max_versions: Option<u64>,
+    /// Maximum age of superseded versions to keep, measured from their entry timestamp.
+    pub max_age: Option<Duration>,
+}
+
+impl HistoryPolicy {
+    /// Check if a superseded version is retained by this policy.
+    ///
+    /// `newer` is the number of newer superseded versions for the same author and key. `timestamp`
+    /// and `now` are in microseconds since the unix epoch.
+    pub fn retains(&self, newer: u64, timestamp: u64, now: u64) -> bool {
+        let within_count = self.max_versions.map_or(true, |max| newer < max);
+        let within_age = self.max_age.map_or(true, |max_age| {
+            now.saturating_sub(timestamp) <= max_age.as_micros() as u64
+        });
+        within_count && within_age
+    }
+}
+
 /// Download policy to decide which content blobs shall be downloaded.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DownloadPolicy {
@@ -172,6 +213,7 @@ pub struct QueryBuilder<K> {
     offset: u64,
     include_empty: bool,
     sort_direction: SortDirection,
+    versions: Versions,
 }
 
 impl<K> QueryBuilder<K> {
@@ -205,6 +247,14 @@ impl<K> QueryBuilder<K> {
         self.offset = offset;
         self
     }
+    /// Query the document as it was at `timestamp`, in microseconds since the unix epoch.
+    ///
+    /// This relies on the history of the document, see [`HistoryPolicy`]. Versions that were
+    /// dropped from the history, or superseded before the policy was set, cannot be restored.
+    pub fn as_of(mut self, timestamp: u64) -> Self {
+        self.versions = Versions::AsOf(timestamp);
+        self
+    }
 }
 
 /// Query on all entries without aggregation.
@@ -227,6 +277,14 @@ impl QueryBuilder<FlatQuery> {
         self
     }
 
+    /// Include the superseded versions kept in the history of the document.
+    ///
+    /// Versions of the same author and key are sorted by timestamp.
+    pub fn all_versions(mut self) -> Self {
+        self.versions = Versions::All;
+        self
+    }
+
     /// Build the query.
     pub fn build(self) -> Query {
         Query::from(self)
@@ -259,6 +317,7 @@ impl From<QueryBuilder<SingleLatestPerKeyQuery>> for Query {
             offset: builder.offset,
             include_empty: builder.include_empty,
             sort_direction: builder.sort_direction,
+            versions: builder.versions,
         }
     }
 }
@@ -273,6 +332,7 @@ impl From<QueryBuilder<FlatQuery>> for Query {
             offset: builder.offset,
             include_empty: builder.include_empty,
             sort_direction: builder.sort_direction,
+            versions: builder.versions,
         }
     }
 }
@@ -288,6 +348,7 @@ pub struct Query {
     offset: u64,
     include_empty: bool,
     sort_direction: SortDirection,
+    versions: Versions,
 }
 
 impl Query {
@@ -316,6 +377,11 @@ impl Query {
         Self::all().key_prefix(prefix)
     }
 
+    /// Create a query for all versions of a key, including those kept in the history.
+    pub fn key_versions(key: impl AsRef<[u8]>) -> QueryBuilder<FlatQuery> {
+        Self::key_exact(key).all_versions()
+    }
+
     /// Get the limit for this query (max. number of entries to emit).
     pub fn limit(&self) -> Option<u64> {
         self.limit
@@ -325,6 +391,10 @@ impl Query {
     pub fn offset(&self) -> u64 {
         self.offset
     }
+
+    pub(crate) fn key_filter_mut(&mut self) -> &mut KeyFilter {
+        &mut self.filter_key
+    }
 }
 
 /// Sort direction
@@ -345,6 +415,18 @@ enum QueryKind {
     SingleLatestPerKey(SingleLatestPerKeyQuery),
 }
 
+/// The versions of entries returned by a query.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Versions {
+    /// Only the current entries.
+    #[default]
+    Current,
+    /// The current entries and the superseded entries kept in the history.
+    All,
+    /// The entries that were current at a timestamp, in microseconds since the unix epoch.
+    AsOf(u64),
+}
+
 /// Fields by which the query can be sorted
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub enum SortBy {
@@ -2,7 +2,7 @@
 
 use std::{
     cmp::Ordering,
-    collections::HashSet,
+    collections::{BTreeSet, HashSet},
     iter::{Chain, Flatten},
     num::NonZeroU64,
     ops::Bound,
@@ -18,15 +18,18 @@ use redb::{Database, DatabaseError, ReadableMultimapTable, ReadableTable, Readab
 
 use crate::{
     keys::Author,
-    ranger::{Fingerprint, Range, RangeEntry},
-    sync::{Entry, EntrySignature, Record, RecordIdentifier, Replica, SignedEntry},
-    AuthorHeads, AuthorId, Capability, CapabilityKind, NamespaceId, NamespaceSecret, PeerIdBytes,
-    ReplicaInfo,
+    ranger::{Fingerprint, InsertOutcome, Range, RangeEntry},
+    sync::{
+        system_time_now, validate_imported_entry, Entry, EntrySignature, Record, RecordIdentifier,
+        Replica, SignedEntry,
+    },
+    AuthorHeads, AuthorId, Capability, CapabilityKind, EncryptionKey, NamespaceId,
+    NamespaceRotation, NamespaceSecret, PeerIdBytes, ReplicaInfo, Snapshot, ROTATION_KEY,
 };
 
 use super::{
-    pubkeys::MemPublicKeyStore, DownloadPolicy, ImportNamespaceOutcome, OpenError, PublicKeyStore,
-    Query,
+    pubkeys::MemPublicKeyStore, DownloadPolicy, HistoryPolicy, ImportNamespaceOutcome, KeyFilter,
+    OpenError, PublicKeyStore, Query, RotateOutcome,
 };
 
 mod bounds;
@@ -37,14 +40,15 @@ mod ranges;
 pub(crate) mod tables;
 
 use self::{
-    bounds::{ByKeyBounds, RecordsBounds},
+    bounds::{ByKeyBounds, HistoryBounds, RecordsBounds},
     ranges::RangeExt,
-    tables::{RecordsTable, TransactionAndTables},
+    tables::{HistoryTable, RecordsTable, TransactionAndTables},
 };
 use self::{
     query::QueryIterator,
     tables::{
-        LatestPerAuthorKey, LatestPerAuthorValue, ReadOnlyTables, RecordsId, RecordsValue, Tables,
+        HistoryId, LatestPerAuthorKey, LatestPerAuthorValue, ReadOnlyTables, RecordsId,
+        RecordsValue, Tables,
     },
 };
 
@@ -280,8 +284,8 @@ impl Store {
         let tables = self.tables()?;
         let info = match tables.namespaces.get(namespace_id.as_bytes()) {
             Ok(Some(db_value)) => {
-                let (raw_kind, raw_bytes) = db_value.value();
-                let namespace = Capability::from_raw(raw_kind, raw_bytes)?;
+                let namespace =
+                    parse_capability(&tables.delegations, namespace_id, db_value.value())?;
                 ReplicaInfo::new(namespace)
             }
             Ok(None) => return Err(OpenError::NotFound),
@@ -304,8 +308,9 @@ impl Store {
             .namespaces
             .iter()?
             .map(|res| {
-                let capability = parse_capability(res?.1.value())?;
-                Ok((capability.id(), capability.kind()))
+                let (id, value) = res?;
+                let (raw_kind, _raw_bytes) = value.value();
+                Ok((NamespaceId::from(id.value()), raw_kind.try_into()?))
             })
             .collect();
         Ok(namespaces.into_iter())
@@ -362,7 +367,11 @@ impl Store {
                 let (capability, outcome) = {
                     let existing = tables.namespaces.get(capability.id().as_bytes())?;
                     if let Some(existing) = existing {
-                        let mut existing = parse_capability(existing.value())?;
+                        let mut existing = parse_capability(
+                            &tables.delegations,
+                            &capability.id(),
+                            existing.value(),
+                        )?;
                         let outcome = if existing.merge(capability)? {
                             ImportNamespaceOutcome::Upgraded
                         } else {
@@ -376,12 +385,156 @@ impl Store {
                 let id = capability.id().to_bytes();
                 let (kind, bytes) = capability.raw();
                 tables.namespaces.insert(&id, (kind, &bytes))?;
+                match &capability {
+                    Capability::Delegated(delegate) => {
+                        let delegation = postcard::to_stdvec(delegate.capability())?;
+                        tables.delegations.insert(&id, delegation.as_slice())?;
+                    }
+                    _ => {
+                        tables.delegations.remove(&id)?;
+                    }
+                }
                 outcome
             };
             Ok(outcome)
         })
     }
 
+    /// Rotate a namespace to a new, random keypair.
+    ///
+    /// The entries of the namespace are signed for the new namespace and inserted with their
+    /// original timestamps. Only entries whose author key is in this store are carried forward,
+    /// since an entry must not be signed by anyone but its author. Deleted entries and the
+    /// pointer of an earlier rotation are not carried forward either.
+    ///
+    /// The old namespace is left unchanged. The returned [`NamespaceRotation`] is meant to be
+    /// inserted into it at [`ROTATION_KEY`], after which the old namespace should be made
+    /// read-only with [`Self::make_read_only`].
+    pub fn rotate_namespace<R: CryptoRngCore + ?Sized>(
+        &mut self,
+        namespace: &NamespaceSecret,
+        rng: &mut R,
+    ) -> Result<RotateOutcome> {
+        let successor = NamespaceSecret::new(rng);
+        let entries = self
+            .get_many(namespace.id(), Query::all())?
+            .collect::<Result<Vec<_>>>()?;
+        self.import_namespace(successor.clone().into())?;
+
+        let mut carried = 0;
+        let mut skipped = 0;
+        for entry in entries {
+            if entry.key() == ROTATION_KEY {
+                continue;
+            }
+            let Some(author) = self.get_author(&entry.author_bytes())? else {
+                skipped += 1;
+                continue;
+            };
+            let id = RecordIdentifier::new(successor.id(), author.id(), entry.key());
+            let entry = Entry::new(id, entry.entry().record().clone()).sign(&successor, &author);
+            let mut instance = StoreInstance::new(successor.id(), self);
+            let outcome = crate::ranger::Store::put(&mut instance, entry)?;
+            if let InsertOutcome::Inserted { .. } = outcome {
+                carried += 1;
+            }
+        }
+
+        let rotation = NamespaceRotation::new(namespace, &successor.id(), system_time_now());
+        Ok(RotateOutcome {
+            namespace: successor.id(),
+            rotation,
+            carried,
+            skipped,
+        })
+    }
+
+    /// Downgrade the capability of a namespace to read-only.
+    ///
+    /// This removes the namespace secret or delegated capability from the store. It is used for
+    /// namespaces that were rotated, whose key is considered leaked.
+    pub fn make_read_only(&mut self, namespace: &NamespaceId) -> Result<()> {
+        self.modify(|tables| {
+            anyhow::ensure!(
+                tables.namespaces.get(namespace.as_bytes())?.is_some(),
+                "document not created"
+            );
+            let (kind, bytes) = Capability::Read(*namespace).raw();
+            tables
+                .namespaces
+                .insert(namespace.as_bytes(), (kind, &bytes))?;
+            tables.delegations.remove(namespace.as_bytes())?;
+            Ok(())
+        })
+    }
+
+    /// Take a [`Snapshot`] of a namespace.
+    ///
+    /// Without `as_of`, the snapshot contains the current entries of the namespace. With `as_of`,
+    /// it contains the entries at that timestamp, which relies on the history of the namespace.
+    /// Empty entries are included in both cases.
+    pub fn export_snapshot(
+        &mut self,
+        namespace: &NamespaceId,
+        as_of: Option<u64>,
+    ) -> Result<Snapshot> {
+        anyhow::ensure!(
+            self.tables()?
+                .namespaces
+                .get(namespace.as_bytes())?
+                .is_some(),
+            "document not created"
+        );
+        let query = Query::all().include_empty();
+        let query = match as_of {
+            Some(timestamp) => query.as_of(timestamp),
+            None => query,
+        };
+        let entries = self
+            .get_many(*namespace, query)?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Snapshot::new(*namespace, entries))
+    }
+
+    /// Import a [`Snapshot`] into the replica of its namespace.
+    ///
+    /// The snapshot is verified, and its entries are validated like entries received from a remote
+    /// peer, before any entry is inserted. If the namespace does not exist yet, it is created with
+    /// a read capability. Entries that are already in the replica, or superseded by entries in the
+    /// replica, are skipped. Returns the number of inserted entries.
+    ///
+    /// Note that the replica has to be closed, since no events are emitted for the inserted
+    /// entries.
+    pub fn import_snapshot(&mut self, snapshot: &Snapshot) -> Result<usize> {
+        let namespace = snapshot.namespace();
+        if self.open_replicas.contains(&namespace) {
+            return Err(anyhow!("replica is not closed"));
+        }
+        snapshot.verify(self)?;
+        let now = system_time_now();
+        let instance = StoreInstance::new(namespace, self);
+        for entry in snapshot.entries() {
+            validate_imported_entry(now, &instance, namespace, entry)?;
+        }
+        self.import_namespace(Capability::Read(namespace))?;
+        let mut instance = StoreInstance::new(namespace, self);
+        let mut inserted = 0;
+        for entry in snapshot.entries() {
+            let existing =
+                instance
+                    .store
+                    .get_exact(namespace, entry.author(), entry.key(), true)?;
+            if existing.as_ref() == Some(entry) {
+                continue;
+            }
+            let outcome = crate::ranger::Store::put(&mut instance, entry.clone())?;
+            if let InsertOutcome::Inserted { .. } = outcome {
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
     /// Remove a replica.
     ///
     /// Completely removes a replica and deletes both the namespace private key and all document
@@ -401,8 +554,12 @@ impl Store {
                 .records_by_key
                 .retain_in(bounds.as_ref(), |_k, _v| false);
             tables.namespaces.remove(namespace.as_bytes())?;
+            tables.delegations.remove(namespace.as_bytes())?;
+            tables.encryption_keys.remove(namespace.as_bytes())?;
             tables.namespace_peers.remove_all(namespace.as_bytes())?;
             tables.download_policy.remove(namespace.as_bytes())?;
+            tables.history_policy.remove(namespace.as_bytes())?;
+            remove_history(&mut tables.history, namespace)?;
             Ok(())
         })
     }
@@ -440,8 +597,7 @@ impl Store {
         assert!(matches!(self.transaction, CurrentTransaction::None));
         let tx = self.db.begin_read()?;
         let tables = ReadOnlyTables::new(tx)?;
-        let records = tables.records;
-        ContentHashesIterator::all(records)
+        ContentHashesIterator::all(tables.records, tables.history)
     }
 
     /// Get the latest entry for each author in a namespace.
@@ -581,6 +737,69 @@ impl Store {
             Some(value) => postcard::from_bytes(value.value())?,
         })
     }
+
+    /// Set the history policy for a namespace.
+    ///
+    /// With a policy, entries that are superseded from now on are kept in the history of the
+    /// namespace, and the existing history is pruned to the new policy. Without a policy, the
+    /// history is removed.
+    pub fn set_history_policy(
+        &mut self,
+        namespace: &NamespaceId,
+        policy: Option<HistoryPolicy>,
+    ) -> Result<()> {
+        self.modify(|tables| {
+            let id = namespace.as_bytes();
+
+            // ensure the document exists
+            anyhow::ensure!(tables.namespaces.get(id)?.is_some(), "document not created");
+
+            match policy {
+                Some(policy) => {
+                    let value = postcard::to_stdvec(&policy)?;
+                    tables.history_policy.insert(id, value.as_slice())?;
+                    prune_history(&mut tables.history, &policy, namespace, None)?;
+                }
+                None => {
+                    tables.history_policy.remove(id)?;
+                    remove_history(&mut tables.history, namespace)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Get the history policy for a namespace, if it keeps a history.
+    pub fn get_history_policy(&mut self, namespace: &NamespaceId) -> Result<Option<HistoryPolicy>> {
+        get_history_policy(&self.tables()?.history_policy, namespace)
+    }
+
+    /// Set the encryption key for a namespace.
+    pub fn set_encryption_key(
+        &mut self,
+        namespace: &NamespaceId,
+        key: &EncryptionKey,
+    ) -> Result<()> {
+        self.modify(|tables| {
+            let namespace = namespace.as_bytes();
+
+            // ensure the document exists
+            anyhow::ensure!(
+                tables.namespaces.get(&namespace)?.is_some(),
+                "document not created"
+            );
+
+            tables.encryption_keys.insert(namespace, &key.to_bytes())?;
+            Ok(())
+        })
+    }
+
+    /// Get the encryption key for a namespace, if it is encrypted.
+    pub fn get_encryption_key(&mut self, namespace: &NamespaceId) -> Result<Option<EncryptionKey>> {
+        let tables = self.tables()?;
+        let value = tables.encryption_keys.get(namespace.as_bytes())?;
+        Ok(value.map(|value| EncryptionKey::from_bytes(value.value())))
+    }
 }
 
 impl PublicKeyStore for Store {
@@ -589,8 +808,123 @@ impl PublicKeyStore for Store {
     }
 }
 
-fn parse_capability((raw_kind, raw_bytes): (u8, &[u8; 32])) -> Result<Capability> {
-    Capability::from_raw(raw_kind, raw_bytes)
+fn parse_capability(
+    delegations: &impl ReadableTable<&'static [u8; 32], &'static [u8]>,
+    namespace: &NamespaceId,
+    (raw_kind, raw_bytes): (u8, &[u8; 32]),
+) -> Result<Capability> {
+    let delegation = match delegations.get(namespace.as_bytes())? {
+        Some(value) => Some(postcard::from_bytes(value.value())?),
+        None => None,
+    };
+    Capability::from_raw_delegated(raw_kind, raw_bytes, delegation)
+}
+
+fn get_history_policy(
+    table: &impl ReadableTable<&'static [u8; 32], &'static [u8]>,
+    namespace: &NamespaceId,
+) -> Result<Option<HistoryPolicy>> {
+    match table.get(namespace.as_bytes())? {
+        Some(value) => Ok(Some(postcard::from_bytes(value.value())?)),
+        None => Ok(None),
+    }
+}
+
+/// Get the versions in the history within `bounds`.
+///
+/// The entries are sorted by author and key, then by timestamp.
+fn history_entries(
+    history: &impl ReadableTable<HistoryId<'static>, RecordsValue<'static>>,
+    bounds: &HistoryBounds,
+) -> Result<Vec<SignedEntry>> {
+    let mut entries = Vec::new();
+    for res in history.range(bounds.as_ref())? {
+        let (id, value) = res?;
+        let (namespace, author, key, _timestamp) = id.value();
+        entries.push(into_entry((namespace, author, key), value.value()));
+    }
+    Ok(entries)
+}
+
+fn insert_history(
+    history: &mut redb::Table<HistoryId<'static>, RecordsValue<'static>>,
+    entry: &SignedEntry,
+) -> Result<()> {
+    let id = entry.id();
+    let key = (
+        &id.namespace().to_bytes(),
+        &id.author().to_bytes(),
+        id.key(),
+        entry.timestamp(),
+    );
+    with_records_value(entry, |value| {
+        history.insert(key, value)?;
+        Ok(())
+    })
+}
+
+/// Remove the versions that are not retained by `policy` from the history of a namespace, or of
+/// a single author and key in it.
+fn prune_history(
+    history: &mut redb::Table<HistoryId<'static>, RecordsValue<'static>>,
+    policy: &HistoryPolicy,
+    namespace: &NamespaceId,
+    author_key: Option<(&AuthorId, &[u8])>,
+) -> Result<()> {
+    let bounds = match author_key {
+        Some((author, key)) => {
+            HistoryBounds::author_key(*namespace, *author, KeyFilter::Exact(key.to_vec().into()))
+        }
+        None => HistoryBounds::namespace(*namespace),
+    };
+    let entries = history_entries(history, &bounds)?;
+    let now = system_time_now();
+    // count the newer versions of the same author and key, starting from the newest
+    let mut newer = 0;
+    for (i, entry) in entries.iter().enumerate().rev() {
+        let same_as_next = entries.get(i + 1).is_some_and(|next| {
+            next.author_bytes() == entry.author_bytes() && next.key() == entry.key()
+        });
+        newer = if same_as_next { newer + 1 } else { 0 };
+        if !policy.retains(newer, entry.timestamp(), now) {
+            let author = entry.author_bytes();
+            let id = (
+                namespace.as_bytes(),
+                author.as_bytes(),
+                entry.key(),
+                entry.timestamp(),
+            );
+            history.remove(id)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_history(
+    history: &mut redb::Table<HistoryId<'static>, RecordsValue<'static>>,
+    namespace: &NamespaceId,
+) -> Result<()> {
+    let bounds = HistoryBounds::namespace(*namespace);
+    history.retain_in(bounds.as_ref(), |_k, _v| false)?;
+    Ok(())
+}
+
+/// Encode an entry as a value of the records table, and pass it to `f`.
+fn with_records_value<T>(e: &SignedEntry, f: impl FnOnce(RecordsValue) -> Result<T>) -> Result<T> {
+    let hash = e.content_hash(); // let binding is needed
+    let delegation = match e.delegation() {
+        Some(capability) => postcard::to_stdvec(capability)?,
+        None => Vec::new(),
+    };
+    let value = (
+        e.timestamp(),
+        &e.signature().namespace().to_bytes(),
+        &e.signature().author().to_bytes(),
+        e.content_len(),
+        hash.as_bytes(),
+        delegation.as_slice(),
+    );
+    f(value)
 }
 
 fn get_exact(
@@ -695,15 +1029,10 @@ impl<'a> crate::ranger::Store<SignedEntry> for StoreInstance<'a> {
                 &id.author().to_bytes(),
                 id.key(),
             );
-            let hash = e.content_hash(); // let binding is needed
-            let value = (
-                e.timestamp(),
-                &e.signature().namespace().to_bytes(),
-                &e.signature().author().to_bytes(),
-                e.content_len(),
-                hash.as_bytes(),
-            );
-            tables.records.insert(key, value)?;
+            with_records_value(&e, |value| {
+                tables.records.insert(key, value)?;
+                Ok(())
+            })?;
 
             // insert into by key index table
             let key = (
@@ -802,14 +1131,36 @@ impl<'a> crate::ranger::Store<SignedEntry> for StoreInstance<'a> {
         let bounds = RecordsBounds::author_prefix(id.namespace(), id.author(), id.key_bytes());
         self.store.as_mut().modify(|tables| {
             let cb = |_k: RecordsId, v: RecordsValue| {
-                let (timestamp, _namespace_sig, _author_sig, len, hash) = v;
+                let (timestamp, _namespace_sig, _author_sig, len, hash, _delegation) = v;
                 let record = Record::new(hash.into(), len, timestamp);
 
                 predicate(&record)
             };
-            let iter = tables.records.extract_from_if(bounds.as_ref(), cb)?;
-            let count = iter.count();
-            Ok(count)
+            let removed = tables
+                .records
+                .extract_from_if(bounds.as_ref(), cb)?
+                .map(|res| {
+                    let (key, value) = res?;
+                    Ok(into_entry(key.value(), value.value()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            // keep the removed entries in the history, if the document has one
+            if let Some(policy) = get_history_policy(&tables.history_policy, &id.namespace())? {
+                for entry in &removed {
+                    insert_history(&mut tables.history, entry)?;
+                }
+                let keys = removed
+                    .iter()
+                    .map(|entry| entry.key().to_vec())
+                    .collect::<BTreeSet<_>>();
+                let author = id.author();
+                for key in keys {
+                    let author_key = Some((&author, key.as_slice()));
+                    prune_history(&mut tables.history, &policy, &id.namespace(), author_key)?;
+                }
+            }
+            Ok(removed.len())
         })
     }
 }
@@ -878,21 +1229,40 @@ self_cell::self_cell!(
     }
 );
 
+type HistoryRange<'a> = redb::Range<'a, HistoryId<'static>, RecordsValue<'static>>;
+
+self_cell::self_cell!(
+    struct HistoryHashesIteratorInner {
+        owner: HistoryTable,
+        #[covariant]
+        dependent: HistoryRange,
+    }
+);
+
 /// Iterator for all content hashes
 ///
+/// This includes the content hashes of entries in the history of documents.
+///
 /// Note that you might get duplicate hashes. Also, the iterator will keep
 /// a database snapshot open until it is dropped.
 ///
 /// Also, this represents a snapshot of the database at the time of creation.
 /// It nees a copy of a redb::ReadOnlyTable to be self-contained.
 #[derive(derive_more::Debug)]
-pub struct ContentHashesIterator(#[debug(skip)] ContentHashesIteratorInner);
+pub struct ContentHashesIterator {
+    #[debug(skip)]
+    records: ContentHashesIteratorInner,
+    #[debug(skip)]
+    history: HistoryHashesIteratorInner,
+}
 
 impl ContentHashesIterator {
     /// Create a new iterator over all content hashes.
-    pub fn all(owner: RecordsTable) -> anyhow::Result<Self> {
-        let inner = ContentHashesIteratorInner::try_new(owner, |owner| RecordsRange::all(owner))?;
-        Ok(Self(inner))
+    pub fn all(records: RecordsTable, history: HistoryTable) -> anyhow::Result<Self> {
+        let records =
+            ContentHashesIteratorInner::try_new(records, |owner| RecordsRange::all(owner))?;
+        let history = HistoryHashesIteratorInner::try_new(history, |owner| owner.iter())?;
+        Ok(Self { records, history })
     }
 }
 
@@ -900,8 +1270,14 @@ impl Iterator for ContentHashesIterator {
     type Item = Result<Hash>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let v = self.0.with_dependent_mut(|_, d| d.next())?;
-        Some(v.map(|e| e.content_hash()))
+        if let Some(v) = self.records.with_dependent_mut(|_, d| d.next()) {
+            return Some(v.map(|e| e.content_hash()));
+        }
+        let v = self.history.with_dependent_mut(|_, d| d.next())?;
+        Some(v.map_err(Into::into).map(|(_id, value)| {
+            let (_timestamp, _namespace_sig, _author_sig, _len, hash, _delegation) = value.value();
+            Hash::from(hash)
+        }))
     }
 }
 
@@ -941,17 +1317,23 @@ impl<'a> Iterator for LatestIterator<'a> {
 
 fn into_entry(key: RecordsId, value: RecordsValue) -> SignedEntry {
     let (namespace, author, key) = key;
-    let (timestamp, namespace_sig, author_sig, len, hash) = value;
+    let (timestamp, namespace_sig, author_sig, len, hash, delegation) = value;
     let id = RecordIdentifier::new(namespace, author, key);
     let record = Record::new(hash.into(), len, timestamp);
     let entry = Entry::new(id, record);
     let entry_signature = EntrySignature::from_parts(namespace_sig, author_sig);
-    SignedEntry::new(entry_signature, entry)
+    // The delegation was encoded by us when storing the entry. If it cannot be decoded, the
+    // entry will fail to verify on other peers, but can still be read locally.
+    let delegation = match delegation {
+        [] => None,
+        bytes => postcard::from_bytes(bytes).ok(),
+    };
+    SignedEntry::new(entry_signature, entry, delegation)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::tables::LATEST_PER_AUTHOR_TABLE;
+    use super::tables::{LATEST_PER_AUTHOR_TABLE, RECORDS_TABLE, RECORDS_TABLE_V1};
 
     use crate::ranger::Store as _;
 
@@ -1119,6 +1501,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_migration_005_records_populate_v2() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let namespace = NamespaceSecret::new(&mut rand::thread_rng());
+
+        // create a store and add some data
+        let expected = {
+            let mut store = Store::persistent(dbfile.path())?;
+            let author = store.new_author(&mut rand::thread_rng())?;
+            let mut replica = store.new_replica(namespace.clone())?;
+            replica.hash_and_insert(b"k1", &author, b"v1")?;
+            replica.hash_and_insert(b"k2", &author, b"v2")?;
+
+            let expected = store
+                .get_many(namespace.id(), Query::all())?
+                .collect::<Result<Vec<_>>>()?;
+            store.close_replica(namespace.id());
+            store.flush()?;
+            drop(store);
+            expected
+        };
+        assert_eq!(expected.len(), 2);
+
+        // create a copy of our db file with the records moved back to the v1 table.
+        let dbfile_before_migration = copy_and_modify(dbfile.path(), |tx| {
+            {
+                let records = tx.open_table(RECORDS_TABLE)?;
+                let mut records_v1 = tx.open_table(RECORDS_TABLE_V1)?;
+                for next in records.iter()? {
+                    let next = next?;
+                    let (timestamp, namespace_sig, author_sig, len, hash, _delegation) =
+                        next.1.value();
+                    let value = (timestamp, namespace_sig, author_sig, len, hash);
+                    records_v1.insert(next.0.value(), value)?;
+                }
+            }
+            tx.delete_table(RECORDS_TABLE)?;
+            Ok(())
+        })?;
+
+        // open the copied db file, which will run the migration.
+        let mut store = Store::persistent(dbfile_before_migration.path())?;
+        let actual = store
+            .get_many(namespace.id(), Query::all())?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(expected, actual);
+
+        Ok(())
+    }
+
     #[test]
     fn test_migration_004_populate_by_key_index() -> Result<()> {
         let dbfile = tempfile::NamedTempFile::new()?;
@@ -4,7 +4,9 @@ use bytes::Bytes;
 
 use crate::{store::KeyFilter, AuthorId, NamespaceId};
 
-use super::tables::{RecordsByKeyId, RecordsByKeyIdOwned, RecordsId, RecordsIdOwned};
+use super::tables::{
+    HistoryId, HistoryIdOwned, RecordsByKeyId, RecordsByKeyIdOwned, RecordsId, RecordsIdOwned,
+};
 
 /// Bounds on the records table.
 ///
@@ -158,6 +160,71 @@ impl RangeBounds<RecordsByKeyIdOwned> for ByKeyBounds {
     }
 }
 
+/// Bounds on the history table.
+///
+/// Supports bounds by author, key
+pub struct HistoryBounds(Bound<HistoryIdOwned>, Bound<HistoryIdOwned>);
+
+impl HistoryBounds {
+    pub fn author_key(ns: NamespaceId, author: AuthorId, key_matcher: KeyFilter) -> Self {
+        let key_is_exact = matches!(key_matcher, KeyFilter::Exact(_));
+        let key = match key_matcher {
+            KeyFilter::Any => Bytes::new(),
+            KeyFilter::Exact(key) => key,
+            KeyFilter::Prefix(prefix) => prefix,
+        };
+        let author = author.to_bytes();
+        let ns = ns.to_bytes();
+        let mut author_end = author;
+        let mut ns_end = ns;
+        let mut key_end = key.to_vec();
+
+        let start = (ns, author, key.to_vec(), 0);
+
+        let end = if key_is_exact {
+            Bound::Included((ns, author, key.to_vec(), u64::MAX))
+        } else if increment_by_one(&mut key_end) {
+            Bound::Excluded((ns, author, key_end, 0))
+        } else if increment_by_one(&mut author_end) {
+            Bound::Excluded((ns, author_end, Vec::new(), 0))
+        } else if increment_by_one(&mut ns_end) {
+            Bound::Excluded((ns_end, [0u8; 32], Vec::new(), 0))
+        } else {
+            Bound::Unbounded
+        };
+
+        Self(Bound::Included(start), end)
+    }
+
+    pub fn namespace(ns: NamespaceId) -> Self {
+        let start = Bound::Included((ns.to_bytes(), [0u8; 32], Vec::new(), 0));
+        let mut ns_end = ns.to_bytes();
+        let end = if increment_by_one(&mut ns_end) {
+            Bound::Excluded((ns_end, [0u8; 32], Vec::new(), 0))
+        } else {
+            Bound::Unbounded
+        };
+        Self(start, end)
+    }
+
+    pub fn as_ref(&self) -> (Bound<HistoryId>, Bound<HistoryId>) {
+        fn map(id: &HistoryIdOwned) -> HistoryId {
+            (&id.0, &id.1, &id.2[..], id.3)
+        }
+        (map_bound(&self.0, map), map_bound(&self.1, map))
+    }
+}
+
+impl RangeBounds<HistoryIdOwned> for HistoryBounds {
+    fn start_bound(&self) -> Bound<&HistoryIdOwned> {
+        map_bound(&self.0, |s| s)
+    }
+
+    fn end_bound(&self) -> Bound<&HistoryIdOwned> {
+        map_bound(&self.1, |s| s)
+    }
+}
+
 /// Increment a byte string by one, by incrementing the last byte that is not 255 by one.
 ///
 /// Returns false if all bytes are 255.
@@ -235,6 +302,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn history_bounds() {
+        let ns = NamespaceId::from(&[255u8; 32]);
+
+        let bounds = HistoryBounds::namespace(ns);
+        assert_eq!(
+            bounds.start_bound(),
+            Bound::Included(&(ns.to_bytes(), [0u8; 32], vec![], 0))
+        );
+        assert_eq!(bounds.end_bound(), Bound::Unbounded);
+
+        let a = AuthorId::from(&[0u8; 32]);
+        let bounds = HistoryBounds::author_key(ns, a, KeyFilter::Prefix(vec![1u8].into()));
+        assert_eq!(
+            bounds.start_bound(),
+            Bound::Included(&(ns.to_bytes(), a.to_bytes(), vec![1u8], 0))
+        );
+        assert_eq!(
+            bounds.end_bound(),
+            Bound::Excluded(&(ns.to_bytes(), a.to_bytes(), vec![2u8], 0))
+        );
+
+        let bounds = HistoryBounds::author_key(ns, a, KeyFilter::Exact(vec![1u8].into()));
+        assert_eq!(
+            bounds.end_bound(),
+            Bound::Included(&(ns.to_bytes(), a.to_bytes(), vec![1u8], u64::MAX))
+        );
+    }
+
     #[test]
     fn by_key_bounds() {
         let ns = NamespaceId::from(&[255u8; 32]);
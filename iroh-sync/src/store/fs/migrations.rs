@@ -8,11 +8,13 @@ use crate::{Capability, NamespaceSecret};
 
 use super::tables::{
     LATEST_PER_AUTHOR_TABLE, NAMESPACES_TABLE, NAMESPACES_TABLE_V1, RECORDS_BY_KEY_TABLE,
-    RECORDS_TABLE,
+    RECORDS_TABLE, RECORDS_TABLE_V1,
 };
 
 /// Run all database migrations, if needed.
 pub fn run_migrations(db: &Database) -> Result<()> {
+    // This has to run first, because the other migrations operate on the current records table.
+    run_migration(db, migration_005_records_populate_v2)?;
     run_migration(db, migration_001_populate_latest_table)?;
     run_migration(db, migration_002_namespaces_populate_v2)?;
     run_migration(db, migration_003_namespaces_delete_v1)?;
@@ -58,7 +60,7 @@ fn migration_001_populate_latest_table(tx: &WriteTransaction) -> Result<MigrateO
     for next in iter {
         let next = next?;
         let (namespace, author, key) = next.0.value();
-        let (timestamp, _namespace_sig, _author_sig, _len, _hash) = next.1.value();
+        let (timestamp, _namespace_sig, _author_sig, _len, _hash, _delegation) = next.1.value();
         heads
             .entry((*namespace, *author))
             .and_modify(|e| {
@@ -137,3 +139,29 @@ fn migration_004_populate_by_key_index(tx: &WriteTransaction) -> Result<MigrateO
     }
     Ok(MigrateOutcome::Execute(len))
 }
+
+/// migration 005: copy the records from V1 to V2, which adds the delegation of each entry, and
+/// delete the V1 table.
+fn migration_005_records_populate_v2(tx: &WriteTransaction) -> Result<MigrateOutcome> {
+    let records_v1_exists = tx
+        .list_tables()?
+        .any(|handle| handle.name() == RECORDS_TABLE_V1.name());
+    if !records_v1_exists {
+        return Ok(MigrateOutcome::Skip);
+    }
+    let mut count = 0;
+    {
+        let records_v1 = tx.open_table(RECORDS_TABLE_V1)?;
+        let mut records_v2 = tx.open_table(RECORDS_TABLE)?;
+        for next in records_v1.iter()? {
+            let next = next?;
+            let (timestamp, namespace_sig, author_sig, len, hash) = next.1.value();
+            // all entries before v2 were signed with the namespace key
+            let value = (timestamp, namespace_sig, author_sig, len, hash, &[][..]);
+            records_v2.insert(next.0.value(), value)?;
+            count += 1;
+        }
+    }
+    tx.delete_table(RECORDS_TABLE_V1)?;
+    Ok(MigrateOutcome::Execute(count))
+}
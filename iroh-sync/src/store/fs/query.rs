@@ -1,17 +1,19 @@
 use anyhow::Result;
 use iroh_base::hash::Hash;
+use redb::ReadableTable;
 
 use crate::{
     store::{
         util::{IndexKind, LatestPerKeySelector, SelectorRes},
-        AuthorFilter, KeyFilter, Query,
+        AuthorFilter, KeyFilter, Query, QueryKind, SortBy, SortDirection, Versions,
     },
     AuthorId, NamespaceId, SignedEntry,
 };
 
 use super::{
-    bounds::{ByKeyBounds, RecordsBounds},
-    ranges::{RecordsByKeyRange, RecordsRange},
+    bounds::{ByKeyBounds, HistoryBounds, RecordsBounds},
+    into_entry,
+    ranges::{RecordsByKeyRange, RecordsRange, VersionsRange},
     tables::Tables,
     RecordsValue,
 };
@@ -36,10 +38,35 @@ enum QueryRange<'a> {
         author_filter: AuthorFilter,
         selector: Option<LatestPerKeySelector>,
     },
+    Versions(VersionsIterator<'a>),
+    SortedVersions(std::vec::IntoIter<SignedEntry>),
 }
 
 impl<'a> QueryIterator<'a> {
     pub fn new(tables: &'a Tables<'a>, namespace: NamespaceId, query: Query) -> Result<Self> {
+        if query.versions != Versions::Current {
+            // the tables are ordered by author and key, so other orders are sorted in memory
+            let in_table_order = match &query.kind {
+                QueryKind::Flat(details) => {
+                    matches!(details.sort_by, SortBy::AuthorKey)
+                        || matches!(query.filter_author, AuthorFilter::Exact(_))
+                }
+                QueryKind::SingleLatestPerKey(_) => false,
+            };
+            let range = if in_table_order {
+                let iter = VersionsIterator::new(tables, namespace, &query, query.sort_direction)?;
+                QueryRange::Versions(iter)
+            } else {
+                let entries = sort_versions(tables, namespace, &query)?;
+                QueryRange::SortedVersions(entries.into_iter())
+            };
+            return Ok(Self {
+                range,
+                query,
+                offset: 0,
+                count: 0,
+            });
+        }
         let index_kind = IndexKind::from(&query);
         let range = match index_kind {
             IndexKind::AuthorKey { range, key_filter } => {
@@ -143,6 +170,9 @@ impl<'a> Iterator for QueryIterator<'a> {
 
                     break next.map(Result::Ok);
                 },
+
+                QueryRange::Versions(iter) => iter.next(),
+                QueryRange::SortedVersions(entries) => entries.next().map(Result::Ok),
             };
 
             // skip the entry if we didn't get past the requested offset yet.
@@ -158,6 +188,206 @@ impl<'a> Iterator for QueryIterator<'a> {
 }
 
 fn value_is_empty(value: &RecordsValue) -> bool {
-    let (_timestamp, _namespace_sig, _author_sig, _len, hash) = value;
+    let (_timestamp, _namespace_sig, _author_sig, _len, hash, _delegation) = value;
     *hash == Hash::EMPTY.as_bytes()
 }
+
+/// An iterator over the versions of entries in the current records and the history of a
+/// namespace.
+///
+/// The records and the history are scanned in the order of author, key and timestamp, bounded
+/// by the author and key filter of the query.
+#[derive(derive_more::Debug)]
+struct VersionsIterator<'a> {
+    range: VersionsRange<'a>,
+    #[debug(skip)]
+    tables: &'a Tables<'a>,
+    namespace: NamespaceId,
+    key_filter: KeyFilter,
+    author_filter: AuthorFilter,
+    as_of: Option<u64>,
+    include_empty: bool,
+    pending: Option<SignedEntry>,
+}
+
+impl<'a> VersionsIterator<'a> {
+    fn new(
+        tables: &'a Tables<'a>,
+        namespace: NamespaceId,
+        query: &Query,
+        direction: SortDirection,
+    ) -> Result<Self> {
+        let (records_bounds, history_bounds) = match &query.filter_author {
+            AuthorFilter::Exact(author) => (
+                RecordsBounds::author_key(namespace, *author, query.filter_key.clone()),
+                HistoryBounds::author_key(namespace, *author, query.filter_key.clone()),
+            ),
+            AuthorFilter::Any => (
+                RecordsBounds::namespace(namespace),
+                HistoryBounds::namespace(namespace),
+            ),
+        };
+        let range = VersionsRange::with_bounds(
+            &tables.records,
+            &tables.history,
+            records_bounds,
+            history_bounds,
+            direction,
+        )?;
+        let as_of = match query.versions {
+            Versions::AsOf(timestamp) => Some(timestamp),
+            _ => None,
+        };
+        Ok(Self {
+            range,
+            tables,
+            namespace,
+            key_filter: query.filter_key.clone(),
+            author_filter: query.filter_author.clone(),
+            as_of,
+            include_empty: query.include_empty,
+            pending: None,
+        })
+    }
+
+    /// Get the next version that matches the filters of the query, including empty entries.
+    fn next_matching(&mut self) -> Option<Result<SignedEntry>> {
+        if let Some(entry) = self.pending.take() {
+            return Some(Ok(entry));
+        }
+        loop {
+            let entry = match self.range.next()? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            if !self.key_filter.matches(entry.key())
+                || !self.author_filter.matches(&entry.author_bytes())
+                || self.as_of.is_some_and(|as_of| entry.timestamp() > as_of)
+            {
+                continue;
+            }
+            return Some(Ok(entry));
+        }
+    }
+
+    /// Get the newest version of the next author and key.
+    fn next_newest(&mut self) -> Option<Result<SignedEntry>> {
+        let mut newest = match self.next_matching()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        loop {
+            match self.next_matching() {
+                None => break,
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(entry)) => {
+                    if entry.author_bytes() != newest.author_bytes() || entry.key() != newest.key()
+                    {
+                        self.pending = Some(entry);
+                        break;
+                    }
+                    if entry.entry().record() > newest.entry().record() {
+                        newest = entry;
+                    }
+                }
+            }
+        }
+        Some(Ok(newest))
+    }
+
+    /// Check if an entry was superseded at `as_of` by an entry of the same author at a prefix of
+    /// its key.
+    fn is_superseded(&self, entry: &SignedEntry, as_of: u64) -> Result<bool> {
+        let namespace = self.namespace.to_bytes();
+        let author = entry.author_bytes().to_bytes();
+        for len in 1..entry.key().len() {
+            let prefix = &entry.key()[..len];
+            let id = (&namespace, &author, prefix);
+            if let Some(value) = self.tables.records.get(id)? {
+                let current = into_entry(id, value.value());
+                if current.timestamp() <= as_of
+                    && current.entry().record() >= entry.entry().record()
+                {
+                    return Ok(true);
+                }
+            }
+            let start = (&namespace, &author, prefix, 0);
+            let end = (&namespace, &author, prefix, as_of);
+            if let Some(res) = self.tables.history.range(start..=end)?.next_back() {
+                let (_id, value) = res?;
+                let previous = into_entry(id, value.value());
+                if previous.entry().record() >= entry.entry().record() {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<'a> Iterator for VersionsIterator<'a> {
+    type Item = Result<SignedEntry>;
+
+    fn next(&mut self) -> Option<Result<SignedEntry>> {
+        loop {
+            let entry = match self.as_of {
+                None => self.next_matching()?,
+                Some(as_of) => match self.next_newest()? {
+                    Ok(entry) => match self.is_superseded(&entry, as_of) {
+                        Ok(true) => continue,
+                        Ok(false) => Ok(entry),
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err),
+                },
+            };
+            if !self.include_empty && matches!(&entry, Ok(e) if e.is_empty()) {
+                continue;
+            }
+            return Some(entry);
+        }
+    }
+}
+
+/// Select and sort the versions of entries in an order that is not the order of the tables.
+///
+/// The history has no index by key, so the sorting is done in memory.
+fn sort_versions(
+    tables: &Tables,
+    namespace: NamespaceId,
+    query: &Query,
+) -> Result<Vec<SignedEntry>> {
+    let mut entries = VersionsIterator::new(tables, namespace, query, SortDirection::Asc)?
+        .collect::<Result<Vec<_>>>()?;
+    let sort_by = match &query.kind {
+        QueryKind::Flat(details) => details.sort_by,
+        QueryKind::SingleLatestPerKey(_) => {
+            entries = latest_per_key(entries);
+            SortBy::KeyAuthor
+        }
+    };
+    entries.sort_by(|a, b| {
+        let by_author = a.author_bytes().cmp(&b.author_bytes());
+        let by_key = a.key().cmp(b.key());
+        match sort_by {
+            SortBy::AuthorKey => by_author.then(by_key),
+            SortBy::KeyAuthor => by_key.then(by_author),
+        }
+        .then_with(|| a.timestamp().cmp(&b.timestamp()))
+    });
+    if let SortDirection::Desc = query.sort_direction {
+        entries.reverse();
+    }
+    Ok(entries)
+}
+
+/// Keep only the latest entry for each key.
+fn latest_per_key(mut entries: Vec<SignedEntry>) -> Vec<SignedEntry> {
+    entries.sort_by(|a, b| {
+        a.key()
+            .cmp(b.key())
+            .then_with(|| b.timestamp().cmp(&a.timestamp()))
+    });
+    entries.dedup_by(|next, prev| next.key() == prev.key());
+    entries
+}
@@ -5,9 +5,9 @@ use redb::{Key, Range, ReadableTable, Table, Value};
 use crate::{store::SortDirection, SignedEntry};
 
 use super::{
-    bounds::{ByKeyBounds, RecordsBounds},
+    bounds::{ByKeyBounds, HistoryBounds, RecordsBounds},
     into_entry,
-    tables::{RecordsByKeyId, RecordsId, RecordsValue},
+    tables::{HistoryId, RecordsByKeyId, RecordsId, RecordsValue},
 };
 
 /// An extension trait for [`Range`] that provides methods for mapped retrieval.
@@ -153,3 +153,88 @@ impl<'a> RecordsByKeyRange<'a> {
         entry
     }
 }
+
+/// An iterator over the current records and the history of a namespace, merged in the order of
+/// author, key and timestamp.
+#[derive(derive_more::Debug)]
+#[debug("VersionsRange")]
+pub struct VersionsRange<'a> {
+    records: Range<'a, RecordsId<'static>, RecordsValue<'static>>,
+    history: Range<'a, HistoryId<'static>, RecordsValue<'static>>,
+    direction: SortDirection,
+    next_record: Option<SignedEntry>,
+    next_history: Option<SignedEntry>,
+}
+
+impl<'a> VersionsRange<'a> {
+    pub fn with_bounds(
+        records: &'a impl ReadableTable<RecordsId<'static>, RecordsValue<'static>>,
+        history: &'a impl ReadableTable<HistoryId<'static>, RecordsValue<'static>>,
+        records_bounds: RecordsBounds,
+        history_bounds: HistoryBounds,
+        direction: SortDirection,
+    ) -> anyhow::Result<Self> {
+        let records = records.range(records_bounds.as_ref())?;
+        let history = history.range(history_bounds.as_ref())?;
+        Ok(Self {
+            records,
+            history,
+            direction,
+            next_record: None,
+            next_history: None,
+        })
+    }
+
+    fn fill(&mut self) -> anyhow::Result<()> {
+        if self.next_record.is_none() {
+            self.next_record = self
+                .records
+                .next_filter_map(&self.direction, |k, v| Some(into_entry(k, v)))
+                .transpose()?;
+        }
+        if self.next_history.is_none() {
+            self.next_history = self
+                .history
+                .next_filter_map(&self.direction, |(ns, author, key, _ts), v| {
+                    Some(into_entry((ns, author, key), v))
+                })
+                .transpose()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for VersionsRange<'a> {
+    type Item = anyhow::Result<SignedEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill() {
+            return Some(Err(err));
+        }
+        let take_record = match (&self.next_record, &self.next_history) {
+            (None, None) => return None,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(record), Some(history)) => {
+                let ordering = version_order(record).cmp(&version_order(history));
+                match self.direction {
+                    SortDirection::Asc => ordering.is_le(),
+                    SortDirection::Desc => ordering.is_ge(),
+                }
+            }
+        };
+        let next = match take_record {
+            true => self.next_record.take(),
+            false => self.next_history.take(),
+        };
+        next.map(Ok)
+    }
+}
+
+fn version_order(entry: &SignedEntry) -> ([u8; 32], &[u8], u64) {
+    (
+        entry.author_bytes().to_bytes(),
+        entry.key(),
+        entry.timestamp(),
+    )
+}
@@ -28,16 +28,28 @@ pub const NAMESPACES_TABLE_V1: TableDefinition<&[u8; 32], &[u8; 32]> =
 pub const NAMESPACES_TABLE: TableDefinition<&[u8; 32], (u8, &[u8; 32])> =
     TableDefinition::new("namespaces-2");
 
-/// Table: Records
+/// Table: Records v1 (replaced by Records v2 in migration 005)
 /// Key:   `([u8; 32], [u8; 32], &[u8])`
 ///      # (NamespaceId, AuthorId, Key)
 /// Value: `(u64, [u8; 32], [u8; 32], u64, [u8; 32])`
 ///      # (timestamp, signature_namespace, signature_author, len, hash)
-pub const RECORDS_TABLE: TableDefinition<RecordsId, RecordsValue> =
+pub const RECORDS_TABLE_V1: TableDefinition<RecordsId, RecordsValueV1> =
     TableDefinition::new("records-1");
+pub type RecordsValueV1<'a> = (u64, &'a [u8; 64], &'a [u8; 64], u64, &'a [u8; 32]);
+
+/// Table: Records v2
+/// Key:   `([u8; 32], [u8; 32], &[u8])`
+///      # (NamespaceId, AuthorId, Key)
+/// Value: `(u64, [u8; 32], [u8; 32], u64, [u8; 32], &[u8])`
+///      # (timestamp, signature_namespace, signature_author, len, hash, delegation)
+///
+/// The delegation is the postcard encoded `WriteCapability` of entries created by a delegate,
+/// and empty otherwise.
+pub const RECORDS_TABLE: TableDefinition<RecordsId, RecordsValue> =
+    TableDefinition::new("records-2");
 pub type RecordsId<'a> = (&'a [u8; 32], &'a [u8; 32], &'a [u8]);
 pub type RecordsIdOwned = ([u8; 32], [u8; 32], Bytes);
-pub type RecordsValue<'a> = (u64, &'a [u8; 64], &'a [u8; 64], u64, &'a [u8; 32]);
+pub type RecordsValue<'a> = (u64, &'a [u8; 64], &'a [u8; 64], u64, &'a [u8; 32], &'a [u8]);
 pub type RecordsTable = ReadOnlyTable<RecordsId<'static>, RecordsValue<'static>>;
 
 /// Table: Latest per author
@@ -56,6 +68,19 @@ pub const RECORDS_BY_KEY_TABLE: TableDefinition<RecordsByKeyId, ()> =
 pub type RecordsByKeyId<'a> = (&'a [u8; 32], &'a [u8], &'a [u8; 32]);
 pub type RecordsByKeyIdOwned = ([u8; 32], Bytes, [u8; 32]);
 
+/// Table: History
+/// Key:   `([u8; 32], [u8; 32], &[u8], u64)`
+///      # (NamespaceId, AuthorId, Key, Timestamp)
+/// Value: `(u64, [u8; 32], [u8; 32], u64, [u8; 32], &[u8])`
+///      # (timestamp, signature_namespace, signature_author, len, hash, delegation)
+///
+/// Superseded entries of documents with a history policy, in the layout of the records table.
+pub const HISTORY_TABLE: TableDefinition<HistoryId, RecordsValue> =
+    TableDefinition::new("history-1");
+pub type HistoryId<'a> = (&'a [u8; 32], &'a [u8; 32], &'a [u8], u64);
+pub type HistoryIdOwned = ([u8; 32], [u8; 32], Vec<u8>, u64);
+pub type HistoryTable = ReadOnlyTable<HistoryId<'static>, RecordsValue<'static>>;
+
 /// Table: Peers per document.
 /// Key:   `[u8; 32]`        # NamespaceId
 /// Value: `(u64, [u8; 32])` # ([`Nanos`], &[`PeerIdBytes`]) representing the last time a peer was used.
@@ -67,6 +92,24 @@ pub const NAMESPACE_PEERS_TABLE: MultimapTableDefinition<&[u8; 32], (Nanos, &Pee
 // which should be more than enough
 pub type Nanos = u64;
 
+/// Table: Delegations
+/// Key:   `[u8; 32]`        # NamespaceId
+/// Value: `Vec<u8>`         # Postcard encoded `WriteCapability` of a delegated namespace
+pub const DELEGATIONS_TABLE: TableDefinition<&[u8; 32], &[u8]> =
+    TableDefinition::new("delegations-1");
+
+/// Table: Encryption keys
+/// Key:   `[u8; 32]`        # NamespaceId
+/// Value: `[u8; 32]`        # EncryptionKey of an encrypted namespace
+pub const ENCRYPTION_KEYS_TABLE: TableDefinition<&[u8; 32], &[u8; 32]> =
+    TableDefinition::new("encryption-keys-1");
+
+/// Table: History policy
+/// Key:   `[u8; 32]`        # NamespaceId
+/// Value: `Vec<u8>`         # Postcard encoded history policy
+pub const HISTORY_POLICY_TABLE: TableDefinition<&[u8; 32], &[u8]> =
+    TableDefinition::new("history-policy-1");
+
 /// Table: Download policy
 /// Key:   `[u8; 32]`        # NamespaceId
 /// Value: `Vec<u8>`         # Postcard encoded download policy
@@ -121,6 +164,10 @@ pub struct Tables<'tx> {
     pub namespace_peers: MultimapTable<'tx, &'static [u8; 32], (Nanos, &'static PeerIdBytes)>,
     pub download_policy: Table<'tx, &'static [u8; 32], &'static [u8]>,
     pub authors: Table<'tx, &'static [u8; 32], &'static [u8; 32]>,
+    pub delegations: Table<'tx, &'static [u8; 32], &'static [u8]>,
+    pub encryption_keys: Table<'tx, &'static [u8; 32], &'static [u8; 32]>,
+    pub history: Table<'tx, HistoryId<'static>, RecordsValue<'static>>,
+    pub history_policy: Table<'tx, &'static [u8; 32], &'static [u8]>,
 }
 
 impl<'tx> Tables<'tx> {
@@ -132,6 +179,10 @@ impl<'tx> Tables<'tx> {
         let namespace_peers = tx.open_multimap_table(NAMESPACE_PEERS_TABLE)?;
         let download_policy = tx.open_table(DOWNLOAD_POLICY_TABLE)?;
         let authors = tx.open_table(AUTHORS_TABLE)?;
+        let delegations = tx.open_table(DELEGATIONS_TABLE)?;
+        let encryption_keys = tx.open_table(ENCRYPTION_KEYS_TABLE)?;
+        let history = tx.open_table(HISTORY_TABLE)?;
+        let history_policy = tx.open_table(HISTORY_POLICY_TABLE)?;
         Ok(Self {
             records,
             records_by_key,
@@ -140,6 +191,10 @@ impl<'tx> Tables<'tx> {
             namespace_peers,
             download_policy,
             authors,
+            delegations,
+            encryption_keys,
+            history,
+            history_policy,
         })
     }
 }
@@ -154,6 +209,10 @@ pub struct ReadOnlyTables {
     pub namespace_peers: ReadOnlyMultimapTable<&'static [u8; 32], (Nanos, &'static PeerIdBytes)>,
     pub download_policy: ReadOnlyTable<&'static [u8; 32], &'static [u8]>,
     pub authors: ReadOnlyTable<&'static [u8; 32], &'static [u8; 32]>,
+    pub delegations: ReadOnlyTable<&'static [u8; 32], &'static [u8]>,
+    pub encryption_keys: ReadOnlyTable<&'static [u8; 32], &'static [u8; 32]>,
+    pub history: ReadOnlyTable<HistoryId<'static>, RecordsValue<'static>>,
+    pub history_policy: ReadOnlyTable<&'static [u8; 32], &'static [u8]>,
     tx: ReadTransaction,
 }
 
@@ -166,6 +225,10 @@ impl ReadOnlyTables {
         let namespace_peers = tx.open_multimap_table(NAMESPACE_PEERS_TABLE)?;
         let download_policy = tx.open_table(DOWNLOAD_POLICY_TABLE)?;
         let authors = tx.open_table(AUTHORS_TABLE)?;
+        let delegations = tx.open_table(DELEGATIONS_TABLE)?;
+        let encryption_keys = tx.open_table(ENCRYPTION_KEYS_TABLE)?;
+        let history = tx.open_table(HISTORY_TABLE)?;
+        let history_policy = tx.open_table(HISTORY_POLICY_TABLE)?;
         Ok(Self {
             records,
             records_by_key,
@@ -174,6 +237,10 @@ impl ReadOnlyTables {
             namespace_peers,
             download_policy,
             authors,
+            delegations,
+            encryption_keys,
+            history,
+            history_policy,
             tx,
         })
     }
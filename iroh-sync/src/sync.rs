@@ -20,13 +20,18 @@ use std::ops::{Deref, DerefMut};
 
 use ed25519_dalek::{Signature, SignatureError};
 use iroh_base::{base32, hash::Hash};
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 
 pub use crate::heads::AuthorHeads;
 #[cfg(feature = "metrics")]
 use crate::metrics::Metrics;
 use crate::{
-    keys::{Author, AuthorId, AuthorPublicKey, NamespaceId, NamespacePublicKey, NamespaceSecret},
+    delegation::{Delegate, DelegationError, WriteCapability, WriteRestrictions},
+    keys::{
+        Author, AuthorId, AuthorPublicKey, DelegatePublicKey, DelegateSecret, NamespaceId,
+        NamespacePublicKey, NamespaceSecret,
+    },
     ranger::{self, Fingerprint, InsertOutcome, RangeEntry, RangeKey, RangeValue, Store},
     store::{self, fs::StoreInstance, DownloadPolicyStore, PublicKeyStore},
 };
@@ -148,6 +153,8 @@ pub enum CapabilityKind {
     Write = 1,
     /// A readable replica.
     Read = 2,
+    /// A replica writable within the restrictions of a [`WriteCapability`].
+    Delegated = 3,
 }
 
 /// The capability of the namespace.
@@ -157,6 +164,8 @@ pub enum Capability {
     Write(NamespaceSecret),
     /// Read only access to the namespace.
     Read(NamespaceId),
+    /// Write access to the namespace, within the restrictions of a [`WriteCapability`].
+    Delegated(Delegate),
 }
 
 impl Capability {
@@ -165,37 +174,94 @@ impl Capability {
         match self {
             Capability::Write(secret) => secret.id(),
             Capability::Read(id) => *id,
+            Capability::Delegated(delegate) => delegate.id(),
         }
     }
 
     /// Get the [`NamespaceSecret`] of this [`Capability`].
-    /// Will fail if the [`Capability`] is read only.
+    /// Will fail if the [`Capability`] is read only or delegated.
     pub fn secret_key(&self) -> Result<&NamespaceSecret, ReadOnly> {
         match self {
             Capability::Write(secret) => Ok(secret),
+            Capability::Read(_) | Capability::Delegated(_) => Err(ReadOnly),
+        }
+    }
+
+    /// Sign an entry with this [`Capability`].
+    ///
+    /// Will fail if the [`Capability`] is read only.
+    pub fn sign_entry(&self, entry: Entry, author: &Author) -> Result<SignedEntry, ReadOnly> {
+        match self {
+            Capability::Write(secret) => Ok(entry.sign(secret, author)),
+            Capability::Delegated(delegate) => {
+                Ok(SignedEntry::from_entry_delegated(entry, delegate, author))
+            }
             Capability::Read(_) => Err(ReadOnly),
         }
     }
 
+    /// Issue a [`Delegate`] with `restrictions` for this namespace.
+    ///
+    /// With full write access, the new capability is signed by the namespace key. A delegated
+    /// capability can be passed on with restrictions that are within its own.
+    pub fn delegate<R: CryptoRngCore + ?Sized>(
+        &self,
+        restrictions: WriteRestrictions,
+        rng: &mut R,
+    ) -> Result<Delegate, CapabilityError> {
+        match self {
+            Capability::Write(secret) => Ok(Delegate::issue(secret, restrictions, rng)),
+            Capability::Delegated(delegate) => Ok(delegate.delegate(restrictions, rng)?),
+            Capability::Read(_) => Err(CapabilityError::ReadOnly),
+        }
+    }
+
     /// Get the kind of capability.
     pub fn kind(&self) -> CapabilityKind {
         match self {
             Capability::Write(_) => CapabilityKind::Write,
             Capability::Read(_) => CapabilityKind::Read,
+            Capability::Delegated(_) => CapabilityKind::Delegated,
         }
     }
 
     /// Get the raw representation of this namespace capability.
+    ///
+    /// For a delegated capability, this only contains the [`DelegateSecret`]. The
+    /// [`WriteCapability`] has to be stored separately.
     pub fn raw(&self) -> (u8, [u8; 32]) {
         let capability_repr: u8 = self.kind().into();
         let bytes = match self {
             Capability::Write(secret) => secret.to_bytes(),
             Capability::Read(id) => id.to_bytes(),
+            Capability::Delegated(delegate) => delegate.secret().to_bytes(),
         };
         (capability_repr, bytes)
     }
 
     /// Create a [`Capability`] from its raw representation.
+    ///
+    /// For a delegated capability, the [`WriteCapability`] has to be passed as well.
+    pub fn from_raw_delegated(
+        kind: u8,
+        bytes: &[u8; 32],
+        capability: Option<WriteCapability>,
+    ) -> anyhow::Result<Self> {
+        match (CapabilityKind::try_from(kind)?, capability) {
+            (CapabilityKind::Delegated, Some(capability)) => {
+                let secret = DelegateSecret::from_bytes(bytes);
+                Ok(Capability::Delegated(Delegate::new(capability, secret)?))
+            }
+            (CapabilityKind::Delegated, None) => {
+                Err(anyhow::anyhow!("missing write capability of delegate"))
+            }
+            _ => Self::from_raw(kind, bytes),
+        }
+    }
+
+    /// Create a [`Capability`] from its raw representation.
+    ///
+    /// Fails for delegated capabilities, use [`Self::from_raw_delegated`] for those.
     pub fn from_raw(kind: u8, bytes: &[u8; 32]) -> anyhow::Result<Self> {
         let kind: CapabilityKind = kind.try_into()?;
         let capability = match kind {
@@ -207,13 +273,17 @@ impl Capability {
                 let id = NamespaceId::from(bytes);
                 Capability::Read(id)
             }
+            CapabilityKind::Delegated => {
+                return Err(anyhow::anyhow!("missing write capability of delegate"))
+            }
         };
         Ok(capability)
     }
 
     /// Merge this capability with another capability.
     ///
-    /// Will return an error if `other` is not a capability for the same namespace.
+    /// Will return an error if `other` is not a capability for the same namespace, or if it is
+    /// a delegated capability with an invalid delegation chain.
     ///
     /// Returns `true` if the capability was changed, `false` otherwise.
     pub fn merge(&mut self, other: Capability) -> Result<bool, CapabilityError> {
@@ -221,13 +291,28 @@ impl Capability {
             return Err(CapabilityError::NamespaceMismatch);
         }
 
-        // the only capability upgrade is from read-only (self) to writable (other)
-        if matches!(self, Capability::Read(_)) && matches!(other, Capability::Write(_)) {
+        // a delegated capability may come from a peer, so its chain has to be valid before we
+        // start signing entries with it
+        if let Capability::Delegated(theirs) = &other {
+            theirs.capability().verify(&())?;
+        }
+
+        // capabilities are upgraded from read-only to delegated to writable, and from a
+        // delegated capability to one with wider restrictions
+        let upgrade = match (&*self, &other) {
+            (Capability::Read(_), Capability::Write(_) | Capability::Delegated(_)) => true,
+            (Capability::Delegated(_), Capability::Write(_)) => true,
+            (Capability::Delegated(ours), Capability::Delegated(theirs)) => {
+                let ours = ours.capability().restrictions();
+                let theirs = theirs.capability().restrictions();
+                ours != theirs && ours.is_within(theirs)
+            }
+            _ => false,
+        };
+        if upgrade {
             let _ = std::mem::replace(self, other);
-            Ok(true)
-        } else {
-            Ok(false)
         }
+        Ok(upgrade)
     }
 }
 
@@ -237,6 +322,12 @@ pub enum CapabilityError {
     /// Namespaces are not the same
     #[error("Namespaces are not the same")]
     NamespaceMismatch,
+    /// Read-only capabilities cannot be delegated
+    #[error("Read-only capabilities cannot be delegated")]
+    ReadOnly,
+    /// Delegation failed
+    #[error("Delegation failed: {0}")]
+    Delegation(#[from] DelegationError),
 }
 
 /// In memory information about an open replica.
@@ -362,8 +453,7 @@ where
         let id = RecordIdentifier::new(self.id(), author.id(), key);
         let record = Record::new_current(hash, len);
         let entry = Entry::new(id, record);
-        let secret = self.secret_key()?;
-        let signed_entry = entry.sign(secret, author);
+        let signed_entry = self.info.capability.sign_entry(entry, author)?;
         self.insert_entry(signed_entry, InsertOrigin::Local)
     }
 
@@ -381,14 +471,15 @@ where
         self.info.ensure_open()?;
         let id = RecordIdentifier::new(self.id(), author.id(), prefix);
         let entry = Entry::new_empty(id);
-        let signed_entry = entry.sign(self.secret_key()?, author);
+        let signed_entry = self.info.capability.sign_entry(entry, author)?;
         self.insert_entry(signed_entry, InsertOrigin::Local)
     }
 
     /// Insert an entry into this replica which was received from a remote peer.
     ///
     /// This will verify both the namespace and author signatures of the entry, emit an `on_insert`
-    /// event, and insert the entry into the replica store.
+    /// event, and insert the entry into the replica store. Entries signed by a delegate are only
+    /// accepted if their [`WriteCapability`] is valid and allows the entry.
     ///
     /// Returns the number of entries removed as a consequence of this insertion,
     /// or an error if the entry failed to validate or if a store operation failed.
@@ -594,6 +685,8 @@ pub struct ReadOnly;
 ///
 /// This validates that
 /// * the entry's author and namespace signatures are correct
+/// * the entry's delegated capability, if any, is valid and allows the entry, and for local
+///   entries has not expired by our system time
 /// * the entry's namespace matches the current replica
 /// * the entry's timestamp is not more than 10 minutes in the future of our system time
 /// * the entry is newer than an existing entry for the same key and author, if such exists.
@@ -603,15 +696,46 @@ fn validate_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
     expected_namespace: NamespaceId,
     entry: &SignedEntry,
     origin: &InsertOrigin,
+) -> Result<(), ValidationFailure> {
+    let is_local = matches!(origin, InsertOrigin::Local);
+    validate_entry_inner(now, store, expected_namespace, entry, is_local)
+}
+
+/// Validate an entry that is imported from outside of sync, e.g. from a snapshot.
+///
+/// The entry is checked like an entry received from a remote peer.
+pub(crate) fn validate_imported_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
+    now: u64,
+    store: &S,
+    expected_namespace: NamespaceId,
+    entry: &SignedEntry,
+) -> Result<(), ValidationFailure> {
+    entry.validate_empty()?;
+    validate_entry_inner(now, store, expected_namespace, entry, false)
+}
+
+fn validate_entry_inner<S: ranger::Store<SignedEntry> + PublicKeyStore>(
+    now: u64,
+    store: &S,
+    expected_namespace: NamespaceId,
+    entry: &SignedEntry,
+    is_local: bool,
 ) -> Result<(), ValidationFailure> {
     // Verify the namespace
     if entry.namespace() != expected_namespace {
         return Err(ValidationFailure::InvalidNamespace);
     }
 
-    // Verify signature for non-local entries.
-    if !matches!(origin, InsertOrigin::Local) && entry.verify(store).is_err() {
-        return Err(ValidationFailure::BadSignature);
+    // Verify signature and capability for non-local entries.
+    if !is_local {
+        entry.verify(store)?;
+    } else if let Some(capability) = entry.delegation() {
+        // Local entries were signed by us, but still need to be within our restrictions.
+        capability.authorize(entry.entry())?;
+        // We choose the timestamp of local entries, so a delegate must not create entries
+        // after its expiry by our own clock. Entries from other peers are not checked against
+        // our clock, because all replicas have to agree on the entries to converge.
+        capability.check_expiry(now)?;
     }
 
     // Verify that the timestamp of the entry is not too far in the future.
@@ -660,13 +784,20 @@ pub enum ValidationFailure {
     /// Entry has length 0 but not the empty hash, or the empty hash but not length 0.
     #[error("Entry has length 0 but not the empty hash, or the empty hash but not length 0")]
     InvalidEmptyEntry,
+    /// Entry is not allowed by its delegated capability.
+    #[error("Entry is not allowed by its delegated capability: {0}")]
+    Unauthorized(#[from] DelegationError),
 }
 
 /// A signed entry.
+///
+/// Entries created by a delegate carry the [`WriteCapability`] of the delegate, whose key
+/// takes the place of the namespace key in the [`EntrySignature`].
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SignedEntry {
     signature: EntrySignature,
     entry: Entry,
+    delegation: Option<Box<WriteCapability>>,
 }
 
 impl From<SignedEntry> for Entry {
@@ -702,14 +833,36 @@ impl Ord for Entry {
 }
 
 impl SignedEntry {
-    pub(crate) fn new(signature: EntrySignature, entry: Entry) -> Self {
-        SignedEntry { signature, entry }
+    pub(crate) fn new(
+        signature: EntrySignature,
+        entry: Entry,
+        delegation: Option<WriteCapability>,
+    ) -> Self {
+        SignedEntry {
+            signature,
+            entry,
+            delegation: delegation.map(Box::new),
+        }
     }
 
     /// Create a new signed entry by signing an entry with the `namespace` and `author`.
     pub fn from_entry(entry: Entry, namespace: &NamespaceSecret, author: &Author) -> Self {
         let signature = EntrySignature::from_entry(&entry, namespace, author);
-        SignedEntry { signature, entry }
+        SignedEntry {
+            signature,
+            entry,
+            delegation: None,
+        }
+    }
+
+    /// Create a new signed entry by signing an entry with the `delegate` and `author`.
+    pub fn from_entry_delegated(entry: Entry, delegate: &Delegate, author: &Author) -> Self {
+        let signature = EntrySignature::from_entry_delegated(&entry, delegate.secret(), author);
+        SignedEntry {
+            signature,
+            entry,
+            delegation: Some(Box::new(delegate.capability().clone())),
+        }
     }
 
     /// Create a new signed entries from its parts.
@@ -725,12 +878,37 @@ impl SignedEntry {
     }
 
     /// Verify the signatures on this entry.
-    pub fn verify<S: store::PublicKeyStore>(&self, store: &S) -> Result<(), SignatureError> {
-        self.signature.verify(
-            &self.entry,
-            &self.entry.namespace().public_key(store)?,
-            &self.entry.author().public_key(store)?,
-        )
+    ///
+    /// For entries created by a delegate, this also verifies the delegation chain and that the
+    /// restrictions of the [`WriteCapability`] allow this entry.
+    pub fn verify<S: store::PublicKeyStore>(&self, store: &S) -> Result<(), ValidationFailure> {
+        let author = self
+            .entry
+            .author()
+            .public_key(store)
+            .map_err(|_| ValidationFailure::BadSignature)?;
+        let res = match &self.delegation {
+            None => {
+                let namespace = self
+                    .entry
+                    .namespace()
+                    .public_key(store)
+                    .map_err(|_| ValidationFailure::BadSignature)?;
+                self.signature.verify(&self.entry, &namespace, &author)
+            }
+            Some(capability) => {
+                capability.verify(store)?;
+                capability.authorize(&self.entry)?;
+                self.signature
+                    .verify_delegated(&self.entry, &capability.holder(), &author)
+            }
+        };
+        res.map_err(|_| ValidationFailure::BadSignature)
+    }
+
+    /// Get the [`WriteCapability`] of the delegate that created this entry, if any.
+    pub fn delegation(&self) -> Option<&WriteCapability> {
+        self.delegation.as_deref()
     }
 
     /// Get the signature.
@@ -834,6 +1012,18 @@ impl EntrySignature {
         }
     }
 
+    /// Create a new signature by signing an entry with a `delegate` and `author`.
+    pub fn from_entry_delegated(entry: &Entry, delegate: &DelegateSecret, author: &Author) -> Self {
+        let bytes = entry.to_vec();
+        let namespace_signature = delegate.sign(&bytes);
+        let author_signature = author.sign(&bytes);
+
+        EntrySignature {
+            author_signature,
+            namespace_signature,
+        }
+    }
+
     /// Verify that this signature was created by signing the `entry` with the
     /// secret keys of the specified `author` and `namespace`.
     pub fn verify(
@@ -849,6 +1039,21 @@ impl EntrySignature {
         Ok(())
     }
 
+    /// Verify that this signature was created by signing the `entry` with the
+    /// secret keys of the specified `author` and `delegate`.
+    pub fn verify_delegated(
+        &self,
+        entry: &Entry,
+        delegate: &DelegatePublicKey,
+        author: &AuthorPublicKey,
+    ) -> Result<(), SignatureError> {
+        let bytes = entry.to_vec();
+        delegate.verify(&bytes, &self.namespace_signature)?;
+        author.verify(&bytes, &self.author_signature)?;
+
+        Ok(())
+    }
+
     pub(crate) fn from_parts(namespace_sig: &[u8; 64], author_sig: &[u8; 64]) -> Self {
         let namespace_signature = Signature::from_bytes(namespace_sig);
         let author_signature = Signature::from_bytes(author_sig);
@@ -978,7 +1183,7 @@ impl RangeKey for RecordIdentifier {
     }
 }
 
-fn system_time_now() -> u64 {
+pub(crate) fn system_time_now() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .expect("time drift")
@@ -1182,7 +1387,7 @@ mod tests {
     use crate::{
         actor::SyncHandle,
         ranger::{Range, Store as _},
-        store::{OpenError, Query, SortBy, SortDirection, Store},
+        store::{HistoryPolicy, OpenError, Query, SortBy, SortDirection, Store},
     };
 
     use super::*;
@@ -2102,6 +2307,402 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_replica_delegated_memory() -> Result<()> {
+        let alice_store = store::Store::memory();
+        let bob_store = store::Store::memory();
+        test_replica_delegated(alice_store, bob_store)
+    }
+
+    #[test]
+    fn test_replica_delegated_fs() -> Result<()> {
+        let alice_dbfile = tempfile::NamedTempFile::new()?;
+        let alice_store = store::fs::Store::persistent(alice_dbfile.path())?;
+        let bob_dbfile = tempfile::NamedTempFile::new()?;
+        let bob_store = store::fs::Store::persistent(bob_dbfile.path())?;
+        test_replica_delegated(alice_store, bob_store)
+    }
+
+    fn test_replica_delegated(mut alice_store: Store, mut bob_store: Store) -> Result<()> {
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(1);
+        let alice_author = Author::new(&mut rng);
+        let bob_author = Author::new(&mut rng);
+        let namespace = NamespaceSecret::new(&mut rng);
+        let mut alice = alice_store.new_replica(namespace.clone())?;
+        alice.hash_and_insert(b"/readme", &alice_author, b"hi")?;
+
+        // bob may only write below /uploads/, and only as bob_author
+        let restrictions = WriteRestrictions::default()
+            .with_prefix(&b"/uploads/"[..])
+            .with_authors([bob_author.id()]);
+        let delegate = alice.capability().delegate(restrictions, &mut rng)?;
+        bob_store.import_namespace(Capability::Delegated(delegate.clone()))?;
+        let mut bob = bob_store.open_replica(&namespace.id())?;
+        bob.hash_and_insert(b"/uploads/a", &bob_author, b"a")?;
+        let res = bob.hash_and_insert(b"/readme", &bob_author, b"b");
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Unauthorized(
+                DelegationError::KeyOutsidePrefix
+            )))
+        ));
+        let res = bob.hash_and_insert(b"/uploads/b", &alice_author, b"b");
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Unauthorized(
+                DelegationError::AuthorNotAllowed
+            )))
+        ));
+
+        // the delegated capability survives reopening the replica
+        bob_store.close_replica(namespace.id());
+        let mut bob = bob_store.open_replica(&namespace.id())?;
+        assert!(matches!(bob.capability(), Capability::Delegated(_)));
+        bob.hash_and_insert(b"/uploads/c", &bob_author, b"c")?;
+
+        // a delegate cannot create capabilities wider than its own
+        let res = bob
+            .capability()
+            .delegate(WriteRestrictions::default(), &mut rng);
+        assert!(matches!(
+            res,
+            Err(CapabilityError::Delegation(DelegationError::Widened))
+        ));
+
+        let (_alice_out, _bob_out) = sync(&mut alice, &mut bob)?;
+        check_entries(
+            &mut alice_store,
+            &namespace.id(),
+            &bob_author,
+            &["/uploads/a", "/uploads/c"],
+        )?;
+        check_entries(&mut bob_store, &namespace.id(), &alice_author, &["/readme"])?;
+        let entry = alice_store
+            .get_exact(namespace.id(), bob_author.id(), b"/uploads/a", false)?
+            .unwrap();
+        assert_eq!(entry.delegation(), Some(delegate.capability()));
+        entry.verify(&())?;
+
+        // entries outside of the restrictions are rejected by other peers
+        let mut alice = alice_store.open_replica(&namespace.id())?;
+        let id = RecordIdentifier::new(namespace.id(), bob_author.id(), b"/readme");
+        let entry = Entry::new(id, Record::new_current(Hash::new(b"evil"), 4));
+        let entry = SignedEntry::from_entry_delegated(entry, &delegate, &bob_author);
+        let res = alice.insert_remote_entry(entry, [0u8; 32], ContentStatus::Complete);
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Unauthorized(
+                DelegationError::KeyOutsidePrefix
+            )))
+        ));
+
+        // entries with a timestamp after the expiry are rejected by other peers
+        let expires_at = system_time_now() - 1_000_000;
+        let expired = alice.capability().delegate(
+            WriteRestrictions::default().with_expiry(expires_at),
+            &mut rng,
+        )?;
+        let id = RecordIdentifier::new(namespace.id(), bob_author.id(), b"/late");
+        let entry = Entry::new(id, Record::new(Hash::new(b"late"), 4, expires_at + 1));
+        let entry = SignedEntry::from_entry_delegated(entry, &expired, &bob_author);
+        let res = alice.insert_remote_entry(entry, [0u8; 32], ContentStatus::Complete);
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Unauthorized(
+                DelegationError::Expired
+            )))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_delegation_expired_sync() -> Result<()> {
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(1);
+        let mut alice_store = store::Store::memory();
+        let mut bob_store = store::Store::memory();
+        let mut carol_store = store::Store::memory();
+        let bob_author = Author::new(&mut rng);
+        let namespace = NamespaceSecret::new(&mut rng);
+        let mut alice = alice_store.new_replica(namespace.clone())?;
+
+        // bob writes an entry shortly before his capability expires
+        let expires_at = system_time_now() + 200_000;
+        let delegate = alice.capability().delegate(
+            WriteRestrictions::default().with_expiry(expires_at),
+            &mut rng,
+        )?;
+        bob_store.import_namespace(Capability::Delegated(delegate))?;
+        let mut bob = bob_store.open_replica(&namespace.id())?;
+        bob.hash_and_insert(b"/a", &bob_author, b"a")?;
+        let (alice_out, _bob_out) = sync(&mut alice, &mut bob)?;
+        assert_eq!(alice_out.num_recv, 1);
+
+        // after the expiry, bob cannot write anymore
+        std::thread::sleep(Duration::from_micros(
+            expires_at + 1_000 - system_time_now(),
+        ));
+        let res = bob.hash_and_insert(b"/b", &bob_author, b"b");
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Unauthorized(
+                DelegationError::Expired
+            )))
+        ));
+
+        // but a new peer still accepts the entry written before the expiry
+        let mut carol = carol_store.new_replica(namespace.clone())?;
+        let (_alice_out, carol_out) = sync(&mut alice, &mut carol)?;
+        assert_eq!(carol_out.num_recv, 1);
+        let (_bob_out, carol_out) = sync(&mut bob, &mut carol)?;
+        assert_eq!(carol_out.num_recv, 0);
+        assert!(carol_store
+            .get_exact(namespace.id(), bob_author.id(), b"/a", false)?
+            .is_some());
+
+        // and so does a snapshot import
+        alice_store.close_replica(namespace.id());
+        let snapshot = alice_store.export_snapshot(&namespace.id(), None)?;
+        let mut other = store::Store::memory();
+        assert_eq!(other.import_snapshot(&snapshot)?, 1);
+        assert!(other
+            .get_exact(namespace.id(), bob_author.id(), b"/a", false)?
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_rotate_memory() -> Result<()> {
+        let store = store::Store::memory();
+        test_replica_rotate(store)
+    }
+
+    #[test]
+    fn test_replica_rotate_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::persistent(dbfile.path())?;
+        test_replica_rotate(store)
+    }
+
+    fn test_replica_rotate(mut store: Store) -> Result<()> {
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(1);
+        let alice = store.new_author(&mut rng)?;
+        let bob = Author::new(&mut rng);
+        let namespace = NamespaceSecret::new(&mut rng);
+        let mut replica = store.new_replica(namespace.clone())?;
+        replica.hash_and_insert(b"/a", &alice, b"a")?;
+        replica.hash_and_insert(b"/b", &alice, b"b")?;
+        replica.hash_and_insert(b"/c", &bob, b"c")?;
+        replica.delete_prefix(b"/b", &alice)?;
+        let timestamp = store
+            .get_exact(namespace.id(), alice.id(), b"/a", false)?
+            .unwrap()
+            .timestamp();
+
+        let outcome = store.rotate_namespace(&namespace, &mut rng)?;
+        assert_eq!(outcome.carried, 1);
+        assert_eq!(outcome.skipped, 1);
+        outcome.rotation.verify(&())?;
+        assert_eq!(outcome.rotation.namespace(), namespace.id());
+        assert!(outcome.rotation.is_successor(&outcome.namespace));
+
+        // bob's key is not in the store, so his entry is not carried forward
+        check_entries(&mut store, &outcome.namespace, &alice, &["/a"])?;
+        let entry = store
+            .get_exact(outcome.namespace, alice.id(), b"/a", false)?
+            .unwrap();
+        assert_eq!(entry.timestamp(), timestamp);
+        entry.verify(&())?;
+        let entries = store
+            .get_many(outcome.namespace, Query::all())?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 1);
+
+        // the old namespace is left unchanged
+        let entries = store
+            .get_many(namespace.id(), Query::all())?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 2);
+
+        // until it is made read-only
+        store.close_replica(namespace.id());
+        store.make_read_only(&namespace.id())?;
+        let mut replica = store.open_replica(&namespace.id())?;
+        assert!(matches!(replica.capability(), Capability::Read(_)));
+        assert!(matches!(
+            replica.hash_and_insert(b"/d", &alice, b"d"),
+            Err(InsertError::ReadOnly)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_history_memory() -> Result<()> {
+        let store = store::Store::memory();
+        test_replica_history(store)
+    }
+
+    #[test]
+    fn test_replica_history_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::persistent(dbfile.path())?;
+        test_replica_history(store)
+    }
+
+    fn test_replica_history(mut store: Store) -> Result<()> {
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(1);
+        let author = store.new_author(&mut rng)?;
+        let namespace = NamespaceSecret::new(&mut rng);
+        let namespace_id = namespace.id();
+        store.new_replica(namespace.clone())?;
+        let policy = HistoryPolicy {
+            max_versions: Some(2),
+            max_age: None,
+        };
+        store.set_history_policy(&namespace_id, Some(policy))?;
+        assert_eq!(store.get_history_policy(&namespace_id)?, Some(policy));
+
+        // insert with explicit timestamps, an empty value is a deletion
+        let t0 = system_time_now() - 1_000_000;
+        let insert = |store: &mut Store, key: &str, value: &str, offset: u64| -> Result<()> {
+            let mut replica = store.open_replica(&namespace_id)?;
+            let record = Record::new(Hash::new(value), value.len() as u64, t0 + offset);
+            let entry = SignedEntry::from_parts(&namespace, &author, key, record);
+            replica.insert_remote_entry(entry, [0u8; 32], ContentStatus::Complete)?;
+            Ok(())
+        };
+        insert(&mut store, "/a", "v1", 1)?;
+        insert(&mut store, "/b/c", "x1", 1)?;
+        insert(&mut store, "/a", "v2", 2)?;
+        insert(&mut store, "/a", "v3", 3)?;
+        insert(&mut store, "/a", "v4", 4)?;
+        insert(&mut store, "/b", "", 5)?;
+
+        let get = |store: &mut Store, query: Query| -> Result<Vec<(Vec<u8>, Hash)>> {
+            store
+                .get_many(namespace_id, query)?
+                .map(|e| e.map(|e| (e.key().to_vec(), e.content_hash())))
+                .collect()
+        };
+
+        // the oldest version of /a was pruned
+        let versions = get(&mut store, Query::key_versions("/a").build())?;
+        let expected = ["v2", "v3", "v4"]
+            .map(|v| (b"/a".to_vec(), Hash::new(v)))
+            .to_vec();
+        assert_eq!(versions, expected);
+
+        // the current state does not contain superseded entries
+        let current = get(&mut store, Query::all().build())?;
+        assert_eq!(current, vec![(b"/a".to_vec(), Hash::new("v4"))]);
+
+        // past states, including entries removed by a prefix deletion
+        let as_of = get(&mut store, Query::all().as_of(t0 + 3).build())?;
+        let expected = vec![
+            (b"/a".to_vec(), Hash::new("v3")),
+            (b"/b/c".to_vec(), Hash::new("x1")),
+        ];
+        assert_eq!(as_of, expected);
+        let as_of = get(
+            &mut store,
+            Query::single_latest_per_key().as_of(t0 + 3).build(),
+        )?;
+        assert_eq!(as_of, expected);
+        let as_of = get(&mut store, Query::all().as_of(t0 + 1).build())?;
+        assert_eq!(as_of, vec![(b"/b/c".to_vec(), Hash::new("x1"))]);
+        let as_of = get(&mut store, Query::all().as_of(t0 + 5).build())?;
+        assert_eq!(as_of, vec![(b"/a".to_vec(), Hash::new("v4"))]);
+
+        // the content of the history is kept alive
+        let hashes = store.content_hashes()?.collect::<Result<HashSet<Hash>>>()?;
+        for value in ["v2", "v3", "x1"] {
+            assert!(hashes.contains(&Hash::new(value)));
+        }
+        assert!(!hashes.contains(&Hash::new("v1")));
+
+        // removing the policy removes the history
+        store.set_history_policy(&namespace_id, None)?;
+        assert_eq!(store.get_history_policy(&namespace_id)?, None);
+        let versions = get(&mut store, Query::key_versions("/a").build())?;
+        assert_eq!(versions, vec![(b"/a".to_vec(), Hash::new("v4"))]);
+        let hashes = store.content_hashes()?.collect::<Result<HashSet<Hash>>>()?;
+        assert!(!hashes.contains(&Hash::new("v3")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_snapshot_memory() -> Result<()> {
+        let store = store::Store::memory();
+        test_replica_snapshot(store)
+    }
+
+    #[test]
+    fn test_replica_snapshot_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::persistent(dbfile.path())?;
+        test_replica_snapshot(store)
+    }
+
+    fn test_replica_snapshot(mut store: Store) -> Result<()> {
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(1);
+        let author = store.new_author(&mut rng)?;
+        let namespace = NamespaceSecret::new(&mut rng);
+        let mut replica = store.new_replica(namespace.clone())?;
+        replica.hash_and_insert(b"/a", &author, b"a")?;
+        replica.hash_and_insert(b"/b/c", &author, b"c")?;
+        let policy = HistoryPolicy {
+            max_versions: None,
+            max_age: None,
+        };
+        store.set_history_policy(&namespace.id(), Some(policy))?;
+        let before_delete = store
+            .get_exact(namespace.id(), author.id(), b"/b/c", false)?
+            .unwrap()
+            .timestamp();
+        let mut replica = store.open_replica(&namespace.id())?;
+        replica.delete_prefix(b"/b", &author)?;
+        store.close_replica(namespace.id());
+
+        // the current state includes the deletion
+        let snapshot = store.export_snapshot(&namespace.id(), None)?;
+        snapshot.verify(&store)?;
+        let keys = snapshot
+            .entries()
+            .iter()
+            .map(|e| e.key())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![b"/a".as_slice(), b"/b".as_slice()]);
+        assert_eq!(
+            store.export_snapshot(&namespace.id(), None)?.hash(),
+            snapshot.hash()
+        );
+
+        // a past state from the history
+        let past = store.export_snapshot(&namespace.id(), Some(before_delete))?;
+        let keys = past.entries().iter().map(|e| e.key()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![b"/a".as_slice(), b"/b/c".as_slice()]);
+
+        // import into a fresh store
+        let mut other = store::Store::memory();
+        assert_eq!(other.import_snapshot(&snapshot)?, 2);
+        check_entries(&mut other, &namespace.id(), &author, &["/a"])?;
+        let replica = other.open_replica(&namespace.id())?;
+        assert!(matches!(replica.capability().kind(), CapabilityKind::Read));
+        other.close_replica(namespace.id());
+        assert_eq!(other.export_snapshot(&namespace.id(), None)?, snapshot);
+        assert_eq!(other.import_snapshot(&snapshot)?, 0);
+
+        // entries that sync would reject are not imported
+        let future = system_time_now() + MAX_TIMESTAMP_FUTURE_SHIFT + 1_000_000;
+        let id = RecordIdentifier::new(namespace.id(), author.id(), b"/future");
+        let entry =
+            Entry::new(id, Record::new(Hash::new(b"x"), 1, future)).sign(&namespace, &author);
+        let invalid = crate::Snapshot::new(namespace.id(), vec![entry]);
+        let mut other = store::Store::memory();
+        assert!(other.import_snapshot(&invalid).is_err());
+        assert!(other.open_replica(&namespace.id()).is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_actor_capability_memory() -> Result<()> {
         let store = store::Store::memory();
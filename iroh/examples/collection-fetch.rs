@@ -5,7 +5,7 @@
 //! Run the `collection-provide` example, which will give you instructions on how to run this example.
 use anyhow::{bail, ensure, Context, Result};
 use iroh::rpc_protocol::{BlobDownloadRequest, DownloadMode};
-use iroh_bytes::BlobFormat;
+use iroh_bytes::{protocol::RangeSpecSeq, BlobFormat};
 use std::env;
 use std::str::FromStr;
 use tracing_subscriber::{prelude::*, EnvFilter};
@@ -60,6 +60,9 @@ async fn main() -> Result<()> {
         // When interacting with the iroh API, you will most likely be using blobs and collections.
         format: ticket.format(),
 
+        // The `ranges` field selects which chunks of the data to download. We want everything.
+        ranges: RangeSpecSeq::all(),
+
         // The `nodes` field is a list of `NodeAddr`, where each combines all of the known address information we have for the remote node.
         // This includes the `node_id` (or `PublicKey` of the node), any direct UDP addresses we know about for that node, as well as the relay url of that node. The relay url is the url of the relay server that that node is connected to.
         // If the direct UDP addresses to that node do not work, than we can use the relay node to attempt to holepunch between your current node and the remote node.
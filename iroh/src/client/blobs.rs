@@ -13,13 +13,19 @@ use futures_lite::{Stream, StreamExt};
 use futures_util::SinkExt;
 use iroh_base::{node_addr::AddrInfoOptions, ticket::BlobTicket};
 use iroh_bytes::{
+    bundle::BundleHeader,
     export::ExportProgress,
-    format::collection::Collection,
+    format::{collection::Collection, diff::Change},
     get::db::DownloadProgress,
+    protocol::{RangeSpec, RangeSpecSeq},
     provider::AddProgress,
-    store::{ConsistencyCheckProgress, ExportFormat, ExportMode, ValidateProgress},
-    BlobFormat, Hash, Tag,
+    store::{
+        BlobMeta, BlobMetaUpdate, ConsistencyCheckProgress, ExportFormat, ExportMode,
+        ValidateProgress,
+    },
+    BlobFormat, Hash, HashAndFormat, Tag,
 };
+use iroh_net::NodeAddr;
 use portable_atomic::{AtomicU64, Ordering};
 use quic_rpc::{client::BoxStreamSync, RpcClient, ServiceConnection};
 use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
@@ -27,13 +33,16 @@ use tokio_util::io::{ReaderStream, StreamReader};
 use tracing::warn;
 
 use crate::rpc_protocol::{
-    BlobAddPathRequest, BlobAddStreamRequest, BlobAddStreamUpdate, BlobConsistencyCheckRequest,
-    BlobDeleteBlobRequest, BlobDownloadRequest, BlobExportRequest, BlobGetCollectionRequest,
-    BlobGetCollectionResponse, BlobListCollectionsRequest, BlobListCollectionsResponse,
-    BlobListIncompleteRequest, BlobListIncompleteResponse, BlobListRequest, BlobListResponse,
-    BlobReadAtRequest, BlobReadAtResponse, BlobValidateRequest, CreateCollectionRequest,
-    CreateCollectionResponse, NodeStatusRequest, NodeStatusResponse, ProviderService, SetTagOption,
-    WrapOption,
+    BlobAddPathRequest, BlobAddStreamRequest, BlobAddStreamUpdate, BlobAvailableRangesRequest,
+    BlobConsistencyCheckRequest, BlobDeleteBlobRequest, BlobDiffRequest, BlobDiffResponse,
+    BlobDownloadRequest, BlobExportBundleRequest, BlobExportBundleResponse, BlobExportRequest,
+    BlobGetCollectionRequest, BlobGetCollectionResponse, BlobGetMetaRequest, BlobGetMetaResponse,
+    BlobImportBundleRequest, BlobImportBundleResponse, BlobListCollectionsRequest,
+    BlobListCollectionsResponse, BlobListIncompleteRequest, BlobListIncompleteResponse,
+    BlobListMetaRequest, BlobListMetaResponse, BlobListRequest, BlobListResponse, BlobPushRequest,
+    BlobReadAtRequest, BlobReadAtResponse, BlobReadBaoRequest, BlobUpdateMetaRequest,
+    BlobValidateRequest, CreateCollectionRequest, CreateCollectionResponse, NodeStatusRequest,
+    NodeStatusResponse, ProviderService, SetTagOption, WrapOption,
 };
 
 use super::{flatten, Iroh};
@@ -67,7 +76,23 @@ where
     ///
     /// If `len` is `None` it will read the full blob.
     pub async fn read_at(&self, hash: Hash, offset: u64, len: Option<usize>) -> Result<BlobReader> {
-        BlobReader::from_rpc_read_at(&self.rpc, hash, offset, len).await
+        BlobReader::from_rpc_read_at(&self.rpc, hash, offset, len, None).await
+    }
+
+    /// Read offset + len from a single blob, downloading the range first if it is missing.
+    ///
+    /// If the blob is incomplete or missing, the chunk groups covering the range are downloaded
+    /// from `nodes`, at least one, so the size of the blob is known. The download is not tagged,
+    /// so only use this for blobs that are protected from garbage collection by other means,
+    /// e.g. children of a tagged collection or content of a document.
+    pub async fn read_at_with_download(
+        &self,
+        hash: Hash,
+        offset: u64,
+        len: Option<usize>,
+        nodes: Vec<NodeAddr>,
+    ) -> Result<BlobReader> {
+        BlobReader::from_rpc_read_at(&self.rpc, hash, offset, len, Some(nodes)).await
     }
 
     /// Read all bytes of single blob.
@@ -91,12 +116,33 @@ where
         offset: u64,
         len: Option<usize>,
     ) -> Result<Bytes> {
-        BlobReader::from_rpc_read_at(&self.rpc, hash, offset, len)
+        BlobReader::from_rpc_read_at(&self.rpc, hash, offset, len, None)
             .await?
             .read_to_bytes()
             .await
     }
 
+    /// Read the bao encoding of `ranges` of a single blob.
+    ///
+    /// This is the same data a provider sends for the blob, so unlike the other read methods,
+    /// the result can be verified against the hash by whoever receives it, e.g. with
+    /// [`iroh_bytes::get::fsm`]. Fails if the blob is not in the store of the node.
+    pub async fn read_bao(
+        &self,
+        hash: Hash,
+        ranges: RangeSpec,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let stream = self
+            .rpc
+            .server_streaming(BlobReadBaoRequest { hash, ranges })
+            .await?;
+        let mut stream = flatten(stream);
+        // a missing blob is reported as the first item
+        let first = stream.next().await.transpose()?;
+        let stream = futures_lite::stream::iter(first.map(Ok)).chain(stream);
+        Ok(stream.map(|res| res.map(|res| res.chunk)))
+    }
+
     /// Import a blob from a filesystem path.
     ///
     /// `path` should be an absolute path valid for the file system on which
@@ -283,6 +329,14 @@ where
         Ok(collection)
     }
 
+    /// Compare two collections or two trees.
+    ///
+    /// Returns the entries that were added, removed or modified from `from` to `to`.
+    pub async fn diff(&self, from: Hash, to: Hash) -> Result<Vec<Change>> {
+        let BlobDiffResponse { changes } = self.rpc.rpc(BlobDiffRequest { from, to }).await??;
+        Ok(changes)
+    }
+
     /// List all collections.
     pub async fn list_collections(
         &self,
@@ -300,6 +354,104 @@ where
         Ok(())
     }
 
+    /// Get the metadata of a blob.
+    ///
+    /// Returns empty metadata if the blob has none.
+    pub async fn meta(&self, hash: Hash) -> Result<BlobMeta> {
+        let BlobGetMetaResponse { meta } = self.rpc.rpc(BlobGetMetaRequest { hash }).await??;
+        Ok(meta)
+    }
+
+    /// Apply an update to the metadata of a blob.
+    pub async fn update_meta(&self, hash: Hash, update: BlobMetaUpdate) -> Result<()> {
+        self.rpc
+            .rpc(BlobUpdateMetaRequest { hash, update })
+            .await??;
+        Ok(())
+    }
+
+    /// Set or clear the MIME type of a blob.
+    pub async fn set_mime_type(&self, hash: Hash, mime_type: Option<String>) -> Result<()> {
+        self.update_meta(hash, BlobMetaUpdate::MimeType(mime_type))
+            .await
+    }
+
+    /// Set or remove an annotation of a blob.
+    pub async fn set_annotation(
+        &self,
+        hash: Hash,
+        key: impl Into<String>,
+        value: Option<String>,
+    ) -> Result<()> {
+        let key = key.into();
+        self.update_meta(hash, BlobMetaUpdate::Annotation { key, value })
+            .await
+    }
+
+    /// List the metadata of all blobs that have any.
+    pub async fn list_meta(&self) -> Result<impl Stream<Item = Result<BlobListMetaResponse>>> {
+        let stream = self.rpc.server_streaming(BlobListMetaRequest).await?;
+        Ok(flatten(stream))
+    }
+
+    /// Write a bundle of blobs to a file on the node.
+    ///
+    /// A bundle contains the given `roots`, restricted to the given ranges, and the tags that
+    /// point to them. It can be imported on another node with [`Self::import_bundle`], without a
+    /// network connection between the nodes. See [`iroh_bytes::bundle`] for details.
+    ///
+    /// `path` should be an absolute path valid for the file system on which the node runs.
+    pub async fn export_bundle(
+        &self,
+        roots: Vec<(HashAndFormat, RangeSpecSeq)>,
+        path: PathBuf,
+    ) -> Result<BundleHeader> {
+        let BlobExportBundleResponse { header } = self
+            .rpc
+            .rpc(BlobExportBundleRequest { roots, path })
+            .await??;
+        Ok(header)
+    }
+
+    /// Verify a bundle written by [`Self::export_bundle`] and import it.
+    ///
+    /// The tags of the bundle are set on the node, unless a tag with the same name already
+    /// exists. Such conflicting tags are skipped and returned. Roots that no tag of the bundle
+    /// was set for get an automatically named tag, so they are not garbage collected.
+    ///
+    /// `path` should be an absolute path valid for the file system on which the node runs.
+    pub async fn import_bundle(&self, path: PathBuf) -> Result<BlobImportBundleResponse> {
+        let res = self.rpc.rpc(BlobImportBundleRequest { path }).await??;
+        Ok(res)
+    }
+
+    /// Push a blob or collection to another node.
+    ///
+    /// This only succeeds if the other node accepts the push and has stored all of the data.
+    pub async fn push(&self, hash: Hash, format: BlobFormat, node: NodeAddr) -> Result<()> {
+        self.rpc
+            .rpc(BlobPushRequest { hash, format, node })
+            .await??;
+        Ok(())
+    }
+
+    /// Ask another node which ranges of a blob or collection it has.
+    ///
+    /// The first element of the result is the root blob, all subsequent elements are
+    /// children. The ranges are not validated, so downloading them can still fail.
+    pub async fn available_ranges(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        node: NodeAddr,
+    ) -> Result<RangeSpecSeq> {
+        let res = self
+            .rpc
+            .rpc(BlobAvailableRangesRequest { hash, format, node })
+            .await??;
+        Ok(res.ranges)
+    }
+
     /// Share a blob.
     pub async fn share(
         &self,
@@ -657,7 +809,7 @@ impl BlobReader {
         rpc: &RpcClient<ProviderService, C>,
         hash: Hash,
     ) -> anyhow::Result<Self> {
-        Self::from_rpc_read_at(rpc, hash, 0, None).await
+        Self::from_rpc_read_at(rpc, hash, 0, None, None).await
     }
 
     async fn from_rpc_read_at<C: ServiceConnection<ProviderService>>(
@@ -665,9 +817,15 @@ impl BlobReader {
         hash: Hash,
         offset: u64,
         len: Option<usize>,
+        fetch_from: Option<Vec<NodeAddr>>,
     ) -> anyhow::Result<Self> {
         let stream = rpc
-            .server_streaming(BlobReadAtRequest { hash, offset, len })
+            .server_streaming(BlobReadAtRequest {
+                hash,
+                offset,
+                len,
+                fetch_from,
+            })
             .await?;
         let mut stream = flatten(stream);
 
@@ -688,6 +846,13 @@ impl BlobReader {
         Ok(Self::new(size.value(), len, is_complete, Box::pin(stream)))
     }
 
+    /// Create a reader over data that is already in memory.
+    pub(crate) fn from_bytes(data: Bytes) -> Self {
+        let size = data.len() as u64;
+        let stream = futures_lite::stream::once(Ok(data));
+        Self::new(size, size, true, Box::pin(stream))
+    }
+
     /// Total size of this blob.
     pub fn size(&self) -> u64 {
         self.size
@@ -1039,4 +1204,405 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_blob_meta() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let client = node.client();
+
+        // the MIME type is detected from the file name on import
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let path = temp_dir.path().join("page.html");
+        std::fs::write(&path, b"no html here")?;
+        let page = client
+            .blobs
+            .add_from_path(path, false, SetTagOption::Auto, WrapOption::NoWrap)
+            .await?
+            .finish()
+            .await?
+            .hash;
+        let meta = client.blobs.meta(page).await?;
+        assert_eq!(meta.mime_type.as_deref(), Some("text/html; charset=utf-8"));
+
+        // or sniffed from the content
+        let image = client
+            .blobs
+            .add_bytes(&b"\x89PNG\r\n\x1a\n...."[..])
+            .await?
+            .hash;
+        let meta = client.blobs.meta(image).await?;
+        assert_eq!(meta.mime_type.as_deref(), Some("image/png"));
+
+        client
+            .blobs
+            .set_annotation(image, "license", Some("CC0".into()))
+            .await?;
+        client
+            .blobs
+            .set_mime_type(page, Some("text/plain".into()))
+            .await?;
+        let metas: Vec<_> = client.blobs.list_meta().await?.try_collect().await?;
+        let metas = metas
+            .into_iter()
+            .map(|item| (item.hash, item.meta))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        assert_eq!(metas.len(), 2);
+        assert_eq!(metas[&page].mime_type.as_deref(), Some("text/plain"));
+        assert_eq!(
+            metas[&image].annotations.get("license").map(String::as_str),
+            Some("CC0")
+        );
+
+        // removing the last annotation keeps the MIME type
+        client.blobs.set_annotation(image, "license", None).await?;
+        let meta = client.blobs.meta(image).await?;
+        assert!(meta.annotations.is_empty());
+        assert_eq!(meta.mime_type.as_deref(), Some("image/png"));
+
+        // metadata belongs to a blob, so it can not be set for unknown hashes
+        let missing = Hash::new(b"missing");
+        assert!(client
+            .blobs
+            .set_annotation(missing, "license", Some("CC0".into()))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blob_bundle() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+
+        let source = crate::node::Node::memory().spawn().await?;
+        let target = crate::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bundle");
+
+        let client = source.client();
+        let tagged = HashAndFormat::raw(client.blobs.add_bytes(vec![1u8; 100_000]).await?.hash);
+        let untagged = HashAndFormat::raw(client.blobs.add_bytes(&b"hello"[..]).await?.hash);
+        // replace the automatically created tags
+        let tags: Vec<_> = client.tags.list().await?.try_collect().await?;
+        for tag in tags {
+            client.tags.delete(tag.name).await?;
+        }
+        client.tags.set(Tag::from("tagged"), tagged).await?;
+        let roots = vec![
+            (tagged, RangeSpecSeq::all()),
+            (untagged, RangeSpecSeq::all()),
+        ];
+        let header = client.blobs.export_bundle(roots, path.clone()).await?;
+        assert_eq!(header.tags, vec![(Tag::from("tagged"), tagged)]);
+
+        let res = target.client().blobs.import_bundle(path).await?;
+        assert_eq!(res.header, header);
+        assert_eq!(res.created_tags.len(), 1);
+        let data = target.client().blobs.read_to_bytes(tagged.hash).await?;
+        assert_eq!(data, Bytes::from(vec![1u8; 100_000]));
+        let data = target.client().blobs.read_to_bytes(untagged.hash).await?;
+        assert_eq!(data, Bytes::from_static(b"hello"));
+        let tags: Vec<_> = target.client().tags.list().await?.try_collect().await?;
+        assert_eq!(tags.len(), 2);
+        assert!(tags
+            .iter()
+            .any(|tag| tag.name == Tag::from("tagged") && tag.hash == tagged.hash));
+        assert!(tags
+            .iter()
+            .any(|tag| tag.name == res.created_tags[0] && tag.hash == untagged.hash));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_blob_tree_roundtrip() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
+
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let in_root = temp_dir.path().join("in");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        std::fs::create_dir_all(in_root.join("sub"))?;
+        std::fs::create_dir_all(in_root.join("empty"))?;
+        std::fs::write(in_root.join("a.txt"), b"hello")?;
+        std::fs::write(in_root.join("sub").join("b.txt"), b"world")?;
+        std::fs::set_permissions(in_root.join("a.txt"), PermissionsExt::from_mode(0o600))?;
+        std::fs::File::options()
+            .write(true)
+            .open(in_root.join("a.txt"))?
+            .set_modified(mtime)?;
+        std::os::unix::fs::symlink("a.txt", in_root.join("link"))?;
+
+        let client = node.client();
+        let import_outcome = client
+            .blobs
+            .add_from_path(
+                in_root,
+                false,
+                SetTagOption::Auto,
+                WrapOption::Tree { previous: None },
+            )
+            .await?
+            .finish()
+            .await?;
+        assert_eq!(import_outcome.format, BlobFormat::HashSeq);
+
+        let out_root = temp_dir.path().join("out");
+        client
+            .blobs
+            .export(
+                import_outcome.hash,
+                out_root.clone(),
+                ExportFormat::Tree,
+                ExportMode::Copy,
+            )
+            .await?
+            .finish()
+            .await?;
+
+        let a = out_root.join("a.txt");
+        assert_eq!(std::fs::read(&a)?, b"hello");
+        assert_eq!(std::fs::metadata(&a)?.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(std::fs::metadata(&a)?.modified()?, mtime);
+        assert_eq!(std::fs::read(out_root.join("sub").join("b.txt"))?, b"world");
+        assert!(out_root.join("empty").is_dir());
+        assert_eq!(
+            std::fs::read_link(out_root.join("link"))?,
+            std::path::Path::new("a.txt")
+        );
+
+        // subtrees are trees of their own, and shared with the root tree
+        let sub_outcome = client
+            .blobs
+            .add_from_path(
+                temp_dir.path().join("in").join("sub"),
+                false,
+                SetTagOption::Auto,
+                WrapOption::Tree { previous: None },
+            )
+            .await?
+            .finish()
+            .await?;
+        let links = client.blobs.read_to_bytes(import_outcome.hash).await?;
+        let links = iroh_bytes::hashseq::HashSeq::try_from(links)?;
+        assert!(links.iter().any(|hash| hash == sub_outcome.hash));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_blob_tree_export_symlink() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let in_root = temp_dir.path().join("in");
+        std::fs::create_dir_all(&in_root)?;
+        std::fs::write(in_root.join("a.txt"), b"hello")?;
+
+        let client = node.client();
+        let import_outcome = client
+            .blobs
+            .add_from_path(
+                in_root,
+                false,
+                SetTagOption::Auto,
+                WrapOption::Tree { previous: None },
+            )
+            .await?
+            .finish()
+            .await?;
+
+        // an existing symlink in the target directory must not be written through
+        let victim = temp_dir.path().join("victim");
+        std::fs::write(&victim, b"untouched")?;
+        let out_root = temp_dir.path().join("out");
+        std::fs::create_dir_all(&out_root)?;
+        std::os::unix::fs::symlink(&victim, out_root.join("a.txt"))?;
+        let res = client
+            .blobs
+            .export(
+                import_outcome.hash,
+                out_root.clone(),
+                ExportFormat::Tree,
+                ExportMode::Copy,
+            )
+            .await?
+            .finish()
+            .await;
+        assert!(res.is_err());
+        assert_eq!(std::fs::read(&victim)?, b"untouched");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blob_tree_incremental() -> Result<()> {
+        use iroh_bytes::format::diff::{Change, ChangeKind};
+
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let root = temp_dir.path().join("in");
+        std::fs::create_dir_all(root.join("sub"))?;
+        std::fs::write(root.join("a.txt"), b"hello")?;
+        std::fs::write(root.join("removed.txt"), b"gone soon")?;
+        std::fs::write(root.join("sub").join("b.txt"), b"world")?;
+
+        let client = node.client();
+        let first = client
+            .blobs
+            .add_from_path(
+                root.clone(),
+                false,
+                SetTagOption::Auto,
+                WrapOption::Tree { previous: None },
+            )
+            .await?
+            .finish()
+            .await?;
+
+        std::fs::remove_file(root.join("removed.txt"))?;
+        std::fs::write(root.join("sub").join("b.txt"), b"world, again")?;
+        std::fs::write(root.join("c.txt"), b"new")?;
+
+        let mut progress = client
+            .blobs
+            .add_from_path(
+                root,
+                false,
+                SetTagOption::Auto,
+                WrapOption::Tree {
+                    previous: Some(first.hash),
+                },
+            )
+            .await?;
+        let mut hashed = Vec::new();
+        let mut second = None;
+        while let Some(item) = progress.next().await {
+            match item? {
+                AddProgress::Found { name, .. } => hashed.push(name),
+                AddProgress::AllDone { hash, .. } => second = Some(hash),
+                _ => {}
+            }
+        }
+        let second = second.context("import did not finish")?;
+        // the unchanged file is not read again
+        assert_eq!(hashed.len(), 2);
+        assert!(hashed.iter().any(|name| name.ends_with("b.txt")));
+        assert!(hashed.iter().any(|name| name.ends_with("c.txt")));
+
+        let changes = client.blobs.diff(first.hash, second).await?;
+        let change = |path: &str, kind| Change {
+            path: path.to_string(),
+            kind,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                change("c.txt", ChangeKind::Added),
+                change("removed.txt", ChangeKind::Removed),
+                change("sub/b.txt", ChangeKind::Modified),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blob_add_chunked() -> Result<()> {
+        use rand::SeedableRng;
+
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let client = node.client();
+
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let path = temp_dir.path().join("data.bin");
+        let mut data = vec![0u8; 1024 * 1024];
+        rand_chacha::ChaCha8Rng::seed_from_u64(0).fill_bytes(&mut data);
+
+        // returns the hash and the chunking stats of the import
+        let add = |data: &[u8]| {
+            std::fs::write(&path, data).unwrap();
+            let path = path.clone();
+            async move {
+                let mut stream = client
+                    .blobs
+                    .add_from_path(path, false, SetTagOption::Auto, WrapOption::Chunked)
+                    .await?;
+                let mut stats = None;
+                while let Some(progress) = stream.next().await {
+                    match progress? {
+                        AddProgress::ChunkingDone {
+                            chunks,
+                            size,
+                            stored,
+                            ..
+                        } => stats = Some((chunks, size, stored)),
+                        AddProgress::AllDone { hash, format, .. } => {
+                            assert_eq!(format, BlobFormat::HashSeq);
+                            return anyhow::Ok((hash, stats.context("no chunking stats")?));
+                        }
+                        AddProgress::Abort(err) => return Err(err.into()),
+                        _ => {}
+                    }
+                }
+                anyhow::bail!("stream ended without providing data")
+            }
+        };
+
+        let (hash, (chunks, size, stored)) = add(&data).await?;
+        assert!(chunks > 1);
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(stored, size);
+
+        // only the chunk containing the change is stored again
+        data[512 * 1024] ^= 0xff;
+        let (changed, (_, size, stored)) = add(&data).await?;
+        assert_ne!(hash, changed);
+        assert!(stored < size / 2);
+
+        let out = temp_dir.path().join("out.bin");
+        client
+            .blobs
+            .export(
+                changed,
+                out.clone(),
+                ExportFormat::Chunked,
+                ExportMode::Copy,
+            )
+            .await?
+            .finish()
+            .await?;
+        assert_eq!(std::fs::read(&out)?, data);
+
+        // directories can not be imported in chunks
+        let res = client
+            .blobs
+            .add_from_path(
+                temp_dir.path().to_path_buf(),
+                false,
+                SetTagOption::Auto,
+                WrapOption::Chunked,
+            )
+            .await?
+            .finish()
+            .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
 }
@@ -13,8 +13,9 @@ use iroh_bytes::{export::ExportProgress, store::ExportMode, Hash};
 use iroh_net::NodeAddr;
 use iroh_sync::{
     actor::OpenState,
-    store::{DownloadPolicy, Query},
-    AuthorId, CapabilityKind, ContentStatus, NamespaceId, PeerIdBytes, RecordIdentifier,
+    store::{DownloadPolicy, HistoryPolicy, Query},
+    AuthorId, CapabilityKind, ContentStatus, EncryptionKey, NamespaceId, NamespaceRotation,
+    PeerIdBytes, RecordIdentifier, ROTATION_KEY,
 };
 use portable_atomic::{AtomicBool, Ordering};
 use quic_rpc::{message::RpcMsg, RpcClient, ServiceConnection};
@@ -23,11 +24,13 @@ use serde::{Deserialize, Serialize};
 use crate::{
     rpc_protocol::{
         DocCloseRequest, DocCreateRequest, DocDelRequest, DocDelResponse, DocDropRequest,
-        DocExportFileRequest, DocGetDownloadPolicyRequest, DocGetExactRequest, DocGetManyRequest,
-        DocGetSyncPeersRequest, DocImportFileRequest, DocImportProgress, DocImportRequest,
-        DocLeaveRequest, DocListRequest, DocOpenRequest, DocSetDownloadPolicyRequest,
-        DocSetHashRequest, DocSetRequest, DocShareRequest, DocStartSyncRequest, DocStatusRequest,
-        DocSubscribeRequest, ProviderService, ShareMode,
+        DocExportFileRequest, DocGetDownloadPolicyRequest, DocGetEncryptionKeyRequest,
+        DocGetExactRequest, DocGetHistoryPolicyRequest, DocGetManyRequest, DocGetSyncPeersRequest,
+        DocImportFileRequest, DocImportProgress, DocImportRequest, DocImportSnapshotRequest,
+        DocLeaveRequest, DocListRequest, DocOpenRequest, DocRotateRequest, DocRotateResponse,
+        DocSetDownloadPolicyRequest, DocSetHashRequest, DocSetHistoryPolicyRequest, DocSetRequest,
+        DocShareRequest, DocSnapshotRequest, DocSnapshotResponse, DocStartSyncRequest,
+        DocStatusRequest, DocSubscribeRequest, ProviderService, SetTagOption, ShareMode,
     },
     sync_engine::SyncEvent,
     ticket::DocTicket,
@@ -47,7 +50,21 @@ where
 {
     /// Create a new document.
     pub async fn create(&self) -> Result<Doc<C>> {
-        let res = self.rpc.rpc(DocCreateRequest {}).await??;
+        let res = self
+            .rpc
+            .rpc(DocCreateRequest { encrypted: false })
+            .await??;
+        let doc = Doc::new(self.rpc.clone(), res.id);
+        Ok(doc)
+    }
+
+    /// Create a new encrypted document.
+    ///
+    /// Entry keys and content are encrypted with a key that is shared in tickets, except for
+    /// tickets with [`ShareMode::Relay`]. Peers without the key can sync the document, but cannot
+    /// read it. Since keys are encrypted, prefix queries and deletions only match complete keys.
+    pub async fn create_encrypted(&self) -> Result<Doc<C>> {
+        let res = self.rpc.rpc(DocCreateRequest { encrypted: true }).await??;
         let doc = Doc::new(self.rpc.clone(), res.id);
         Ok(doc)
     }
@@ -69,6 +86,16 @@ where
         Ok(doc)
     }
 
+    /// Import a document from a snapshot blob on this node.
+    ///
+    /// The snapshot is verified before its entries are inserted. If the document does not exist
+    /// yet, it is created with read access only. The document must not be open when importing.
+    pub async fn import_snapshot(&self, hash: Hash) -> Result<Doc<C>> {
+        let res = self.rpc.rpc(DocImportSnapshotRequest { hash }).await??;
+        let doc = Doc::new(self.rpc.clone(), res.doc_id);
+        Ok(doc)
+    }
+
     /// List all documents.
     pub async fn list(&self) -> Result<impl Stream<Item = Result<(NamespaceId, CapabilityKind)>>> {
         let stream = self.rpc.server_streaming(DocListRequest {}).await?;
@@ -177,6 +204,13 @@ where
     }
 
     /// Set an entries on the doc via its key, hash, and size.
+    ///
+    /// For an encrypted document, the key is encrypted by the node, but the content is not:
+    /// it is already stored under `hash`. So `hash` and `size` must be those of content that
+    /// was encrypted with the [`EncryptionKey`] of the document, see
+    /// [`EncryptionKey::encrypt_content`] and [`EncryptionKey::content_encryptor`]. Otherwise
+    /// the content can not be read through the document. [`Self::set_bytes`] and
+    /// [`Self::import_file`] encrypt the content themselves.
     pub async fn set_hash(
         &self,
         author_id: AuthorId,
@@ -276,7 +310,8 @@ where
                 include_empty,
             })
             .await??;
-        Ok(res.entry.map(|entry| entry.into()))
+        let encryption = self.encryption_key().await?;
+        Ok(res.entry.map(|entry| Entry::new(entry, encryption)))
     }
 
     /// Get entries.
@@ -285,6 +320,7 @@ where
         query: impl Into<Query>,
     ) -> Result<impl Stream<Item = Result<Entry>>> {
         self.ensure_open()?;
+        let encryption = self.encryption_key().await?;
         let stream = self
             .0
             .rpc
@@ -293,7 +329,10 @@ where
                 query: query.into(),
             })
             .await?;
-        Ok(flatten(stream).map(|res| res.map(|res| res.entry.into())))
+        Ok(
+            flatten(stream)
+                .map(move |res| res.map(|res| Entry::new(res.entry, encryption.clone()))),
+        )
     }
 
     /// Get a single entry.
@@ -318,6 +357,79 @@ where
         Ok(res.0)
     }
 
+    /// Rotate this document to a new keypair.
+    ///
+    /// The entries are carried forward into a new document, which requires write access to this
+    /// document. Only entries by authors whose key is on this node can be signed for the new
+    /// document, entries by other authors are skipped. This document gets a [`NamespaceRotation`]
+    /// entry at [`ROTATION_KEY`] by `author_id`, which points to the new document without
+    /// revealing it. Afterwards, this document is read-only on this node, since its key is
+    /// considered leaked.
+    ///
+    /// Tickets and delegated capabilities for this document do not grant access to the new one.
+    /// Share the new document to give peers access again.
+    pub async fn rotate(&self, author_id: AuthorId) -> Result<DocRotateResponse> {
+        self.ensure_open()?;
+        let res = self
+            .rpc(DocRotateRequest {
+                doc_id: self.id(),
+                author_id,
+            })
+            .await??;
+        Ok(res)
+    }
+
+    /// Store a snapshot of this document as a blob.
+    ///
+    /// The snapshot contains all entries of the document, including deletions, sorted and
+    /// encoded deterministically. With `as_of`, it contains the state at that timestamp, as far as
+    /// the history of the document reaches. The blob can be shared like any other blob, e.g. with
+    /// a [`BlobTicket`](crate::ticket::BlobTicket), and imported with [`Client::import_snapshot`].
+    pub async fn snapshot(
+        &self,
+        as_of: Option<u64>,
+        tag: SetTagOption,
+    ) -> Result<DocSnapshotResponse> {
+        self.ensure_open()?;
+        let res = self
+            .rpc(DocSnapshotRequest {
+                doc_id: self.id(),
+                as_of,
+                tag,
+            })
+            .await??;
+        Ok(res)
+    }
+
+    /// Get the latest valid [`NamespaceRotation`] of this document, if it was rotated.
+    ///
+    /// The pointer is signed with the key of this document, so it is only as trustworthy as
+    /// that key. Use [`NamespaceRotation::is_successor`] to check a document shared by other
+    /// means against it.
+    pub async fn rotation(&self) -> Result<Option<NamespaceRotation>> {
+        let mut entries = self.get_many(Query::key_exact(ROTATION_KEY)).await?;
+        let mut latest: Option<NamespaceRotation> = None;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let Ok(content) = entry.content_bytes(self).await else {
+                continue;
+            };
+            let Ok(rotation) = NamespaceRotation::from_bytes(&content) else {
+                continue;
+            };
+            if rotation.namespace() != self.id() || rotation.verify(&()).is_err() {
+                continue;
+            }
+            if latest
+                .as_ref()
+                .map_or(true, |latest| rotation.timestamp() > latest.timestamp())
+            {
+                latest = Some(rotation);
+            }
+        }
+        Ok(latest)
+    }
+
     /// Start to sync this document with a list of peers.
     pub async fn start_sync(&self, peers: Vec<NodeAddr>) -> Result<()> {
         self.ensure_open()?;
@@ -340,13 +452,14 @@ where
     /// Subscribe to events for this document.
     pub async fn subscribe(&self) -> anyhow::Result<impl Stream<Item = anyhow::Result<LiveEvent>>> {
         self.ensure_open()?;
+        let encryption = self.encryption_key().await?;
         let stream = self
             .0
             .rpc
             .try_server_streaming(DocSubscribeRequest { doc_id: self.id() })
             .await?;
-        Ok(stream.map(|res| match res {
-            Ok(res) => Ok(res.event.into()),
+        Ok(stream.map(move |res| match res {
+            Ok(res) => Ok(LiveEvent::from(res.event).with_encryption(&encryption)),
             Err(err) => Err(err.into()),
         }))
     }
@@ -376,6 +489,29 @@ where
         Ok(res.policy)
     }
 
+    /// Set the history policy for this document
+    ///
+    /// With a policy, superseded entries are kept locally and can be read with
+    /// [`Query::key_versions`] and [`QueryBuilder::as_of`]. Pass `None` to drop the history.
+    ///
+    /// [`QueryBuilder::as_of`]: iroh_sync::store::QueryBuilder::as_of
+    pub async fn set_history_policy(&self, policy: Option<HistoryPolicy>) -> Result<()> {
+        self.rpc(DocSetHistoryPolicyRequest {
+            doc_id: self.id(),
+            policy,
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Get the history policy for this document
+    pub async fn get_history_policy(&self) -> Result<Option<HistoryPolicy>> {
+        let res = self
+            .rpc(DocGetHistoryPolicyRequest { doc_id: self.id() })
+            .await??;
+        Ok(res.policy)
+    }
+
     /// Get sync peers for this document
     pub async fn get_sync_peers(&self) -> Result<Option<Vec<PeerIdBytes>>> {
         let res = self
@@ -383,6 +519,16 @@ where
             .await??;
         Ok(res.peers)
     }
+
+    /// Get the encryption key of this document.
+    ///
+    /// Returns `None` if the document is not encrypted, or if this node only relays it.
+    pub async fn encryption_key(&self) -> Result<Option<EncryptionKey>> {
+        let res = self
+            .rpc(DocGetEncryptionKeyRequest { doc_id: self.id() })
+            .await??;
+        Ok(res.key)
+    }
 }
 
 impl<'a, C: ServiceConnection<ProviderService>> From<&'a Doc<C>>
@@ -395,21 +541,31 @@ impl<'a, C: ServiceConnection<ProviderService>> From<&'a Doc<C>>
 
 /// A single entry in a [`Doc`].
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
-pub struct Entry(iroh_sync::Entry);
+#[serde(transparent)]
+pub struct Entry(
+    iroh_sync::Entry,
+    /// The key to decrypt the content, if the document is encrypted.
+    #[serde(skip)]
+    Option<EncryptionKey>,
+);
 
 impl From<iroh_sync::Entry> for Entry {
     fn from(value: iroh_sync::Entry) -> Self {
-        Self(value)
+        Self(value, None)
     }
 }
 
 impl From<iroh_sync::SignedEntry> for Entry {
     fn from(value: iroh_sync::SignedEntry) -> Self {
-        Self(value.into())
+        Self(value.into(), None)
     }
 }
 
 impl Entry {
+    fn new(entry: impl Into<iroh_sync::Entry>, encryption: Option<EncryptionKey>) -> Self {
+        Self(entry.into(), encryption)
+    }
+
     /// Get the [`RecordIdentifier`] for this entry.
     pub fn id(&self) -> &RecordIdentifier {
         self.0.id()
@@ -426,6 +582,8 @@ impl Entry {
     }
 
     /// Get the length of the data addressed by this record's content hash.
+    ///
+    /// For an encrypted document, this is the length of the encrypted content.
     pub fn content_len(&self) -> u64 {
         self.0.content_len()
     }
@@ -450,7 +608,10 @@ impl Entry {
     where
         C: ServiceConnection<ProviderService>,
     {
-        BlobReader::from_rpc_read(client.into(), self.content_hash()).await
+        match &self.1 {
+            Some(_) => Ok(BlobReader::from_bytes(self.content_bytes(client).await?)),
+            None => BlobReader::from_rpc_read(client.into(), self.content_hash()).await,
+        }
     }
 
     /// Read all content of an [`Entry`] into a buffer.
@@ -463,10 +624,14 @@ impl Entry {
     where
         C: ServiceConnection<ProviderService>,
     {
-        BlobReader::from_rpc_read(client.into(), self.content_hash())
+        let content = BlobReader::from_rpc_read(client.into(), self.content_hash())
             .await?
             .read_to_bytes()
-            .await
+            .await?;
+        match &self.1 {
+            Some(encryption) => Ok(encryption.decrypt_content(&content)?.into()),
+            None => Ok(content),
+        }
     }
 }
 
@@ -523,6 +688,15 @@ impl From<crate::sync_engine::LiveEvent> for LiveEvent {
     }
 }
 
+impl LiveEvent {
+    fn with_encryption(mut self, encryption: &Option<EncryptionKey>) -> Self {
+        if let Self::InsertLocal { entry } | Self::InsertRemote { entry, .. } = &mut self {
+            entry.1.clone_from(encryption);
+        }
+        self
+    }
+}
+
 /// Progress stream for doc import operations.
 #[derive(derive_more::Debug)]
 #[must_use = "streams do nothing unless polled"]
@@ -763,4 +937,56 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_doc_import_export_encrypted() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let path = temp_dir.path().join("test");
+        let out = temp_dir.path().join("out");
+
+        // spans several segments, the last one partial
+        let size = iroh_sync::CONTENT_SEGMENT_SIZE * 3 + 100;
+        let mut buf = vec![0u8; size];
+        rand::thread_rng().fill_bytes(&mut buf);
+        tokio::fs::write(&path, &buf).await.context("write")?;
+
+        let client = node.client();
+        let doc = client.docs.create_encrypted().await.context("doc create")?;
+        let author = client.authors.create().await.context("author create")?;
+
+        let import_outcome = doc
+            .import_file(author, Bytes::from_static(b"test"), path, false)
+            .await
+            .context("import file")?
+            .finish()
+            .await
+            .context("import finish")?;
+        assert_eq!(import_outcome.size, size as u64);
+
+        let entry = doc
+            .get_one(Query::author(author).key_exact(import_outcome.key))
+            .await
+            .context("get one")?
+            .unwrap();
+        assert!(entry.content_len() > size as u64);
+        assert_eq!(entry.content_bytes(&doc).await?, buf);
+
+        let export_outcome = doc
+            .export_file(entry, out, ExportMode::Copy)
+            .await
+            .context("export file")?
+            .finish()
+            .await
+            .context("export finish")?;
+        let got_bytes = tokio::fs::read(export_outcome.path)
+            .await
+            .context("tokio read")?;
+        assert_eq!(buf, got_bytes);
+
+        Ok(())
+    }
 }
@@ -1,9 +1,12 @@
 use anyhow::Result;
 use futures_lite::{Stream, StreamExt};
-use iroh_bytes::Tag;
+use iroh_bytes::{HashAndFormat, Tag};
 use quic_rpc::{RpcClient, ServiceConnection};
 
-use crate::rpc_protocol::{DeleteTagRequest, ListTagsRequest, ListTagsResponse, ProviderService};
+use crate::rpc_protocol::{
+    CompareAndSwapTagRequest, CompareAndSwapTagResponse, DeleteTagRequest, ListTagsRequest,
+    ListTagsResponse, ProviderService, RenameTagRequest, SetTagRequest,
+};
 
 /// Iroh tags client.
 #[derive(Debug, Clone)]
@@ -17,13 +20,147 @@ where
 {
     /// List all tags.
     pub async fn list(&self) -> Result<impl Stream<Item = Result<ListTagsResponse>>> {
-        let stream = self.rpc.server_streaming(ListTagsRequest).await?;
+        self.list_request(ListTagsRequest::default()).await
+    }
+
+    /// List all tags starting with `prefix`.
+    ///
+    /// Tags can be organized hierarchically with `/` separated names, e.g. `builds/latest`.
+    /// All tags in the `builds` namespace are listed with the prefix `builds/`.
+    pub async fn list_prefix(
+        &self,
+        prefix: impl Into<Tag>,
+    ) -> Result<impl Stream<Item = Result<ListTagsResponse>>> {
+        let prefix = Some(prefix.into());
+        self.list_request(ListTagsRequest { prefix }).await
+    }
+
+    async fn list_request(
+        &self,
+        req: ListTagsRequest,
+    ) -> Result<impl Stream<Item = Result<ListTagsResponse>>> {
+        let stream = self.rpc.server_streaming(req).await?;
         Ok(stream.map(|res| res.map_err(anyhow::Error::from)))
     }
 
+    /// Set a tag, replacing its previous value if any.
+    pub async fn set(&self, name: Tag, value: HashAndFormat) -> Result<()> {
+        self.rpc.rpc(SetTagRequest { name, value }).await??;
+        Ok(())
+    }
+
+    /// Atomically set a tag to `value`, if its current value is `expected`.
+    ///
+    /// `None` stands for a tag that does not exist, so this can also create a tag only if it
+    /// is absent, or delete it only if it was not changed in the meantime. If the current
+    /// value differs from `expected`, the tag is left unchanged and the inner result contains
+    /// the current value.
+    pub async fn compare_and_swap(
+        &self,
+        name: Tag,
+        expected: Option<HashAndFormat>,
+        value: Option<HashAndFormat>,
+    ) -> Result<std::result::Result<(), Option<HashAndFormat>>> {
+        let CompareAndSwapTagResponse { result } = self
+            .rpc
+            .rpc(CompareAndSwapTagRequest {
+                name,
+                expected,
+                value,
+            })
+            .await??;
+        Ok(result)
+    }
+
+    /// Rename a tag.
+    ///
+    /// Fails if the tag does not exist, or if a tag named `to` already exists.
+    pub async fn rename(&self, from: Tag, to: Tag) -> Result<()> {
+        self.rpc.rpc(RenameTagRequest { from, to }).await??;
+        Ok(())
+    }
+
     /// Delete a tag.
     pub async fn delete(&self, name: Tag) -> Result<()> {
         self.rpc.rpc(DeleteTagRequest { name }).await??;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tags() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let client = node.client();
+        let a = HashAndFormat::raw(client.blobs.add_bytes(&b"a"[..]).await?.hash);
+        let b = HashAndFormat::raw(client.blobs.add_bytes(&b"b"[..]).await?.hash);
+        let names =
+            |tags: Vec<ListTagsResponse>| tags.into_iter().map(|tag| tag.name).collect::<Vec<_>>();
+
+        client.tags.set(Tag::from("builds/latest"), a).await?;
+        client.tags.set(Tag::from("builds/stable"), a).await?;
+        client.tags.set(Tag::from("buildsx"), b).await?;
+        let tags: Vec<_> = client
+            .tags
+            .list_prefix("builds/")
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(
+            names(tags),
+            [Tag::from("builds/latest"), Tag::from("builds/stable")]
+        );
+
+        // compare and swap
+        let latest = Tag::from("builds/latest");
+        let res = client
+            .tags
+            .compare_and_swap(latest.clone(), Some(b), Some(b))
+            .await?;
+        assert_eq!(res, Err(Some(a)));
+        let res = client
+            .tags
+            .compare_and_swap(latest.clone(), Some(a), Some(b))
+            .await?;
+        assert_eq!(res, Ok(()));
+        let res = client
+            .tags
+            .compare_and_swap(Tag::from("builds/next"), None, Some(a))
+            .await?;
+        assert_eq!(res, Ok(()));
+
+        // rename
+        let stable = Tag::from("builds/stable");
+        assert!(client
+            .tags
+            .rename(stable.clone(), latest.clone())
+            .await
+            .is_err());
+        client
+            .tags
+            .rename(stable, Tag::from("releases/1.0"))
+            .await?;
+        let tags: Vec<_> = client
+            .tags
+            .list_prefix("releases/")
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].hash, a.hash);
+        let tags: Vec<_> = client
+            .tags
+            .list_prefix("builds/")
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(names(tags), [latest, Tag::from("builds/next")]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,719 @@
+//! An HTTP gateway to the content of a node.
+//!
+//! The gateway serves the following routes:
+//!
+//! - `/blob/<hash>`: a raw blob.
+//! - `/collection/<hash>`: the names in a collection, one per line.
+//! - `/collection/<hash>/<name>`: an entry of a collection.
+//! - `/doc/<namespace>/<key>`: the content of the latest entry for a key in a document.
+//! - `/ticket/<ticket>`: the raw blob of a [`BlobTicket`]. Missing ranges are downloaded from
+//!   the node in the ticket when they are requested.
+//! - `/bao/<hash>?ranges=<ranges>`: the bao encoding of ranges of a blob, which can be
+//!   verified by the client. See `iroh_bytes::get::http` for the format and a client.
+//!
+//! Blobs are served with single `Range` requests, an `ETag` derived from the hash, and the
+//! MIME type stored in the blob metadata. If there is none, the content type is guessed from
+//! the name of the entry or sniffed from the content.
+//!
+//! By default, only content that is already on the node is served. Downloading content from
+//! other nodes, for tickets and missing document content, must be enabled with
+//! [`Options::with_remote_fetch`], since it lets anyone who can reach the gateway make the
+//! node connect to arbitrary nodes.
+//!
+//! All content is served with `X-Content-Type-Options: nosniff`. Content that a browser would
+//! execute, like HTML or SVG, is additionally served with `Content-Security-Policy: sandbox`,
+//! so it can not act on behalf of the gateway origin.
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    extract::{Path, Query as UrlQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use iroh_base::ticket::BlobTicket;
+use iroh_bytes::{protocol::RangeSpec, util::mime, BlobFormat, Hash};
+use iroh_net::NodeAddr;
+use iroh_sync::{store::Query, NamespaceId};
+use quic_rpc::ServiceConnection;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{debug, info};
+
+use crate::{client::Iroh, rpc_protocol::ProviderService};
+
+/// Cache control for content addressed data, which never changes.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Content types that browsers execute or render as active documents.
+const ACTIVE_CONTENT_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "application/xml",
+    "text/xml",
+    "text/javascript",
+    "application/javascript",
+];
+
+/// Options for a [`Gateway`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Whether content that is not on the node is downloaded from other nodes.
+    pub remote_fetch: bool,
+}
+
+impl Options {
+    /// Allow the gateway to download content from other nodes.
+    ///
+    /// This enables the `/ticket/<ticket>` route, and downloads missing content of documents
+    /// from the peers of the document. Disabled by default.
+    pub fn with_remote_fetch(mut self, remote_fetch: bool) -> Self {
+        self.remote_fetch = remote_fetch;
+        self
+    }
+}
+
+/// The state shared by all routes.
+#[derive(Debug, Clone)]
+struct AppState<C> {
+    iroh: Iroh<C>,
+    options: Options,
+}
+
+/// An HTTP gateway serving the content of a node.
+///
+/// The gateway is stopped when this is dropped.
+#[derive(Debug)]
+pub struct Gateway {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Gateway {
+    /// Spawn a gateway for the node behind `iroh`, listening on `addr`.
+    pub async fn spawn<C>(iroh: Iroh<C>, addr: SocketAddr, options: Options) -> Result<Self>
+    where
+        C: ServiceConnection<ProviderService>,
+    {
+        let app = Router::new()
+            .route("/blob/:hash", get(blob::<C>))
+            .route("/collection/:hash", get(collection::<C>))
+            .route("/collection/:hash/*name", get(collection_entry::<C>))
+            .route("/doc/:namespace/*key", get(doc_entry::<C>))
+            .route("/ticket/:ticket", get(ticket::<C>))
+            .route("/bao/:hash", get(bao::<C>))
+            .with_state(AppState { iroh, options });
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("gateway listening on {local_addr}");
+        let task = tokio::task::spawn(async move {
+            if let Err(err) = axum::serve(listener, app).await {
+                debug!("gateway stopped: {err}");
+            }
+        });
+        Ok(Self { local_addr, task })
+    }
+
+    /// The address the gateway is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// An error response.
+#[derive(Debug)]
+struct GatewayError(StatusCode, String);
+
+impl GatewayError {
+    fn not_found(what: impl std::fmt::Display) -> Self {
+        Self(StatusCode::NOT_FOUND, format!("{what} not found"))
+    }
+
+    fn bad_request(err: impl std::fmt::Display) -> Self {
+        Self(StatusCode::BAD_REQUEST, err.to_string())
+    }
+
+    fn forbidden(err: impl std::fmt::Display) -> Self {
+        Self(StatusCode::FORBIDDEN, err.to_string())
+    }
+}
+
+impl From<anyhow::Error> for GatewayError {
+    fn from(err: anyhow::Error) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        (self.0, [NOSNIFF], self.1).into_response()
+    }
+}
+
+const NOSNIFF: (header::HeaderName, HeaderValue) = (
+    header::X_CONTENT_TYPE_OPTIONS,
+    HeaderValue::from_static("nosniff"),
+);
+
+type GatewayResult = std::result::Result<Response, GatewayError>;
+
+fn parse_hash(hash: &str) -> std::result::Result<Hash, GatewayError> {
+    hash.parse().map_err(GatewayError::bad_request)
+}
+
+async fn blob<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, .. }): State<AppState<C>>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> GatewayResult {
+    let hash = parse_hash(&hash)?;
+    serve_blob(&iroh, hash, None, &headers, None).await
+}
+
+async fn collection<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, .. }): State<AppState<C>>,
+    Path(hash): Path<String>,
+) -> GatewayResult {
+    let hash = parse_hash(&hash)?;
+    let collection = iroh
+        .blobs
+        .get_collection(hash)
+        .await
+        .map_err(|_| GatewayError::not_found("collection"))?;
+    let listing = collection
+        .iter()
+        .map(|(name, _)| format!("{name}\n"))
+        .collect::<String>();
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            ),
+            NOSNIFF,
+        ],
+        listing,
+    )
+        .into_response())
+}
+
+async fn collection_entry<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, .. }): State<AppState<C>>,
+    Path((hash, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> GatewayResult {
+    let hash = parse_hash(&hash)?;
+    let collection = iroh
+        .blobs
+        .get_collection(hash)
+        .await
+        .map_err(|_| GatewayError::not_found("collection"))?;
+    let (_, hash) = collection
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .ok_or_else(|| GatewayError::not_found("entry"))?;
+    serve_blob(&iroh, *hash, Some(&name), &headers, None).await
+}
+
+async fn doc_entry<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, options }): State<AppState<C>>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> GatewayResult {
+    let namespace: NamespaceId = namespace.parse().map_err(GatewayError::bad_request)?;
+    let doc = iroh
+        .docs
+        .open(namespace)
+        .await
+        .ok()
+        .flatten()
+        .ok_or_else(|| GatewayError::not_found("document"))?;
+    // keys of imported files are terminated with a null byte
+    let mut entry = None;
+    for key in [key.as_bytes().to_vec(), [key.as_bytes(), b"\0"].concat()] {
+        let query = Query::single_latest_per_key().key_exact(key);
+        entry = doc.get_one(query).await?;
+        if entry.is_some() {
+            break;
+        }
+    }
+    let entry = entry.ok_or_else(|| GatewayError::not_found("entry"))?;
+    // document content may be missing, and is downloaded from the peers of the document if allowed
+    let fetch_from = options.remote_fetch.then(Vec::new);
+    serve_blob(
+        &iroh,
+        entry.content_hash(),
+        Some(&key),
+        &headers,
+        fetch_from,
+    )
+    .await
+}
+
+async fn ticket<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, options }): State<AppState<C>>,
+    Path(ticket): Path<String>,
+    headers: HeaderMap,
+) -> GatewayResult {
+    if !options.remote_fetch {
+        return Err(GatewayError::forbidden(
+            "downloading from other nodes is disabled on this gateway",
+        ));
+    }
+    let ticket: BlobTicket = ticket.parse().map_err(GatewayError::bad_request)?;
+    let (node, hash, format) = ticket.into_parts();
+    if format != BlobFormat::Raw {
+        return Err(GatewayError::bad_request(
+            "only tickets for raw blobs are supported",
+        ));
+    }
+    serve_blob(&iroh, hash, None, &headers, Some(vec![node])).await
+}
+
+/// Query of the `/bao/<hash>` route.
+#[derive(Debug, serde::Deserialize)]
+struct BaoQuery {
+    /// The text form of a [`RangeSpec`]. Defaults to the entire blob.
+    ranges: Option<String>,
+}
+
+async fn bao<C: ServiceConnection<ProviderService>>(
+    State(AppState { iroh, .. }): State<AppState<C>>,
+    Path(hash): Path<String>,
+    UrlQuery(query): UrlQuery<BaoQuery>,
+    headers: HeaderMap,
+) -> GatewayResult {
+    let hash = parse_hash(&hash)?;
+    let ranges = match query.ranges {
+        Some(ranges) => ranges
+            .parse::<RangeSpec>()
+            .map_err(GatewayError::bad_request)?,
+        None => RangeSpec::all(),
+    };
+    let etag = format!("\"{hash}-{ranges}\"");
+    let stream = iroh
+        .blobs
+        .read_bao(hash, ranges)
+        .await
+        .map_err(|_| GatewayError::not_found("blob"))?;
+    if not_modified(&headers, &etag) {
+        return Ok(not_modified_response(&etag));
+    }
+
+    let mut response = Response::new(Body::from_stream(stream));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("valid etag"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    headers.insert(NOSNIFF.0, NOSNIFF.1);
+    Ok(response)
+}
+
+/// Whether any `If-None-Match` header matches `etag`, which must be a strong entity tag.
+///
+/// The headers can contain `*` or lists of entity tags, which are compared with the weak
+/// comparison function, as required for `If-None-Match`.
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// A `304 Not Modified` response for a resource with `etag`.
+fn not_modified_response(etag: &str) -> Response {
+    let etag = HeaderValue::from_str(etag).expect("valid etag");
+    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+}
+
+/// Whether browsers would execute or render content of this type as an active document.
+fn is_active_content(content_type: &HeaderValue) -> bool {
+    let Ok(content_type) = content_type.to_str() else {
+        // be conservative with content types we can not parse
+        return true;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    ACTIVE_CONTENT_TYPES.contains(&essence.as_str())
+}
+
+/// Serve a blob, honoring `Range` and `If-None-Match` headers.
+///
+/// If `fetch_from` is set, missing ranges of the blob are downloaded before they are served.
+/// Otherwise, only complete blobs are served.
+async fn serve_blob<C: ServiceConnection<ProviderService>>(
+    iroh: &Iroh<C>,
+    hash: Hash,
+    name: Option<&str>,
+    headers: &HeaderMap,
+    fetch_from: Option<Vec<NodeAddr>>,
+) -> GatewayResult {
+    let etag = format!("\"{hash}\"");
+    let read_at = |offset: u64, len: usize| {
+        let fetch_from = fetch_from.clone();
+        async move {
+            match fetch_from {
+                Some(nodes) => {
+                    iroh.blobs
+                        .read_at_with_download(hash, offset, Some(len), nodes)
+                        .await
+                }
+                None => iroh.blobs.read_at(hash, offset, Some(len)).await,
+            }
+        }
+    };
+
+    let mut head = read_at(0, mime::SNIFF_LEN)
+        .await
+        .map_err(|_| GatewayError::not_found("blob"))?;
+    if !head.is_complete() && fetch_from.is_none() {
+        return Err(GatewayError::not_found("complete blob"));
+    }
+    // only check after the lookup, so `If-None-Match: *` does not match missing blobs
+    if not_modified(headers, &etag) {
+        return Ok(not_modified_response(&etag));
+    }
+    let size = head.size();
+    // prefer the MIME type stored with the blob, if it is a valid header value
+    let stored = iroh
+        .blobs
+        .meta(hash)
+        .await
+        .ok()
+        .and_then(|meta| meta.mime_type)
+        .and_then(|mime_type| HeaderValue::from_str(&mime_type).ok());
+    let content_type = match stored {
+        Some(content_type) => content_type,
+        None => HeaderValue::from_static(match name.and_then(mime::content_type_from_name) {
+            Some(content_type) => content_type,
+            None => mime::sniff_content_type(&head.read_to_bytes().await?),
+        }),
+    };
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, size));
+    let (status, start, end) = match range {
+        None | Some(Err(RangeError::Unsupported)) => (StatusCode::OK, 0, size),
+        Some(Ok((start, end))) => (StatusCode::PARTIAL_CONTENT, start, end),
+        Some(Err(RangeError::Unsatisfiable)) => {
+            let content_range = format!("bytes */{size}");
+            let content_range = HeaderValue::from_str(&content_range).expect("valid content range");
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, content_range), NOSNIFF],
+            )
+                .into_response());
+        }
+    };
+    let len = end - start;
+    let body = if len == 0 {
+        Body::empty()
+    } else {
+        let reader = read_at(start, len as usize).await?;
+        Body::from_stream(reader)
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    if is_active_content(&content_type) {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        );
+    }
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(NOSNIFF.0, NOSNIFF.1);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("valid etag"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {start}-{}/{size}", end - 1);
+        headers.insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).expect("valid content range"),
+        );
+    }
+    Ok(response)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeError {
+    /// The header is malformed, or requests multiple ranges. The whole blob is served.
+    Unsupported,
+    /// The range is outside of the blob.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a blob of `size` bytes into a half-open byte range.
+fn parse_range(value: &str, size: u64) -> std::result::Result<(u64, u64), RangeError> {
+    let spec = value
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or(RangeError::Unsupported)?;
+    if spec.contains(',') {
+        return Err(RangeError::Unsupported);
+    }
+    let (start, end) = spec.split_once('-').ok_or(RangeError::Unsupported)?;
+    let parse = |s: &str| s.trim().parse::<u64>().map_err(|_| RangeError::Unsupported);
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return Err(RangeError::Unsupported),
+        // the last `suffix` bytes
+        ("", suffix) => (size.saturating_sub(parse(suffix)?), size),
+        (start, "") => (parse(start)?, size),
+        (start, end) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end < start {
+                return Err(RangeError::Unsupported);
+            }
+            (start, end.saturating_add(1).min(size))
+        }
+    };
+    if start >= size {
+        return Err(RangeError::Unsatisfiable);
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Ok((0, 10)));
+        assert_eq!(parse_range("bytes=90-", 100), Ok((90, 100)));
+        assert_eq!(parse_range("bytes=-10", 100), Ok((90, 100)));
+        assert_eq!(parse_range("bytes=-200", 100), Ok((0, 100)));
+        assert_eq!(parse_range("bytes=50-200", 100), Ok((50, 100)));
+        assert_eq!(
+            parse_range("bytes=100-", 100),
+            Err(RangeError::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range("bytes=0-1,5-6", 100),
+            Err(RangeError::Unsupported)
+        );
+        assert_eq!(parse_range("bytes=5-1", 100), Err(RangeError::Unsupported));
+        assert_eq!(parse_range("items=0-1", 100), Err(RangeError::Unsupported));
+    }
+
+    #[tokio::test]
+    async fn bao_roundtrip() -> Result<()> {
+        use bao_tree::{ChunkNum, ChunkRanges};
+        use iroh_bytes::{
+            get::{self, error::GetError},
+            store::{Map, MapEntry},
+        };
+        use iroh_io::AsyncSliceReader;
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let hash = node.client().blobs.add_bytes(data.clone()).await?.hash;
+        let gateway = Gateway::spawn(
+            node.client().clone(),
+            "127.0.0.1:0".parse()?,
+            Options::default(),
+        )
+        .await?;
+        let base: reqwest::Url = format!("http://{}", gateway.local_addr()).parse()?;
+        let client = reqwest::Client::new();
+
+        let db = iroh_bytes::store::mem::Store::new();
+        let first = RangeSpec::new(ChunkRanges::from(..ChunkNum(16)));
+        get::http::get_to_db(&db, &client, &base, &hash, &first).await?;
+        let entry = db.get(&hash).await?.expect("first chunks downloaded");
+        assert!(!entry.is_complete());
+        get::http::get_to_db(&db, &client, &base, &hash, &RangeSpec::all()).await?;
+        let entry = db.get(&hash).await?.expect("blob downloaded");
+        assert!(entry.is_complete());
+        let content = entry.data_reader().await?.read_at(0, data.len()).await?;
+        assert_eq!(content.as_ref(), data.as_slice());
+
+        let missing = Hash::new(b"missing");
+        let res = get::http::get_to_db(&db, &client, &base, &missing, &RangeSpec::all()).await;
+        assert!(matches!(res, Err(GetError::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = "\"abc\"";
+        let matches = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+            }
+            not_modified(&headers, etag)
+        };
+        assert!(matches(&["\"abc\""]));
+        assert!(matches(&["W/\"abc\""]));
+        assert!(matches(&["*"]));
+        assert!(matches(&["\"x\", W/\"abc\""]));
+        assert!(matches(&["\"x\"", "\"abc\""]));
+        assert!(!matches(&[]));
+        assert!(!matches(&["\"x\", \"y\""]));
+        assert!(!matches(&["abc"]));
+    }
+
+    #[tokio::test]
+    async fn serve_blob_headers() -> Result<()> {
+        // reqwest uses a different version of the http crate
+        use reqwest::header;
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let data = b"<html><body>hello</body></html>".to_vec();
+        let size = data.len();
+        let hash = node.client().blobs.add_bytes(data.clone()).await?.hash;
+        let gateway = Gateway::spawn(
+            node.client().clone(),
+            "127.0.0.1:0".parse()?,
+            Options::default(),
+        )
+        .await?;
+        let url = format!("http://{}/blob/{hash}", gateway.local_addr());
+        let client = reqwest::Client::new();
+        let etag = format!("\"{hash}\"");
+
+        // 200 with the whole blob, sandboxed since it is html
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let headers = res.headers().clone();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(headers[header::CONTENT_LENGTH], size.to_string().as_str());
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "sandbox");
+        assert_eq!(res.bytes().await?.as_ref(), data.as_slice());
+
+        // 206 for a single range
+        let res = client
+            .get(&url)
+            .header(header::RANGE, "bytes=6-11")
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        let headers = res.headers().clone();
+        let content_range = format!("bytes 6-11/{size}");
+        assert_eq!(headers[header::CONTENT_RANGE], content_range.as_str());
+        assert_eq!(headers[header::CONTENT_LENGTH], "6");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(res.bytes().await?.as_ref(), &data[6..12]);
+
+        // 304 for matching entity tags
+        for value in [
+            etag.clone(),
+            format!("W/{etag}"),
+            format!("\"x\", {etag}"),
+            "*".into(),
+        ] {
+            let res = client
+                .get(&url)
+                .header(header::IF_NONE_MATCH, value)
+                .send()
+                .await?;
+            assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+            assert_eq!(res.headers()[header::ETAG], etag.as_str());
+        }
+        let res = client
+            .get(&url)
+            .header(header::IF_NONE_MATCH, "\"other\"")
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+        // 416 for a range after the end of the blob
+        let res = client
+            .get(&url)
+            .header(header::RANGE, format!("bytes={size}-"))
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::RANGE_NOT_SATISFIABLE);
+        let content_range = format!("bytes */{size}");
+        assert_eq!(res.headers()[header::CONTENT_RANGE], content_range.as_str());
+
+        // `*` does not match a missing blob
+        let missing = Hash::new(b"missing");
+        let res = client
+            .get(format!("http://{}/blob/{missing}", gateway.local_addr()))
+            .header(header::IF_NONE_MATCH, "*")
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+        // plain text is not sandboxed
+        let text = node
+            .client()
+            .blobs
+            .add_bytes(b"just text".to_vec())
+            .await?
+            .hash;
+        let res = client
+            .get(format!("http://{}/blob/{text}", gateway.local_addr()))
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert!(res.headers().get(header::CONTENT_SECURITY_POLICY).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remote_fetch_is_opt_in() -> Result<()> {
+        let node = crate::node::Node::memory().spawn().await?;
+        let hash = node.client().blobs.add_bytes(b"hello".to_vec()).await?.hash;
+        let ticket = BlobTicket::new(node.my_addr().await?, hash, BlobFormat::Raw)?;
+        let client = reqwest::Client::new();
+
+        let gateway = Gateway::spawn(
+            node.client().clone(),
+            "127.0.0.1:0".parse()?,
+            Options::default(),
+        )
+        .await?;
+        let url = format!("http://{}/ticket/{ticket}", gateway.local_addr());
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+        let gateway = Gateway::spawn(
+            node.client().clone(),
+            "127.0.0.1:0".parse()?,
+            Options::default().with_remote_fetch(true),
+        )
+        .await?;
+        let url = format!("http://{}/ticket/{ticket}", gateway.local_addr());
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(res.bytes().await?.as_ref(), b"hello");
+        Ok(())
+    }
+}
@@ -23,3 +23,9 @@ pub mod util;
 /// Expose metrics module
 #[cfg(feature = "metrics")]
 pub mod metrics;
+
+#[cfg(feature = "fuse")]
+pub mod mount;
+
+#[cfg(feature = "gateway")]
+pub mod gateway;
@@ -0,0 +1,658 @@
+//! Mount the content of a node as a read-only file system.
+//!
+//! The file system has two top-level directories. `tags` contains an entry per tag: a tagged
+//! collection is a directory with the entries of the collection, a tagged raw blob is a file.
+//! `docs` contains a directory per document, with a file for the latest entry of each key.
+//! Names containing `/` are mapped to nested directories.
+//!
+//! The directory structure is read when mounting. File contents are read lazily through the
+//! node, and missing ranges of incomplete blobs are downloaded on demand when they are read.
+//! Blobs that are not in the store at all are listed, but their size is only known once it
+//! was fetched; until then looking them up fails with `EIO`.
+//!
+//! This requires FUSE, and is only supported on Linux.
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    Request,
+};
+use futures_buffered::BufferedStreamExt;
+use futures_lite::StreamExt;
+use iroh_bytes::{BlobFormat, Hash};
+use iroh_net::{key::PublicKey, NodeAddr};
+use iroh_sync::store::Query;
+use quic_rpc::ServiceConnection;
+use tokio::runtime::Handle;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{BlobReader, Iroh},
+    rpc_protocol::{ListTagsResponse, ProviderService},
+};
+
+/// How long the kernel may cache attributes and lookups.
+///
+/// The directory structure does not change while mounted, so this can be long.
+const TTL: Duration = Duration::from_secs(60);
+
+/// The inode of the root directory.
+const ROOT_INO: u64 = 1;
+
+/// How many collections are loaded concurrently when mounting.
+const LOAD_PARALLELISM: usize = 4;
+
+/// A mounted file system.
+///
+/// The file system is unmounted when this is dropped.
+#[derive(derive_more::Debug)]
+pub struct Mount {
+    #[debug("BackgroundSession")]
+    session: fuser::BackgroundSession,
+}
+
+impl Mount {
+    /// Unmount the file system, and wait for it to shut down.
+    pub fn unmount(self) {
+        self.session.join()
+    }
+}
+
+/// Mount the tags and documents of the node at `mountpoint`.
+///
+/// Reads of incomplete or missing blobs download the missing ranges from `nodes`. Content of
+/// a document is also downloaded from the sync peers the document had when it was mounted.
+///
+/// Must be called from within a tokio runtime, which is used to talk to the node.
+pub async fn mount<C>(
+    iroh: &Iroh<C>,
+    mountpoint: impl AsRef<Path>,
+    nodes: Vec<NodeAddr>,
+) -> Result<Mount>
+where
+    C: ServiceConnection<ProviderService>,
+{
+    let inodes = Inodes::load(iroh, nodes).await?;
+    let fs = IrohFs {
+        iroh: iroh.clone(),
+        rt: Handle::current(),
+        inodes,
+    };
+    let options = [
+        MountOption::RO,
+        MountOption::FSName("iroh".to_string()),
+        MountOption::DefaultPermissions,
+    ];
+    let session = fuser::spawn_mount2(fs, mountpoint, &options)?;
+    Ok(Mount { session })
+}
+
+#[derive(Debug)]
+enum Inode {
+    Dir {
+        parent: u64,
+        children: BTreeMap<String, u64>,
+    },
+    File {
+        hash: Hash,
+        /// The size of the blob, `None` if the blob is not in the store.
+        size: Option<u64>,
+        /// The nodes to download missing data from.
+        providers: Arc<Vec<NodeAddr>>,
+    },
+}
+
+/// The directory structure of the file system.
+///
+/// The inode number of an entry is its index plus one.
+#[derive(Debug)]
+struct Inodes(Vec<Inode>);
+
+impl Inodes {
+    fn new() -> Self {
+        Self(vec![Inode::Dir {
+            parent: ROOT_INO,
+            children: BTreeMap::new(),
+        }])
+    }
+
+    async fn load<C>(iroh: &Iroh<C>, nodes: Vec<NodeAddr>) -> Result<Self>
+    where
+        C: ServiceConnection<ProviderService>,
+    {
+        let mut inodes = Self::new();
+        let sizes = blob_sizes(iroh).await?;
+        let size = |hash: &Hash| sizes.get(hash).copied();
+
+        let tags = inodes.insert_dir(ROOT_INO, "tags");
+        let providers = Arc::new(nodes.clone());
+        let mut list = iroh
+            .tags
+            .list()
+            .await?
+            .map(|tag| {
+                let iroh = iroh.clone();
+                async move {
+                    let tag = tag?;
+                    let collection = match tag.format {
+                        BlobFormat::Raw => None,
+                        BlobFormat::HashSeq => Some(iroh.blobs.get_collection(tag.hash).await),
+                    };
+                    anyhow::Ok((tag, collection))
+                }
+            })
+            .buffered_ordered(LOAD_PARALLELISM);
+        while let Some(res) = list.next().await {
+            let (ListTagsResponse { name, hash, .. }, collection) = res?;
+            let name = match std::str::from_utf8(&name.0) {
+                Ok(name) => name.to_string(),
+                Err(_) => hex::encode(&name.0),
+            };
+            match collection {
+                None => inodes.insert_path(tags, &name, hash, size(&hash), &providers),
+                Some(Ok(collection)) => {
+                    let Some(dir) = inodes.insert_path_dir(tags, &name) else {
+                        continue;
+                    };
+                    for (path, hash) in collection.iter() {
+                        inodes.insert_path(dir, path, *hash, size(hash), &providers);
+                    }
+                }
+                Some(Err(err)) => debug!("skipping tag {name}, not a collection: {err}"),
+            }
+        }
+
+        let docs = inodes.insert_dir(ROOT_INO, "docs");
+        let mut list = iroh.docs.list().await?;
+        while let Some(doc) = list.next().await {
+            let (id, _) = doc?;
+            let Some(doc) = iroh.docs.open(id).await? else {
+                continue;
+            };
+            let dir = inodes.insert_dir(docs, &id.to_string());
+            let mut providers = nodes.clone();
+            for peer in doc.get_sync_peers().await?.unwrap_or_default() {
+                match PublicKey::from_bytes(&peer) {
+                    Ok(peer) => providers.push(NodeAddr::new(peer)),
+                    Err(err) => debug!("skipping invalid sync peer of {id}: {err}"),
+                }
+            }
+            let providers = Arc::new(providers);
+            let mut entries = doc.get_many(Query::single_latest_per_key()).await?;
+            while let Some(entry) = entries.next().await {
+                let entry = entry?;
+                // keys of imported files are terminated with a null byte
+                let key = entry.key();
+                let key = key.strip_suffix(b"\0").unwrap_or(key);
+                let Ok(path) = std::str::from_utf8(key) else {
+                    debug!(
+                        "skipping key {} in {id}, not valid unicode",
+                        hex::encode(key)
+                    );
+                    continue;
+                };
+                // the entry states the size, so it is known even if the content is missing
+                let size = Some(entry.content_len());
+                inodes.insert_path(dir, path, entry.content_hash(), size, &providers);
+            }
+        }
+        Ok(inodes)
+    }
+
+    fn get(&self, ino: u64) -> Option<&Inode> {
+        self.0.get(usize::try_from(ino.checked_sub(1)?).ok()?)
+    }
+
+    fn get_mut(&mut self, ino: u64) -> Option<&mut Inode> {
+        self.0.get_mut(usize::try_from(ino.checked_sub(1)?).ok()?)
+    }
+
+    fn push(&mut self, parent: u64, name: &str, inode: Inode) -> u64 {
+        self.0.push(inode);
+        let ino = self.0.len() as u64;
+        if let Some(Inode::Dir { children, .. }) = self.0.get_mut(parent as usize - 1) {
+            children.insert(name.to_string(), ino);
+        }
+        ino
+    }
+
+    /// Get the directory `name` in `parent`, creating it if needed.
+    ///
+    /// Returns `None` if a file of that name exists.
+    fn get_or_insert_dir(&mut self, parent: u64, name: &str) -> Option<u64> {
+        let Some(Inode::Dir { children, .. }) = self.get(parent) else {
+            return None;
+        };
+        let existing = children.get(name).copied();
+        match existing {
+            Some(ino) => matches!(self.get(ino), Some(Inode::Dir { .. })).then_some(ino),
+            None => Some(self.push(
+                parent,
+                name,
+                Inode::Dir {
+                    parent,
+                    children: BTreeMap::new(),
+                },
+            )),
+        }
+    }
+
+    fn insert_dir(&mut self, parent: u64, name: &str) -> u64 {
+        self.get_or_insert_dir(parent, name)
+            .expect("directories are created before any files")
+    }
+
+    /// Create the directories for all components of `path` below `parent`.
+    fn insert_path_dir(&mut self, parent: u64, path: &str) -> Option<u64> {
+        path.split('/')
+            .filter(|name| !matches!(*name, "" | "." | ".."))
+            .try_fold(parent, |dir, name| self.get_or_insert_dir(dir, name))
+    }
+
+    /// Insert a file at `path` below `parent`, creating intermediate directories.
+    fn insert_path(
+        &mut self,
+        parent: u64,
+        path: &str,
+        hash: Hash,
+        size: Option<u64>,
+        providers: &Arc<Vec<NodeAddr>>,
+    ) {
+        let (dir, name) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some((dir, name)) => (self.insert_path_dir(parent, dir), name),
+            None => (Some(parent), path.trim_end_matches('/')),
+        };
+        let Some(dir) = dir.filter(|_| !matches!(name, "" | "." | "..")) else {
+            warn!("skipping entry {path:?}, it conflicts with another entry");
+            return;
+        };
+        let is_free = match self.get(dir) {
+            Some(Inode::Dir { children, .. }) => !children.contains_key(name),
+            _ => false,
+        };
+        if is_free {
+            let providers = providers.clone();
+            self.push(
+                dir,
+                name,
+                Inode::File {
+                    hash,
+                    size,
+                    providers,
+                },
+            );
+        } else {
+            warn!("skipping entry {path:?}, it conflicts with another entry");
+        }
+    }
+}
+
+/// Get the sizes of all blobs in the store.
+///
+/// For incomplete blobs this is the expected size, if it is known.
+async fn blob_sizes<C>(iroh: &Iroh<C>) -> Result<HashMap<Hash, u64>>
+where
+    C: ServiceConnection<ProviderService>,
+{
+    let mut sizes = HashMap::new();
+    let mut list = iroh.blobs.list().await?;
+    while let Some(blob) = list.next().await {
+        let blob = blob?;
+        sizes.insert(blob.hash, blob.size);
+    }
+    let mut list = iroh.blobs.list_incomplete().await?;
+    while let Some(blob) = list.next().await {
+        let blob = blob?;
+        // an incomplete blob of size zero has not received any data yet
+        if blob.expected_size > 0 {
+            sizes.insert(blob.hash, blob.expected_size);
+        }
+    }
+    Ok(sizes)
+}
+
+struct IrohFs<C: ServiceConnection<ProviderService>> {
+    iroh: Iroh<C>,
+    rt: Handle,
+    inodes: Inodes,
+}
+
+impl<C: ServiceConnection<ProviderService>> IrohFs<C> {
+    /// Read a range of a blob, downloading it from `providers` if it is incomplete.
+    async fn read_at(
+        iroh: &Iroh<C>,
+        hash: Hash,
+        offset: u64,
+        len: usize,
+        providers: &[NodeAddr],
+    ) -> Result<BlobReader> {
+        if providers.is_empty() {
+            iroh.blobs.read_at(hash, offset, Some(len)).await
+        } else {
+            let providers = providers.to_vec();
+            iroh.blobs
+                .read_at_with_download(hash, offset, Some(len), providers)
+                .await
+        }
+    }
+
+    /// Get the attributes of an inode.
+    ///
+    /// If the size of a file is not known yet, it is fetched, and the lookup fails with `EIO`
+    /// if that is not possible.
+    fn attr(&mut self, ino: u64) -> Result<FileAttr, libc::c_int> {
+        let (kind, size, perm, nlink) = match self.inodes.get(ino) {
+            None => return Err(libc::ENOENT),
+            Some(Inode::Dir { .. }) => (FileType::Directory, 0, 0o555, 2),
+            Some(Inode::File {
+                size: Some(size), ..
+            }) => (FileType::RegularFile, *size, 0o444, 1),
+            Some(Inode::File {
+                hash,
+                size: None,
+                providers,
+            }) => {
+                let hash = *hash;
+                // reading nothing only returns the size, fetching it if needed
+                let res = self
+                    .rt
+                    .block_on(Self::read_at(&self.iroh, hash, 0, 0, providers));
+                let size = match res {
+                    Ok(reader) => reader.size(),
+                    Err(err) => {
+                        debug!("failed to get the size of {hash}: {err}");
+                        return Err(libc::EIO);
+                    }
+                };
+                if let Some(Inode::File { size: known, .. }) = self.inodes.get_mut(ino) {
+                    *known = Some(size);
+                }
+                (FileType::RegularFile, size, 0o444, 1)
+            }
+        };
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+}
+
+impl<C: ServiceConnection<ProviderService>> Filesystem for IrohFs<C> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match (self.inodes.get(parent), name.to_str()) {
+            (Some(Inode::Dir { children, .. }), Some(name)) => children.get(name).copied(),
+            _ => None,
+        };
+        match ino.map_or(Err(libc::ENOENT), |ino| self.attr(ino)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let len = match self.attr(ino) {
+            Ok(attr) => attr.size,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let Some(Inode::File {
+            hash, providers, ..
+        }) = self.inodes.get(ino)
+        else {
+            reply.error(libc::EISDIR);
+            return;
+        };
+        let offset = offset.max(0) as u64;
+        let size = (size as u64).min(len.saturating_sub(offset)) as usize;
+        if size == 0 {
+            reply.data(&[]);
+            return;
+        }
+        let res = self.rt.block_on(async {
+            Self::read_at(&self.iroh, *hash, offset, size, providers)
+                .await?
+                .read_to_bytes()
+                .await
+        });
+        match res {
+            Ok(data) => reply.data(&data),
+            Err(err) => {
+                debug!("failed to read {hash} at {offset}: {err}");
+                reply.error(libc::EIO)
+            }
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Inode::Dir { parent, children }) = self.inodes.get(ino) else {
+            reply.error(libc::ENOTDIR);
+            return;
+        };
+        let entries = [
+            (ino, FileType::Directory, "."),
+            (*parent, FileType::Directory, ".."),
+        ]
+        .into_iter()
+        .chain(children.iter().map(|(name, &ino)| {
+            let kind = match self.inodes.get(ino) {
+                Some(Inode::Dir { .. }) => FileType::Directory,
+                _ => FileType::RegularFile,
+            };
+            (ino, kind, name.as_str())
+        }));
+        for (i, (ino, kind, name)) in entries.enumerate().skip(offset.max(0) as usize) {
+            // the offset is the offset of the next entry
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::{format::collection::Collection, util::SetTagOption};
+    use iroh_sync::NamespaceId;
+
+    use super::*;
+
+    fn lookup(inodes: &Inodes, path: &str) -> Option<u64> {
+        path.split('/').try_fold(ROOT_INO, |dir, name| {
+            let Some(Inode::Dir { children, .. }) = inodes.get(dir) else {
+                return None;
+            };
+            children.get(name).copied()
+        })
+    }
+
+    #[test]
+    fn insert_paths() {
+        let hash = Hash::new(b"hello");
+        let providers = Arc::new(Vec::new());
+        let mut inodes = Inodes::new();
+        let tags = inodes.insert_dir(ROOT_INO, "tags");
+        inodes.insert_path(tags, "a/b/c.txt", hash, Some(5), &providers);
+        inodes.insert_path(tags, "a/d.txt", hash, None, &providers);
+        // conflicts with the file and the directory
+        inodes.insert_path(tags, "a/d.txt/e.txt", hash, Some(5), &providers);
+        inodes.insert_path(tags, "a/b", hash, Some(5), &providers);
+
+        let c = lookup(&inodes, "tags/a/b/c.txt").unwrap();
+        assert!(matches!(
+            inodes.get(c),
+            Some(Inode::File { size: Some(5), .. })
+        ));
+        let d = lookup(&inodes, "tags/a/d.txt").unwrap();
+        assert!(matches!(
+            inodes.get(d),
+            Some(Inode::File { size: None, .. })
+        ));
+        let b = lookup(&inodes, "tags/a/b").unwrap();
+        assert!(matches!(inodes.get(b), Some(Inode::Dir { .. })));
+        assert_eq!(lookup(&inodes, "tags/a/d.txt/e.txt"), None);
+        assert!(inodes.get(0).is_none());
+    }
+
+    /// Add a tagged blob, a collection with a missing child and a document to the node.
+    ///
+    /// Returns the hash of the missing child and the id of the document.
+    async fn add_content(iroh: &crate::client::mem::Iroh) -> Result<(Hash, NamespaceId)> {
+        iroh.blobs.add_bytes_named(b"raw".to_vec(), "raw").await?;
+        let hello = iroh.blobs.add_bytes(b"hello".to_vec()).await?.hash;
+        let missing = Hash::new(b"missing");
+        let collection: Collection = [("a/hello.txt", hello), ("missing.txt", missing)]
+            .into_iter()
+            .collect();
+        iroh.blobs
+            .create_collection(collection, SetTagOption::Named("dir".into()), vec![])
+            .await?;
+        let author = iroh.authors.create().await?;
+        let doc = iroh.docs.create().await?;
+        doc.set_bytes(author, b"doc/key.txt".to_vec(), b"value".to_vec())
+            .await?;
+        Ok((missing, doc.id()))
+    }
+
+    /// Whether FUSE file systems can be mounted here.
+    fn fuse_available() -> bool {
+        let available = Path::new("/dev/fuse").exists();
+        if !available {
+            eprintln!("skipping test, FUSE is not available");
+        }
+        available
+    }
+
+    #[tokio::test]
+    async fn load() -> Result<()> {
+        let node = crate::node::Node::memory().spawn().await?;
+        let (missing, doc) = add_content(node.client()).await?;
+
+        let inodes = Inodes::load(node.client(), vec![]).await?;
+        let file = |path: &str| match lookup(&inodes, path).and_then(|ino| inodes.get(ino)) {
+            Some(Inode::File { hash, size, .. }) => Some((*hash, *size)),
+            _ => None,
+        };
+        assert_eq!(file("tags/raw"), Some((Hash::new(b"raw"), Some(3))));
+        assert_eq!(
+            file("tags/dir/a/hello.txt"),
+            Some((Hash::new(b"hello"), Some(5)))
+        );
+        // the size of a missing blob is not known
+        assert_eq!(file("tags/dir/missing.txt"), Some((missing, None)));
+        assert_eq!(
+            file(&format!("docs/{doc}/doc/key.txt")),
+            Some((Hash::new(b"value"), Some(5)))
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mount_and_read() -> Result<()> {
+        if !fuse_available() {
+            return Ok(());
+        }
+        let node = crate::node::Node::memory().spawn().await?;
+        let (_, doc) = add_content(node.client()).await?;
+
+        let dir = tempfile::tempdir()?;
+        let mount = mount(node.client(), dir.path(), vec![]).await?;
+        let root = dir.path().to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            assert_eq!(std::fs::read(root.join("tags/raw"))?, b"raw");
+            assert_eq!(std::fs::read(root.join("tags/dir/a/hello.txt"))?, b"hello");
+            let key = root.join(format!("docs/{doc}/doc/key.txt"));
+            assert_eq!(std::fs::read(key)?, b"value");
+
+            let mut names = std::fs::read_dir(root.join("tags/dir"))?
+                .map(|entry| Ok(entry?.file_name()))
+                .collect::<Result<Vec<_>>>()?;
+            names.sort();
+            assert_eq!(names, ["a", "missing.txt"]);
+            // there is no node to download the missing blob from
+            let err = std::fs::metadata(root.join("tags/dir/missing.txt")).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EIO));
+
+            mount.unmount();
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mount_downloads_missing_blobs() -> Result<()> {
+        if !fuse_available() {
+            return Ok(());
+        }
+        let provider = crate::node::Node::memory().spawn().await?;
+        let data = vec![7u8; 100_000];
+        let hash = provider.client().blobs.add_bytes(data.clone()).await?.hash;
+
+        let node = crate::node::Node::memory().spawn().await?;
+        let collection: Collection = [("remote.bin", hash)].into_iter().collect();
+        node.client()
+            .blobs
+            .create_collection(collection, SetTagOption::Named("dir".into()), vec![])
+            .await?;
+
+        let dir = tempfile::tempdir()?;
+        let nodes = vec![provider.my_addr().await?];
+        let mount = mount(node.client(), dir.path(), nodes).await?;
+        let path = dir.path().join("tags/dir/remote.bin");
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            assert_eq!(std::fs::metadata(&path)?.len(), data.len() as u64);
+            assert!(std::fs::read(&path)? == data);
+            mount.unmount();
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+}
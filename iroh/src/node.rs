@@ -13,6 +13,7 @@ use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use futures_lite::{future::Boxed as BoxFuture, FutureExt, StreamExt};
 use iroh_bytes::downloader::Downloader;
+use iroh_bytes::provider::{PushPolicy, RateLimiter, RequestAuthorizationHandler};
 use iroh_bytes::store::Store as BaoStore;
 use iroh_bytes::BlobFormat;
 use iroh_bytes::Hash;
@@ -107,6 +108,9 @@ struct NodeInner<D> {
     rt: LocalPoolHandle,
     pub(crate) sync: SyncEngine,
     downloader: Downloader,
+    authorization_handler: Option<Arc<dyn RequestAuthorizationHandler>>,
+    push_policy: Option<Arc<dyn PushPolicy>>,
+    rate_limiter: RateLimiter,
 }
 
 /// Events emitted by the [`Node`] informing about the current status.
@@ -278,8 +282,13 @@ mod tests {
     use std::time::Duration;
 
     use anyhow::{bail, Context};
+    use bao_tree::{ChunkNum, ChunkRanges};
     use bytes::Bytes;
-    use iroh_bytes::provider::AddProgress;
+    use iroh_bytes::{
+        format::collection::Collection,
+        protocol::{PushRequest, RangeSpec, RangeSpecSeq},
+        provider::AddProgress,
+    };
     use iroh_net::relay::RelayMode;
 
     use crate::{
@@ -433,6 +442,7 @@ mod tests {
             hash,
             tag: SetTagOption::Auto,
             format: BlobFormat::Raw,
+            ranges: RangeSpecSeq::all(),
             mode: DownloadMode::Direct,
             nodes: vec![addr],
         };
@@ -448,4 +458,283 @@ mod tests {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_download_ranges() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+        let node1 = Node::memory().bind_port(0).spawn().await?;
+        let node2 = Node::memory().bind_port(0).spawn().await?;
+        let data = (0..1_000_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let BlobAddOutcome { hash, .. } = node1.blobs.add_bytes(data.clone()).await?;
+        let addr = node1.my_addr().await?;
+        let ranges = |start, end| {
+            RangeSpecSeq::from_ranges([ChunkRanges::from(ChunkNum(start)..ChunkNum(end))])
+        };
+
+        // download the first 64 chunks directly
+        let req = BlobDownloadRequest {
+            hash,
+            tag: SetTagOption::Auto,
+            format: BlobFormat::Raw,
+            ranges: ranges(0, 64),
+            mode: DownloadMode::Direct,
+            nodes: vec![addr.clone()],
+        };
+        node2.blobs.download(req).await?.await?;
+        assert!(matches!(
+            node2.blobs.status(hash).await?,
+            crate::client::BlobStatus::Partial { .. }
+        ));
+        let head = node2.blobs.read_at_to_bytes(hash, 0, Some(65536)).await?;
+        assert_eq!(&head[..], &data[..65536]);
+
+        // download the rest through the queue
+        let req = BlobDownloadRequest {
+            hash,
+            tag: SetTagOption::Auto,
+            format: BlobFormat::Raw,
+            ranges: RangeSpecSeq::from_ranges([ChunkRanges::from(ChunkNum(64)..)]),
+            mode: DownloadMode::Queued,
+            nodes: vec![addr],
+        };
+        node2.blobs.download(req).await?.await?;
+        assert!(matches!(
+            node2.blobs.status(hash).await?,
+            crate::client::BlobStatus::Complete { .. }
+        ));
+        assert_eq!(&node2.blobs.read_to_bytes(hash).await?[..], &data[..]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_progress_child_ids() -> Result<()> {
+        use futures_lite::StreamExt;
+        use iroh_bytes::get::db::{BlobId, DownloadProgress};
+
+        let _guard = iroh_test::logging::setup();
+        let provider = Node::memory().bind_port(0).spawn().await?;
+        let node = Node::memory().bind_port(0).spawn().await?;
+        let BlobAddOutcome { hash: a, .. } = provider.blobs.add_bytes(vec![1u8; 100]).await?;
+        let BlobAddOutcome { hash: b, .. } = provider.blobs.add_bytes(vec![2u8; 100]).await?;
+        let collection = [("a", a), ("b", b)].into_iter().collect();
+        let (hash, _) = provider
+            .blobs
+            .create_collection(collection, SetTagOption::Auto, vec![])
+            .await?;
+
+        let req = BlobDownloadRequest {
+            hash,
+            tag: SetTagOption::Auto,
+            format: BlobFormat::HashSeq,
+            ranges: RangeSpecSeq::all(),
+            mode: DownloadMode::Direct,
+            nodes: vec![provider.my_addr().await?],
+        };
+        let mut progress = node.blobs.download(req).await?;
+        let mut found = Vec::new();
+        while let Some(item) = progress.next().await {
+            if let DownloadProgress::Found { child, hash, .. } = item? {
+                found.push((u64::from(child), hash));
+            }
+        }
+        // the root is reported as such, the children by their offset in the hash seq, after
+        // the collection metadata at offset 1
+        assert_eq!(found[0], (u64::from(BlobId::Root), hash));
+        assert_eq!(found[2..], [(2, a), (3, b)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_swarm() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+        let data = (0..10_000_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let provider1 = Node::memory().bind_port(0).spawn().await?;
+        let provider2 = Node::memory().bind_port(0).spawn().await?;
+        let BlobAddOutcome { hash, .. } = provider1.blobs.add_bytes(data.clone()).await?;
+        provider2.blobs.add_bytes(data.clone()).await?;
+        let node = Node::memory().bind_port(0).spawn().await?;
+
+        // the blob is larger than a segment, so it is split among both providers
+        let req = BlobDownloadRequest {
+            hash,
+            tag: SetTagOption::Auto,
+            format: BlobFormat::Raw,
+            ranges: RangeSpecSeq::all(),
+            mode: DownloadMode::Queued,
+            nodes: vec![provider1.my_addr().await?, provider2.my_addr().await?],
+        };
+        node.blobs.download(req).await?.await?;
+        assert!(matches!(
+            node.blobs.status(hash).await?,
+            crate::client::BlobStatus::Complete { .. }
+        ));
+        assert_eq!(&node.blobs.read_to_bytes(hash).await?[..], &data[..]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_available_ranges() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+        let provider = Node::memory().bind_port(0).spawn().await?;
+        let partial = Node::memory().bind_port(0).spawn().await?;
+        let node = Node::memory().bind_port(0).spawn().await?;
+        let data = (0..1_000_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let BlobAddOutcome { hash, .. } = provider.blobs.add_bytes(data).await?;
+        let BlobAddOutcome { hash: hash2, .. } = provider.blobs.add_bytes(vec![1u8; 100]).await?;
+        let collection = [("a", hash), ("b", hash2)].into_iter().collect();
+        let (collection_hash, _) = provider
+            .blobs
+            .create_collection(collection, SetTagOption::Auto, vec![])
+            .await?;
+        let root_ranges = |ranges: RangeSpecSeq| ranges.iter().next().unwrap().to_chunk_ranges();
+
+        // complete blobs
+        let ranges = node
+            .blobs
+            .available_ranges(hash, BlobFormat::Raw, provider.my_addr().await?)
+            .await?;
+        assert_eq!(root_ranges(ranges), ChunkRanges::all());
+        let ranges = node
+            .blobs
+            .available_ranges(
+                collection_hash,
+                BlobFormat::HashSeq,
+                provider.my_addr().await?,
+            )
+            .await?;
+        // the root, the collection metadata and the two blobs
+        let children = ranges.iter().take(5).cloned().collect::<Vec<_>>();
+        let mut expected = vec![RangeSpec::all(); 4];
+        expected.push(RangeSpec::EMPTY);
+        assert_eq!(children, expected);
+
+        // a partial blob
+        let req = BlobDownloadRequest {
+            hash,
+            tag: SetTagOption::Auto,
+            format: BlobFormat::Raw,
+            ranges: RangeSpecSeq::from_ranges([ChunkRanges::from(ChunkNum(0)..ChunkNum(64))]),
+            mode: DownloadMode::Direct,
+            nodes: vec![provider.my_addr().await?],
+        };
+        partial.blobs.download(req).await?.await?;
+        let ranges = node
+            .blobs
+            .available_ranges(hash, BlobFormat::Raw, partial.my_addr().await?)
+            .await?;
+        assert_eq!(
+            root_ranges(ranges),
+            ChunkRanges::from(ChunkNum(0)..ChunkNum(64))
+        );
+
+        // an unknown blob
+        let ranges = node
+            .blobs
+            .available_ranges(hash2, BlobFormat::Raw, partial.my_addr().await?)
+            .await?;
+        assert_eq!(root_ranges(ranges), ChunkRanges::empty());
+
+        // the children of a huge hash seq are not listed
+        let children = iroh_bytes::protocol::MAX_HAVE_CHILDREN + 1;
+        let huge = (0..children)
+            .flat_map(|i| *Hash::new(i.to_le_bytes()).as_bytes())
+            .collect::<Vec<_>>();
+        let BlobAddOutcome { hash: huge, .. } = provider.blobs.add_bytes(huge).await?;
+        let res = node
+            .blobs
+            .available_ranges(huge, BlobFormat::HashSeq, provider.my_addr().await?)
+            .await;
+        assert!(res.unwrap_err().to_string().contains("too large"));
+        Ok(())
+    }
+
+    /// Accepts pushes from a single node.
+    #[derive(Debug)]
+    struct AcceptFrom(PublicKey);
+
+    impl PushPolicy for AcceptFrom {
+        fn accept(&self, node_id: PublicKey, _request: &PushRequest) -> BoxFuture<Result<()>> {
+            let accepted = node_id == self.0;
+            Box::pin(async move {
+                anyhow::ensure!(accepted, "not accepting pushes from {node_id}");
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push() -> Result<()> {
+        let _guard = iroh_test::logging::setup();
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let pusher = Node::memory().bind_port(0).spawn().await?;
+        let other = Node::memory().bind_port(0).spawn().await?;
+        let receiver = Node::memory()
+            .bind_port(0)
+            .push_policy(Arc::new(AcceptFrom(pusher.node_id())))
+            .spawn()
+            .await?;
+        let BlobAddOutcome { hash, .. } = pusher.blobs.add_bytes(data.clone()).await?;
+        other.blobs.add_bytes(data.clone()).await?;
+        let addr = receiver.my_addr().await?;
+
+        // pushes from other nodes are refused
+        let res = other.blobs.push(hash, BlobFormat::Raw, addr.clone()).await;
+        assert!(res.unwrap_err().to_string().contains("push refused"));
+        let tags = receiver
+            .tags
+            .list()
+            .await?
+            .try_collect::<_, _, Vec<_>>()
+            .await?;
+        assert!(tags.is_empty());
+
+        pusher
+            .blobs
+            .push(hash, BlobFormat::Raw, addr.clone())
+            .await?;
+        assert_eq!(&receiver.blobs.read_to_bytes(hash).await?[..], &data[..]);
+        // pushing the same data again does not add another tag
+        pusher.blobs.push(hash, BlobFormat::Raw, addr).await?;
+        let tags = receiver
+            .tags
+            .list()
+            .await?
+            .try_collect::<_, _, Vec<_>>()
+            .await?;
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].hash, hash);
+
+        // only the missing children of a hash seq are sent
+        let other_data = vec![7u8; 10_000];
+        let BlobAddOutcome {
+            hash: other_hash, ..
+        } = pusher.blobs.add_bytes(other_data.clone()).await?;
+        let collection: Collection = [("a", hash), ("b", other_hash)].into_iter().collect();
+        let (collection_hash, _) = pusher
+            .blobs
+            .create_collection(collection, SetTagOption::Auto, Vec::new())
+            .await?;
+        pusher
+            .blobs
+            .push(
+                collection_hash,
+                BlobFormat::HashSeq,
+                receiver.my_addr().await?,
+            )
+            .await?;
+        assert_eq!(
+            &receiver.blobs.read_to_bytes(other_hash).await?[..],
+            &other_data[..]
+        );
+        let collection = receiver.blobs.get_collection(collection_hash).await?;
+        assert_eq!(collection.len(), 2);
+
+        // nodes without a push policy refuse all pushes
+        let res = pusher
+            .blobs
+            .push(hash, BlobFormat::Raw, other.my_addr().await?)
+            .await;
+        assert!(res.unwrap_err().to_string().contains("push refused"));
+        Ok(())
+    }
 }
@@ -12,6 +12,7 @@ use iroh_base::key::SecretKey;
 use iroh_bytes::{
     downloader::Downloader,
     protocol::Closed,
+    provider::{PushPolicy, RateLimiter, RateLimits, RequestAuthorizationHandler},
     store::{GcMarkEvent, GcSweepEvent, Map, Store as BaoStore},
 };
 use iroh_gossip::net::{Gossip, GOSSIP_ALPN};
@@ -83,6 +84,9 @@ where
     keylog: bool,
     relay_mode: RelayMode,
     gc_policy: GcPolicy,
+    authorization_handler: Option<Arc<dyn RequestAuthorizationHandler>>,
+    push_policy: Option<Arc<dyn PushPolicy>>,
+    upload_rate_limits: RateLimits,
     node_discovery: NodeDiscoveryConfig,
     docs_store: iroh_sync::store::fs::Store,
     #[cfg(any(test, feature = "test-utils"))]
@@ -123,6 +127,9 @@ impl Default for Builder<iroh_bytes::store::mem::Store> {
             relay_mode: RelayMode::Default,
             rpc_endpoint: Default::default(),
             gc_policy: GcPolicy::Disabled,
+            authorization_handler: None,
+            push_policy: None,
+            upload_rate_limits: RateLimits::default(),
             docs_store: iroh_sync::store::Store::memory(),
             node_discovery: Default::default(),
             #[cfg(any(test, feature = "test-utils"))]
@@ -147,6 +154,9 @@ impl<D: Map> Builder<D> {
             relay_mode: RelayMode::Default,
             rpc_endpoint: Default::default(),
             gc_policy: GcPolicy::Disabled,
+            authorization_handler: None,
+            push_policy: None,
+            upload_rate_limits: RateLimits::default(),
             docs_store,
             node_discovery: Default::default(),
             #[cfg(any(test, feature = "test-utils"))]
@@ -208,6 +218,9 @@ where
             rpc_endpoint: self.rpc_endpoint,
             relay_mode: self.relay_mode,
             gc_policy: self.gc_policy,
+            authorization_handler: self.authorization_handler,
+            push_policy: self.push_policy,
+            upload_rate_limits: self.upload_rate_limits,
             docs_store,
             node_discovery: self.node_discovery,
             #[cfg(any(test, feature = "test-utils"))]
@@ -227,6 +240,9 @@ where
             rpc_endpoint: value,
             relay_mode: self.relay_mode,
             gc_policy: self.gc_policy,
+            authorization_handler: self.authorization_handler,
+            push_policy: self.push_policy,
+            upload_rate_limits: self.upload_rate_limits,
             docs_store: self.docs_store,
             node_discovery: self.node_discovery,
             #[cfg(any(test, feature = "test-utils"))]
@@ -253,6 +269,9 @@ where
             rpc_endpoint: ep,
             relay_mode: self.relay_mode,
             gc_policy: self.gc_policy,
+            authorization_handler: self.authorization_handler,
+            push_policy: self.push_policy,
+            upload_rate_limits: self.upload_rate_limits,
             docs_store: self.docs_store,
             node_discovery: self.node_discovery,
             #[cfg(any(test, feature = "test-utils"))]
@@ -268,6 +287,33 @@ where
         self
     }
 
+    /// Sets the handler deciding which get requests are served by the node.
+    ///
+    /// By default all requests for blobs in the store are served.
+    pub fn request_authorization_handler(
+        mut self,
+        handler: Arc<dyn RequestAuthorizationHandler>,
+    ) -> Self {
+        self.authorization_handler = Some(handler);
+        self
+    }
+
+    /// Sets the policy deciding which blobs pushed by other nodes are stored.
+    ///
+    /// By default all pushes are refused.
+    pub fn push_policy(mut self, policy: Arc<dyn PushPolicy>) -> Self {
+        self.push_policy = Some(policy);
+        self
+    }
+
+    /// Sets the limits for the rate at which blob data is sent to other nodes.
+    ///
+    /// By default the upload rate is not limited.
+    pub fn upload_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.upload_rate_limits = limits;
+        self
+    }
+
     /// Sets the relay servers to assist in establishing connectivity.
     ///
     /// Relay servers are used to discover other nodes by `PublicKey` and also help
@@ -425,6 +471,9 @@ where
             rt: lp.clone(),
             sync,
             downloader,
+            authorization_handler: self.authorization_handler,
+            push_policy: self.push_policy,
+            rate_limiter: RateLimiter::new(self.upload_rate_limits),
         });
         let task = {
             let gossip = gossip.clone();
@@ -647,6 +696,9 @@ where
                     GcSweepEvent::CustomWarning(text, _) => {
                         tracing::warn!("{}", text);
                     }
+                    GcSweepEvent::Evicted { tag, content, size } => {
+                        tracing::info!("evicted {} {:?}, freeing {} bytes", tag, content, size);
+                    }
                     GcSweepEvent::Error(err) => {
                         tracing::error!("Fatal error during GC mark {}", err);
                         continue 'outer;
@@ -692,6 +744,9 @@ async fn handle_connection<D: BaoStore>(
                 connecting,
                 node.db.clone(),
                 node.callbacks.clone(),
+                node.authorization_handler.clone(),
+                node.push_policy.clone(),
+                node.rate_limiter.clone(),
                 node.rt.clone(),
             )
             .await
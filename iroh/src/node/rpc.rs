@@ -1,30 +1,39 @@
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::io;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::{anyhow, ensure, Result};
+use bao_tree::{ChunkNum, ChunkRanges};
+use bytes::{Bytes, BytesMut};
 use futures_buffered::BufferedStreamExt;
 use futures_lite::{Stream, StreamExt};
 use genawaiter::sync::{Co, Gen};
 use iroh_base::rpc::RpcResult;
+use iroh_bytes::bundle::ReadBundleOutcome;
 use iroh_bytes::downloader::{DownloadRequest, Downloader};
 use iroh_bytes::export::ExportProgress;
-use iroh_bytes::format::collection::Collection;
+use iroh_bytes::format::{collection::Collection, diff, tree::Tree};
 use iroh_bytes::get::db::DownloadProgress;
 use iroh_bytes::get::Stats;
-use iroh_bytes::store::{ConsistencyCheckProgress, ExportFormat, ImportProgress, MapEntry};
-use iroh_bytes::util::progress::ProgressSender;
+use iroh_bytes::protocol::{PushRequest, RangeSpecSeq};
+use iroh_bytes::store::{
+    BaoBlobSize, ConsistencyCheckProgress, ExportFormat, ImportProgress, MapEntry,
+};
+use iroh_bytes::util::progress::{IdGenerator, IgnoreProgressSender, ProgressSender};
 use iroh_bytes::BlobFormat;
 use iroh_bytes::{
     hashseq::parse_hash_seq,
-    provider::AddProgress,
+    provider::{send_blob, AddProgress, SentStatus},
     store::{Store as BaoStore, ValidateProgress},
     util::progress::FlumeProgressSender,
-    HashAndFormat,
+    Hash, HashAndFormat, IROH_BLOCK_SIZE,
 };
-use iroh_io::AsyncSliceReader;
+use iroh_io::{AsyncSliceReader, AsyncSliceReaderExt, AsyncStreamWriter};
 use iroh_net::{MagicEndpoint, NodeAddr};
+use iroh_sync::{EncryptionKey, CONTENT_SEGMENT_SIZE};
 use quic_rpc::{
     server::{RpcChannel, RpcServerError},
     ServiceEndpoint,
@@ -34,18 +43,23 @@ use tracing::{debug, info};
 
 use crate::rpc_protocol::{
     BlobAddPathRequest, BlobAddPathResponse, BlobAddStreamRequest, BlobAddStreamResponse,
-    BlobAddStreamUpdate, BlobConsistencyCheckRequest, BlobDeleteBlobRequest, BlobDownloadRequest,
-    BlobDownloadResponse, BlobExportRequest, BlobExportResponse, BlobGetCollectionRequest,
-    BlobGetCollectionResponse, BlobListCollectionsRequest, BlobListCollectionsResponse,
-    BlobListIncompleteRequest, BlobListIncompleteResponse, BlobListRequest, BlobListResponse,
-    BlobReadAtRequest, BlobReadAtResponse, BlobValidateRequest, CreateCollectionRequest,
-    CreateCollectionResponse, DeleteTagRequest, DocExportFileRequest, DocExportFileResponse,
-    DocImportFileRequest, DocImportFileResponse, DocImportProgress, DocSetHashRequest,
+    BlobAddStreamUpdate, BlobAvailableRangesRequest, BlobAvailableRangesResponse,
+    BlobConsistencyCheckRequest, BlobDeleteBlobRequest, BlobDiffRequest, BlobDiffResponse,
+    BlobDownloadRequest, BlobDownloadResponse, BlobExportBundleRequest, BlobExportBundleResponse,
+    BlobExportRequest, BlobExportResponse, BlobGetCollectionRequest, BlobGetCollectionResponse,
+    BlobGetMetaRequest, BlobGetMetaResponse, BlobImportBundleRequest, BlobImportBundleResponse,
+    BlobListCollectionsRequest, BlobListCollectionsResponse, BlobListIncompleteRequest,
+    BlobListIncompleteResponse, BlobListMetaRequest, BlobListMetaResponse, BlobListRequest,
+    BlobListResponse, BlobPushRequest, BlobReadAtRequest, BlobReadAtResponse, BlobReadBaoRequest,
+    BlobReadBaoResponse, BlobUpdateMetaRequest, BlobValidateRequest, CompareAndSwapTagRequest,
+    CompareAndSwapTagResponse, CreateCollectionRequest, CreateCollectionResponse, DeleteTagRequest,
+    DocExportFileRequest, DocExportFileResponse, DocImportFileRequest, DocImportFileResponse,
+    DocImportProgress, DocImportSnapshotRequest, DocImportSnapshotResponse, DocSetHashRequest,
     DownloadMode, ListTagsRequest, ListTagsResponse, NodeConnectionInfoRequest,
     NodeConnectionInfoResponse, NodeConnectionsRequest, NodeConnectionsResponse,
     NodeShutdownRequest, NodeStatsRequest, NodeStatsResponse, NodeStatusRequest,
     NodeStatusResponse, NodeWatchRequest, NodeWatchResponse, ProviderRequest, ProviderService,
-    SetTagOption,
+    RenameTagRequest, SetTagOption, SetTagRequest,
 };
 
 use super::{Event, NodeInner};
@@ -92,12 +106,31 @@ impl<D: BaoStore> Handler<D> {
                 }
                 CreateCollection(msg) => chan.rpc(msg, handler, Self::create_collection).await,
                 BlobGetCollection(msg) => chan.rpc(msg, handler, Self::blob_get_collection).await,
+                BlobDiff(msg) => chan.rpc(msg, handler, Self::blob_diff).await,
+                BlobGetMeta(msg) => chan.rpc(msg, handler, Self::blob_get_meta).await,
+                BlobUpdateMeta(msg) => chan.rpc(msg, handler, Self::blob_update_meta).await,
+                BlobListMeta(msg) => {
+                    chan.server_streaming(msg, handler, Self::blob_list_meta)
+                        .await
+                }
+                BlobExportBundle(msg) => chan.rpc(msg, handler, Self::blob_export_bundle).await,
+                BlobImportBundle(msg) => chan.rpc(msg, handler, Self::blob_import_bundle).await,
                 ListTags(msg) => {
                     chan.server_streaming(msg, handler, Self::blob_list_tags)
                         .await
                 }
                 DeleteTag(msg) => chan.rpc(msg, handler, Self::blob_delete_tag).await,
+                SetTag(msg) => chan.rpc(msg, handler, Self::blob_set_tag).await,
+                CompareAndSwapTag(msg) => {
+                    chan.rpc(msg, handler, Self::blob_compare_and_swap_tag)
+                        .await
+                }
+                RenameTag(msg) => chan.rpc(msg, handler, Self::blob_rename_tag).await,
                 BlobDeleteBlob(msg) => chan.rpc(msg, handler, Self::blob_delete_blob).await,
+                BlobPush(msg) => chan.rpc(msg, handler, Self::blob_push).await,
+                BlobAvailableRanges(msg) => {
+                    chan.rpc(msg, handler, Self::blob_available_ranges).await
+                }
                 BlobAddPath(msg) => {
                     chan.server_streaming(msg, handler, Self::blob_add_from_path)
                         .await
@@ -119,6 +152,10 @@ impl<D: BaoStore> Handler<D> {
                     chan.server_streaming(msg, handler, Self::blob_read_at)
                         .await
                 }
+                BlobReadBao(msg) => {
+                    chan.server_streaming(msg, handler, Self::blob_read_bao)
+                        .await
+                }
                 BlobAddStream(msg) => {
                     chan.bidi_streaming(msg, handler, Self::blob_add_stream)
                         .await
@@ -253,6 +290,21 @@ impl<D: BaoStore> Handler<D> {
                     })
                     .await
                 }
+                DocRotate(msg) => {
+                    let bao_store = handler.inner.db.clone();
+                    chan.rpc(msg, handler, |handler, req| async move {
+                        handler.inner.sync.doc_rotate(&bao_store, req).await
+                    })
+                    .await
+                }
+                DocSnapshot(msg) => {
+                    let bao_store = handler.inner.db.clone();
+                    chan.rpc(msg, handler, |handler, req| async move {
+                        handler.inner.sync.doc_snapshot(&bao_store, req).await
+                    })
+                    .await
+                }
+                DocImportSnapshot(msg) => chan.rpc(msg, handler, Self::doc_import_snapshot).await,
                 DocSubscribe(msg) => {
                     chan.try_server_streaming(msg, handler, |handler, req| async move {
                         handler.inner.sync.doc_subscribe(req).await
@@ -271,12 +323,30 @@ impl<D: BaoStore> Handler<D> {
                     })
                     .await
                 }
+                DocSetHistoryPolicy(msg) => {
+                    chan.rpc(msg, handler, |handler, req| async move {
+                        handler.inner.sync.doc_set_history_policy(req).await
+                    })
+                    .await
+                }
+                DocGetHistoryPolicy(msg) => {
+                    chan.rpc(msg, handler, |handler, req| async move {
+                        handler.inner.sync.doc_get_history_policy(req).await
+                    })
+                    .await
+                }
                 DocGetSyncPeers(msg) => {
                     chan.rpc(msg, handler, |handler, req| async move {
                         handler.inner.sync.doc_get_sync_peers(req).await
                     })
                     .await
                 }
+                DocGetEncryptionKey(msg) => {
+                    chan.rpc(msg, handler, |handler, req| async move {
+                        handler.inner.sync.doc_get_encryption_key(req).await
+                    })
+                    .await
+                }
             }
         });
     }
@@ -398,18 +468,192 @@ impl<D: BaoStore> Handler<D> {
         Ok(())
     }
 
+    async fn blob_get_meta(self, msg: BlobGetMetaRequest) -> RpcResult<BlobGetMetaResponse> {
+        let meta = self.inner.db.blob_meta(&msg.hash).await?;
+        Ok(BlobGetMetaResponse {
+            meta: meta.unwrap_or_default(),
+        })
+    }
+
+    async fn blob_update_meta(self, msg: BlobUpdateMetaRequest) -> RpcResult<()> {
+        self.inner.db.update_blob_meta(msg.hash, msg.update).await?;
+        Ok(())
+    }
+
+    fn blob_list_meta(
+        self,
+        _msg: BlobListMetaRequest,
+    ) -> impl Stream<Item = RpcResult<BlobListMetaResponse>> + Send + 'static {
+        Gen::new(|co| async move {
+            let metas = match self.inner.db.blob_metas().await {
+                Ok(metas) => metas,
+                Err(e) => {
+                    co.yield_(Err(e.into())).await;
+                    return;
+                }
+            };
+            for item in metas {
+                let item = item
+                    .map(|(hash, meta)| BlobListMetaResponse { hash, meta })
+                    .map_err(Into::into);
+                co.yield_(item).await;
+            }
+        })
+    }
+
+    async fn blob_export_bundle(
+        self,
+        msg: BlobExportBundleRequest,
+    ) -> RpcResult<BlobExportBundleResponse> {
+        let BlobExportBundleRequest { roots, path } = msg;
+        if !path.is_absolute() {
+            return Err(anyhow!("bundle path must be absolute").into());
+        }
+        let db = self.inner.db.clone();
+        // readers are not Send, so the bundle is written on the local pool
+        let header = self
+            .rt()
+            .spawn_pinned(move || async move {
+                let file = tokio::fs::File::create(path).await?;
+                let writer = tokio::io::BufWriter::new(file);
+                iroh_bytes::bundle::write_bundle(&db, roots, writer).await
+            })
+            .await
+            .map_err(anyhow::Error::from)??;
+        Ok(BlobExportBundleResponse { header })
+    }
+
+    async fn blob_import_bundle(
+        self,
+        msg: BlobImportBundleRequest,
+    ) -> RpcResult<BlobImportBundleResponse> {
+        let BlobImportBundleRequest { path } = msg;
+        if !path.is_absolute() {
+            return Err(anyhow!("bundle path must be absolute").into());
+        }
+        let db = self.inner.db.clone();
+        let outcome = self
+            .rt()
+            .spawn_pinned(move || async move {
+                let file = tokio::fs::File::open(path).await?;
+                let reader = tokio::io::BufReader::new(file);
+                iroh_bytes::bundle::read_bundle(&db, reader).await
+            })
+            .await
+            .map_err(anyhow::Error::from)??;
+        let ReadBundleOutcome {
+            header,
+            temp_tags,
+            conflicting_tags,
+        } = outcome;
+        // keep roots that no tag of the bundle was set for, like an add does
+        let tagged = header
+            .tags
+            .iter()
+            .filter(|(tag, _)| !conflicting_tags.contains(tag))
+            .map(|(_, value)| *value)
+            .collect::<BTreeSet<_>>();
+        let untagged = header
+            .roots
+            .iter()
+            .map(|(root, _)| *root)
+            .filter(|root| !tagged.contains(root))
+            .collect::<BTreeSet<_>>();
+        let mut created_tags = Vec::new();
+        for root in untagged {
+            created_tags.push(self.inner.db.create_tag(root).await?);
+        }
+        drop(temp_tags);
+        Ok(BlobImportBundleResponse {
+            header,
+            created_tags,
+            conflicting_tags,
+        })
+    }
+
+    async fn blob_set_tag(self, msg: SetTagRequest) -> RpcResult<()> {
+        self.inner.db.set_tag(msg.name, Some(msg.value)).await?;
+        Ok(())
+    }
+
+    async fn blob_compare_and_swap_tag(
+        self,
+        msg: CompareAndSwapTagRequest,
+    ) -> RpcResult<CompareAndSwapTagResponse> {
+        let CompareAndSwapTagRequest {
+            name,
+            expected,
+            value,
+        } = msg;
+        let result = self
+            .inner
+            .db
+            .compare_and_swap_tag(name, expected, value)
+            .await?;
+        Ok(CompareAndSwapTagResponse { result })
+    }
+
+    async fn blob_rename_tag(self, msg: RenameTagRequest) -> RpcResult<()> {
+        let RenameTagRequest { from, to } = msg;
+        self.inner.db.rename_tag(from, to).await?;
+        Ok(())
+    }
+
     async fn blob_delete_blob(self, msg: BlobDeleteBlobRequest) -> RpcResult<()> {
         self.inner.db.delete(vec![msg.hash]).await?;
         Ok(())
     }
 
+    async fn blob_push(self, msg: BlobPushRequest) -> RpcResult<()> {
+        let BlobPushRequest { hash, format, node } = msg;
+        let conn = self
+            .inner
+            .endpoint
+            .connect(node, iroh_bytes::protocol::ALPN)
+            .await?;
+        let db = self.inner.db.clone();
+        let events = self.inner.callbacks.clone();
+        let rate_limiter = self.inner.rate_limiter.clone();
+        // readers are not Send, so the data is sent from the local pool
+        self.rt()
+            .spawn_pinned(move || {
+                iroh_bytes::provider::push(
+                    conn,
+                    db,
+                    PushRequest::new(hash, format),
+                    events,
+                    rate_limiter,
+                )
+            })
+            .await
+            .map_err(anyhow::Error::from)??;
+        Ok(())
+    }
+
+    async fn blob_available_ranges(
+        self,
+        msg: BlobAvailableRangesRequest,
+    ) -> RpcResult<BlobAvailableRangesResponse> {
+        let BlobAvailableRangesRequest { hash, format, node } = msg;
+        let conn = self
+            .inner
+            .endpoint
+            .connect(node, iroh_bytes::protocol::ALPN)
+            .await?;
+        let ranges = iroh_bytes::get::request::get_available_ranges(&conn, &hash, format).await?;
+        Ok(BlobAvailableRangesResponse { ranges })
+    }
+
     fn blob_list_tags(
         self,
-        _msg: ListTagsRequest,
+        msg: ListTagsRequest,
     ) -> impl Stream<Item = ListTagsResponse> + Send + 'static {
         tracing::info!("blob_list_tags");
         Gen::new(|co| async move {
-            let tags = self.inner.db.tags().await.unwrap();
+            let tags = match msg.prefix {
+                Some(prefix) => self.inner.db.tags_with_prefix(prefix).await.unwrap(),
+                None => self.inner.db.tags().await.unwrap(),
+            };
             #[allow(clippy::manual_flatten)]
             for item in tags {
                 if let Ok((name, HashAndFormat { hash, format })) = item {
@@ -540,11 +784,45 @@ impl<D: BaoStore> Handler<D> {
             false => ImportMode::Copy,
         };
 
-        let (temp_tag, size) = self
-            .inner
-            .db
-            .import_file(root, import_mode, BlobFormat::Raw, import_progress)
-            .await?;
+        let encryption = self.inner.sync.sync.get_encryption_key(doc_id).await?;
+        let (temp_tag, size) = match encryption {
+            Some(encryption) => {
+                // the content is encrypted while it is read, so it cannot be referenced in place
+                anyhow::ensure!(
+                    !in_place,
+                    "cannot import in place into an encrypted document"
+                );
+                let id = progress.new_id();
+                let file = tokio::fs::File::open(&root).await?;
+                let name = root.display().to_string();
+                let size = file.metadata().await?.len();
+                progress
+                    .send(DocImportProgress::Found { id, name, size })
+                    .await?;
+                let progress2 = progress.clone();
+                let data = encrypt_file(encryption, file, move |offset| {
+                    progress2
+                        .try_send(DocImportProgress::Progress { id, offset })
+                        .ok();
+                });
+                let (temp_tag, size) = self
+                    .inner
+                    .db
+                    .import_stream(data, BlobFormat::Raw, IgnoreProgressSender::default())
+                    .await?;
+                let hash = *temp_tag.hash();
+                progress
+                    .send(DocImportProgress::IngestDone { id, hash })
+                    .await?;
+                (temp_tag, size)
+            }
+            None => {
+                self.inner
+                    .db
+                    .import_file(root, import_mode, BlobFormat::Raw, import_progress)
+                    .await?
+            }
+        };
 
         let hash_and_format = temp_tag.inner();
         let HashAndFormat { hash, .. } = *hash_and_format;
@@ -584,6 +862,17 @@ impl<D: BaoStore> Handler<D> {
     ) -> anyhow::Result<()> {
         let progress = FlumeProgressSender::new(progress);
         let DocExportFileRequest { entry, path, mode } = msg;
+        if let Some(encryption) = self
+            .inner
+            .sync
+            .sync
+            .get_encryption_key(entry.namespace())
+            .await?
+        {
+            return self
+                .doc_export_file_encrypted(encryption, entry, path, progress)
+                .await;
+        }
         let key = bytes::Bytes::from(entry.key().to_vec());
         let export_progress = progress.clone().with_map(move |mut x| {
             // assign the doc key to the `meta` field of the initial progress event
@@ -605,6 +894,44 @@ impl<D: BaoStore> Handler<D> {
         Ok(())
     }
 
+    /// Export the decrypted content of an entry of an encrypted document.
+    ///
+    /// The decrypted content only exists in memory, so it is always copied to the target.
+    async fn doc_export_file_encrypted(
+        self,
+        encryption: EncryptionKey,
+        entry: iroh_sync::Entry,
+        outpath: PathBuf,
+        progress: FlumeProgressSender<ExportProgress>,
+    ) -> anyhow::Result<()> {
+        let hash = entry.content_hash();
+        let blob = self
+            .inner
+            .db
+            .get(&hash)
+            .await?
+            .ok_or_else(|| anyhow!("entry not there"))?;
+        let data = blob.data_reader().await?.read_to_end().await?;
+        let data = encryption.decrypt_content(&data)?;
+        let id = progress.new_id();
+        progress
+            .send(ExportProgress::Found {
+                id,
+                hash,
+                size: BaoBlobSize::Verified(data.len() as u64),
+                outpath: outpath.clone(),
+                meta: Some(entry.key().to_vec().into()),
+            })
+            .await?;
+        if let Some(parent) = outpath.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&outpath, &data).await?;
+        progress.send(ExportProgress::Done { id }).await?;
+        progress.send(ExportProgress::AllDone).await?;
+        Ok(())
+    }
+
     fn blob_download(self, msg: BlobDownloadRequest) -> impl Stream<Item = BlobDownloadResponse> {
         let (sender, receiver) = flume::bounded(1024);
         let db = self.inner.db.clone();
@@ -655,6 +982,7 @@ impl<D: BaoStore> Handler<D> {
 
         let progress = FlumeProgressSender::new(progress);
         let names = Arc::new(Mutex::new(BTreeMap::new()));
+        let chunked = matches!(msg.wrap, WrapOption::Chunked);
         // convert import progress to provide progress
         let import_progress = progress.clone().with_filter_map(move |x| match x {
             ImportProgress::Found { id, name } => {
@@ -665,9 +993,24 @@ impl<D: BaoStore> Handler<D> {
                 let name = names.lock().unwrap().remove(&id)?;
                 Some(AddProgress::Found { id, name, size })
             }
+            // chunking reads the file only once, so there is no outboard progress
+            ImportProgress::CopyProgress { id, offset } if chunked => {
+                Some(AddProgress::Progress { id, offset })
+            }
             ImportProgress::OutboardProgress { id, offset } => {
                 Some(AddProgress::Progress { id, offset })
             }
+            ImportProgress::ChunkingDone {
+                id,
+                chunks,
+                size,
+                stored,
+            } => Some(AddProgress::ChunkingDone {
+                id,
+                chunks,
+                size,
+                stored,
+            }),
             ImportProgress::OutboardDone { hash, id } => Some(AddProgress::Done { hash, id }),
             _ => None,
         });
@@ -685,17 +1028,28 @@ impl<D: BaoStore> Handler<D> {
             root.display()
         );
 
-        let import_mode = match in_place {
-            true => ImportMode::TryReference,
-            false => ImportMode::Copy,
+        let import_mode = match (in_place, chunked) {
+            (_, true) => {
+                anyhow::ensure!(
+                    root.is_file(),
+                    "only single files can be imported in chunks"
+                );
+                anyhow::ensure!(!in_place, "chunked imports can not be done in place");
+                ImportMode::Chunked
+            }
+            (true, false) => ImportMode::TryReference,
+            (false, false) => ImportMode::Copy,
         };
 
         let create_collection = match wrap {
             WrapOption::Wrap { .. } => true,
             WrapOption::NoWrap => root.is_dir(),
+            WrapOption::Tree { .. } | WrapOption::Chunked => false,
         };
 
-        let temp_tag = if create_collection {
+        let temp_tag = if let WrapOption::Tree { previous } = wrap {
+            Tree::import(&self.inner.db, root, import_mode, previous, import_progress).await?
+        } else if create_collection {
             // import all files below root recursively
             let data_sources = crate::util::fs::scan_path(root, wrap)?;
             const IO_PARALLELISM: usize = 4;
@@ -893,7 +1247,24 @@ impl<D: BaoStore> Handler<D> {
     ) -> impl Stream<Item = RpcResult<BlobReadAtResponse>> + Send + 'static {
         let (tx, rx) = flume::bounded(RPC_BLOB_GET_CHANNEL_CAP);
         let db = self.inner.db.clone();
+        let endpoint = self.inner.endpoint.clone();
+        let downloader = self.inner.downloader.clone();
         self.inner.rt.spawn_pinned(move || async move {
+            if let Some(nodes) = req.fetch_from {
+                if let Err(err) = download_read_range(
+                    &db,
+                    endpoint,
+                    &downloader,
+                    req.hash,
+                    req.offset,
+                    req.len,
+                    nodes,
+                )
+                .await
+                {
+                    debug!(?err, "failed to download range of {}", req.hash);
+                }
+            }
             let entry = db.get(&req.hash).await.unwrap();
             if let Err(err) = read_loop(
                 req.offset,
@@ -959,6 +1330,26 @@ impl<D: BaoStore> Handler<D> {
         rx.into_stream()
     }
 
+    fn blob_read_bao(
+        self,
+        req: BlobReadBaoRequest,
+    ) -> impl Stream<Item = RpcResult<BlobReadBaoResponse>> + Send + 'static {
+        let (tx, rx) = flume::bounded(RPC_BLOB_GET_CHANNEL_CAP);
+        let db = self.inner.db.clone();
+        self.inner.rt.spawn_pinned(move || async move {
+            let mut writer = BaoChunkWriter::new(tx.clone(), RPC_BLOB_GET_CHUNK_SIZE);
+            let res = match send_blob(&db, req.hash, &req.ranges, &mut writer).await {
+                Ok((SentStatus::Sent, _, _)) => writer.flush().await.map_err(anyhow::Error::from),
+                Ok((SentStatus::NotFound, _, _)) => Err(anyhow!("Blob not found")),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                tx.send_async(RpcResult::Err(err.into())).await.ok();
+            }
+        });
+        rx.into_stream()
+    }
+
     fn node_connections(
         self,
         _: NodeConnectionsRequest,
@@ -1033,6 +1424,40 @@ impl<D: BaoStore> Handler<D> {
 
         Ok(BlobGetCollectionResponse { collection })
     }
+
+    async fn doc_import_snapshot(
+        self,
+        req: DocImportSnapshotRequest,
+    ) -> RpcResult<DocImportSnapshotResponse> {
+        let hash = req.hash;
+        let db = self.inner.db.clone();
+        let data = self
+            .rt()
+            .spawn_pinned(move || async move {
+                let blob = db
+                    .get(&hash)
+                    .await?
+                    .ok_or_else(|| anyhow!("snapshot blob not found"))?;
+                anyhow::ensure!(blob.is_complete(), "snapshot blob is incomplete");
+                let data = blob.data_reader().await?.read_to_end().await?;
+                anyhow::Ok(data)
+            })
+            .await
+            .map_err(|_| anyhow!("join failed"))??;
+        self.inner.sync.doc_import_snapshot(&data).await
+    }
+
+    async fn blob_diff(self, req: BlobDiffRequest) -> RpcResult<BlobDiffResponse> {
+        let BlobDiffRequest { from, to } = req;
+        let db = self.inner.db.clone();
+        let changes = self
+            .rt()
+            .spawn_pinned(move || async move { diff::diff(&db, &from, &to).await })
+            .await
+            .map_err(|_| anyhow!("join failed"))??;
+
+        Ok(BlobDiffResponse { changes })
+    }
 }
 
 async fn download<D>(
@@ -1048,6 +1473,7 @@ where
     let BlobDownloadRequest {
         hash,
         format,
+        ranges,
         nodes,
         tag,
         mode,
@@ -1059,6 +1485,7 @@ where
                 endpoint,
                 downloader,
                 hash_and_format,
+                ranges,
                 nodes,
                 tag,
                 progress.clone(),
@@ -1066,8 +1493,16 @@ where
             .await?
         }
         DownloadMode::Direct => {
-            download_direct_from_nodes(db, endpoint, hash_and_format, nodes, tag, progress.clone())
-                .await?
+            download_direct_from_nodes(
+                db,
+                endpoint,
+                hash_and_format,
+                ranges,
+                nodes,
+                tag,
+                progress.clone(),
+            )
+            .await?
         }
     };
 
@@ -1080,6 +1515,7 @@ async fn download_queued(
     endpoint: MagicEndpoint,
     downloader: &Downloader,
     hash_and_format: HashAndFormat,
+    ranges: RangeSpecSeq,
     nodes: Vec<NodeAddr>,
     tag: SetTagOption,
     progress: FlumeProgressSender<DownloadProgress>,
@@ -1090,6 +1526,7 @@ async fn download_queued(
         endpoint.add_node_addr(node)?;
     }
     let req = DownloadRequest::new(hash_and_format, node_ids)
+        .ranges(ranges)
         .progress_sender(progress)
         .tag(tag);
     let handle = downloader.queue(req).await;
@@ -1097,10 +1534,150 @@ async fn download_queued(
     Ok(stats)
 }
 
+/// Sends the bao encoding written by [`send_blob`] as [`BlobReadBaoResponse`]s.
+///
+/// The encoding consists of many small writes, which are collected into chunks of up to
+/// `chunk_size` bytes.
+struct BaoChunkWriter {
+    tx: flume::Sender<RpcResult<BlobReadBaoResponse>>,
+    buf: BytesMut,
+    chunk_size: usize,
+}
+
+impl BaoChunkWriter {
+    fn new(tx: flume::Sender<RpcResult<BlobReadBaoResponse>>, chunk_size: usize) -> Self {
+        Self {
+            tx,
+            buf: BytesMut::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = self.buf.split().freeze();
+        self.tx
+            .send_async(Ok(BlobReadBaoResponse { chunk }))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "receiver dropped"))
+    }
+}
+
+impl AsyncStreamWriter for BaoChunkWriter {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= self.chunk_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_bytes(&mut self, data: Bytes) -> io::Result<()> {
+        self.write(&data).await
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        self.flush().await
+    }
+}
+
+/// Encrypt a file for an encrypted document, segment by segment.
+///
+/// Yields the encrypted content in pieces. `on_progress` is called with the number of bytes
+/// of the file that were encrypted so far.
+fn encrypt_file(
+    encryption: EncryptionKey,
+    mut file: tokio::fs::File,
+    on_progress: impl Fn(u64) + Send + Sync + 'static,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static {
+    Box::pin(Gen::new(|co| async move {
+        let mut encryptor = encryption.content_encryptor();
+        co.yield_(Ok(Bytes::copy_from_slice(encryptor.header())))
+            .await;
+        let res = async {
+            let mut offset = 0;
+            let mut segment = read_segment(&mut file).await?;
+            loop {
+                // a full segment is only the last one if nothing follows it
+                let next = match segment.len() {
+                    CONTENT_SEGMENT_SIZE => read_segment(&mut file).await?,
+                    _ => Vec::new(),
+                };
+                let last = next.is_empty();
+                offset += segment.len() as u64;
+                encryptor.seal_segment(&mut segment, last);
+                co.yield_(Ok(segment.into())).await;
+                on_progress(offset);
+                if last {
+                    return io::Result::Ok(());
+                }
+                segment = next;
+            }
+        }
+        .await;
+        if let Err(err) = res {
+            co.yield_(Err(err)).await;
+        }
+    }))
+}
+
+/// Read a segment of [`CONTENT_SEGMENT_SIZE`] bytes, or less at the end of the file.
+async fn read_segment(file: &mut tokio::fs::File) -> io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut segment = Vec::with_capacity(CONTENT_SEGMENT_SIZE);
+    file.take(CONTENT_SEGMENT_SIZE as u64)
+        .read_to_end(&mut segment)
+        .await?;
+    Ok(segment)
+}
+
+/// Download the chunk groups covering a byte range of a blob, unless the blob is complete.
+///
+/// At least one chunk group is downloaded, so the size of a missing blob is known afterwards.
+async fn download_read_range<D: BaoStore>(
+    db: &D,
+    endpoint: MagicEndpoint,
+    downloader: &Downloader,
+    hash: Hash,
+    offset: u64,
+    len: Option<usize>,
+    nodes: Vec<NodeAddr>,
+) -> Result<()> {
+    if let Some(entry) = db.get(&hash).await? {
+        if entry.is_complete() {
+            return Ok(());
+        }
+    }
+    // the store treats a partially written chunk group as complete, so only whole groups
+    // may be downloaded
+    let group = IROH_BLOCK_SIZE.bytes() as u64;
+    let start = ChunkNum::full_chunks(offset - offset % group);
+    let ranges = match len {
+        Some(len) => {
+            let end = offset + (len as u64).max(1);
+            ChunkRanges::from(start..ChunkNum::chunks(end.next_multiple_of(group)))
+        }
+        None => ChunkRanges::from(start..),
+    };
+    let mut node_ids = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        node_ids.push(node.node_id);
+        endpoint.add_node_addr(node)?;
+    }
+    let req = DownloadRequest::untagged(HashAndFormat::raw(hash), node_ids)
+        .ranges(RangeSpecSeq::from_ranges([ranges]));
+    downloader.queue(req).await.await?;
+    Ok(())
+}
+
 async fn download_direct_from_nodes<D>(
     db: &D,
     endpoint: MagicEndpoint,
     hash_and_format: HashAndFormat,
+    ranges: RangeSpecSeq,
     nodes: Vec<NodeAddr>,
     tag: SetTagOption,
     progress: FlumeProgressSender<DownloadProgress>,
@@ -1116,6 +1693,7 @@ where
             db,
             endpoint.clone(),
             hash_and_format,
+            &ranges,
             node,
             tag.clone(),
             progress.clone(),
@@ -1136,6 +1714,7 @@ async fn download_direct<D>(
     db: &D,
     endpoint: MagicEndpoint,
     hash_and_format: HashAndFormat,
+    ranges: &RangeSpecSeq,
     node: NodeAddr,
     tag: SetTagOption,
     progress: FlumeProgressSender<DownloadProgress>,
@@ -1153,7 +1732,9 @@ where
         }
     };
 
-    let res = iroh_bytes::get::db::get_to_db(db, get_conn, &hash_and_format, progress).await;
+    let res =
+        iroh_bytes::get::db::get_ranges_to_db(db, get_conn, &hash_and_format, ranges, progress)
+            .await;
 
     if res.is_ok() {
         match tag {
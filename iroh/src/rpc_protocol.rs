@@ -12,12 +12,15 @@ use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf};
 use bytes::Bytes;
 use derive_more::{From, TryInto};
 use iroh_base::node_addr::AddrInfoOptions;
-pub use iroh_bytes::{export::ExportProgress, get::db::DownloadProgress, BlobFormat, Hash};
 use iroh_bytes::{
-    format::collection::Collection,
-    store::{BaoBlobSize, ConsistencyCheckProgress},
+    bundle::BundleHeader,
+    format::{collection::Collection, diff::Change},
+    protocol::{RangeSpec, RangeSpecSeq},
+    store::{BaoBlobSize, BlobMeta, BlobMetaUpdate, ConsistencyCheckProgress},
     util::Tag,
+    HashAndFormat,
 };
+pub use iroh_bytes::{export::ExportProgress, get::db::DownloadProgress, BlobFormat, Hash};
 use iroh_net::{
     key::PublicKey,
     magic_endpoint::{ConnectionInfo, NodeAddr},
@@ -25,8 +28,9 @@ use iroh_net::{
 
 use iroh_sync::{
     actor::OpenState,
-    store::{DownloadPolicy, Query},
-    Author, PeerIdBytes, {AuthorId, CapabilityKind, Entry, NamespaceId, SignedEntry},
+    store::{DownloadPolicy, HistoryPolicy, Query},
+    Author, EncryptionKey, PeerIdBytes, WriteRestrictions,
+    {AuthorId, CapabilityKind, Entry, NamespaceId, SignedEntry},
 };
 use quic_rpc::{
     message::{BidiStreaming, BidiStreamingMsg, Msg, RpcMsg, ServerStreaming, ServerStreamingMsg},
@@ -72,10 +76,32 @@ pub enum WrapOption {
     /// Do not wrap the file or directory.
     NoWrap,
     /// Wrap the file or directory in a collection.
+    ///
+    /// Collection imports are never incremental: a collection only records names and hashes,
+    /// so there is nothing to compare a file against. Use [`WrapOption::Tree`] with `previous`
+    /// to skip hashing unchanged files.
     Wrap {
         /// Override the filename in the wrapping collection.
         name: Option<String>,
     },
+    /// Add the file or directory as a [`iroh_bytes::format::tree::Tree`].
+    ///
+    /// Unlike a collection, a tree preserves empty directories, symlinks and the metadata of
+    /// the entries. A directory becomes the root of the tree, a file is wrapped in a tree
+    /// with a single entry.
+    Tree {
+        /// The root hash of an earlier tree import of the same path.
+        ///
+        /// Files whose size and modification time match the earlier import are not hashed
+        /// again, so only changed files are read.
+        previous: Option<Hash>,
+    },
+    /// Split the file into content-defined chunks, see [`iroh_bytes::store::ImportMode::Chunked`].
+    ///
+    /// The result is a [`BlobFormat::HashSeq`] of the chunks, which can be reassembled with
+    /// [`iroh_bytes::store::ExportFormat::Chunked`]. Only supported for single files, and not
+    /// in place.
+    Chunked,
 }
 
 impl Msg<ProviderService> for BlobAddPathRequest {
@@ -98,6 +124,12 @@ pub struct BlobDownloadRequest {
     /// If the format is [`BlobFormat::HashSeq`], all children are downloaded and shared as
     /// well.
     pub format: BlobFormat,
+    /// The chunk ranges to download.
+    ///
+    /// Use [`RangeSpecSeq::all`] to download everything. For [`BlobFormat::Raw`], only the
+    /// first range spec is used. For [`BlobFormat::HashSeq`], the first range spec applies to
+    /// the hash sequence itself, and the following ones apply to its children.
+    pub ranges: RangeSpecSeq,
     /// This mandatory field specifies the nodes to download the data from.
     ///
     /// If set to more than a single node, they will all be tried. If `mode` is set to
@@ -276,11 +308,15 @@ impl ServerStreamingMsg<ProviderService> for BlobListCollectionsRequest {
     type Response = RpcResult<BlobListCollectionsResponse>;
 }
 
-/// List all collections
+/// List all tags, or all tags starting with a prefix
 ///
-/// Lists all collections that have been explicitly added to the database.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ListTagsRequest;
+/// Tags can be organized hierarchically by using `/` separated names such as
+/// `builds/latest`, and listed per namespace with a prefix such as `builds/`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListTagsRequest {
+    /// Only list tags starting with this prefix
+    pub prefix: Option<Tag>,
+}
 
 /// A response to a list collections request
 #[derive(Debug, Serialize, Deserialize)]
@@ -312,6 +348,48 @@ impl RpcMsg<ProviderService> for BlobDeleteBlobRequest {
     type Response = RpcResult<()>;
 }
 
+/// Push a blob or collection to another node
+///
+/// The other node decides whether to accept the push, and then this node sends it the data it
+/// is missing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobPushRequest {
+    /// The hash of the data to push
+    pub hash: Hash,
+    /// Whether to push a single blob or a hash sequence with all of its children
+    pub format: BlobFormat,
+    /// The node to push to
+    pub node: NodeAddr,
+}
+
+impl RpcMsg<ProviderService> for BlobPushRequest {
+    type Response = RpcResult<()>;
+}
+
+/// Ask another node which ranges of a blob or collection it has
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobAvailableRangesRequest {
+    /// The hash of the data
+    pub hash: Hash,
+    /// Whether to also ask for the ranges of the children of a hash sequence
+    pub format: BlobFormat,
+    /// The node to ask
+    pub node: NodeAddr,
+}
+
+impl RpcMsg<ProviderService> for BlobAvailableRangesRequest {
+    type Response = RpcResult<BlobAvailableRangesResponse>;
+}
+
+/// The response for a [`BlobAvailableRangesRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobAvailableRangesResponse {
+    /// The ranges the other node has
+    ///
+    /// The first element is the root blob, all subsequent elements are children.
+    pub ranges: RangeSpecSeq,
+}
+
 /// Delete a tag
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeleteTagRequest {
@@ -323,6 +401,58 @@ impl RpcMsg<ProviderService> for DeleteTagRequest {
     type Response = RpcResult<()>;
 }
 
+/// Set a tag to a value, replacing the previous value if any
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetTagRequest {
+    /// Name of the tag
+    pub name: Tag,
+    /// The new value of the tag
+    pub value: HashAndFormat,
+}
+
+impl RpcMsg<ProviderService> for SetTagRequest {
+    type Response = RpcResult<()>;
+}
+
+/// Atomically set or delete a tag if it has an expected value
+///
+/// See [`iroh_bytes::store::Store::compare_and_swap_tag`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareAndSwapTagRequest {
+    /// Name of the tag
+    pub name: Tag,
+    /// The expected current value, or `None` if the tag is expected to not exist
+    pub expected: Option<HashAndFormat>,
+    /// The new value, or `None` to delete the tag
+    pub value: Option<HashAndFormat>,
+}
+
+impl RpcMsg<ProviderService> for CompareAndSwapTagRequest {
+    type Response = RpcResult<CompareAndSwapTagResponse>;
+}
+
+/// The response for a [`CompareAndSwapTagRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareAndSwapTagResponse {
+    /// `Ok` if the tag was updated, otherwise the current value of the tag
+    pub result: Result<(), Option<HashAndFormat>>,
+}
+
+/// Rename a tag
+///
+/// Fails if the tag does not exist or if a tag with the new name already exists.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameTagRequest {
+    /// Current name of the tag
+    pub from: Tag,
+    /// New name of the tag
+    pub to: Tag,
+}
+
+impl RpcMsg<ProviderService> for RenameTagRequest {
+    type Response = RpcResult<()>;
+}
+
 /// Get a collection
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlobGetCollectionRequest {
@@ -341,6 +471,126 @@ pub struct BlobGetCollectionResponse {
     pub collection: Collection,
 }
 
+/// Compare two collections or two trees
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobDiffRequest {
+    /// Hash of the old collection or tree
+    pub from: Hash,
+    /// Hash of the new collection or tree
+    pub to: Hash,
+}
+
+impl RpcMsg<ProviderService> for BlobDiffRequest {
+    type Response = RpcResult<BlobDiffResponse>;
+}
+
+/// The response for a [`BlobDiffRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobDiffResponse {
+    /// The changes from the old to the new collection or tree.
+    pub changes: Vec<Change>,
+}
+
+/// Get the metadata of a blob
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobGetMetaRequest {
+    /// Hash of the blob
+    pub hash: Hash,
+}
+
+impl RpcMsg<ProviderService> for BlobGetMetaRequest {
+    type Response = RpcResult<BlobGetMetaResponse>;
+}
+
+/// The response for a [`BlobGetMetaRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobGetMetaResponse {
+    /// The metadata of the blob, empty if it has none.
+    pub meta: BlobMeta,
+}
+
+/// Update the metadata of a blob
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobUpdateMetaRequest {
+    /// Hash of the blob
+    pub hash: Hash,
+    /// The update to apply
+    pub update: BlobMetaUpdate,
+}
+
+impl RpcMsg<ProviderService> for BlobUpdateMetaRequest {
+    type Response = RpcResult<()>;
+}
+
+/// List the metadata of all blobs that have any
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobListMetaRequest;
+
+/// A response to a [`BlobListMetaRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobListMetaResponse {
+    /// The hash of the blob
+    pub hash: Hash,
+    /// The metadata of the blob
+    pub meta: BlobMeta,
+}
+
+impl Msg<ProviderService> for BlobListMetaRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for BlobListMetaRequest {
+    type Response = RpcResult<BlobListMetaResponse>;
+}
+
+/// Write a bundle of blobs to a file on the node, see [`iroh_bytes::bundle`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobExportBundleRequest {
+    /// The roots to include, and the ranges of them.
+    pub roots: Vec<(HashAndFormat, RangeSpecSeq)>,
+    /// The path of the bundle file
+    ///
+    /// This should be an absolute path valid for the file system on which
+    /// the node runs.
+    pub path: PathBuf,
+}
+
+impl RpcMsg<ProviderService> for BlobExportBundleRequest {
+    type Response = RpcResult<BlobExportBundleResponse>;
+}
+
+/// The response for a [`BlobExportBundleRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobExportBundleResponse {
+    /// The header of the written bundle
+    pub header: BundleHeader,
+}
+
+/// Import a bundle of blobs from a file on the node, see [`iroh_bytes::bundle`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobImportBundleRequest {
+    /// The path of the bundle file
+    ///
+    /// This should be an absolute path valid for the file system on which
+    /// the node runs.
+    pub path: PathBuf,
+}
+
+impl RpcMsg<ProviderService> for BlobImportBundleRequest {
+    type Response = RpcResult<BlobImportBundleResponse>;
+}
+
+/// The response for a [`BlobImportBundleRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobImportBundleResponse {
+    /// The header of the imported bundle
+    pub header: BundleHeader,
+    /// Tags created for roots that no tag of the bundle was set for
+    pub created_tags: Vec<Tag>,
+    /// Tags of the bundle that were not set, since a tag with the same name already exists
+    pub conflicting_tags: Vec<Tag>,
+}
+
 /// Create a collection.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateCollectionRequest {
@@ -557,6 +807,10 @@ pub enum ShareMode {
     Read,
     /// Write access
     Write,
+    /// Read-only access without the encryption key, to store and sync an encrypted document
+    Relay,
+    /// Write access within the given restrictions, through a newly issued delegate key
+    Delegate(WriteRestrictions),
 }
 
 /// Subscribe to events for a document.
@@ -606,7 +860,10 @@ pub struct DocListResponse {
 
 /// Create a new document
 #[derive(Serialize, Deserialize, Debug)]
-pub struct DocCreateRequest {}
+pub struct DocCreateRequest {
+    /// Whether to encrypt the entry keys and content of the document
+    pub encrypted: bool,
+}
 
 impl RpcMsg<ProviderService> for DocCreateRequest {
     type Response = RpcResult<DocCreateResponse>;
@@ -653,6 +910,95 @@ impl RpcMsg<ProviderService> for DocShareRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocShareResponse(pub DocTicket);
 
+/// Rotate a document to a new keypair.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocRotateRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Author of the rotation pointer
+    pub author_id: AuthorId,
+}
+
+impl RpcMsg<ProviderService> for DocRotateRequest {
+    type Response = RpcResult<DocRotateResponse>;
+}
+
+/// Response to [`DocRotateRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocRotateResponse {
+    /// The id of the new document
+    pub doc_id: NamespaceId,
+    /// The number of entries carried forward
+    pub carried: u64,
+    /// The number of entries that were not carried forward, since their author is not on this
+    /// node
+    pub skipped: u64,
+}
+
+/// Store a snapshot of a document as a blob.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSnapshotRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Take the snapshot as of this timestamp, from the history of the document
+    pub as_of: Option<u64>,
+    /// Tag to protect the snapshot blob from garbage collection
+    pub tag: SetTagOption,
+}
+
+impl RpcMsg<ProviderService> for DocSnapshotRequest {
+    type Response = RpcResult<DocSnapshotResponse>;
+}
+
+/// Response to [`DocSnapshotRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSnapshotResponse {
+    /// The hash of the snapshot blob
+    pub hash: Hash,
+    /// The tag of the snapshot blob
+    pub tag: Tag,
+    /// The number of entries in the snapshot
+    pub entries: u64,
+}
+
+/// Import a document from a snapshot blob.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocImportSnapshotRequest {
+    /// The hash of the snapshot blob
+    pub hash: Hash,
+}
+
+impl RpcMsg<ProviderService> for DocImportSnapshotRequest {
+    type Response = RpcResult<DocImportSnapshotResponse>;
+}
+
+/// Response to [`DocImportSnapshotRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocImportSnapshotResponse {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// The number of entries that were inserted
+    pub inserted: u64,
+}
+
+/// Get the encryption key of a document
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetEncryptionKeyRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+}
+
+impl RpcMsg<ProviderService> for DocGetEncryptionKeyRequest {
+    type Response = RpcResult<DocGetEncryptionKeyResponse>;
+}
+
+/// Response to [`DocGetEncryptionKeyRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetEncryptionKeyResponse {
+    /// The encryption key, if the document is encrypted and the key is known
+    pub key: Option<EncryptionKey>,
+}
+
 /// Get info on a document
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocStatusRequest {
@@ -905,6 +1251,9 @@ pub struct DocDelResponse {
 }
 
 /// Set an entry in a document via its hash
+///
+/// If the document is encrypted, the key is encrypted by the node, but the content must
+/// already be encrypted with the key of the document.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocSetHashRequest {
     /// The document id
@@ -1010,6 +1359,41 @@ pub struct DocGetDownloadPolicyResponse {
     pub policy: DownloadPolicy,
 }
 
+/// Set a history policy
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetHistoryPolicyRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// History policy, `None` to stop keeping a history
+    pub policy: Option<HistoryPolicy>,
+}
+
+impl RpcMsg<ProviderService> for DocSetHistoryPolicyRequest {
+    type Response = RpcResult<DocSetHistoryPolicyResponse>;
+}
+
+/// Response to [`DocSetHistoryPolicyRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetHistoryPolicyResponse {}
+
+/// Get a history policy
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetHistoryPolicyRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+}
+
+impl RpcMsg<ProviderService> for DocGetHistoryPolicyRequest {
+    type Response = RpcResult<DocGetHistoryPolicyResponse>;
+}
+
+/// Response to [`DocGetHistoryPolicyRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetHistoryPolicyResponse {
+    /// The history policy, if the document keeps a history
+    pub policy: Option<HistoryPolicy>,
+}
+
 /// Get peers for document
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocGetSyncPeersRequest {
@@ -1037,6 +1421,14 @@ pub struct BlobReadAtRequest {
     pub offset: u64,
     /// Lenghth of the data to get
     pub len: Option<usize>,
+    /// Download the requested range first if the blob is incomplete.
+    ///
+    /// If set, the chunk groups covering the range are fetched with the node's downloader from
+    /// the given nodes. At least one chunk group is fetched, so the size of a missing blob is
+    /// known afterwards. The download is not tagged, so the blob must be protected from
+    /// garbage collection by other means. If the download fails, the locally available data
+    /// is returned.
+    pub fetch_from: Option<Vec<NodeAddr>>,
 }
 
 impl Msg<ProviderService> for BlobReadAtRequest {
@@ -1064,6 +1456,34 @@ pub enum BlobReadAtResponse {
     },
 }
 
+/// Get the bao encoding of ranges of a blob
+///
+/// The response is the same as the response of a provider for the blob, so it can be verified
+/// by the receiver: the size of the blob as a little endian u64, followed by the encoded ranges.
+/// If the blob is not in the store, the first response is an error.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlobReadBaoRequest {
+    /// Hash of the blob
+    pub hash: Hash,
+    /// Ranges of the blob to encode
+    pub ranges: RangeSpec,
+}
+
+impl Msg<ProviderService> for BlobReadBaoRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for BlobReadBaoRequest {
+    type Response = RpcResult<BlobReadBaoResponse>;
+}
+
+/// Response to [`BlobReadBaoRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlobReadBaoResponse {
+    /// The next chunk of the encoding
+    pub chunk: Bytes,
+}
+
 /// Write a blob from a byte stream
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BlobAddStreamRequest {
@@ -1133,6 +1553,7 @@ pub enum ProviderRequest {
     NodeWatch(NodeWatchRequest),
 
     BlobReadAt(BlobReadAtRequest),
+    BlobReadBao(BlobReadBaoRequest),
     BlobAddStream(BlobAddStreamRequest),
     BlobAddStreamUpdate(BlobAddStreamUpdate),
     BlobAddPath(BlobAddPathRequest),
@@ -1142,13 +1563,24 @@ pub enum ProviderRequest {
     BlobListIncomplete(BlobListIncompleteRequest),
     BlobListCollections(BlobListCollectionsRequest),
     BlobDeleteBlob(BlobDeleteBlobRequest),
+    BlobPush(BlobPushRequest),
+    BlobAvailableRanges(BlobAvailableRangesRequest),
     BlobValidate(BlobValidateRequest),
     BlobFsck(BlobConsistencyCheckRequest),
     CreateCollection(CreateCollectionRequest),
     BlobGetCollection(BlobGetCollectionRequest),
+    BlobDiff(BlobDiffRequest),
+    BlobGetMeta(BlobGetMetaRequest),
+    BlobUpdateMeta(BlobUpdateMetaRequest),
+    BlobListMeta(BlobListMetaRequest),
+    BlobExportBundle(BlobExportBundleRequest),
+    BlobImportBundle(BlobImportBundleRequest),
 
     DeleteTag(DeleteTagRequest),
     ListTags(ListTagsRequest),
+    SetTag(SetTagRequest),
+    CompareAndSwapTag(CompareAndSwapTagRequest),
+    RenameTag(RenameTagRequest),
 
     DocOpen(DocOpenRequest),
     DocClose(DocCloseRequest),
@@ -1167,9 +1599,15 @@ pub enum ProviderRequest {
     DocStartSync(DocStartSyncRequest),
     DocLeave(DocLeaveRequest),
     DocShare(DocShareRequest),
+    DocRotate(DocRotateRequest),
+    DocSnapshot(DocSnapshotRequest),
+    DocImportSnapshot(DocImportSnapshotRequest),
+    DocGetEncryptionKey(DocGetEncryptionKeyRequest),
     DocSubscribe(DocSubscribeRequest),
     DocGetDownloadPolicy(DocGetDownloadPolicyRequest),
     DocSetDownloadPolicy(DocSetDownloadPolicyRequest),
+    DocGetHistoryPolicy(DocGetHistoryPolicyRequest),
+    DocSetHistoryPolicy(DocSetHistoryPolicyRequest),
     DocGetSyncPeers(DocGetSyncPeersRequest),
 
     AuthorList(AuthorListRequest),
@@ -1191,6 +1629,7 @@ pub enum ProviderResponse {
     NodeWatch(NodeWatchResponse),
 
     BlobReadAt(RpcResult<BlobReadAtResponse>),
+    BlobReadBao(RpcResult<BlobReadBaoResponse>),
     BlobAddStream(BlobAddStreamResponse),
     BlobAddPath(BlobAddPathResponse),
     BlobList(RpcResult<BlobListResponse>),
@@ -1202,9 +1641,16 @@ pub enum ProviderResponse {
     BlobValidate(ValidateProgress),
     CreateCollection(RpcResult<CreateCollectionResponse>),
     BlobGetCollection(RpcResult<BlobGetCollectionResponse>),
+    BlobDiff(RpcResult<BlobDiffResponse>),
+    BlobGetMeta(RpcResult<BlobGetMetaResponse>),
+    BlobListMeta(RpcResult<BlobListMetaResponse>),
+    BlobExportBundle(RpcResult<BlobExportBundleResponse>),
+    BlobImportBundle(RpcResult<BlobImportBundleResponse>),
+    BlobAvailableRanges(RpcResult<BlobAvailableRangesResponse>),
 
     ListTags(ListTagsResponse),
     DeleteTag(RpcResult<()>),
+    CompareAndSwapTag(RpcResult<CompareAndSwapTagResponse>),
 
     DocOpen(RpcResult<DocOpenResponse>),
     DocClose(RpcResult<DocCloseResponse>),
@@ -1221,11 +1667,17 @@ pub enum ProviderResponse {
     DocExportFile(DocExportFileResponse),
     DocDel(RpcResult<DocDelResponse>),
     DocShare(RpcResult<DocShareResponse>),
+    DocRotate(RpcResult<DocRotateResponse>),
+    DocSnapshot(RpcResult<DocSnapshotResponse>),
+    DocImportSnapshot(RpcResult<DocImportSnapshotResponse>),
+    DocGetEncryptionKey(RpcResult<DocGetEncryptionKeyResponse>),
     DocStartSync(RpcResult<DocStartSyncResponse>),
     DocLeave(RpcResult<DocLeaveResponse>),
     DocSubscribe(RpcResult<DocSubscribeResponse>),
     DocGetDownloadPolicy(RpcResult<DocGetDownloadPolicyResponse>),
     DocSetDownloadPolicy(RpcResult<DocSetDownloadPolicyResponse>),
+    DocGetHistoryPolicy(RpcResult<DocGetHistoryPolicyResponse>),
+    DocSetHistoryPolicy(RpcResult<DocSetHistoryPolicyResponse>),
     DocGetSyncPeers(RpcResult<DocGetSyncPeersResponse>),
     StreamCreated(RpcResult<StreamCreated>),
 
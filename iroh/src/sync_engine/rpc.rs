@@ -3,7 +3,10 @@
 use anyhow::anyhow;
 use futures_lite::Stream;
 use iroh_bytes::{store::Store as BaoStore, BlobFormat};
-use iroh_sync::{Author, NamespaceSecret};
+use iroh_sync::{
+    actor::SyncHandle, store::Query, Author, EncryptionKey, NamespaceId, NamespaceSecret,
+    SignedEntry, Snapshot, ROTATION_KEY,
+};
 use tokio_stream::StreamExt;
 
 use crate::rpc_protocol::{
@@ -15,15 +18,19 @@ use crate::{
         AuthorCreateRequest, AuthorCreateResponse, AuthorListRequest, AuthorListResponse,
         DocCloseRequest, DocCloseResponse, DocCreateRequest, DocCreateResponse, DocDelRequest,
         DocDelResponse, DocDropRequest, DocDropResponse, DocGetDownloadPolicyRequest,
-        DocGetDownloadPolicyResponse, DocGetExactRequest, DocGetExactResponse, DocGetManyRequest,
-        DocGetManyResponse, DocImportRequest, DocImportResponse, DocLeaveRequest, DocLeaveResponse,
-        DocListRequest, DocListResponse, DocOpenRequest, DocOpenResponse,
-        DocSetDownloadPolicyRequest, DocSetDownloadPolicyResponse, DocSetHashRequest,
-        DocSetHashResponse, DocSetRequest, DocSetResponse, DocShareRequest, DocShareResponse,
-        DocStartSyncRequest, DocStartSyncResponse, DocStatusRequest, DocStatusResponse,
-        DocSubscribeRequest, DocSubscribeResponse, DocTicket, RpcResult, ShareMode,
+        DocGetDownloadPolicyResponse, DocGetEncryptionKeyRequest, DocGetEncryptionKeyResponse,
+        DocGetExactRequest, DocGetExactResponse, DocGetHistoryPolicyRequest,
+        DocGetHistoryPolicyResponse, DocGetManyRequest, DocGetManyResponse, DocImportRequest,
+        DocImportResponse, DocImportSnapshotResponse, DocLeaveRequest, DocLeaveResponse,
+        DocListRequest, DocListResponse, DocOpenRequest, DocOpenResponse, DocRotateRequest,
+        DocRotateResponse, DocSetDownloadPolicyRequest, DocSetDownloadPolicyResponse,
+        DocSetHashRequest, DocSetHashResponse, DocSetHistoryPolicyRequest,
+        DocSetHistoryPolicyResponse, DocSetRequest, DocSetResponse, DocShareRequest,
+        DocShareResponse, DocSnapshotRequest, DocSnapshotResponse, DocStartSyncRequest,
+        DocStartSyncResponse, DocStatusRequest, DocStatusResponse, DocSubscribeRequest,
+        DocSubscribeResponse, DocTicket, RpcResult, SetTagOption, ShareMode,
     },
-    sync_engine::SyncEngine,
+    sync_engine::{LiveEvent, SyncEngine},
 };
 
 /// Capacity for the flume channels to forward sync store iterators to async RPC streams.
@@ -79,10 +86,14 @@ impl SyncEngine {
         Ok(AuthorDeleteResponse)
     }
 
-    pub async fn doc_create(&self, _req: DocCreateRequest) -> RpcResult<DocCreateResponse> {
+    pub async fn doc_create(&self, req: DocCreateRequest) -> RpcResult<DocCreateResponse> {
         let namespace = NamespaceSecret::new(&mut rand::rngs::OsRng {});
         let id = namespace.id();
         self.sync.import_namespace(namespace.into()).await?;
+        if req.encrypted {
+            let key = EncryptionKey::new(&mut rand::rngs::OsRng {});
+            self.sync.set_encryption_key(id, key).await?;
+        }
         self.sync.open(id, Default::default()).await?;
         Ok(DocCreateResponse { id })
     }
@@ -135,30 +146,125 @@ impl SyncEngine {
         let mut me = self.endpoint.my_addr().await?;
         me.apply_options(addr_options);
 
+        let encryption = match mode {
+            ShareMode::Relay => None,
+            _ => self.sync.get_encryption_key(doc_id).await?,
+        };
         let capability = match mode {
-            ShareMode::Read => iroh_sync::Capability::Read(doc_id),
+            ShareMode::Read | ShareMode::Relay => iroh_sync::Capability::Read(doc_id),
             ShareMode::Write => {
                 let secret = self.sync.export_secret_key(doc_id).await?;
                 iroh_sync::Capability::Write(secret)
             }
+            ShareMode::Delegate(restrictions) => {
+                let delegate = self.sync.delegate(doc_id, restrictions).await?;
+                iroh_sync::Capability::Delegated(delegate)
+            }
         };
         self.start_sync(doc_id, vec![]).await?;
 
         Ok(DocShareResponse(DocTicket {
             capability,
             nodes: vec![me],
+            encryption,
         }))
     }
 
+    pub async fn doc_rotate<B: BaoStore>(
+        &self,
+        bao_store: &B,
+        req: DocRotateRequest,
+    ) -> RpcResult<DocRotateResponse> {
+        let DocRotateRequest { doc_id, author_id } = req;
+        let encryption = self.sync.get_encryption_key(doc_id).await?;
+        let outcome = self.sync.rotate(doc_id).await?;
+        // the carried entries are encrypted with the key of the old document
+        let (key, pointer) = match &encryption {
+            Some(encryption) => {
+                self.sync
+                    .set_encryption_key(outcome.namespace, encryption.clone())
+                    .await?;
+                (
+                    encryption.encrypt_key(ROTATION_KEY),
+                    encryption.encrypt_content(&outcome.rotation.to_bytes()),
+                )
+            }
+            None => (ROTATION_KEY.to_vec(), outcome.rotation.to_bytes()),
+        };
+        // store the pointer before inserting it, so that peers can fetch it right away
+        let len = pointer.len();
+        let tag = bao_store
+            .import_bytes(pointer.into(), BlobFormat::Raw)
+            .await?;
+        self.sync
+            .insert_local(doc_id, author_id, key.into(), *tag.hash(), len as u64)
+            .await?;
+        // the key of the old document is considered leaked, so stop writing to it
+        self.sync.make_read_only(doc_id).await?;
+        self.sync
+            .open(outcome.namespace, Default::default())
+            .await?;
+        Ok(DocRotateResponse {
+            doc_id: outcome.namespace,
+            carried: outcome.carried,
+            skipped: outcome.skipped,
+        })
+    }
+
+    pub async fn doc_snapshot<B: BaoStore>(
+        &self,
+        bao_store: &B,
+        req: DocSnapshotRequest,
+    ) -> RpcResult<DocSnapshotResponse> {
+        let DocSnapshotRequest { doc_id, as_of, tag } = req;
+        let snapshot = self.sync.export_snapshot(doc_id, as_of).await?;
+        let temp_tag = bao_store
+            .import_bytes(snapshot.to_bytes().into(), BlobFormat::Raw)
+            .await?;
+        let hash_and_format = *temp_tag.inner();
+        let tag = match tag {
+            SetTagOption::Named(tag) => {
+                bao_store
+                    .set_tag(tag.clone(), Some(hash_and_format))
+                    .await?;
+                tag
+            }
+            SetTagOption::Auto => bao_store.create_tag(hash_and_format).await?,
+        };
+        Ok(DocSnapshotResponse {
+            hash: hash_and_format.hash,
+            tag,
+            entries: snapshot.entries().len() as u64,
+        })
+    }
+
+    pub async fn doc_import_snapshot(&self, data: &[u8]) -> RpcResult<DocImportSnapshotResponse> {
+        let snapshot = Snapshot::from_bytes(data).map_err(anyhow::Error::from)?;
+        let doc_id = snapshot.namespace();
+        let inserted = self.sync.import_snapshot(snapshot).await?;
+        self.sync.open(doc_id, Default::default()).await?;
+        Ok(DocImportSnapshotResponse {
+            doc_id,
+            inserted: inserted as u64,
+        })
+    }
+
     pub async fn doc_subscribe(
         &self,
         req: DocSubscribeRequest,
     ) -> RpcResult<impl Stream<Item = RpcResult<DocSubscribeResponse>>> {
+        let encryption = self.sync.get_encryption_key(req.doc_id).await?;
         let stream = self.subscribe(req.doc_id).await?;
 
-        Ok(stream.map(|el| {
-            el.map(|event| DocSubscribeResponse { event })
-                .map_err(Into::into)
+        Ok(stream.filter_map(move |el| match el {
+            Ok(event) => {
+                let event = match &encryption {
+                    Some(encryption) => decrypt_event(encryption, event)?,
+                    None => event,
+                };
+                Some(Ok(DocSubscribeResponse { event }))
+            }
+            Err(err) => Some(Err(err.into())),
         }))
     }
 
@@ -166,8 +272,12 @@ impl SyncEngine {
         let DocImportRequest(DocTicket {
             capability,
             nodes: peers,
+            encryption,
         }) = req;
         let doc_id = self.sync.import_namespace(capability).await?;
+        if let Some(encryption) = encryption {
+            self.sync.set_encryption_key(doc_id, encryption).await?;
+        }
         self.sync.open(doc_id, Default::default()).await?;
         self.start_sync(doc_id, peers).await?;
         Ok(DocImportResponse { doc_id })
@@ -199,6 +309,14 @@ impl SyncEngine {
             key,
             value,
         } = req;
+        let encryption = self.sync.get_encryption_key(doc_id).await?;
+        let (key, value) = match &encryption {
+            Some(encryption) => (
+                encryption.encrypt_key(&key).into(),
+                encryption.encrypt_content(&value).into(),
+            ),
+            None => (key, value),
+        };
         let len = value.len();
         let tag = bao_store.import_bytes(value, BlobFormat::Raw).await?;
         self.sync
@@ -209,6 +327,12 @@ impl SyncEngine {
             .get_exact(doc_id, author_id, key, false)
             .await?
             .ok_or_else(|| anyhow!("failed to get entry after insertion"))?;
+        let entry = match &encryption {
+            Some(encryption) => encryption
+                .decrypt_signed_entry(&entry)
+                .map_err(anyhow::Error::from)?,
+            None => entry,
+        };
         Ok(DocSetResponse { entry })
     }
 
@@ -218,6 +342,10 @@ impl SyncEngine {
             author_id,
             prefix,
         } = req;
+        let prefix = match self.sync.get_encryption_key(doc_id).await? {
+            Some(encryption) => encryption.encrypt_key(&prefix).into(),
+            None => prefix,
+        };
         let removed = self.sync.delete_prefix(doc_id, author_id, prefix).await?;
         Ok(DocDelResponse { removed })
     }
@@ -230,6 +358,10 @@ impl SyncEngine {
             hash,
             size,
         } = req;
+        let key = match self.sync.get_encryption_key(doc_id).await? {
+            Some(encryption) => encryption.encrypt_key(&key).into(),
+            None => key,
+        };
         self.sync
             .insert_local(doc_id, author_id, key.clone(), hash, size)
             .await?;
@@ -247,7 +379,7 @@ impl SyncEngine {
         // itself must be sync.
         tokio::task::spawn(async move {
             let tx2 = tx.clone();
-            if let Err(err) = sync.get_many(doc_id, query, tx).await {
+            if let Err(err) = get_many_decrypted(sync, doc_id, query, tx).await {
                 tx2.send_async(Err(err)).await.ok();
             }
         });
@@ -264,10 +396,23 @@ impl SyncEngine {
             key,
             include_empty,
         } = req;
+        let encryption = self.sync.get_encryption_key(doc_id).await?;
+        let key = match &encryption {
+            Some(encryption) => encryption.encrypt_key(&key).into(),
+            None => key,
+        };
         let entry = self
             .sync
             .get_exact(doc_id, author, key, include_empty)
             .await?;
+        let entry = match (entry, &encryption) {
+            (Some(entry), Some(encryption)) => Some(
+                encryption
+                    .decrypt_signed_entry(&entry)
+                    .map_err(anyhow::Error::from)?,
+            ),
+            (entry, _) => entry,
+        };
         Ok(DocGetExactResponse { entry })
     }
 
@@ -288,6 +433,30 @@ impl SyncEngine {
         Ok(DocGetDownloadPolicyResponse { policy })
     }
 
+    pub async fn doc_set_history_policy(
+        &self,
+        req: DocSetHistoryPolicyRequest,
+    ) -> RpcResult<DocSetHistoryPolicyResponse> {
+        self.sync.set_history_policy(req.doc_id, req.policy).await?;
+        Ok(DocSetHistoryPolicyResponse {})
+    }
+
+    pub async fn doc_get_history_policy(
+        &self,
+        req: DocGetHistoryPolicyRequest,
+    ) -> RpcResult<DocGetHistoryPolicyResponse> {
+        let policy = self.sync.get_history_policy(req.doc_id).await?;
+        Ok(DocGetHistoryPolicyResponse { policy })
+    }
+
+    pub async fn doc_get_encryption_key(
+        &self,
+        req: DocGetEncryptionKeyRequest,
+    ) -> RpcResult<DocGetEncryptionKeyResponse> {
+        let key = self.sync.get_encryption_key(req.doc_id).await?;
+        Ok(DocGetEncryptionKeyResponse { key })
+    }
+
     pub async fn doc_get_sync_peers(
         &self,
         req: DocGetSyncPeersRequest,
@@ -296,3 +465,52 @@ impl SyncEngine {
         Ok(DocGetSyncPeersResponse { peers })
     }
 }
+
+/// Get entries from the sync store, with the keys decrypted if the document is encrypted.
+///
+/// Entries that were not written with the key of the document are skipped.
+async fn get_many_decrypted(
+    sync: SyncHandle,
+    doc_id: NamespaceId,
+    query: Query,
+    tx: flume::Sender<anyhow::Result<SignedEntry>>,
+) -> anyhow::Result<()> {
+    let Some(encryption) = sync.get_encryption_key(doc_id).await? else {
+        return sync.get_many(doc_id, query, tx).await;
+    };
+    let (inner_tx, inner_rx) = flume::bounded(ITER_CHANNEL_CAP);
+    sync.get_many(doc_id, encryption.encrypt_query(query), inner_tx)
+        .await?;
+    while let Ok(entry) = inner_rx.recv_async().await {
+        let entry = match entry {
+            Ok(entry) => match encryption.decrypt_signed_entry(&entry) {
+                Ok(entry) => Ok(entry),
+                Err(_) => continue,
+            },
+            Err(err) => Err(err),
+        };
+        if tx.send_async(entry).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Decrypt the entry of an insert event, or return `None` if it was not written with the key.
+fn decrypt_event(encryption: &EncryptionKey, event: LiveEvent) -> Option<LiveEvent> {
+    Some(match event {
+        LiveEvent::InsertLocal { entry } => LiveEvent::InsertLocal {
+            entry: encryption.decrypt_entry(&entry).ok()?,
+        },
+        LiveEvent::InsertRemote {
+            from,
+            entry,
+            content_status,
+        } => LiveEvent::InsertRemote {
+            from,
+            entry: encryption.decrypt_entry(&entry).ok()?,
+            content_status,
+        },
+        event => event,
+    })
+}
@@ -2,7 +2,7 @@
 
 use iroh_base::ticket;
 use iroh_net::NodeAddr;
-use iroh_sync::Capability;
+use iroh_sync::{Capability, EncryptionKey};
 use serde::{Deserialize, Serialize};
 
 /// Contains both a key (either secret or public) to a document, and a list of peers to join.
@@ -13,29 +13,65 @@ pub struct DocTicket {
     pub capability: Capability,
     /// A list of nodes to contact.
     pub nodes: Vec<NodeAddr>,
+    /// The key to read an encrypted document.
+    pub encryption: Option<EncryptionKey>,
 }
 
 /// Wire format for [`DocTicket`].
 ///
-/// In the future we might have multiple variants (not versions, since they
-/// might be both equally valid), so this is a single variant enum to force
-/// postcard to add a discriminator.
+/// Tickets without an encryption key use the first variant, so that they stay readable by
+/// older versions.
 #[derive(Serialize, Deserialize)]
 enum TicketWireFormat {
-    Variant0(DocTicket),
+    Variant0 {
+        capability: Capability,
+        nodes: Vec<NodeAddr>,
+    },
+    Variant1 {
+        capability: Capability,
+        nodes: Vec<NodeAddr>,
+        encryption: EncryptionKey,
+    },
 }
 
 impl ticket::Ticket for DocTicket {
     const KIND: &'static str = "doc";
 
     fn to_bytes(&self) -> Vec<u8> {
-        let data = TicketWireFormat::Variant0(self.clone());
+        let DocTicket {
+            capability,
+            nodes,
+            encryption,
+        } = self.clone();
+        let data = match encryption {
+            None => TicketWireFormat::Variant0 { capability, nodes },
+            Some(encryption) => TicketWireFormat::Variant1 {
+                capability,
+                nodes,
+                encryption,
+            },
+        };
         postcard::to_stdvec(&data).expect("postcard serialization failed")
     }
 
     fn from_bytes(bytes: &[u8]) -> Result<Self, ticket::Error> {
         let res: TicketWireFormat = postcard::from_bytes(bytes).map_err(ticket::Error::Postcard)?;
-        let TicketWireFormat::Variant0(res) = res;
+        let res = match res {
+            TicketWireFormat::Variant0 { capability, nodes } => DocTicket {
+                capability,
+                nodes,
+                encryption: None,
+            },
+            TicketWireFormat::Variant1 {
+                capability,
+                nodes,
+                encryption,
+            } => DocTicket {
+                capability,
+                nodes,
+                encryption: Some(encryption),
+            },
+        };
         if res.nodes.is_empty() {
             return Err(ticket::Error::Verify("addressing info cannot be empty"));
         }
@@ -49,8 +85,15 @@ impl DocTicket {
         Self {
             capability,
             nodes: peers,
+            encryption: None,
         }
     }
+
+    /// Add the key to read an encrypted document to this ticket.
+    pub fn with_encryption(mut self, encryption: EncryptionKey) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
 }
 
 impl std::str::FromStr for DocTicket {
@@ -86,6 +129,7 @@ mod tests {
         let ticket = DocTicket {
             capability: Capability::Read(namespace_id),
             nodes: vec![NodeAddr::from_parts(node_id, None, vec![])],
+            encryption: None,
         };
         let base32 = base32::parse_vec(ticket.to_string().strip_prefix("doc").unwrap()).unwrap();
         let expected = parse_hexdump("
@@ -99,4 +143,20 @@ mod tests {
         ").unwrap();
         assert_eq_hex!(base32, expected);
     }
+
+    #[test]
+    fn test_ticket_encryption_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let node_id = iroh_net::key::SecretKey::generate().public();
+        let namespace = iroh_sync::NamespaceSecret::new(&mut rng);
+        let encryption = EncryptionKey::new(&mut rng);
+        let ticket = DocTicket::new(
+            Capability::Read(namespace.id()),
+            vec![NodeAddr::from_parts(node_id, None, vec![])],
+        )
+        .with_encryption(encryption.clone());
+        let decoded = DocTicket::from_str(&ticket.to_string()).unwrap();
+        assert_eq!(decoded.encryption, Some(encryption));
+        assert_eq!(decoded.capability.id(), namespace.id());
+    }
 }
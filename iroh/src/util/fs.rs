@@ -68,6 +68,8 @@ pub fn scan_path(path: PathBuf, wrap: WrapOption) -> anyhow::Result<Vec<DataSour
     } else {
         let name = match wrap {
             WrapOption::NoWrap => bail!("Cannot scan a file without wrapping"),
+            WrapOption::Tree { .. } => bail!("Cannot scan a file as a tree"),
+            WrapOption::Chunked => bail!("Cannot scan a file in chunks"),
             WrapOption::Wrap { name: None } => file_name(&path)?,
             WrapOption::Wrap { name: Some(name) } => name,
         };
@@ -86,6 +88,8 @@ pub fn scan_dir(root: PathBuf, wrap: WrapOption) -> anyhow::Result<Vec<DataSourc
     }
     let prefix = match wrap {
         WrapOption::NoWrap => None,
+        WrapOption::Tree { .. } => bail!("Cannot scan a directory as a tree"),
+        WrapOption::Chunked => bail!("Cannot scan a directory in chunks"),
         WrapOption::Wrap { name: None } => Some(file_name(&root)?),
         WrapOption::Wrap { name: Some(name) } => Some(name),
     };
@@ -1,18 +1,19 @@
 use std::{
-    io::{Cursor, Write},
+    collections::BTreeSet,
+    io::{self, Cursor, Write},
     time::Duration,
 };
 
 use anyhow::Result;
 use bao_tree::{blake3, io::sync::Outboard, ChunkRanges};
 use bytes::Bytes;
-use futures_lite::FutureExt;
+use futures_lite::{FutureExt, StreamExt};
 use iroh::node::{self, Node};
 use rand::RngCore;
 
 use iroh_bytes::{
     hashseq::HashSeq,
-    store::{EntryStatus, MapMut, Store},
+    store::{EntryStatus, EvictionOrder, GcMarkEvent, GcSweepEvent, Map, MapMut, Quota, Store},
     util::Tag,
     BlobFormat, HashAndFormat, IROH_BLOCK_SIZE,
 };
@@ -189,22 +190,117 @@ async fn gc_hashseq_impl() -> Result<()> {
     Ok(())
 }
 
+/// Run a full gc cycle on a store without a node, returning the sweep events.
+async fn run_gc<S: Store>(bao_store: &S) -> Result<Vec<GcSweepEvent>> {
+    bao_store.gc_start().await?;
+    let mut live = BTreeSet::new();
+    let mut mark = bao_store.gc_mark(&mut live);
+    while let Some(ev) = mark.next().await {
+        if let GcMarkEvent::Error(err) = ev {
+            return Err(err);
+        }
+    }
+    drop(mark);
+    let mut events = Vec::new();
+    let mut sweep = bao_store.gc_sweep(&live);
+    while let Some(ev) = sweep.next().await {
+        if let GcSweepEvent::Error(err) = ev {
+            return Err(err);
+        }
+        events.push(ev);
+    }
+    Ok(events)
+}
+
+/// Fill a store with a quota of 3000 bytes with 4000 bytes of tagged blobs,
+/// and check that gc evicts the least recently served evictable blob.
+async fn check_quota_eviction<S: Store>(bao_store: S) -> Result<()> {
+    let mut hashes = Vec::new();
+    for name in ["pinned", "cache/a", "cache/b", "cache/c"] {
+        let tt = bao_store
+            .import_bytes(create_test_data(1000), BlobFormat::Raw)
+            .await?;
+        bao_store
+            .set_tag(Tag::from(name), Some(*tt.inner()))
+            .await?;
+        hashes.push(*tt.hash());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let [pinned, a, b, c] = hashes[..] else {
+        unreachable!()
+    };
+    // a was imported first, but served last
+    bao_store.record_served(&a).await?;
+
+    let events = run_gc(&bao_store).await?;
+    let evicted = events
+        .iter()
+        .filter_map(|ev| match ev {
+            GcSweepEvent::Evicted { tag, content, size } => Some((tag.clone(), *content, *size)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        evicted,
+        vec![(Tag::from("cache/b"), HashAndFormat::raw(b), 1000)]
+    );
+    assert_eq!(bao_store.entry_status(&b).await?, EntryStatus::NotFound);
+    for hash in [pinned, a, c] {
+        assert_eq!(bao_store.entry_status(&hash).await?, EntryStatus::Complete);
+    }
+    let tags = bao_store
+        .tags()
+        .await?
+        .map(|item| item.map(|(tag, _)| tag))
+        .collect::<io::Result<Vec<_>>>()?;
+    assert_eq!(
+        tags,
+        vec![
+            Tag::from("cache/a"),
+            Tag::from("cache/c"),
+            Tag::from("pinned")
+        ]
+    );
+
+    // within the quota, nothing more is evicted
+    let events = run_gc(&bao_store).await?;
+    assert!(!events
+        .iter()
+        .any(|ev| matches!(ev, GcSweepEvent::Evicted { .. })));
+    Ok(())
+}
+
+fn test_quota() -> Quota {
+    Quota {
+        max_size: 3000,
+        evictable_prefix: Tag::from("cache/"),
+        order: EvictionOrder::LeastRecentlyServed,
+    }
+}
+
+/// Test that gc evicts blobs held by evictable tags once over the quota.
+#[tokio::test]
+async fn gc_quota() -> Result<()> {
+    let _ = tracing_subscriber::fmt::try_init();
+    let bao_store = iroh_bytes::store::mem::Store::new().with_quota(test_quota());
+    check_quota_eviction(bao_store).await
+}
+
 #[cfg(feature = "fs-store")]
 mod file {
     use super::*;
-    use std::{io, path::PathBuf};
+    use std::path::PathBuf;
 
     use bao_tree::{
         io::fsm::{BaoContentItem, ResponseDecoderNext},
         BaoTree,
     };
 
-    use futures_lite::StreamExt;
     use iroh_io::AsyncSliceReaderExt;
     use testdir::testdir;
 
     use iroh_bytes::{
-        store::{BaoBatchWriter, ConsistencyCheckProgress, Map, MapEntryMut, ReportLevel},
+        store::{BaoBatchWriter, ConsistencyCheckProgress, MapEntryMut, ReportLevel},
         util::progress::{FlumeProgressSender, ProgressSender as _},
         TempTag,
     };
@@ -241,6 +337,18 @@ mod file {
         Ok(max_level)
     }
 
+    /// Test that gc evicts blobs held by evictable tags once over the quota.
+    #[tokio::test]
+    async fn gc_file_quota() -> Result<()> {
+        let _ = tracing_subscriber::fmt::try_init();
+        let dir = testdir!();
+        let options = iroh_bytes::store::fs::Options::new(&dir).with_quota(test_quota());
+        let bao_store = iroh_bytes::store::fs::Store::new(dir.join("blobs.db"), options).await?;
+        check_quota_eviction(bao_store.clone()).await?;
+        bao_store.shutdown().await;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn redb_doc_import_stress() -> Result<()> {
         let _ = tracing_subscriber::fmt::try_init();
@@ -2,17 +2,18 @@ use std::{
     collections::BTreeMap,
     net::SocketAddr,
     ops::Range,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use futures_lite::FutureExt;
+use futures_lite::{future::Boxed as BoxFuture, FutureExt};
 use iroh::{
     dial::Options,
     node::{Builder, Event},
 };
-use iroh_net::{key::SecretKey, NodeId};
+use iroh_net::{key::SecretKey, relay::RelayMode, MagicEndpoint, NodeAddr, NodeId};
 use quic_rpc::transport::misc::DummyServerEndpoint;
 use rand::RngCore;
 use tokio::sync::mpsc;
@@ -22,8 +23,8 @@ use iroh_bytes::{
     format::collection::Collection,
     get::{
         fsm::ConnectedNext,
-        fsm::{self, DecodeError},
-        Stats,
+        fsm::{self, AtBlobHeaderNextError, DecodeError},
+        GetResponseError, Stats,
     },
     protocol::{GetRequest, RangeSpecSeq},
     provider,
@@ -603,6 +604,131 @@ async fn test_size_request_blob() {
     .expect("get failed");
 }
 
+/// Connect to a node using only its direct addresses.
+async fn dial_direct(secret_key: SecretKey, addr: NodeAddr) -> Result<quinn::Connection> {
+    let endpoint = MagicEndpoint::builder()
+        .secret_key(secret_key)
+        .relay_mode(RelayMode::Disabled)
+        .bind(0)
+        .await?;
+    endpoint.connect(addr, iroh_bytes::protocol::ALPN).await
+}
+
+/// Only serves requests from a single node.
+#[derive(Debug)]
+struct AllowNode(NodeId);
+
+impl provider::RequestAuthorizationHandler for AllowNode {
+    fn authorize(&self, node_id: NodeId, _request: &GetRequest) -> BoxFuture<Result<()>> {
+        let allowed = node_id == self.0;
+        async move {
+            anyhow::ensure!(allowed, "node {} is not allowed", node_id.fmt_short());
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[tokio::test]
+async fn test_request_authorization() {
+    let data = make_test_data(1234);
+    let (db, hashes) = iroh_bytes::store::readonly_mem::Store::new([("test", &data)]);
+    let hash = Hash::from(*hashes.values().next().unwrap());
+    let allowed = SecretKey::generate();
+    let node = test_node(db)
+        .request_authorization_handler(Arc::new(AllowNode(allowed.public())))
+        .spawn()
+        .await
+        .unwrap();
+    let addr = NodeAddr::from_parts(
+        node.node_id(),
+        None,
+        node.local_endpoint_addresses().await.unwrap(),
+    );
+    tokio::time::timeout(Duration::from_secs(10), async move {
+        // the allowed node gets the data
+        let connection = dial_direct(allowed, addr.clone()).await?;
+        let response = fsm::start(connection, GetRequest::single(hash));
+        let connected = response.next().await?;
+        let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            panic!()
+        };
+        let (_, actual) = start.next().concatenate_into_vec().await?;
+        assert_eq!(actual, data);
+
+        // any other node is refused
+        let connection = dial_direct(SecretKey::generate(), addr).await?;
+        let response = fsm::start(connection, GetRequest::single(hash));
+        let connected = response.next().await?;
+        let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            panic!()
+        };
+        let Err(AtBlobHeaderNextError::Read(cause)) = start.next().next().await else {
+            panic!("expected read error")
+        };
+        assert!(matches!(
+            GetResponseError::from(cause),
+            GetResponseError::Unauthorized
+        ));
+        anyhow::Ok(())
+    })
+    .await
+    .expect("timeout")
+    .expect("get failed");
+}
+
+#[tokio::test]
+async fn test_upload_rate_limit() {
+    let data = make_test_data(256 * 1024);
+    let (db, hashes) = iroh_bytes::store::readonly_mem::Store::new([("test", &data)]);
+    let hash = Hash::from(*hashes.values().next().unwrap());
+    let node = test_node(db)
+        .upload_rate_limits(provider::RateLimits {
+            global: None,
+            per_peer: Some(512 * 1024),
+        })
+        .spawn()
+        .await
+        .unwrap();
+    let (events_sender, mut events_recv) = mpsc::unbounded_channel();
+    node.subscribe(move |event| {
+        let events_sender = events_sender.clone();
+        async move {
+            events_sender.send(event).ok();
+        }
+        .boxed()
+    })
+    .await
+    .unwrap();
+    let addr = NodeAddr::from_parts(
+        node.node_id(),
+        None,
+        node.local_endpoint_addresses().await.unwrap(),
+    );
+    let stats = tokio::time::timeout(Duration::from_secs(10), async move {
+        let connection = dial_direct(SecretKey::generate(), addr).await?;
+        let response = fsm::start(connection, GetRequest::single(hash));
+        let connected = response.next().await?;
+        let ConnectedNext::StartRoot(start) = connected.next().await? else {
+            panic!()
+        };
+        let (_, actual) = start.next().concatenate_into_vec().await?;
+        assert_eq!(actual, data);
+        while let Some(event) = events_recv.recv().await {
+            if let Event::ByteProvide(provider::Event::TransferCompleted { stats, .. }) = event {
+                return anyhow::Ok(stats);
+            }
+        }
+        anyhow::bail!("no transfer completed event")
+    })
+    .await
+    .expect("timeout")
+    .expect("get failed");
+    // 256 KiB at 512 KiB/s, minus the initial burst
+    assert!(stats.throttled >= Duration::from_millis(300), "{stats:?}");
+    assert!(stats.duration >= stats.throttled);
+}
+
 #[tokio::test]
 #[ignore = "flaky"]
 async fn test_collection_stat() {
@@ -25,7 +25,7 @@ use iroh_bytes::Hash;
 use iroh_net::relay::RelayMode;
 use iroh_sync::{
     store::{DownloadPolicy, FilterKind, Query},
-    AuthorId, ContentStatus,
+    AuthorId, ContentStatus, WriteRestrictions,
 };
 
 const TIMEOUT: Duration = Duration::from_secs(60);
@@ -124,6 +124,175 @@ async fn sync_simple() -> Result<()> {
     Ok(())
 }
 
+/// Test that a delegate can only write within the restrictions of its capability.
+#[tokio::test]
+async fn sync_delegated() -> Result<()> {
+    setup_logging();
+    let mut rng = test_rng(b"sync_delegated");
+    let nodes = spawn_nodes(2, &mut rng).await?;
+    let clients = nodes.iter().map(|node| node.client()).collect::<Vec<_>>();
+
+    // share a doc on node0, restricted to /uploads/ and to a single author of node1
+    let doc0 = clients[0].docs.create().await?;
+    let author1 = clients[1].authors.create().await?;
+    let restrictions = WriteRestrictions::default()
+        .with_prefix(b"/uploads/".to_vec())
+        .with_authors([author1]);
+    let ticket = doc0
+        .share(
+            ShareMode::Delegate(restrictions),
+            AddrInfoOptions::RelayAndAddresses,
+        )
+        .await?;
+    let events0 = doc0.subscribe().await?;
+
+    info!("node1: join and write");
+    let peer1 = nodes[1].node_id();
+    let doc1 = clients[1].docs.import(ticket).await?;
+    doc1.set_bytes(author1, b"/uploads/a".to_vec(), b"a".to_vec())
+        .await?;
+    let res = doc1
+        .set_bytes(author1, b"/other".to_vec(), b"b".to_vec())
+        .await;
+    assert!(res.is_err());
+
+    info!("node0: wait for the delegated entry");
+    wait_for_events(events0, 2, TIMEOUT, |e| match e {
+        LiveEvent::InsertRemote { from, .. } => *from == peer1,
+        LiveEvent::ContentReady { .. } => true,
+        _ => false,
+    })
+    .await?;
+    assert_latest(&doc0, b"/uploads/a", b"a").await;
+    assert_eq!(get_all(&doc0).await?.len(), 1);
+
+    for node in nodes {
+        node.shutdown().await?;
+    }
+    Ok(())
+}
+
+/// Test that rotating a doc carries its entries forward, and that peers of the old doc only
+/// learn about the rotation pointer.
+#[tokio::test]
+async fn sync_rotate() -> Result<()> {
+    setup_logging();
+    let mut rng = test_rng(b"sync_rotate");
+    let nodes = spawn_nodes(2, &mut rng).await?;
+    let clients = nodes.iter().map(|node| node.client()).collect::<Vec<_>>();
+
+    let peer0 = nodes[0].node_id();
+    let author0 = clients[0].authors.create().await?;
+    let doc0 = clients[0].docs.create().await?;
+    let hash0 = doc0
+        .set_bytes(author0, b"k1".to_vec(), b"v1".to_vec())
+        .await?;
+    let ticket = doc0
+        .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
+        .await?;
+
+    info!("node1: join");
+    let doc1 = clients[1].docs.import(ticket).await?;
+    let mut events1 = doc1.subscribe().await?;
+    assert_next_unordered(
+        &mut events1,
+        TIMEOUT,
+        vec![
+            Box::new(move |e| matches!(e, LiveEvent::NeighborUp(peer) if *peer == peer0)),
+            Box::new(move |e| matches!(e, LiveEvent::InsertRemote { from, .. } if *from == peer0 )),
+            Box::new(move |e| match_sync_finished(e, peer0)),
+            Box::new(move |e| matches!(e, LiveEvent::ContentReady { hash } if *hash == hash0)),
+        ],
+    )
+    .await;
+
+    info!("node0: rotate");
+    let res = doc0.rotate(author0).await?;
+    assert_eq!(res.carried, 1);
+    assert_eq!(res.skipped, 0);
+    let rotated = clients[0].docs.open(res.doc_id).await?.unwrap();
+    assert_latest(&rotated, b"k1", b"v1").await;
+    let rotation = doc0.rotation().await?.context("missing rotation")?;
+    assert!(rotation.is_successor(&res.doc_id));
+    // the old document is read-only now
+    assert!(doc0
+        .set_bytes(author0, b"k2".to_vec(), b"v2".to_vec())
+        .await
+        .is_err());
+
+    info!("node1: wait for the rotation pointer");
+    wait_for_events(events1, 2, TIMEOUT, |e| match e {
+        LiveEvent::InsertRemote { from, .. } => *from == peer0,
+        LiveEvent::ContentReady { .. } => true,
+        _ => false,
+    })
+    .await?;
+    let rotation = doc1.rotation().await?.context("missing rotation")?;
+    assert!(rotation.is_successor(&res.doc_id));
+    assert!(!rotation.is_successor(&doc1.id()));
+    assert!(clients[1].docs.open(res.doc_id).await.is_err());
+
+    for node in nodes {
+        node.shutdown().await?;
+    }
+    Ok(())
+}
+
+/// Test that a peer with a relay ticket syncs an encrypted document without reading it.
+#[tokio::test]
+async fn sync_encrypted() -> Result<()> {
+    setup_logging();
+    let mut rng = test_rng(b"sync_encrypted");
+    let nodes = spawn_nodes(2, &mut rng).await?;
+    let clients = nodes.iter().map(|node| node.client()).collect::<Vec<_>>();
+
+    let peer0 = nodes[0].node_id();
+    let author0 = clients[0].authors.create().await?;
+    let doc0 = clients[0].docs.create_encrypted().await?;
+    let hash0 = doc0
+        .set_bytes(author0, b"k1".to_vec(), b"v1".to_vec())
+        .await?;
+    assert_latest(&doc0, b"k1", b"v1").await;
+    let ticket = doc0
+        .share(ShareMode::Relay, AddrInfoOptions::RelayAndAddresses)
+        .await?;
+    assert!(ticket.encryption.is_none());
+
+    info!("node1: join as relay");
+    let doc1 = clients[1].docs.import(ticket).await?;
+    let mut events1 = doc1.subscribe().await?;
+    assert_next_unordered(
+        &mut events1,
+        TIMEOUT,
+        vec![
+            Box::new(move |e| matches!(e, LiveEvent::NeighborUp(peer) if *peer == peer0)),
+            Box::new(move |e| matches!(e, LiveEvent::InsertRemote { from, .. } if *from == peer0 )),
+            Box::new(move |e| match_sync_finished(e, peer0)),
+            Box::new(move |e| matches!(e, LiveEvent::ContentReady { hash } if *hash == hash0)),
+        ],
+    )
+    .await;
+    assert!(doc1.encryption_key().await?.is_none());
+    let entries = get_all_with_content(&doc1).await?;
+    assert_eq!(entries.len(), 1);
+    assert_ne!(entries[0].0.key(), b"k1");
+    assert_ne!(entries[0].1.as_ref(), b"v1");
+    assert!(get_latest(&doc1, b"k1").await.is_err());
+
+    info!("node1: join with the key");
+    let ticket = doc0
+        .share(ShareMode::Read, AddrInfoOptions::RelayAndAddresses)
+        .await?;
+    assert_eq!(ticket.encryption, doc0.encryption_key().await?);
+    let doc1 = clients[1].docs.import(ticket).await?;
+    assert_latest(&doc1, b"k1", b"v1").await;
+
+    for node in nodes {
+        node.shutdown().await?;
+    }
+    Ok(())
+}
+
 /// Test subscribing to replica events (without sync)
 #[tokio::test]
 async fn sync_subscribe_no_sync() -> Result<()> {